@@ -0,0 +1,380 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, FixedOffset, NaiveDate, Utc};
+
+use crate::devices::Device;
+use crate::lock_connection;
+use crate::sunsaver::{Alarm, ArrayFault, ChargeState, LoadFault, LoggedResponse, LoggedResponseDay, SunSaverResponse};
+use crate::sunsaver_connection::SunSaverConnectionError;
+
+/// Totals across the latest status of every device, for controllers charging one battery bank.
+#[derive(Debug, Clone, Serialize)]
+pub struct AggregateStatus {
+    /// Devices included in the totals
+    devices: Vec<String>,
+    /// Devices whose status has not been refreshed recently, so are left out
+    stale: Vec<String>,
+    /// Devices that have not been read yet, so are left out
+    missing: Vec<String>,
+    generation: AggregateStatusGeneration,
+    storage: AggregateStatusStorage,
+    load: AggregateStatusLoad,
+    faults: AggregateStatusFaults,
+    alarms: Alarm,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AggregateStatusGeneration {
+    calculated_generation_power: f32,
+    output_power: f32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AggregateStatusStorage {
+    battery_voltage_min: f32,
+    battery_voltage_max: f32,
+    battery_charge_current_filtered: f32,
+    battery_charge_power_calculated: f32,
+    /// The state of the device furthest from having charged the battery
+    charge_state: ChargeState,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AggregateStatusLoad {
+    load_current_filtered: f32,
+    load_power_calculated: f32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AggregateStatusFaults {
+    array: ArrayFault,
+    load: LoadFault,
+}
+
+impl AggregateStatus {
+    /// Returns `None` unless at least one device has a recent status
+    pub fn new(devices: &[Device], now: DateTime<Utc>) -> Option<AggregateStatus> {
+        let mut included = Vec::new();
+        let mut stale = Vec::new();
+        let mut missing = Vec::new();
+        let mut statuses: Vec<SunSaverResponse> = Vec::new();
+        for device in devices {
+            match device.poller.latest() {
+                // An old status would be added to the totals as if it were still current
+                Some(ref snapshot) if device.poller.is_stale(snapshot, now) => stale.push(device.id.clone()),
+                Some(snapshot) => {
+                    included.push(device.id.clone());
+                    statuses.push(snapshot.status);
+                }
+                None => missing.push(device.id.clone()),
+            }
+        }
+        if statuses.is_empty() {
+            return None;
+        }
+
+        let sum = |value: fn(&SunSaverResponse) -> f32| statuses.iter().map(value).sum::<f32>();
+        let generation = AggregateStatusGeneration {
+            calculated_generation_power: sum(|status| {
+                (status.load_current_filtered() + status.battery_charge_current_filtered()) * status.solar_input_voltage_filtered()
+            }),
+            output_power: sum(SunSaverResponse::output_power),
+        };
+        let storage = AggregateStatusStorage {
+            battery_voltage_min: statuses
+                .iter()
+                .map(SunSaverResponse::battery_voltage_filtered)
+                .fold(f32::INFINITY, f32::min),
+            battery_voltage_max: statuses
+                .iter()
+                .map(SunSaverResponse::battery_voltage_filtered)
+                .fold(f32::NEG_INFINITY, f32::max),
+            battery_charge_current_filtered: sum(SunSaverResponse::battery_charge_current_filtered),
+            battery_charge_power_calculated: sum(|status| status.battery_voltage_filtered() * status.battery_charge_current_filtered()),
+            charge_state: statuses
+                .iter()
+                .map(SunSaverResponse::charge_state)
+                .max_by_key(ChargeState::severity)
+                .unwrap(),
+        };
+        let load = AggregateStatusLoad {
+            load_current_filtered: sum(SunSaverResponse::load_current_filtered),
+            load_power_calculated: sum(|status| status.load_voltage_filtered() * status.load_current_filtered()),
+        };
+        let faults = AggregateStatusFaults {
+            array: statuses.iter().fold(ArrayFault::empty(), |faults, status| faults | status.array_fault()),
+            load: statuses.iter().fold(LoadFault::empty(), |faults, status| faults | status.load_fault()),
+        };
+        let alarms = statuses.iter().fold(Alarm::empty(), |alarms, status| alarms | status.alarm());
+
+        Some(AggregateStatus {
+            devices: included,
+            stale,
+            missing,
+            generation,
+            storage,
+            load,
+            faults,
+            alarms,
+        })
+    }
+}
+
+/// The logged days of every device, merged by their estimated date.
+#[derive(Debug, Clone, Serialize)]
+pub struct AggregateLogged {
+    days: Vec<AggregateLoggedDay>,
+    /// Why each device that could not be read was left out
+    errors: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AggregateLoggedDay {
+    date: NaiveDate,
+    devices: Vec<String>,
+    battery_voltage_min: f32,
+    battery_voltage_max: f32,
+    battery_charge_daily: f32,
+    load_charge_daily: f32,
+    array_voltage_max: f32,
+    /// The longest time any of the devices spent in each state
+    time_in_absorption: u16,
+    time_in_equalize: u16,
+    time_in_float: u16,
+    array_faults: ArrayFault,
+    load_faults: LoadFault,
+    alarms: Alarm,
+}
+
+impl AggregateLoggedDay {
+    fn new(date: NaiveDate, id: &str, day: &LoggedResponseDay) -> AggregateLoggedDay {
+        AggregateLoggedDay {
+            date,
+            devices: vec![String::from(id)],
+            battery_voltage_min: day.battery_voltage_min(),
+            battery_voltage_max: day.battery_voltage_max(),
+            battery_charge_daily: day.battery_charge_daily(),
+            load_charge_daily: day.load_charge_daily(),
+            array_voltage_max: day.array_voltage_max(),
+            time_in_absorption: day.time_in_absorption(),
+            time_in_equalize: day.time_in_equalize(),
+            time_in_float: day.time_in_float(),
+            array_faults: day.array_fault(),
+            load_faults: day.load_fault(),
+            alarms: day.alarm(),
+        }
+    }
+
+    fn merge(&mut self, id: &str, day: &LoggedResponseDay) {
+        // A device can log two days on one date when it lost power in between
+        if !self.devices.iter().any(|device| device == id) {
+            self.devices.push(String::from(id));
+        }
+        self.battery_voltage_min = self.battery_voltage_min.min(day.battery_voltage_min());
+        self.battery_voltage_max = self.battery_voltage_max.max(day.battery_voltage_max());
+        self.battery_charge_daily += day.battery_charge_daily();
+        self.load_charge_daily += day.load_charge_daily();
+        self.array_voltage_max = self.array_voltage_max.max(day.array_voltage_max());
+        self.time_in_absorption = self.time_in_absorption.max(day.time_in_absorption());
+        self.time_in_equalize = self.time_in_equalize.max(day.time_in_equalize());
+        self.time_in_float = self.time_in_float.max(day.time_in_float());
+        self.array_faults |= day.array_fault();
+        self.load_faults |= day.load_fault();
+        self.alarms |= day.alarm();
+    }
+}
+
+impl AggregateLogged {
+    /// Reads the logged days of every device, dating them from the hourmeter of each device's latest snapshot.
+    ///
+    /// Fails with the first device's error when none of them could be read.
+    pub fn read(devices: &[Device], now: &DateTime<FixedOffset>) -> Result<AggregateLogged, SunSaverConnectionError> {
+        let logged = devices.iter().map(|device| {
+            let mut connection = lock_connection(&device.connection);
+            let logged = device
+                .poller
+                .current_hourmeter(connection.as_mut())
+                .and_then(|hourmeter| Ok((hourmeter, connection.read_logged()?)));
+            (device.id.as_str(), logged)
+        });
+        AggregateLogged::merge(logged, now)
+    }
+
+    fn merge<'a, I>(logged: I, now: &DateTime<FixedOffset>) -> Result<AggregateLogged, SunSaverConnectionError>
+    where
+        I: IntoIterator<Item = (&'a str, Result<(u32, LoggedResponse), SunSaverConnectionError>)>,
+    {
+        let mut days: BTreeMap<NaiveDate, AggregateLoggedDay> = BTreeMap::new();
+        let mut errors = BTreeMap::new();
+        let mut first_error = None;
+        let mut read = 0;
+        for (id, logged) in logged {
+            let (current_hourmeter, logged) = match logged {
+                Ok(logged) => logged,
+                Err(error) => {
+                    warn!("Aggregate: failed to read logged days of {:?}: {}", id, error);
+                    errors.insert(String::from(id), error.to_string());
+                    first_error.get_or_insert(error);
+                    continue;
+                }
+            };
+            read += 1;
+            for day in logged.days.iter() {
+                // Days logged after the current hourmeter cannot be dated
+                let date = match day.estimated_date(current_hourmeter, now) {
+                    Some(date) => date,
+                    None => continue,
+                };
+                days.entry(date)
+                    .and_modify(|merged| merged.merge(id, day))
+                    .or_insert_with(|| AggregateLoggedDay::new(date, id, day));
+            }
+        }
+        match first_error {
+            Some(error) if read == 0 => Err(error),
+            _ => Ok(AggregateLogged {
+                days: days.into_iter().map(|(_, day)| day).collect(),
+                errors,
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use serde_json;
+
+    use super::*;
+
+    use crate::metrics::ModbusMetrics;
+    use crate::poller::Poller;
+    use crate::sunsaver_connection::SunSaverConnection;
+
+    struct TestConnection {
+        registers: [u16; 44],
+    }
+
+    impl SunSaverConnection for TestConnection {
+        fn read_raw_registers(&mut self) -> Result<[u16; 44], SunSaverConnectionError> {
+            Ok(self.registers)
+        }
+
+        fn read_raw_logged(&mut self) -> Result<[u16; 32 * 16], SunSaverConnectionError> {
+            Err(SunSaverConnectionError::Unsupported("reading logged data"))
+        }
+    }
+
+    fn test_device(id: &str, registers: Option<[u16; 44]>, poll_interval: Duration) -> Device {
+        let mut connection = TestConnection {
+            registers: registers.unwrap_or([0u16; 44]),
+        };
+        let poller = Poller::new(poll_interval);
+        if registers.is_some() {
+            poller.read(&mut connection).unwrap();
+        }
+        Device {
+            id: String::from(id),
+            path: String::from("tcp://192.168.1.10"),
+            slave_id: 1,
+            connection: Arc::new(Mutex::new(Box::new(connection))),
+            poller,
+            metrics: Arc::new(ModbusMetrics::new()),
+        }
+    }
+
+    #[test]
+    fn aggregatestatus_new() {
+        let poll_interval = Duration::from_secs(5);
+        assert!(AggregateStatus::new(&[test_device("cabin", None, poll_interval)], Utc::now()).is_none());
+
+        let mut north = [0u16; 44];
+        north[0] = 0x1200; // 14.0625 V
+        north[3] = 0x1000; // 9.89 A charge
+        north[9] = 7; // Float
+        north[10] = 0x0001;
+        let mut south = [0u16; 44];
+        south[0] = 0x1000; // 12.5 V
+        south[3] = 0x0800; // 4.95 A charge
+        south[9] = 5; // Bulk charge
+        south[19] = 0x0002;
+        let devices = vec![
+            test_device("north", Some(north), poll_interval),
+            test_device("south", Some(south), poll_interval),
+            test_device("cabin", None, poll_interval),
+            // Polled so often that its status is already stale a second later
+            test_device("shed", Some(north), Duration::from_millis(1)),
+        ];
+        let now = Utc::now() + chrono::Duration::seconds(1);
+        let aggregate = serde_json::to_value(AggregateStatus::new(&devices, now).unwrap()).unwrap();
+
+        assert_eq!(aggregate["devices"], serde_json::json!(["north", "south"]));
+        assert_eq!(aggregate["stale"], serde_json::json!(["shed"]));
+        assert_eq!(aggregate["missing"], serde_json::json!(["cabin"]));
+        assert_eq!(aggregate["storage"]["battery_voltage_min"], 12.5);
+        assert_eq!(aggregate["storage"]["battery_voltage_max"], 14.0625);
+        let current = aggregate["storage"]["battery_charge_current_filtered"].as_f64().unwrap();
+        assert!((current - 14.84).abs() < 0.01, "{}", current);
+        assert_eq!(aggregate["storage"]["charge_state"], "BulkCharge");
+        assert_eq!(aggregate["faults"]["array"]["OVERCURENT"], true);
+        assert_eq!(aggregate["faults"]["load"]["OVERCURRENT"], true);
+        assert_eq!(aggregate["faults"]["load"]["EXTERNAL_SHORT_CIRCUIT"], false);
+
+        // Only stale statuses are not enough for totals
+        assert!(AggregateStatus::new(&devices[3..], now).is_none());
+    }
+
+    fn logged_day(hourmeter: u16, battery_charge: u16, time_in_float: u16) -> [u16; 16] {
+        let mut day = [0u16; 16];
+        day[0] = hourmeter;
+        day[3] = 0x1000; // 12.5 V
+        day[4] = 0x1200; // 14.0625 V
+        day[5] = battery_charge;
+        day[12] = time_in_float;
+        day
+    }
+
+    fn logged_response(days: &[[u16; 16]]) -> LoggedResponse {
+        let mut raw = [0xffffu16; 32 * 16];
+        for (index, day) in days.iter().enumerate() {
+            raw[index * 16..(index + 1) * 16].copy_from_slice(day);
+        }
+        LoggedResponse::from_raw_bits(raw)
+    }
+
+    #[test]
+    fn aggregatelogged_merge() {
+        let now = DateTime::parse_from_rfc3339("2019-07-02T12:00:00+01:00").unwrap();
+        let north = logged_response(&[logged_day(100, 20, 60), logged_day(124, 30, 90)]);
+        // The south controller has run 48 hours longer, so its day lines up with the north's latest
+        let south = logged_response(&[logged_day(172, 10, 120)]);
+        let logged = vec![
+            ("north", Ok((136, north))),
+            ("south", Ok((184, south))),
+            ("cabin", Err(SunSaverConnectionError::Timeout)),
+        ];
+        let aggregate = serde_json::to_value(AggregateLogged::merge(logged, &now).unwrap()).unwrap();
+
+        assert_eq!(aggregate["errors"], serde_json::json!({"cabin": "Timed out waiting for the device"}));
+        let days = aggregate["days"].as_array().unwrap();
+        assert_eq!(days.len(), 2);
+        assert_eq!(days[0]["date"], "2019-07-01");
+        assert_eq!(days[0]["devices"], serde_json::json!(["north"]));
+        assert_eq!(days[0]["battery_charge_daily"], 2.0);
+        assert_eq!(days[1]["date"], "2019-07-02");
+        assert_eq!(days[1]["devices"], serde_json::json!(["north", "south"]));
+        assert_eq!(days[1]["battery_charge_daily"], 4.0);
+        assert_eq!(days[1]["time_in_float"], 120);
+        assert_eq!(days[1]["battery_voltage_min"], 12.5);
+
+        // Fails when no device could be read
+        let logged = vec![
+            ("north", Err(SunSaverConnectionError::Timeout)),
+            ("south", Err(SunSaverConnectionError::DeviceGone(String::from("Broken pipe")))),
+        ];
+        assert_eq!(AggregateLogged::merge(logged, &now).err(), Some(SunSaverConnectionError::Timeout));
+        assert!(AggregateLogged::merge(Vec::new(), &now).is_ok());
+    }
+}
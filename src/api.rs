@@ -1,6 +1,58 @@
 use std::convert::From;
 
-use crate::sunsaver::{ArrayFault, ChargeState, LoggedResponse, LoggedResponseDay, SunSaverResponse};
+use chrono::{DateTime, FixedOffset, NaiveDate, Utc};
+
+use crate::devices::Device;
+use crate::history::{HistoryBucket, HistoryQuery};
+use crate::poller::Snapshot;
+use crate::sunsaver::{
+    Alarm, ArrayFault, ChargeState, Command, EepromSetting, EepromSettingChange, EepromSettings, LoadFault, LoadState, LoggedResponse,
+    LoggedResponseDay, SettingsValidationError, SunSaverResponse,
+};
+use crate::sunsaver_connection::SunSaverConnectionError;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ApiErrorResponse {
+    error: ApiErrorResponseError,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ApiErrorResponseError {
+    kind: &'static str,
+    message: String,
+}
+
+impl ApiErrorResponse {
+    pub fn new(kind: &'static str, message: String) -> ApiErrorResponse {
+        ApiErrorResponse {
+            error: ApiErrorResponseError { kind, message },
+        }
+    }
+}
+
+impl<'a> From<&'a SunSaverConnectionError> for ApiErrorResponse {
+    fn from(error: &'a SunSaverConnectionError) -> Self {
+        ApiErrorResponse::new(error.kind(), error.to_string())
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ApiCommandResponse {
+    command: Command,
+}
+
+impl<'a> From<&'a [SettingsValidationError]> for ApiErrorResponse {
+    fn from(errors: &'a [SettingsValidationError]) -> Self {
+        let messages: Vec<String> = errors.iter().map(SettingsValidationError::to_string).collect();
+        ApiErrorResponse::new("invalid_settings", messages.join("; "))
+    }
+}
+
+impl From<Command> for ApiCommandResponse {
+    fn from(command: Command) -> Self {
+        ApiCommandResponse { command }
+    }
+}
 
 #[derive(Debug, Clone, Serialize)]
 pub struct ApiStatusResponse {
@@ -9,17 +61,27 @@ pub struct ApiStatusResponse {
     load: ApiStatusResponseLoad,
     temperature: ApiStatusResponseTemperature,
     faults: ApiStatusResponseFaults,
+    alarms: Alarm,
+    counters: ApiStatusResponseCounters,
+    mppt_sweep: ApiStatusResponseMpptSweep,
+    today: ApiStatusResponseToday,
+    controller: ApiStatusResponseController,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    snapshot: Option<ApiStatusResponseSnapshot>,
 }
 
 #[derive(Debug, Clone, Serialize)]
 pub struct ApiStatusResponseGeneration {
     solar_input_voltage_filtered: f32,
     calculated_generation_power: f32,
+    output_power: f32,
 }
 
 #[derive(Debug, Clone, Serialize)]
 pub struct ApiStatusResponseStorage {
     battery_voltage_filtered: f32,
+    battery_voltage_slow_filtered: f32,
+    battery_regulation_voltage: f32,
     battery_charge_current_filtered: f32,
     battery_charge_power_calculated: f32,
     charge_state: ChargeState,
@@ -30,6 +92,8 @@ pub struct ApiStatusResponseLoad {
     load_voltage_filtered: f32,
     load_current_filtered: f32,
     load_power_calculated: f32,
+    state: LoadState,
+    low_voltage_disconnect: f32,
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -43,6 +107,49 @@ pub struct ApiStatusResponseTemperature {
 #[derive(Debug, Clone, Serialize)]
 pub struct ApiStatusResponseFaults {
     array: ArrayFault,
+    load: LoadFault,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ApiStatusResponseCounters {
+    battery_charge_resettable: f32,
+    battery_charge_total: f32,
+    battery_charge_energy_total: f32,
+    load_charge_resettable: f32,
+    load_charge_total: f32,
+    hourmeter: u32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ApiStatusResponseMpptSweep {
+    maximum_power_voltage: f32,
+    maximum_power: f32,
+    open_circuit_voltage: f32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ApiStatusResponseToday {
+    battery_voltage_min: f32,
+    battery_voltage_max: f32,
+    battery_charge: f32,
+    load_charge: f32,
+    array_faults: ArrayFault,
+    load_faults: LoadFault,
+    alarms: Alarm,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ApiStatusResponseSnapshot {
+    read_at: DateTime<Utc>,
+    age: f32,
+    stale: bool,
+    last_error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ApiStatusResponseController {
+    dip_switches: [bool; 4],
+    led_state: u16,
 }
 
 impl From<SunSaverResponse> for ApiStatusResponse {
@@ -56,9 +163,12 @@ impl From<SunSaverResponse> for ApiStatusResponse {
         let generation = ApiStatusResponseGeneration {
             solar_input_voltage_filtered,
             calculated_generation_power: (load_current_filtered + battery_charge_current_filtered) * solar_input_voltage_filtered,
+            output_power: response.output_power(),
         };
         let storage = ApiStatusResponseStorage {
             battery_voltage_filtered,
+            battery_voltage_slow_filtered: response.battery_voltage_slow_filtered(),
+            battery_regulation_voltage: response.battery_regulation_voltage(),
             battery_charge_current_filtered,
             battery_charge_power_calculated: battery_voltage_filtered * battery_charge_current_filtered,
             charge_state: response.charge_state(),
@@ -67,6 +177,8 @@ impl From<SunSaverResponse> for ApiStatusResponse {
             load_voltage_filtered,
             load_current_filtered,
             load_power_calculated: load_voltage_filtered * load_current_filtered,
+            state: response.load_state(),
+            low_voltage_disconnect: response.load_low_voltage_disconnect(),
         };
         let temperature = ApiStatusResponseTemperature {
             heatsink_temperature: response.heatsink_temperature(),
@@ -76,6 +188,33 @@ impl From<SunSaverResponse> for ApiStatusResponse {
         };
         let faults = ApiStatusResponseFaults {
             array: response.array_fault(),
+            load: response.load_fault(),
+        };
+        let counters = ApiStatusResponseCounters {
+            battery_charge_resettable: response.battery_charge_resettable(),
+            battery_charge_total: response.battery_charge_total(),
+            battery_charge_energy_total: response.battery_charge_energy_total(),
+            load_charge_resettable: response.load_charge_resettable(),
+            load_charge_total: response.load_charge_total(),
+            hourmeter: response.hourmeter(),
+        };
+        let mppt_sweep = ApiStatusResponseMpptSweep {
+            maximum_power_voltage: response.sweep_maximum_power_voltage(),
+            maximum_power: response.sweep_maximum_power(),
+            open_circuit_voltage: response.sweep_open_circuit_voltage(),
+        };
+        let today = ApiStatusResponseToday {
+            battery_voltage_min: response.battery_voltage_min_daily(),
+            battery_voltage_max: response.battery_voltage_max_daily(),
+            battery_charge: response.battery_charge_daily(),
+            load_charge: response.load_charge_daily(),
+            array_faults: response.array_fault_daily(),
+            load_faults: response.load_fault_daily(),
+            alarms: response.alarm_daily(),
+        };
+        let controller = ApiStatusResponseController {
+            dip_switches: response.dip_switches(),
+            led_state: response.led_state(),
         };
         ApiStatusResponse {
             generation,
@@ -83,10 +222,66 @@ impl From<SunSaverResponse> for ApiStatusResponse {
             load,
             temperature,
             faults,
+            alarms: response.alarm(),
+            counters,
+            mppt_sweep,
+            today,
+            controller,
+            snapshot: None,
         }
     }
 }
 
+impl ApiStatusResponse {
+    /// Builds the response from a cached snapshot, reporting how old it is and why it may not have been refreshed
+    pub fn from_snapshot(snapshot: Snapshot, now: DateTime<Utc>, stale: bool, last_error: Option<&SunSaverConnectionError>) -> ApiStatusResponse {
+        let snapshot_response = ApiStatusResponseSnapshot {
+            read_at: snapshot.read_at,
+            age: snapshot.age(now).num_milliseconds() as f32 / 1000.0,
+            stale,
+            last_error: last_error.map(SunSaverConnectionError::to_string),
+        };
+        let mut response = ApiStatusResponse::from(snapshot.status);
+        response.snapshot = Some(snapshot_response);
+        response
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ApiDevicesResponse {
+    devices: Vec<ApiDeviceResponse>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ApiDeviceResponse {
+    id: String,
+    path: String,
+    slave_id: u8,
+    read_at: Option<DateTime<Utc>>,
+    stale: bool,
+    last_error: Option<String>,
+}
+
+impl ApiDevicesResponse {
+    pub fn new(devices: &[Device], now: DateTime<Utc>) -> ApiDevicesResponse {
+        let devices = devices
+            .iter()
+            .map(|device| {
+                let latest = device.poller.latest();
+                ApiDeviceResponse {
+                    id: device.id.clone(),
+                    path: device.path.clone(),
+                    slave_id: device.slave_id,
+                    read_at: latest.as_ref().map(|snapshot| snapshot.read_at),
+                    stale: latest.as_ref().map_or(false, |snapshot| device.poller.is_stale(snapshot, now)),
+                    last_error: device.poller.last_error().as_ref().map(SunSaverConnectionError::to_string),
+                }
+            })
+            .collect();
+        ApiDevicesResponse { devices }
+    }
+}
+
 #[derive(Debug, Clone, Serialize)]
 pub struct ApiLoggedResponse {
     days: Vec<ApiLoggedDayResponse>,
@@ -95,29 +290,274 @@ pub struct ApiLoggedResponse {
 #[derive(Debug, Clone, Serialize)]
 pub struct ApiLoggedDayResponse {
     hourmeter: u32,
+    estimated_date: Option<NaiveDate>,
     battery_voltage_min: f32,
     battery_voltage_max: f32,
     battery_charge_daily: f32,
     load_charge_daily: f32,
     array_voltage_max: f32,
+    time_in_absorption: u16,
+    time_in_equalize: u16,
+    time_in_float: u16,
+    array_faults: ArrayFault,
+    load_faults: LoadFault,
+    alarms: Alarm,
 }
 
-impl From<LoggedResponse> for ApiLoggedResponse {
-    fn from(response: LoggedResponse) -> Self {
-        let days = response.days.into_iter().map(ApiLoggedDayResponse::from).collect();
+impl ApiLoggedResponse {
+    pub fn new(response: LoggedResponse, current_hourmeter: u32, now: &DateTime<FixedOffset>) -> ApiLoggedResponse {
+        let days = response
+            .days
+            .into_iter()
+            .map(|day| ApiLoggedDayResponse::new(day, current_hourmeter, now))
+            .collect();
         ApiLoggedResponse { days }
     }
 }
 
-impl From<LoggedResponseDay> for ApiLoggedDayResponse {
-    fn from(response: LoggedResponseDay) -> Self {
+impl ApiLoggedDayResponse {
+    pub fn new(response: LoggedResponseDay, current_hourmeter: u32, now: &DateTime<FixedOffset>) -> ApiLoggedDayResponse {
         ApiLoggedDayResponse {
             hourmeter: response.hourmeter,
+            estimated_date: response.estimated_date(current_hourmeter, now),
             battery_voltage_min: response.battery_voltage_min(),
             battery_voltage_max: response.battery_voltage_max(),
             battery_charge_daily: response.battery_charge_daily(),
             load_charge_daily: response.load_charge_daily(),
             array_voltage_max: response.array_voltage_max(),
+            time_in_absorption: response.time_in_absorption(),
+            time_in_equalize: response.time_in_equalize(),
+            time_in_float: response.time_in_float(),
+            array_faults: response.array_fault(),
+            load_faults: response.load_fault(),
+            alarms: response.alarm(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ApiValue<T> {
+    value: T,
+    unit: &'static str,
+}
+
+impl<T> ApiValue<T> {
+    fn new(value: T, unit: &'static str) -> ApiValue<T> {
+        ApiValue { value, unit }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ApiSettingsResponse {
+    charge: ApiSettingsResponseCharge,
+    load: ApiSettingsResponseLoad,
+    counters: ApiSettingsResponseCounters,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ApiSettingsResponseCharge {
+    absorption_voltage: ApiValue<f32>,
+    absorption_time: ApiValue<u16>,
+    absorption_time_low_battery: ApiValue<u16>,
+    low_battery_trip_voltage: ApiValue<f32>,
+    float_voltage: ApiValue<f32>,
+    float_cancel_voltage: ApiValue<f32>,
+    equalize_voltage: ApiValue<f32>,
+    equalize_interval: ApiValue<u16>,
+    equalize_time: ApiValue<u16>,
+    equalize_time_above_regulation: ApiValue<u16>,
+    temperature_compensation: ApiValue<f32>,
+    temperature_compensation_min: ApiValue<i8>,
+    temperature_compensation_max: ApiValue<i8>,
+    high_voltage_disconnect: ApiValue<f32>,
+    high_voltage_reconnect: ApiValue<f32>,
+    charge_reference_limit: ApiValue<f32>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ApiSettingsResponseLoad {
+    low_voltage_disconnect: ApiValue<f32>,
+    low_voltage_reconnect: ApiValue<f32>,
+    low_voltage_disconnect_warning_time: ApiValue<f32>,
+    high_voltage_disconnect: ApiValue<f32>,
+    high_voltage_reconnect: ApiValue<f32>,
+    current_compensation: ApiValue<f32>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ApiSettingsResponseCounters {
+    hourmeter: ApiValue<u32>,
+    battery_charge_resettable: ApiValue<f32>,
+    battery_charge_total: ApiValue<f32>,
+    battery_charge_energy_total: ApiValue<f32>,
+    load_charge_resettable: ApiValue<f32>,
+    load_charge_total: ApiValue<f32>,
+    battery_voltage_min: ApiValue<f32>,
+    battery_voltage_max: ApiValue<f32>,
+    array_voltage_max: ApiValue<f32>,
+    days_since_equalize: ApiValue<u16>,
+}
+
+impl From<EepromSettings> for ApiSettingsResponse {
+    fn from(settings: EepromSettings) -> Self {
+        let charge = ApiSettingsResponseCharge {
+            absorption_voltage: ApiValue::new(settings.absorption_voltage(), "V"),
+            absorption_time: ApiValue::new(settings.absorption_time(), "s"),
+            absorption_time_low_battery: ApiValue::new(settings.absorption_time_low_battery(), "s"),
+            low_battery_trip_voltage: ApiValue::new(settings.low_battery_trip_voltage(), "V"),
+            float_voltage: ApiValue::new(settings.float_voltage(), "V"),
+            float_cancel_voltage: ApiValue::new(settings.float_cancel_voltage(), "V"),
+            equalize_voltage: ApiValue::new(settings.equalize_voltage(), "V"),
+            equalize_interval: ApiValue::new(settings.equalize_interval(), "days"),
+            equalize_time: ApiValue::new(settings.equalize_time(), "s"),
+            equalize_time_above_regulation: ApiValue::new(settings.equalize_time_above_regulation(), "s"),
+            temperature_compensation: ApiValue::new(settings.temperature_compensation(), "V/C"),
+            temperature_compensation_min: ApiValue::new(settings.temperature_compensation_min(), "C"),
+            temperature_compensation_max: ApiValue::new(settings.temperature_compensation_max(), "C"),
+            high_voltage_disconnect: ApiValue::new(settings.high_voltage_disconnect(), "V"),
+            high_voltage_reconnect: ApiValue::new(settings.high_voltage_reconnect(), "V"),
+            charge_reference_limit: ApiValue::new(settings.charge_reference_limit(), "V"),
+        };
+        let load = ApiSettingsResponseLoad {
+            low_voltage_disconnect: ApiValue::new(settings.load_low_voltage_disconnect(), "V"),
+            low_voltage_reconnect: ApiValue::new(settings.load_low_voltage_reconnect(), "V"),
+            low_voltage_disconnect_warning_time: ApiValue::new(settings.load_low_voltage_disconnect_warning_time(), "s"),
+            high_voltage_disconnect: ApiValue::new(settings.load_high_voltage_disconnect(), "V"),
+            high_voltage_reconnect: ApiValue::new(settings.load_high_voltage_reconnect(), "V"),
+            current_compensation: ApiValue::new(settings.load_current_compensation(), "ohm"),
+        };
+        let counters = ApiSettingsResponseCounters {
+            hourmeter: ApiValue::new(settings.hourmeter(), "h"),
+            battery_charge_resettable: ApiValue::new(settings.battery_charge_resettable(), "Ah"),
+            battery_charge_total: ApiValue::new(settings.battery_charge_total(), "Ah"),
+            battery_charge_energy_total: ApiValue::new(settings.battery_charge_energy_total(), "kWh"),
+            load_charge_resettable: ApiValue::new(settings.load_charge_resettable(), "Ah"),
+            load_charge_total: ApiValue::new(settings.load_charge_total(), "Ah"),
+            battery_voltage_min: ApiValue::new(settings.battery_voltage_min(), "V"),
+            battery_voltage_max: ApiValue::new(settings.battery_voltage_max(), "V"),
+            array_voltage_max: ApiValue::new(settings.array_voltage_max(), "V"),
+            days_since_equalize: ApiValue::new(settings.days_since_equalize(), "days"),
+        };
+        ApiSettingsResponse { charge, load, counters }
+    }
+}
+
+/// Partial settings document accepted by `PUT /api/v1/settings`, shaped like the settings response without units
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ApiSettingsUpdateRequest {
+    #[serde(default)]
+    charge: ApiSettingsUpdateRequestCharge,
+    #[serde(default)]
+    load: ApiSettingsUpdateRequestLoad,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ApiSettingsUpdateRequestCharge {
+    absorption_voltage: Option<f32>,
+    absorption_time: Option<f32>,
+    absorption_time_low_battery: Option<f32>,
+    low_battery_trip_voltage: Option<f32>,
+    float_voltage: Option<f32>,
+    float_cancel_voltage: Option<f32>,
+    equalize_voltage: Option<f32>,
+    equalize_interval: Option<f32>,
+    equalize_time: Option<f32>,
+    equalize_time_above_regulation: Option<f32>,
+    high_voltage_disconnect: Option<f32>,
+    high_voltage_reconnect: Option<f32>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ApiSettingsUpdateRequestLoad {
+    low_voltage_disconnect: Option<f32>,
+    low_voltage_reconnect: Option<f32>,
+    low_voltage_disconnect_warning_time: Option<f32>,
+    high_voltage_disconnect: Option<f32>,
+    high_voltage_reconnect: Option<f32>,
+}
+
+impl ApiSettingsUpdateRequest {
+    pub fn values(&self) -> Vec<(EepromSetting, f32)> {
+        let charge = &self.charge;
+        let load = &self.load;
+        let values = [
+            (EepromSetting::AbsorptionVoltage, charge.absorption_voltage),
+            (EepromSetting::AbsorptionTime, charge.absorption_time),
+            (EepromSetting::AbsorptionTimeLowBattery, charge.absorption_time_low_battery),
+            (EepromSetting::LowBatteryTripVoltage, charge.low_battery_trip_voltage),
+            (EepromSetting::FloatVoltage, charge.float_voltage),
+            (EepromSetting::FloatCancelVoltage, charge.float_cancel_voltage),
+            (EepromSetting::EqualizeVoltage, charge.equalize_voltage),
+            (EepromSetting::EqualizeInterval, charge.equalize_interval),
+            (EepromSetting::EqualizeTime, charge.equalize_time),
+            (EepromSetting::EqualizeTimeAboveRegulation, charge.equalize_time_above_regulation),
+            (EepromSetting::HighVoltageDisconnect, charge.high_voltage_disconnect),
+            (EepromSetting::HighVoltageReconnect, charge.high_voltage_reconnect),
+            (EepromSetting::LoadLowVoltageDisconnect, load.low_voltage_disconnect),
+            (EepromSetting::LoadLowVoltageReconnect, load.low_voltage_reconnect),
+            (
+                EepromSetting::LoadLowVoltageDisconnectWarningTime,
+                load.low_voltage_disconnect_warning_time,
+            ),
+            (EepromSetting::LoadHighVoltageDisconnect, load.high_voltage_disconnect),
+            (EepromSetting::LoadHighVoltageReconnect, load.high_voltage_reconnect),
+        ];
+        values
+            .iter()
+            .filter_map(|(setting, value)| value.map(|value| (*setting, value)))
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ApiSettingsUpdateResponse {
+    dry_run: bool,
+    changes: Vec<ApiSettingsUpdateResponseChange>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ApiSettingsUpdateResponseChange {
+    setting: &'static str,
+    unit: &'static str,
+    from: f32,
+    to: f32,
+}
+
+impl ApiSettingsUpdateResponse {
+    pub fn new(dry_run: bool, changes: &[EepromSettingChange]) -> ApiSettingsUpdateResponse {
+        let changes = changes
+            .iter()
+            .map(|change| ApiSettingsUpdateResponseChange {
+                setting: change.setting.name(),
+                unit: change.setting.unit(),
+                from: change.setting.decode(change.from),
+                to: change.setting.decode(change.to),
+            })
+            .collect();
+        ApiSettingsUpdateResponse { dry_run, changes }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ApiHistoryResponse {
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    resolution: i64,
+    fields: Vec<&'static str>,
+    buckets: Vec<HistoryBucket>,
+}
+
+impl ApiHistoryResponse {
+    pub fn new(query: &HistoryQuery, buckets: Vec<HistoryBucket>) -> ApiHistoryResponse {
+        ApiHistoryResponse {
+            from: query.from,
+            to: query.to,
+            resolution: query.resolution.num_seconds(),
+            fields: query.fields.iter().map(|field| field.name()).collect(),
+            buckets,
         }
     }
 }
@@ -126,12 +566,138 @@ impl From<LoggedResponseDay> for ApiLoggedDayResponse {
 mod test {
     use serde_json;
 
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
     use super::*;
-    use crate::sunsaver::ArrayFault;
+    use crate::metrics::ModbusMetrics;
+    use crate::poller::Poller;
+    use crate::sunsaver::{ArrayFault, LoadFault};
+    use crate::sunsaver_connection::SunSaverConnection;
+
+    #[test]
+    fn api_errorresponse() {
+        let native = ApiErrorResponse::from(&SunSaverConnectionError::ShortRead { expected: 44, actual: 22 });
+        let json = serde_json::to_string(&native).unwrap();
+        assert_eq!(
+            json,
+            "{\"error\":{\"kind\":\"short_read\",\"message\":\"Short read, expected 44 registers got 22\"}}"
+        );
+    }
+
+    #[test]
+    fn api_value() {
+        let native = ApiValue::new(14.4f32, "V");
+        let json = serde_json::to_string(&native).unwrap();
+        assert_eq!(json, "{\"value\":14.4,\"unit\":\"V\"}");
+    }
+
+    #[test]
+    fn api_settingsupdaterequest_values() {
+        let native: ApiSettingsUpdateRequest =
+            serde_json::from_str("{\"charge\":{\"float_voltage\":13.6},\"load\":{\"low_voltage_disconnect\":11.5}}").unwrap();
+        assert_eq!(
+            native.values(),
+            vec![(EepromSetting::FloatVoltage, 13.6), (EepromSetting::LoadLowVoltageDisconnect, 11.5)]
+        );
+    }
+
+    #[test]
+    fn api_settingsupdaterequest_unknown_field() {
+        assert!(serde_json::from_str::<ApiSettingsUpdateRequest>("{\"charge\":{\"flot_voltage\":13.6}}").is_err());
+        assert!(serde_json::from_str::<ApiSettingsUpdateRequest>("{\"counters\":{\"hourmeter\":0}}").is_err());
+    }
+
+    #[test]
+    fn api_settingsupdateresponse() {
+        let change = EepromSettingChange {
+            setting: EepromSetting::AbsorptionTime,
+            from: 3600,
+            to: 7200,
+        };
+        let native = ApiSettingsUpdateResponse::new(true, &[change]);
+        let json = serde_json::to_string(&native).unwrap();
+        assert_eq!(
+            json,
+            "{\"dry_run\":true,\"changes\":[{\"setting\":\"charge.absorption_time\",\"unit\":\"s\",\"from\":3600.0,\"to\":7200.0}]}"
+        );
+    }
+
+    #[test]
+    fn api_loggedresponse_estimated_date() {
+        let mut raw_bits = [0xffffu16; 32 * 16];
+        raw_bits[..16].copy_from_slice(&[
+            0x2402, 0x0100, 0x0000, 0x1011, 0x11fb, 0x0047, 0x001b, 0x0000, 0x0000, 0x1a84, 0x00b4, 0x0000, 0x010f, 0xffff, 0xffff, 0xffff,
+        ]);
+        let now = DateTime::parse_from_rfc3339("2019-06-30T12:00:00+01:00").unwrap();
+        let native = ApiLoggedResponse::new(LoggedResponse::from_raw_bits(raw_bits), 0x2402 + 48, &now);
+        let json = serde_json::to_string(&native).unwrap();
+        assert!(
+            json.starts_with("{\"days\":[{\"hourmeter\":9218,\"estimated_date\":\"2019-06-28\","),
+            json
+        );
+    }
+
+    #[test]
+    fn api_statusresponse_from_snapshot() {
+        let read_at = DateTime::parse_from_rfc3339("2019-06-30T12:00:00Z").unwrap().with_timezone(&Utc);
+        let snapshot = Snapshot {
+            status: SunSaverResponse::from_raw_bits([0u16; 44]),
+            read_at,
+        };
+        let native = ApiStatusResponse::from_snapshot(
+            snapshot,
+            read_at + chrono::Duration::milliseconds(2500),
+            false,
+            Some(&SunSaverConnectionError::Timeout),
+        );
+        let json = serde_json::to_string(&native).unwrap();
+        assert!(
+            json.ends_with(
+                "\"snapshot\":{\"read_at\":\"2019-06-30T12:00:00Z\",\"age\":2.5,\"stale\":false,\"last_error\":\"Timed out waiting for the device\"}}"
+            ),
+            json
+        );
+    }
+
+    struct TimeoutConnection;
+
+    impl SunSaverConnection for TimeoutConnection {
+        fn read_raw_registers(&mut self) -> Result<[u16; 44], SunSaverConnectionError> {
+            Err(SunSaverConnectionError::Timeout)
+        }
+
+        fn read_raw_logged(&mut self) -> Result<[u16; 32 * 16], SunSaverConnectionError> {
+            Err(SunSaverConnectionError::Timeout)
+        }
+    }
+
+    #[test]
+    fn api_devicesresponse() {
+        let poller = Poller::new(Duration::from_secs(5));
+        poller.read(&mut TimeoutConnection).unwrap_err();
+        let device = Device {
+            id: String::from("shed"),
+            path: String::from("tcp://192.168.1.10"),
+            slave_id: 2,
+            connection: Arc::new(Mutex::new(Box::new(TimeoutConnection))),
+            poller,
+            metrics: Arc::new(ModbusMetrics::new()),
+        };
+        let native = ApiDevicesResponse::new(&[device], Utc::now());
+        let json = serde_json::to_string(&native).unwrap();
+        assert_eq!(
+            json,
+            "{\"devices\":[{\"id\":\"shed\",\"path\":\"tcp://192.168.1.10\",\"slave_id\":2,\"read_at\":null,\"stale\":false,\"last_error\":\"Timed out waiting for the device\"}]}"
+        );
+    }
 
     #[test]
     fn api_statusresponse_faults() {
-        let native = ApiStatusResponseFaults { array: ArrayFault::empty() };
+        let native = ApiStatusResponseFaults {
+            array: ArrayFault::empty(),
+            load: LoadFault::empty(),
+        };
         let json = serde_json::to_string(&native).unwrap();
         assert_eq!(
             json,
@@ -145,6 +711,15 @@ mod test {
              \"RTS_SHORTED\":false,\
              \"RTS_DISCONECTED\":false,\
              \"INTERNAL_TEMP_SENSOR_FAIL\":false\
+             },\
+             \"load\":{\
+             \"EXTERNAL_SHORT_CIRCUIT\":false,\
+             \"OVERCURRENT\":false,\
+             \"FETS_SHORTED\":false,\
+             \"SOFTWARE_BUGS\":false,\
+             \"HVD\":false,\
+             \"HEATSINK_OVER_TEMP\":false,\
+             \"EEPROM_EDIT\":false\
              }}"
         );
     }
@@ -0,0 +1,351 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+
+use toml;
+
+use crate::recording::ReplayOptions;
+use crate::sunsaver_connection::{ModbusOptions, RetryPolicy, SerialOptions};
+
+// Printed by --print-config in place of passwords and tokens
+const REDACTED: &str = "<redacted>";
+// Names the [device] controller when no id is given
+pub const DEFAULT_DEVICE_ID: &str = "default";
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    Parse(toml::de::Error),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io(error) => write!(f, "Failed to read the config file: {}", error),
+            ConfigError::Parse(error) => write!(f, "Invalid config file: {}", error),
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+impl From<io::Error> for ConfigError {
+    fn from(error: io::Error) -> Self {
+        ConfigError::Io(error)
+    }
+}
+
+impl From<toml::de::Error> for ConfigError {
+    fn from(error: toml::de::Error) -> Self {
+        ConfigError::Parse(error)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DeviceConfig {
+    /// Names the device in `/api/v1/devices/{id}`, required for each of `[[devices]]`
+    pub id: Option<String>,
+    /// Serial device, `tcp://host[:port]` Modbus TCP gateway or a recording or fixture to replay
+    pub path: Option<String>,
+    pub slave_id: u8,
+    pub response_timeout_ms: u64,
+    /// Only used by serial devices
+    pub serial: SerialOptions,
+    pub retry: RetryPolicy,
+    /// Appends every Modbus request and its answer to this file, for replaying as a device later
+    pub record: Option<String>,
+    /// Only used by recordings and fixtures
+    pub replay: ReplayOptions,
+}
+
+impl Default for DeviceConfig {
+    fn default() -> DeviceConfig {
+        let options = ModbusOptions::default();
+        DeviceConfig {
+            id: None,
+            path: None,
+            slave_id: options.slave_id,
+            response_timeout_ms: options.response_timeout.as_secs() * 1000 + u64::from(options.response_timeout.subsec_millis()),
+            serial: SerialOptions::default(),
+            retry: options.retry,
+            record: None,
+            replay: ReplayOptions::default(),
+        }
+    }
+}
+
+impl DeviceConfig {
+    pub fn modbus_options(&self) -> ModbusOptions {
+        ModbusOptions {
+            slave_id: self.slave_id,
+            response_timeout: Duration::from_millis(self.response_timeout_ms),
+            retry: self.retry,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub bind_address: String,
+    pub port: u16,
+    pub web_root: String,
+    pub api_token: Option<String>,
+    pub utc_offset: String,
+}
+
+impl Default for ServerConfig {
+    fn default() -> ServerConfig {
+        ServerConfig {
+            bind_address: String::from("0.0.0.0"),
+            port: 8080,
+            web_root: String::from("web"),
+            api_token: None,
+            utc_offset: String::from("+00:00"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PollerConfig {
+    pub interval_seconds: u64,
+}
+
+impl Default for PollerConfig {
+    fn default() -> PollerConfig {
+        PollerConfig { interval_seconds: 5 }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HistoryConfig {
+    /// SQLite file, no history is kept without one
+    pub path: Option<String>,
+    /// 0 keeps samples forever
+    pub retention_days: u32,
+    /// 0 keeps logged days forever
+    pub logged_retention_days: u32,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> HistoryConfig {
+        HistoryConfig {
+            path: None,
+            retention_days: 30,
+            logged_retention_days: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MqttConfig {
+    /// `host[:port]`, nothing is published without one
+    pub broker: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub topic: String,
+    pub discovery_prefix: String,
+    /// Commands that may be run by publishing to the command topics
+    pub commands: Vec<String>,
+}
+
+impl Default for MqttConfig {
+    fn default() -> MqttConfig {
+        MqttConfig {
+            broker: None,
+            username: None,
+            password: None,
+            topic: String::from("sunsaver"),
+            discovery_prefix: String::from("homeassistant"),
+            commands: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct InfluxConfig {
+    /// Write URL, nothing is written without one
+    pub url: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub token: Option<String>,
+    pub batch_size: usize,
+    pub buffer: Option<String>,
+    pub tags: BTreeMap<String, String>,
+}
+
+impl Default for InfluxConfig {
+    fn default() -> InfluxConfig {
+        InfluxConfig {
+            url: None,
+            username: None,
+            password: None,
+            token: None,
+            batch_size: 12,
+            buffer: None,
+            tags: BTreeMap::new(),
+        }
+    }
+}
+
+/// Everything that can be set in the TOML config file, each section is optional.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub device: DeviceConfig,
+    /// Several controllers, in place of `device`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub devices: Vec<DeviceConfig>,
+    pub server: ServerConfig,
+    pub poller: PollerConfig,
+    pub history: HistoryConfig,
+    pub mqtt: MqttConfig,
+    pub influxdb: InfluxConfig,
+}
+
+fn redact(secret: &Option<String>) -> Option<String> {
+    secret.as_ref().map(|_| String::from(REDACTED))
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Config, ConfigError> {
+        Ok(toml::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    /// The configured controllers, `device` on its own when no `devices` are given
+    pub fn devices(&self) -> Vec<DeviceConfig> {
+        if !self.devices.is_empty() {
+            return self.devices.clone();
+        }
+        let mut device = self.device.clone();
+        device.id.get_or_insert_with(|| String::from(DEFAULT_DEVICE_ID));
+        vec![device]
+    }
+
+    /// A copy with the passwords and tokens hidden, for printing
+    pub fn redacted(&self) -> Config {
+        let mut config = self.clone();
+        config.server.api_token = redact(&config.server.api_token);
+        config.mqtt.password = redact(&config.mqtt.password);
+        config.influxdb.password = redact(&config.influxdb.password);
+        config.influxdb.token = redact(&config.influxdb.token);
+        config
+    }
+
+    pub fn to_toml(&self) -> String {
+        toml::to_string_pretty(self).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn config_parse() {
+        let config: Config = toml::from_str(
+            r#"
+            [device]
+            path = "/dev/ttyUSB0"
+            slave_id = 2
+
+            [device.serial]
+            baud_rate = 19200
+
+            [device.retry]
+            attempts = 5
+
+            [device.replay]
+            loop = true
+
+            [mqtt]
+            broker = "localhost"
+            commands = ["load_disconnect", "load_connect"]
+
+            [influxdb]
+            url = "http://localhost:8086/write?db=sunsaver"
+            tags = { site = "home", controller = "1" }
+            "#,
+        )
+        .unwrap();
+
+        let mut expected = Config::default();
+        expected.device.path = Some(String::from("/dev/ttyUSB0"));
+        expected.device.slave_id = 2;
+        expected.device.serial.baud_rate = 19200;
+        expected.device.retry.attempts = 5;
+        expected.device.replay.repeat = true;
+        expected.mqtt.broker = Some(String::from("localhost"));
+        expected.mqtt.commands = vec![String::from("load_disconnect"), String::from("load_connect")];
+        expected.influxdb.url = Some(String::from("http://localhost:8086/write?db=sunsaver"));
+        expected.influxdb.tags.insert(String::from("site"), String::from("home"));
+        expected.influxdb.tags.insert(String::from("controller"), String::from("1"));
+        assert_eq!(config, expected);
+
+        assert_eq!(config.device.modbus_options().retry, RetryPolicy { attempts: 5, wait_ms: 100 });
+        assert_eq!(config.device.modbus_options().response_timeout, Duration::from_secs(1));
+        assert_eq!(config.device.serial.parity, 'N');
+    }
+
+    #[test]
+    fn config_devices() {
+        let config = Config::default();
+        assert_eq!(config.devices().len(), 1);
+        assert_eq!(config.devices()[0].id, Some(String::from(DEFAULT_DEVICE_ID)));
+
+        let config: Config = toml::from_str(
+            r#"
+            [[devices]]
+            id = "north"
+            path = "/dev/ttyUSB0"
+            slave_id = 1
+
+            [[devices]]
+            id = "south"
+            path = "/dev/ttyUSB0"
+            slave_id = 2
+
+            [[devices]]
+            id = "cabin"
+            path = "tcp://192.168.1.10"
+            "#,
+        )
+        .unwrap();
+        let devices = config.devices();
+        let ids: Vec<_> = devices.iter().map(|device| device.id.clone().unwrap()).collect();
+        assert_eq!(ids, vec!["north", "south", "cabin"]);
+        assert_eq!(devices[1].slave_id, 2);
+        assert_eq!(devices[2].slave_id, 1);
+        assert_eq!(toml::from_str::<Config>(&config.to_toml()).unwrap(), config);
+    }
+
+    #[test]
+    fn config_parse_unknown_field() {
+        let error = toml::from_str::<Config>("[server]\nprot = 8080\n").unwrap_err();
+        assert!(error.to_string().contains("unknown field `prot`"));
+    }
+
+    #[test]
+    fn config_to_toml() {
+        let mut config = Config::default();
+        config.device.path = Some(String::from("tcp://192.168.1.10"));
+        config.server.api_token = Some(String::from("s3cret"));
+        config.influxdb.tags.insert(String::from("site"), String::from("home"));
+        assert_eq!(toml::from_str::<Config>(&config.to_toml()).unwrap(), config);
+
+        let redacted = config.redacted().to_toml();
+        assert!(!redacted.contains("s3cret"));
+        assert!(!redacted.contains("password"));
+        let redacted = toml::from_str::<Config>(&redacted).unwrap();
+        assert_eq!(redacted.server.api_token, Some(String::from("<redacted>")));
+    }
+}
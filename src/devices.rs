@@ -0,0 +1,187 @@
+use std::sync::Arc;
+
+use crate::metrics::ModbusMetrics;
+use crate::poller::Poller;
+use crate::sunsaver::Coil;
+use crate::sunsaver_connection::{SunSaverConnection, SunSaverConnectionError};
+use crate::{lock_connection, SharedConnection};
+
+/// A controller served under `/api/v1/devices/{id}`.
+#[derive(Clone)]
+pub struct Device {
+    pub id: String,
+    /// Serial device or `tcp://host[:port]` address the controller is reached through
+    pub path: String,
+    pub slave_id: u8,
+    pub connection: SharedConnection,
+    pub poller: Poller,
+    /// Counters for this controller's Modbus reads, even when it shares a bus
+    pub metrics: Arc<ModbusMetrics>,
+}
+
+pub fn find<'a>(devices: &'a [Device], id: &str) -> Option<&'a Device> {
+    devices.iter().find(|device| device.id == id)
+}
+
+/// One controller on a bus shared with others, the bus is held and the slave id selected for each request.
+pub struct BusDeviceConnection {
+    bus: SharedConnection,
+    slave_id: u8,
+    metrics: Option<Arc<ModbusMetrics>>,
+}
+
+impl BusDeviceConnection {
+    pub fn new(bus: SharedConnection, slave_id: u8) -> BusDeviceConnection {
+        BusDeviceConnection {
+            bus,
+            slave_id,
+            metrics: None,
+        }
+    }
+
+    fn on_bus<T, F>(&self, request: F) -> Result<T, SunSaverConnectionError>
+    where
+        F: FnOnce(&mut dyn SunSaverConnection) -> Result<T, SunSaverConnectionError>,
+    {
+        let mut bus = lock_connection(&self.bus);
+        bus.select_slave(self.slave_id)?;
+        if let Some(metrics) = &self.metrics {
+            bus.measure(metrics.clone());
+        }
+        request(bus.as_mut())
+    }
+}
+
+impl SunSaverConnection for BusDeviceConnection {
+    fn read_raw_registers(&mut self) -> Result<[u16; 44], SunSaverConnectionError> {
+        self.on_bus(|bus| bus.read_raw_registers())
+    }
+
+    fn read_raw_logged(&mut self) -> Result<[u16; 32 * 16], SunSaverConnectionError> {
+        self.on_bus(|bus| bus.read_raw_logged())
+    }
+
+    fn write_coil(&mut self, coil: Coil, value: bool) -> Result<(), SunSaverConnectionError> {
+        self.on_bus(|bus| bus.write_coil(coil, value))
+    }
+
+    fn read_raw_eeprom(&mut self) -> Result<[u16; 47], SunSaverConnectionError> {
+        self.on_bus(|bus| bus.read_raw_eeprom())
+    }
+
+    fn write_register(&mut self, address: u16, value: u16) -> Result<(), SunSaverConnectionError> {
+        self.on_bus(|bus| bus.write_register(address, value))
+    }
+
+    fn select_slave(&mut self, slave_id: u8) -> Result<(), SunSaverConnectionError> {
+        self.slave_id = slave_id;
+        Ok(())
+    }
+
+    fn measure(&mut self, metrics: Arc<ModbusMetrics>) {
+        self.metrics = Some(metrics);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    use super::*;
+
+    use crate::metrics::MetricsWriter;
+    use crate::sunsaver::Command;
+
+    /// Answers with the selected slave id in the first register so each request shows who it was sent to
+    struct TestBus {
+        slave_id: Option<u8>,
+        coil_writes: Arc<Mutex<Vec<(u8, Coil, bool)>>>,
+        metrics: Option<Arc<ModbusMetrics>>,
+    }
+
+    impl SunSaverConnection for TestBus {
+        fn read_raw_registers(&mut self) -> Result<[u16; 44], SunSaverConnectionError> {
+            if let Some(metrics) = &self.metrics {
+                metrics.record_read(1, Duration::from_millis(10), None);
+            }
+            let mut registers = [0u16; 44];
+            registers[0] = u16::from(self.slave_id.ok_or(SunSaverConnectionError::Timeout)?);
+            Ok(registers)
+        }
+
+        fn read_raw_logged(&mut self) -> Result<[u16; 32 * 16], SunSaverConnectionError> {
+            Err(SunSaverConnectionError::Unsupported("reading logged data"))
+        }
+
+        fn write_coil(&mut self, coil: Coil, value: bool) -> Result<(), SunSaverConnectionError> {
+            self.coil_writes.lock().unwrap().push((self.slave_id.unwrap(), coil, value));
+            Ok(())
+        }
+
+        fn select_slave(&mut self, slave_id: u8) -> Result<(), SunSaverConnectionError> {
+            self.slave_id = Some(slave_id);
+            Ok(())
+        }
+
+        fn measure(&mut self, metrics: Arc<ModbusMetrics>) {
+            self.metrics = Some(metrics);
+        }
+    }
+
+    fn reads_total(metrics: &ModbusMetrics) -> String {
+        let mut writer = MetricsWriter::new();
+        metrics.write(&mut writer);
+        let output = writer.finish();
+        String::from(output.lines().find(|line| line.starts_with("sunsaver_modbus_reads_total ")).unwrap())
+    }
+
+    #[test]
+    fn busdeviceconnection_selects_slave() {
+        let coil_writes = Arc::new(Mutex::new(Vec::new()));
+        let bus: SharedConnection = Arc::new(Mutex::new(Box::new(TestBus {
+            slave_id: None,
+            coil_writes: coil_writes.clone(),
+            metrics: None,
+        })));
+        let mut first = BusDeviceConnection::new(bus.clone(), 1);
+        let mut second = BusDeviceConnection::new(bus.clone(), 2);
+        let first_metrics = Arc::new(ModbusMetrics::new());
+        let second_metrics = Arc::new(ModbusMetrics::new());
+        first.measure(first_metrics.clone());
+        second.measure(second_metrics.clone());
+
+        assert_eq!(first.read_raw_registers().unwrap()[0], 1);
+        assert_eq!(second.read_raw_registers().unwrap()[0], 2);
+        assert_eq!(first.read_raw_registers().unwrap()[0], 1);
+        // Reads are counted for the device that made them, not the bus
+        assert_eq!(reads_total(&first_metrics), "sunsaver_modbus_reads_total 2");
+        assert_eq!(reads_total(&second_metrics), "sunsaver_modbus_reads_total 1");
+        second.execute_command(Command::LoadDisconnect).unwrap();
+        assert_eq!(*coil_writes.lock().unwrap(), vec![(2, Coil::LoadDisconnect, true)]);
+        assert_eq!(first.read_raw_logged(), Err(SunSaverConnectionError::Unsupported("reading logged data")));
+        // Requests not supported by the bus are still refused
+        assert_eq!(first.read_raw_eeprom(), Err(SunSaverConnectionError::Unsupported("reading EEPROM")));
+
+        let devices = vec![
+            Device {
+                id: String::from("north"),
+                path: String::from("/dev/ttyUSB0"),
+                slave_id: 1,
+                connection: Arc::new(Mutex::new(Box::new(first))),
+                poller: Poller::new(Duration::from_secs(5)),
+                metrics: Arc::new(ModbusMetrics::new()),
+            },
+            Device {
+                id: String::from("south"),
+                path: String::from("/dev/ttyUSB0"),
+                slave_id: 2,
+                connection: Arc::new(Mutex::new(Box::new(second))),
+                poller: Poller::new(Duration::from_secs(5)),
+                metrics: Arc::new(ModbusMetrics::new()),
+            },
+        ];
+        assert_eq!(find(&devices, "south").map(|device| device.slave_id), Some(2));
+        assert!(find(&devices, "east").is_none());
+    }
+}
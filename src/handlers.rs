@@ -0,0 +1,706 @@
+use std::sync::Arc;
+
+use chrono::{DateTime, FixedOffset, Utc};
+
+use actix::{Actor, ActorContext, AsyncContext, StreamHandler};
+use actix_web;
+use actix_web::dev::{Handler, HttpResponseBuilder};
+use actix_web::http::ContentEncoding;
+use actix_web::ws;
+use actix_web::{FutureResponse, HttpMessage, HttpRequest, HttpResponse};
+
+use bytes::Bytes;
+
+use futures::future::{self, Future};
+use futures::sync::mpsc;
+use futures::Stream;
+
+use serde::Serialize;
+use serde_json;
+
+use crate::aggregate::{AggregateLogged, AggregateStatus};
+use crate::api::*;
+use crate::devices::{self, Device};
+use crate::history::{History, HistoryQuery};
+use crate::metrics::{self, MetricsWriter};
+use crate::poller::Poller;
+use crate::stream::{FieldFilter, StatusStream};
+use crate::sunsaver::Command;
+use crate::sunsaver_connection::{SunSaverConnection, SunSaverConnectionError};
+use crate::{lock_connection, SharedConnection};
+
+/// A JSON response builder allowing cross origin requests with `methods`, and with `headers` when given
+fn json_response_builder(methods: &str, headers: Option<&str>) -> HttpResponseBuilder {
+    let mut response_builder = HttpResponse::Ok();
+    response_builder.header(http::header::ACCESS_CONTROL_ALLOW_METHODS, methods);
+    if let Some(headers) = headers {
+        response_builder.header(http::header::ACCESS_CONTROL_ALLOW_HEADERS, headers);
+    }
+    response_builder.header(http::header::ACCESS_CONTROL_ALLOW_ORIGIN, "*");
+    response_builder.header(http::header::CONTENT_TYPE, "application/json");
+    response_builder
+}
+
+#[derive(Clone)]
+pub struct ApiHandler {
+    connection: SharedConnection,
+    poller: Poller,
+    utc_offset: FixedOffset,
+}
+
+impl ApiHandler {
+    pub fn new(connection: SharedConnection, poller: Poller, utc_offset: FixedOffset) -> ApiHandler {
+        ApiHandler {
+            connection,
+            poller,
+            utc_offset,
+        }
+    }
+
+    fn status_response(&self, response_builder: &mut HttpResponseBuilder) -> HttpResponse {
+        let last_error = self.poller.last_error();
+        let snapshot = match self.poller.latest() {
+            Some(snapshot) => snapshot,
+            None => {
+                return match last_error {
+                    Some(error) => json_response::<ApiStatusResponse>(response_builder, Err(error)),
+                    None => error_response(
+                        response_builder,
+                        http::StatusCode::SERVICE_UNAVAILABLE,
+                        &ApiErrorResponse::new("not_ready", String::from("The device has not been read yet")),
+                    ),
+                };
+            }
+        };
+        let now = Utc::now();
+        let stale = self.poller.is_stale(&snapshot, now);
+        response_builder.header(http::header::AGE, snapshot.age(now).num_seconds().max(0).to_string());
+        let response = ApiStatusResponse::from_snapshot(snapshot, now, stale, last_error.as_ref());
+        json_response(response_builder, Ok(response))
+    }
+}
+
+/// Serves `/api/v1/devices` and each device's status and logged days under `/api/v1/devices/{id}`
+#[derive(Clone)]
+pub struct DevicesHandler {
+    devices: Vec<Device>,
+    utc_offset: FixedOffset,
+}
+
+impl DevicesHandler {
+    pub fn new(devices: Vec<Device>, utc_offset: FixedOffset) -> DevicesHandler {
+        DevicesHandler { devices, utc_offset }
+    }
+}
+
+impl<S> Handler<S> for DevicesHandler {
+    type Result = HttpResponse;
+
+    fn handle(&self, req: &HttpRequest<S>) -> Self::Result {
+        debug!("DevicesHandler: {:?}", req.uri());
+
+        let mut response_builder = json_response_builder("GET", None);
+
+        let path = req.path().trim_start_matches("/api/v1/devices").trim_matches('/');
+        if path.is_empty() {
+            return json_response(&mut response_builder, Ok(ApiDevicesResponse::new(&self.devices, Utc::now())));
+        }
+        let mut parts = path.splitn(2, '/');
+        let id = parts.next().unwrap();
+        trace!("DevicesHandler: id={:?}", id);
+        match (devices::find(&self.devices, id), parts.next()) {
+            (Some(device), Some("status")) | (Some(device), Some("logged")) => {
+                ApiHandler::new(device.connection.clone(), device.poller.clone(), self.utc_offset).handle(req)
+            }
+            (Some(device), Some("metrics")) => MetricsHandler::new(vec![device.clone()]).handle(req),
+            (Some(_), _) => response_builder.status(http::StatusCode::NOT_FOUND).finish(),
+            (None, _) => error_response(
+                &mut response_builder,
+                http::StatusCode::NOT_FOUND,
+                &ApiErrorResponse::new("not_found", format!("Unknown device {:?}", id)),
+            ),
+        }
+    }
+}
+
+/// Serves the totals across every device under `/api/v1/aggregate`
+#[derive(Clone)]
+pub struct AggregateHandler {
+    devices: Vec<Device>,
+    utc_offset: FixedOffset,
+}
+
+impl AggregateHandler {
+    pub fn new(devices: Vec<Device>, utc_offset: FixedOffset) -> AggregateHandler {
+        AggregateHandler { devices, utc_offset }
+    }
+}
+
+impl<S> Handler<S> for AggregateHandler {
+    type Result = HttpResponse;
+
+    fn handle(&self, req: &HttpRequest<S>) -> Self::Result {
+        debug!("AggregateHandler: {:?}", req.uri());
+
+        let mut response_builder = json_response_builder("GET", None);
+
+        let path = req.path();
+        let last_path = path.rsplitn(2, '/').next().unwrap();
+        trace!("AggregateHandler: last_path={:?}", last_path);
+        match last_path {
+            "status" => match AggregateStatus::new(&self.devices, Utc::now()) {
+                Some(status) => json_response(&mut response_builder, Ok(status)),
+                None => error_response(
+                    &mut response_builder,
+                    http::StatusCode::SERVICE_UNAVAILABLE,
+                    &ApiErrorResponse::new("not_ready", String::from("No device has been read recently")),
+                ),
+            },
+            "logged" => {
+                let now = Utc::now().with_timezone(&self.utc_offset);
+                json_response(&mut response_builder, AggregateLogged::read(&self.devices, &now))
+            }
+            _ => response_builder.status(http::StatusCode::NOT_FOUND).finish(),
+        }
+    }
+}
+
+fn read_logged_with_dates(
+    connection: &mut dyn SunSaverConnection,
+    poller: &Poller,
+    now: &DateTime<FixedOffset>,
+) -> Result<ApiLoggedResponse, SunSaverConnectionError> {
+    // The current hourmeter anchors the logged hourmeters to the server clock
+    let current_hourmeter = poller.current_hourmeter(connection)?;
+    let logged = connection.read_logged()?;
+    Ok(ApiLoggedResponse::new(logged, current_hourmeter, now))
+}
+
+fn error_status_code(error: &SunSaverConnectionError) -> http::StatusCode {
+    match error {
+        SunSaverConnectionError::Timeout => http::StatusCode::GATEWAY_TIMEOUT,
+        SunSaverConnectionError::DeviceGone(_) => http::StatusCode::SERVICE_UNAVAILABLE,
+        SunSaverConnectionError::Unsupported(_) => http::StatusCode::NOT_IMPLEMENTED,
+        _ => http::StatusCode::BAD_GATEWAY,
+    }
+}
+
+fn json_response<T: Serialize>(response_builder: &mut HttpResponseBuilder, response: Result<T, SunSaverConnectionError>) -> HttpResponse {
+    match response {
+        Ok(response) => {
+            let body = serde_json::to_string_pretty(&response).unwrap();
+            response_builder.status(http::StatusCode::OK).body(body)
+        }
+        Err(error) => {
+            error!("Device request failed: {}", error);
+            let body = serde_json::to_string_pretty(&ApiErrorResponse::from(&error)).unwrap();
+            response_builder.status(error_status_code(&error)).body(body)
+        }
+    }
+}
+
+impl<S> Handler<S> for ApiHandler {
+    type Result = HttpResponse;
+
+    fn handle(&self, req: &HttpRequest<S>) -> Self::Result {
+        debug!("ApiHandler: {:?}", req.uri());
+
+        let mut response_builder = json_response_builder("GET", None);
+
+        let path = req.path();
+        let last_path = path.rsplitn(2, '/').next().unwrap();
+        trace!("ApiHandler: last_path={:?}", last_path);
+        match last_path {
+            "status" => self.status_response(&mut response_builder),
+            "logged" => {
+                let now = Utc::now().with_timezone(&self.utc_offset);
+                let response = read_logged_with_dates(lock_connection(&self.connection).as_mut(), &self.poller, &now);
+                json_response(&mut response_builder, response)
+            }
+            _ => response_builder.status(http::StatusCode::NOT_FOUND).finish(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct CommandHandler {
+    connection: SharedConnection,
+    api_token: Option<String>,
+}
+
+impl CommandHandler {
+    pub fn new(connection: SharedConnection, api_token: Option<String>) -> CommandHandler {
+        CommandHandler { connection, api_token }
+    }
+}
+
+fn check_authorization<S>(api_token: &Option<String>, req: &HttpRequest<S>) -> Result<(), (http::StatusCode, ApiErrorResponse)> {
+    let api_token = match api_token {
+        Some(api_token) => api_token,
+        None => {
+            return Err((
+                http::StatusCode::FORBIDDEN,
+                ApiErrorResponse::new("forbidden", String::from("Writes are disabled as no API token is configured")),
+            ))
+        }
+    };
+    let authorization = req
+        .headers()
+        .get(http::header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("");
+    if !is_bearer_token(authorization, api_token) {
+        return Err((
+            http::StatusCode::UNAUTHORIZED,
+            ApiErrorResponse::new("unauthorized", String::from("Missing or invalid bearer token")),
+        ));
+    }
+    Ok(())
+}
+
+fn error_response(response_builder: &mut HttpResponseBuilder, status: http::StatusCode, error: &ApiErrorResponse) -> HttpResponse {
+    let body = serde_json::to_string_pretty(error).unwrap();
+    response_builder.status(status).body(body)
+}
+
+fn unauthorized_response(response_builder: &mut HttpResponseBuilder, status: http::StatusCode, error: &ApiErrorResponse) -> HttpResponse {
+    response_builder.header(http::header::WWW_AUTHENTICATE, "Bearer");
+    error_response(response_builder, status, error)
+}
+
+fn is_bearer_token(authorization: &str, api_token: &str) -> bool {
+    let mut parts = authorization.splitn(2, ' ');
+    match (parts.next(), parts.next()) {
+        (Some(scheme), Some(token)) if scheme.eq_ignore_ascii_case("bearer") => constant_time_eq(token.trim().as_bytes(), api_token.as_bytes()),
+        _ => false,
+    }
+}
+
+// Compares every byte so the time taken does not reveal how much of the token matched
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b.iter()).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+impl<S> Handler<S> for CommandHandler {
+    type Result = HttpResponse;
+
+    fn handle(&self, req: &HttpRequest<S>) -> Self::Result {
+        debug!("CommandHandler: {:?}", req.uri());
+
+        let mut response_builder = json_response_builder("POST", Some("Authorization"));
+
+        if *req.method() == http::Method::OPTIONS {
+            return response_builder.status(http::StatusCode::OK).finish();
+        }
+        if *req.method() != http::Method::POST {
+            return response_builder.status(http::StatusCode::METHOD_NOT_ALLOWED).finish();
+        }
+        if let Err((status, error)) = check_authorization(&self.api_token, req) {
+            warn!("CommandHandler: rejected {:?}: {}", req.uri(), status);
+            return unauthorized_response(&mut response_builder, status, &error);
+        }
+
+        let path = req.path();
+        let last_path = path.rsplitn(2, '/').next().unwrap();
+        trace!("CommandHandler: last_path={:?}", last_path);
+        let command = match last_path.parse::<Command>() {
+            Ok(command) => command,
+            Err(_) => return response_builder.status(http::StatusCode::NOT_FOUND).finish(),
+        };
+        let response = lock_connection(&self.connection)
+            .execute_command(command)
+            .map(|_| ApiCommandResponse::from(command));
+        json_response(&mut response_builder, response)
+    }
+}
+
+#[derive(Clone)]
+pub struct SettingsHandler {
+    connection: SharedConnection,
+    api_token: Option<String>,
+}
+
+impl SettingsHandler {
+    pub fn new(connection: SharedConnection, api_token: Option<String>) -> SettingsHandler {
+        SettingsHandler { connection, api_token }
+    }
+}
+
+fn is_dry_run(dry_run: Option<&str>) -> bool {
+    match dry_run {
+        // A bare ?dry_run counts as asking for one
+        Some(value) => value.is_empty() || value == "true" || value == "1",
+        None => false,
+    }
+}
+
+fn update_settings(
+    response_builder: &mut HttpResponseBuilder,
+    connection: &SharedConnection,
+    request: &ApiSettingsUpdateRequest,
+    dry_run: bool,
+) -> HttpResponse {
+    let mut connection = lock_connection(connection);
+    let current = match connection.read_eeprom() {
+        Ok(current) => current,
+        Err(error) => return json_response::<ApiSettingsUpdateResponse>(response_builder, Err(error)),
+    };
+    let updated = match current.with_values(&request.values()) {
+        Ok(updated) => updated,
+        Err(errors) => {
+            warn!("SettingsHandler: rejected update: {:?}", errors);
+            return error_response(
+                response_builder,
+                http::StatusCode::UNPROCESSABLE_ENTITY,
+                &ApiErrorResponse::from(errors.as_slice()),
+            );
+        }
+    };
+    let changes = current.changes_to(&updated);
+    if dry_run || changes.is_empty() {
+        return json_response(response_builder, Ok(ApiSettingsUpdateResponse::new(dry_run, &changes)));
+    }
+    let response = connection
+        .write_eeprom(&changes)
+        .map(|_| ApiSettingsUpdateResponse::new(dry_run, &changes));
+    json_response(response_builder, response)
+}
+
+impl<S: 'static> Handler<S> for SettingsHandler {
+    type Result = FutureResponse<HttpResponse>;
+
+    fn handle(&self, req: &HttpRequest<S>) -> Self::Result {
+        debug!("SettingsHandler: {:?}", req.uri());
+
+        let mut response_builder = json_response_builder("GET, PUT", Some("Authorization, Content-Type"));
+        match *req.method() {
+            http::Method::OPTIONS => Box::new(future::ok(response_builder.status(http::StatusCode::OK).finish())),
+            http::Method::GET => {
+                let response = lock_connection(&self.connection).read_eeprom().map(ApiSettingsResponse::from);
+                Box::new(future::ok(json_response(&mut response_builder, response)))
+            }
+            http::Method::PUT => {
+                if let Err((status, error)) = check_authorization(&self.api_token, req) {
+                    warn!("SettingsHandler: rejected {:?}: {}", req.uri(), status);
+                    return Box::new(future::ok(unauthorized_response(&mut response_builder, status, &error)));
+                }
+                let dry_run = is_dry_run(req.query().get("dry_run").map(String::as_str));
+                let connection = self.connection.clone();
+                Box::new(req.json::<ApiSettingsUpdateRequest>().limit(4096).then(move |request| {
+                    let mut response_builder = json_response_builder("GET, PUT", Some("Authorization, Content-Type"));
+                    Ok(match request {
+                        Ok(request) => update_settings(&mut response_builder, &connection, &request, dry_run),
+                        Err(error) => error_response(
+                            &mut response_builder,
+                            http::StatusCode::BAD_REQUEST,
+                            &ApiErrorResponse::new("invalid_request", error.to_string()),
+                        ),
+                    })
+                }))
+            }
+            _ => Box::new(future::ok(response_builder.status(http::StatusCode::METHOD_NOT_ALLOWED).finish())),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct HistoryHandler {
+    history: Arc<History>,
+}
+
+impl HistoryHandler {
+    pub fn new(history: Arc<History>) -> HistoryHandler {
+        HistoryHandler { history }
+    }
+}
+
+impl<S> Handler<S> for HistoryHandler {
+    type Result = HttpResponse;
+
+    fn handle(&self, req: &HttpRequest<S>) -> Self::Result {
+        debug!("HistoryHandler: {:?}", req.uri());
+
+        let mut response_builder = json_response_builder("GET", None);
+
+        let params = req.query();
+        let query = HistoryQuery::parse(
+            params.get("from").map(String::as_str),
+            params.get("to").map(String::as_str),
+            params.get("fields").map(String::as_str),
+            params.get("resolution").map(String::as_str),
+            Utc::now(),
+        );
+        let query = match query {
+            Ok(query) => query,
+            Err(message) => {
+                return error_response(
+                    &mut response_builder,
+                    http::StatusCode::BAD_REQUEST,
+                    &ApiErrorResponse::new("invalid_request", message),
+                )
+            }
+        };
+        match self.history.query(&query) {
+            Ok(buckets) => {
+                let body = serde_json::to_string_pretty(&ApiHistoryResponse::new(&query, buckets)).unwrap();
+                response_builder.status(http::StatusCode::OK).body(body)
+            }
+            Err(error) => {
+                error!("History query failed: {}", error);
+                error_response(
+                    &mut response_builder,
+                    http::StatusCode::INTERNAL_SERVER_ERROR,
+                    &ApiErrorResponse::new("history", error.to_string()),
+                )
+            }
+        }
+    }
+}
+
+/// Serves each device's latest status and Modbus counters for Prometheus, labelled with its id
+#[derive(Clone)]
+pub struct MetricsHandler {
+    devices: Vec<Device>,
+}
+
+impl MetricsHandler {
+    pub fn new(devices: Vec<Device>) -> MetricsHandler {
+        MetricsHandler { devices }
+    }
+}
+
+impl<S> Handler<S> for MetricsHandler {
+    type Result = HttpResponse;
+
+    fn handle(&self, req: &HttpRequest<S>) -> Self::Result {
+        debug!("MetricsHandler: {:?}", req.uri());
+
+        let now = Utc::now();
+        let mut writer = MetricsWriter::new();
+        for device in &self.devices {
+            writer.set_label("device", &device.id);
+            let latest = device.poller.latest();
+            let up = latest.is_some() && device.poller.last_error().is_none();
+            writer.gauge("sunsaver_up", "1 if the last poll of the device succeeded", if up { 1.0 } else { 0.0 });
+            if let Some(snapshot) = latest {
+                metrics::write_snapshot(&mut writer, &snapshot, now);
+            }
+            device.metrics.write(&mut writer);
+        }
+
+        HttpResponse::Ok()
+            .header(http::header::CONTENT_TYPE, metrics::CONTENT_TYPE)
+            .body(writer.finish())
+    }
+}
+
+/// Relays a status stream subscription to a WebSocket client as text messages
+struct StatusSocket {
+    statuses: Option<mpsc::Receiver<String>>,
+}
+
+impl Actor for StatusSocket {
+    type Context = ws::WebsocketContext<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        if let Some(statuses) = self.statuses.take() {
+            ctx.add_stream(statuses);
+        }
+    }
+}
+
+impl StreamHandler<ws::Message, ws::ProtocolError> for StatusSocket {
+    fn handle(&mut self, message: ws::Message, ctx: &mut Self::Context) {
+        match message {
+            ws::Message::Ping(message) => ctx.pong(&message),
+            ws::Message::Close(_) => ctx.stop(),
+            _ => (),
+        }
+    }
+}
+
+impl StreamHandler<String, ()> for StatusSocket {
+    fn handle(&mut self, status: String, ctx: &mut Self::Context) {
+        ctx.text(status);
+    }
+}
+
+fn is_websocket_upgrade<S>(req: &HttpRequest<S>) -> bool {
+    req.headers()
+        .get(http::header::UPGRADE)
+        .and_then(|upgrade| upgrade.to_str().ok())
+        .map_or(false, |upgrade| upgrade.eq_ignore_ascii_case("websocket"))
+}
+
+#[derive(Clone)]
+pub struct StatusStreamHandler {
+    stream: StatusStream,
+    poller: Poller,
+}
+
+impl StatusStreamHandler {
+    pub fn new(stream: StatusStream, poller: Poller) -> StatusStreamHandler {
+        StatusStreamHandler { stream, poller }
+    }
+}
+
+impl Handler<()> for StatusStreamHandler {
+    type Result = actix_web::Result<HttpResponse>;
+
+    fn handle(&self, req: &HttpRequest<()>) -> Self::Result {
+        debug!("StatusStreamHandler: {:?}", req.uri());
+
+        let filter = match FieldFilter::parse(req.query().get("fields").map(String::as_str)) {
+            Ok(filter) => filter,
+            Err(message) => {
+                let mut response_builder = json_response_builder("GET", None);
+                return Ok(error_response(
+                    &mut response_builder,
+                    http::StatusCode::BAD_REQUEST,
+                    &ApiErrorResponse::new("invalid_request", message),
+                ));
+            }
+        };
+        // New subscribers get the cached status straight away rather than waiting for the next poll
+        let current = self.poller.latest().map(|snapshot| {
+            let now = Utc::now();
+            let stale = self.poller.is_stale(&snapshot, now);
+            ApiStatusResponse::from_snapshot(snapshot, now, stale, self.poller.last_error().as_ref())
+        });
+        let statuses = self.stream.subscribe(filter, current.as_ref());
+        debug!("StatusStreamHandler: {} subscribers", self.stream.subscriber_count());
+
+        if is_websocket_upgrade(req) {
+            return ws::start(req, StatusSocket { statuses: Some(statuses) });
+        }
+        let events = statuses
+            .map(|status| Bytes::from(format!("data: {}\n\n", status)))
+            .map_err(|()| actix_web::error::ErrorInternalServerError("Status stream ended"));
+        Ok(HttpResponse::Ok()
+            .header(http::header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+            .header(http::header::CACHE_CONTROL, "no-cache")
+            .content_type("text/event-stream")
+            // Compression would hold events back until enough had been written
+            .content_encoding(ContentEncoding::Identity)
+            .streaming(events))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::TcpListener;
+    use std::sync::Mutex;
+    use std::thread;
+    use std::time::Duration;
+
+    use actix_web::test::TestServer;
+
+    use super::*;
+
+    use crate::recording::{Fixture, ReplayOptions, ReplaySunSaverConnection};
+    use crate::simulator::{Profile, SimulatedController, SimulatorServer};
+    use crate::sunsaver_connection::{ModbusOptions, ModbusSunSaverConnection};
+
+    #[test]
+    fn is_bearer_token_test() {
+        assert_eq!(is_bearer_token("Bearer s3cret", "s3cret"), true);
+        assert_eq!(is_bearer_token("bearer s3cret", "s3cret"), true);
+        assert_eq!(is_bearer_token("Bearer s3cre", "s3cret"), false);
+        assert_eq!(is_bearer_token("Bearer s3cret!", "s3cret"), false);
+        assert_eq!(is_bearer_token("Basic s3cret", "s3cret"), false);
+        assert_eq!(is_bearer_token("s3cret", "s3cret"), false);
+        assert_eq!(is_bearer_token("", "s3cret"), false);
+    }
+
+    #[test]
+    fn is_dry_run_test() {
+        assert_eq!(is_dry_run(None), false);
+        assert_eq!(is_dry_run(Some("")), true);
+        assert_eq!(is_dry_run(Some("true")), true);
+        assert_eq!(is_dry_run(Some("1")), true);
+        assert_eq!(is_dry_run(Some("false")), false);
+        assert_eq!(is_dry_run(Some("0")), false);
+    }
+
+    #[test]
+    fn error_status_code_test() {
+        assert_eq!(error_status_code(&SunSaverConnectionError::Timeout), http::StatusCode::GATEWAY_TIMEOUT);
+        assert_eq!(
+            error_status_code(&SunSaverConnectionError::DeviceGone(String::from("gone"))),
+            http::StatusCode::SERVICE_UNAVAILABLE
+        );
+        assert_eq!(error_status_code(&SunSaverConnectionError::Crc), http::StatusCode::BAD_GATEWAY);
+        assert_eq!(
+            error_status_code(&SunSaverConnectionError::Unsupported("writing coils")),
+            http::StatusCode::NOT_IMPLEMENTED
+        );
+        assert_eq!(
+            error_status_code(&SunSaverConnectionError::ShortRead { expected: 44, actual: 22 }),
+            http::StatusCode::BAD_GATEWAY
+        );
+        assert_eq!(
+            error_status_code(&SunSaverConnectionError::Protocol(String::from("Modbus error 112345680"))),
+            http::StatusCode::BAD_GATEWAY
+        );
+    }
+
+    fn get_json(server: &mut TestServer, path: &str) -> (http::StatusCode, serde_json::Value) {
+        let request = server.client(http::Method::GET, path).finish().unwrap();
+        let response = server.execute(request.send()).unwrap();
+        let body = server.execute(response.body()).unwrap();
+        (response.status(), serde_json::from_slice(&body).unwrap())
+    }
+
+    #[test]
+    fn serve_simulator_test() {
+        let mut controller = SimulatedController::new(Profile::named("day").unwrap());
+        controller.warm_up(2);
+        let simulator = SimulatorServer::new(controller, 0x01, 0);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || simulator.serve_tcp(listener));
+
+        let connection = ModbusSunSaverConnection::open_tcp("127.0.0.1", port, &ModbusOptions::default()).unwrap();
+        let connection: SharedConnection = Arc::new(Mutex::new(Box::new(connection)));
+        let poller = Poller::new(Duration::from_secs(5));
+        poller.read(lock_connection(&connection).as_mut()).unwrap();
+        let api_handler = ApiHandler::new(connection, poller, FixedOffset::east(0));
+        let mut server = TestServer::with_factory(move || {
+            actix_web::App::new()
+                .handler("/api/v1/status", api_handler.clone())
+                .handler("/api/v1/logged", api_handler.clone())
+        });
+
+        let (status_code, status) = get_json(&mut server, "/api/v1/status");
+        assert_eq!(status_code, http::StatusCode::OK);
+        assert_eq!(status["storage"]["charge_state"], "Float");
+        assert_eq!(status["load"]["state"], "Normal");
+
+        let (status_code, logged) = get_json(&mut server, "/api/v1/logged");
+        assert_eq!(status_code, http::StatusCode::OK);
+        assert_eq!(logged["days"].as_array().map(Vec::len), Some(2));
+    }
+
+    #[test]
+    fn read_logged_with_dates_test() {
+        let content = include_str!("../fixtures/day.json");
+        let fixture: Fixture = serde_json::from_str(content).unwrap();
+        let mut connection = ReplaySunSaverConnection::parse(content, ReplayOptions::default()).unwrap();
+        let now = Utc::now().with_timezone(&FixedOffset::east(0));
+
+        let poller = Poller::new(Duration::from_secs(5));
+
+        // Without a snapshot yet the status is read to date the logged days, playing the first snapshot
+        let logged = serde_json::to_value(read_logged_with_dates(&mut connection, &poller, &now).unwrap()).unwrap();
+        assert_eq!(logged["days"].as_array().map(Vec::len), Some(3));
+
+        // Once polled the snapshot's hourmeter is used, so the status is not read again
+        poller.read(&mut connection).unwrap();
+        let logged = serde_json::to_value(read_logged_with_dates(&mut connection, &poller, &now).unwrap()).unwrap();
+        assert_eq!(logged["days"].as_array().map(Vec::len), Some(3));
+        assert_eq!(
+            connection.read_raw_registers().unwrap().to_vec(),
+            fixture.snapshots[2].ram.clone().unwrap()
+        );
+    }
+}
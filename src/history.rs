@@ -0,0 +1,592 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use rusqlite::types::ToSql;
+use rusqlite::{params, Connection};
+
+use crate::poller::{PollListener, Snapshot};
+use crate::sunsaver::{LoggedResponseDay, SunSaverResponse};
+use crate::{lock_connection, SharedConnection};
+
+// Without a resolution a query is split into this many buckets
+const DEFAULT_BUCKETS: i64 = 288;
+// Queries that would return more buckets than this are rejected
+const MAX_BUCKETS: i64 = 10_000;
+
+/// Values of each polled status kept in the history, named as in `/api/v1/status`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HistoryField {
+    BatteryVoltage,
+    BatteryChargeCurrent,
+    BatteryChargePower,
+    SolarInputVoltage,
+    LoadVoltage,
+    LoadCurrent,
+    LoadPower,
+    OutputPower,
+    HeatsinkTemperature,
+    BatteryTemperature,
+    AmbientTemperature,
+}
+
+impl HistoryField {
+    pub const ALL: [HistoryField; 11] = [
+        HistoryField::BatteryVoltage,
+        HistoryField::BatteryChargeCurrent,
+        HistoryField::BatteryChargePower,
+        HistoryField::SolarInputVoltage,
+        HistoryField::LoadVoltage,
+        HistoryField::LoadCurrent,
+        HistoryField::LoadPower,
+        HistoryField::OutputPower,
+        HistoryField::HeatsinkTemperature,
+        HistoryField::BatteryTemperature,
+        HistoryField::AmbientTemperature,
+    ];
+
+    /// Name of the field, also used as its column in the samples table
+    pub fn name(self) -> &'static str {
+        match self {
+            HistoryField::BatteryVoltage => "battery_voltage_filtered",
+            HistoryField::BatteryChargeCurrent => "battery_charge_current_filtered",
+            HistoryField::BatteryChargePower => "battery_charge_power_calculated",
+            HistoryField::SolarInputVoltage => "solar_input_voltage_filtered",
+            HistoryField::LoadVoltage => "load_voltage_filtered",
+            HistoryField::LoadCurrent => "load_current_filtered",
+            HistoryField::LoadPower => "load_power_calculated",
+            HistoryField::OutputPower => "output_power",
+            HistoryField::HeatsinkTemperature => "heatsink_temperature",
+            HistoryField::BatteryTemperature => "battery_temperature",
+            HistoryField::AmbientTemperature => "ambient_temperature",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<HistoryField> {
+        HistoryField::ALL.iter().cloned().find(|field| field.name() == name)
+    }
+
+    fn value(self, status: &SunSaverResponse) -> f64 {
+        let value = match self {
+            HistoryField::BatteryVoltage => status.battery_voltage_filtered(),
+            HistoryField::BatteryChargeCurrent => status.battery_charge_current_filtered(),
+            HistoryField::BatteryChargePower => status.battery_voltage_filtered() * status.battery_charge_current_filtered(),
+            HistoryField::SolarInputVoltage => status.solar_input_voltage_filtered(),
+            HistoryField::LoadVoltage => status.load_voltage_filtered(),
+            HistoryField::LoadCurrent => status.load_current_filtered(),
+            HistoryField::LoadPower => status.load_voltage_filtered() * status.load_current_filtered(),
+            HistoryField::OutputPower => status.output_power(),
+            HistoryField::HeatsinkTemperature => f32::from(status.heatsink_temperature()),
+            HistoryField::BatteryTemperature => f32::from(status.battery_temperature()),
+            HistoryField::AmbientTemperature => f32::from(status.ambient_temperature()),
+        };
+        f64::from(value)
+    }
+}
+
+#[derive(Debug)]
+pub struct HistoryError(rusqlite::Error);
+
+impl fmt::Display for HistoryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "History database error: {}", self.0)
+    }
+}
+
+impl Error for HistoryError {}
+
+impl From<rusqlite::Error> for HistoryError {
+    fn from(error: rusqlite::Error) -> Self {
+        HistoryError(error)
+    }
+}
+
+/// How long to keep history for, `None` keeps it forever.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Retention {
+    pub samples: Option<Duration>,
+    pub logged_days: Option<Duration>,
+}
+
+/// A time range of the history split into fixed size buckets.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistoryQuery {
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    pub fields: Vec<HistoryField>,
+    pub resolution: Duration,
+}
+
+impl HistoryQuery {
+    /// Parses the query string values of `/api/v1/history`, defaulting to the last day in every field
+    pub fn parse(
+        from: Option<&str>,
+        to: Option<&str>,
+        fields: Option<&str>,
+        resolution: Option<&str>,
+        now: DateTime<Utc>,
+    ) -> Result<HistoryQuery, String> {
+        let to = match to {
+            Some(to) => parse_timestamp(to).ok_or_else(|| format!("Invalid to {:?}", to))?,
+            None => now,
+        };
+        let from = match from {
+            Some(from) => parse_timestamp(from).ok_or_else(|| format!("Invalid from {:?}", from))?,
+            None => to - Duration::days(1),
+        };
+        if from >= to {
+            return Err(String::from("from must be before to"));
+        }
+
+        let fields = match fields {
+            Some(fields) => fields
+                .split(',')
+                .map(|name| HistoryField::from_name(name.trim()).ok_or_else(|| format!("Unknown field {:?}", name)))
+                .collect::<Result<Vec<HistoryField>, String>>()?,
+            None => HistoryField::ALL.to_vec(),
+        };
+
+        let range = to - from;
+        let resolution = match resolution {
+            Some(resolution) => parse_resolution(resolution).ok_or_else(|| format!("Invalid resolution {:?}", resolution))?,
+            None => Duration::seconds((range.num_seconds() + DEFAULT_BUCKETS - 1) / DEFAULT_BUCKETS).max(Duration::seconds(1)),
+        };
+        if range.num_milliseconds() / resolution.num_milliseconds() > MAX_BUCKETS {
+            return Err(format!("Resolution is too fine, at most {} buckets may be returned", MAX_BUCKETS));
+        }
+
+        Ok(HistoryQuery {
+            from,
+            to,
+            fields,
+            resolution,
+        })
+    }
+}
+
+fn parse_timestamp(timestamp: &str) -> Option<DateTime<Utc>> {
+    if let Ok(seconds) = timestamp.parse::<i64>() {
+        return Utc.timestamp_opt(seconds, 0).single();
+    }
+    DateTime::parse_from_rfc3339(timestamp)
+        .ok()
+        .map(|timestamp| timestamp.with_timezone(&Utc))
+}
+
+fn parse_resolution(resolution: &str) -> Option<Duration> {
+    let (number, unit) = match resolution.find(|c: char| !c.is_ascii_digit()) {
+        Some(index) => resolution.split_at(index),
+        None => (resolution, "s"),
+    };
+    let number = number.parse::<i64>().ok().filter(|number| *number > 0)?;
+    match unit {
+        "s" => Some(Duration::seconds(number)),
+        "m" => Some(Duration::minutes(number)),
+        "h" => Some(Duration::hours(number)),
+        "d" => Some(Duration::days(number)),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct HistoryStats {
+    pub min: f64,
+    pub max: f64,
+    pub avg: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct HistoryBucket {
+    pub start: DateTime<Utc>,
+    pub count: u32,
+    pub values: BTreeMap<&'static str, HistoryStats>,
+}
+
+/// Samples and logged days stored in a local SQLite database.
+pub struct History {
+    connection: Mutex<Connection>,
+    retention: Retention,
+}
+
+impl History {
+    pub fn open(path: &Path, retention: Retention) -> Result<History, HistoryError> {
+        History::init(Connection::open(path)?, retention)
+    }
+
+    #[cfg(test)]
+    fn open_in_memory(retention: Retention) -> Result<History, HistoryError> {
+        History::init(Connection::open_in_memory()?, retention)
+    }
+
+    fn init(connection: Connection, retention: Retention) -> Result<History, HistoryError> {
+        let columns: Vec<String> = HistoryField::ALL.iter().map(|field| format!("{} REAL NOT NULL", field.name())).collect();
+        connection.execute_batch(&format!(
+            "CREATE TABLE IF NOT EXISTS samples (read_at INTEGER NOT NULL, {});
+             CREATE INDEX IF NOT EXISTS samples_read_at ON samples (read_at);
+             CREATE TABLE IF NOT EXISTS logged_days (
+                 hourmeter INTEGER PRIMARY KEY,
+                 logged_at INTEGER NOT NULL,
+                 battery_voltage_min REAL NOT NULL,
+                 battery_voltage_max REAL NOT NULL,
+                 battery_charge_daily REAL NOT NULL,
+                 load_charge_daily REAL NOT NULL,
+                 array_voltage_max REAL NOT NULL,
+                 time_in_absorption INTEGER NOT NULL,
+                 time_in_equalize INTEGER NOT NULL,
+                 time_in_float INTEGER NOT NULL,
+                 array_faults INTEGER NOT NULL,
+                 load_faults INTEGER NOT NULL,
+                 alarms INTEGER NOT NULL
+             );",
+            columns.join(", ")
+        ))?;
+        Ok(History {
+            connection: Mutex::new(connection),
+            retention,
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<Connection> {
+        self.connection.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn record_sample(&self, snapshot: &Snapshot) -> Result<(), HistoryError> {
+        let names: Vec<&str> = HistoryField::ALL.iter().map(|field| field.name()).collect();
+        let placeholders: Vec<String> = (0..=HistoryField::ALL.len()).map(|i| format!("?{}", i + 1)).collect();
+        let read_at = snapshot.read_at.timestamp_millis();
+        let values: Vec<f64> = HistoryField::ALL.iter().map(|field| field.value(&snapshot.status)).collect();
+        let mut parameters: Vec<&dyn ToSql> = vec![&read_at];
+        parameters.extend(values.iter().map(|value| value as &dyn ToSql));
+        self.lock().execute(
+            &format!("INSERT INTO samples (read_at, {}) VALUES ({})", names.join(", "), placeholders.join(", ")),
+            &parameters,
+        )?;
+        Ok(())
+    }
+
+    /// Stores logged days not seen before, returning how many were new
+    pub fn record_logged_days(&self, days: &[LoggedResponseDay], current_hourmeter: u32, now: DateTime<Utc>) -> Result<usize, HistoryError> {
+        let mut connection = self.lock();
+        let transaction = connection.transaction()?;
+        let mut inserted = 0;
+        for day in days {
+            // Days from before a controller reset cannot be placed in time
+            let hours_ago = match current_hourmeter.checked_sub(day.hourmeter) {
+                Some(hours_ago) => hours_ago,
+                None => continue,
+            };
+            let logged_at = now - Duration::hours(i64::from(hours_ago));
+            inserted += transaction.execute(
+                "INSERT OR IGNORE INTO logged_days VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+                params![
+                    day.hourmeter,
+                    logged_at.timestamp_millis(),
+                    f64::from(day.battery_voltage_min()),
+                    f64::from(day.battery_voltage_max()),
+                    f64::from(day.battery_charge_daily()),
+                    f64::from(day.load_charge_daily()),
+                    f64::from(day.array_voltage_max()),
+                    day.time_in_absorption(),
+                    day.time_in_equalize(),
+                    day.time_in_float(),
+                    day.array_fault().bits(),
+                    day.load_fault().bits(),
+                    day.alarm().bits(),
+                ],
+            )?;
+        }
+        transaction.commit()?;
+        Ok(inserted)
+    }
+
+    /// Deletes everything older than the retention policy allows
+    pub fn prune(&self, now: DateTime<Utc>) -> Result<(), HistoryError> {
+        let connection = self.lock();
+        if let Some(samples) = self.retention.samples {
+            let deleted = connection.execute("DELETE FROM samples WHERE read_at < ?1", params![(now - samples).timestamp_millis()])?;
+            debug!("History: pruned {} samples", deleted);
+        }
+        if let Some(logged_days) = self.retention.logged_days {
+            let deleted = connection.execute(
+                "DELETE FROM logged_days WHERE logged_at < ?1",
+                params![(now - logged_days).timestamp_millis()],
+            )?;
+            debug!("History: pruned {} logged days", deleted);
+        }
+        Ok(())
+    }
+
+    /// Minimum, maximum and average of each field per bucket, buckets without samples are left out
+    pub fn query(&self, query: &HistoryQuery) -> Result<Vec<HistoryBucket>, HistoryError> {
+        let aggregates: Vec<String> = query
+            .fields
+            .iter()
+            .map(|field| format!("MIN({0}), MAX({0}), AVG({0})", field.name()))
+            .collect();
+        let sql = format!(
+            "SELECT (read_at - ?1) / ?3 AS bucket, COUNT(*), {} FROM samples WHERE read_at >= ?1 AND read_at < ?2 GROUP BY bucket ORDER BY bucket",
+            aggregates.join(", ")
+        );
+        let from = query.from.timestamp_millis();
+        let resolution = query.resolution.num_milliseconds();
+
+        let connection = self.lock();
+        let mut statement = connection.prepare(&sql)?;
+        let rows = statement.query_map(params![from, query.to.timestamp_millis(), resolution], |row| {
+            let bucket: i64 = row.get(0)?;
+            let mut values = BTreeMap::new();
+            for (i, field) in query.fields.iter().enumerate() {
+                let stats = HistoryStats {
+                    min: row.get(2 + i * 3)?,
+                    max: row.get(3 + i * 3)?,
+                    avg: row.get(4 + i * 3)?,
+                };
+                values.insert(field.name(), stats);
+            }
+            Ok(HistoryBucket {
+                start: Utc.timestamp_millis(from + bucket * resolution),
+                count: row.get(1)?,
+                values,
+            })
+        })?;
+        let buckets = rows.collect::<Result<Vec<HistoryBucket>, rusqlite::Error>>()?;
+        Ok(buckets)
+    }
+
+    #[cfg(test)]
+    fn logged_day_count(&self) -> Result<u32, HistoryError> {
+        Ok(self
+            .lock()
+            .query_row("SELECT COUNT(*) FROM logged_days", rusqlite::NO_PARAMS, |row| row.get(0))?)
+    }
+}
+
+/// Stores every polled sample and, every `logged_interval`, any new logged days read through `connection`.
+pub struct HistoryRecorder {
+    history: Arc<History>,
+    connection: SharedConnection,
+    logged_interval: Duration,
+    last_logged: Option<DateTime<Utc>>,
+}
+
+impl HistoryRecorder {
+    pub fn new(history: Arc<History>, connection: SharedConnection, logged_interval: Duration) -> HistoryRecorder {
+        HistoryRecorder {
+            history,
+            connection,
+            logged_interval,
+            last_logged: None,
+        }
+    }
+
+    fn record_logged_days(&self, snapshot: &Snapshot) {
+        // The connection is only held for the read, not while the days are stored
+        let logged = lock_connection(&self.connection).read_logged();
+        let logged = match logged {
+            Ok(logged) => logged,
+            Err(error) => {
+                warn!("History: failed to read logged days: {}", error);
+                return;
+            }
+        };
+        match self
+            .history
+            .record_logged_days(&logged.days, snapshot.status.hourmeter(), snapshot.read_at)
+        {
+            Ok(inserted) => debug!("History: recorded {} new logged days", inserted),
+            Err(error) => error!("History: failed to record logged days: {}", error),
+        }
+        if let Err(error) = self.history.prune(snapshot.read_at) {
+            error!("History: failed to prune: {}", error);
+        }
+    }
+}
+
+impl PollListener for HistoryRecorder {
+    fn on_snapshot(&mut self, snapshot: &Snapshot) {
+        if let Err(error) = self.history.record_sample(snapshot) {
+            error!("History: failed to record sample: {}", error);
+        }
+        let logged_due = match self.last_logged {
+            Some(last_logged) => snapshot.read_at - last_logged >= self.logged_interval,
+            None => true,
+        };
+        if logged_due {
+            self.last_logged = Some(snapshot.read_at);
+            self.record_logged_days(snapshot);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::sunsaver_connection::{SunSaverConnection, SunSaverConnectionError};
+
+    const RETENTION: Retention = Retention {
+        samples: None,
+        logged_days: None,
+    };
+
+    fn timestamp(timestamp: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(timestamp).unwrap().with_timezone(&Utc)
+    }
+
+    fn snapshot(read_at: &str, battery_voltage: u16) -> Snapshot {
+        let mut registers = [0u16; 44];
+        registers[0] = battery_voltage;
+        Snapshot {
+            status: SunSaverResponse::from_raw_bits(registers),
+            read_at: timestamp(read_at),
+        }
+    }
+
+    #[test]
+    fn historyquery_parse_defaults() {
+        let now = timestamp("2019-06-30T12:00:00Z");
+        let query = HistoryQuery::parse(None, None, None, None, now).unwrap();
+
+        assert_eq!(query.from, timestamp("2019-06-29T12:00:00Z"));
+        assert_eq!(query.to, now);
+        assert_eq!(query.fields, HistoryField::ALL.to_vec());
+        assert_eq!(query.resolution, Duration::minutes(5));
+    }
+
+    #[test]
+    fn historyquery_parse() {
+        let now = timestamp("2019-06-30T12:00:00Z");
+        let query = HistoryQuery::parse(
+            Some("2019-06-30T10:00:00+01:00"),
+            Some("1561892400"),
+            Some("battery_voltage_filtered, output_power"),
+            Some("15m"),
+            now,
+        )
+        .unwrap();
+
+        assert_eq!(query.from, timestamp("2019-06-30T09:00:00Z"));
+        assert_eq!(query.to, timestamp("2019-06-30T11:00:00Z"));
+        assert_eq!(query.fields, vec![HistoryField::BatteryVoltage, HistoryField::OutputPower]);
+        assert_eq!(query.resolution, Duration::minutes(15));
+    }
+
+    #[test]
+    fn historyquery_parse_invalid() {
+        let now = timestamp("2019-06-30T12:00:00Z");
+        assert!(HistoryQuery::parse(Some("yesterday"), None, None, None, now).is_err());
+        assert!(HistoryQuery::parse(Some("2019-07-01T00:00:00Z"), None, None, None, now).is_err());
+        assert!(HistoryQuery::parse(None, None, Some("battery_voltage_filtered,password"), None, now).is_err());
+        assert!(HistoryQuery::parse(None, None, None, Some("0"), now).is_err());
+        assert!(HistoryQuery::parse(None, None, None, Some("5w"), now).is_err());
+        assert!(HistoryQuery::parse(None, None, None, Some("1s"), now).is_err());
+    }
+
+    #[test]
+    fn history_query_buckets() {
+        let history = History::open_in_memory(RETENTION).unwrap();
+        history.record_sample(&snapshot("2019-06-30T12:00:00Z", 0x1000)).unwrap();
+        history.record_sample(&snapshot("2019-06-30T12:04:59Z", 0x1200)).unwrap();
+        history.record_sample(&snapshot("2019-06-30T12:05:00Z", 0x1400)).unwrap();
+        history.record_sample(&snapshot("2019-06-30T12:20:00Z", 0x1800)).unwrap();
+
+        let query = HistoryQuery::parse(
+            Some("2019-06-30T12:00:00Z"),
+            Some("2019-06-30T12:20:00Z"),
+            Some("battery_voltage_filtered"),
+            Some("5m"),
+            Utc::now(),
+        )
+        .unwrap();
+        let buckets = history.query(&query).unwrap();
+
+        // The sample at the end of the range is excluded and empty buckets are left out
+        assert_eq!(buckets.len(), 2);
+        assert_eq!(buckets[0].start, timestamp("2019-06-30T12:00:00Z"));
+        assert_eq!(buckets[0].count, 2);
+        assert_eq!(
+            buckets[0].values["battery_voltage_filtered"],
+            HistoryStats {
+                min: 12.5,
+                max: 14.0625,
+                avg: 13.281_25
+            }
+        );
+        assert_eq!(buckets[1].start, timestamp("2019-06-30T12:05:00Z"));
+        assert_eq!(buckets[1].count, 1);
+    }
+
+    #[test]
+    fn history_logged_days() {
+        let history = History::open_in_memory(RETENTION).unwrap();
+        let day = LoggedResponseDay::from_raw_bits([
+            0x2402, 0x0100, 0x0000, 0x1011, 0x11fb, 0x0047, 0x001b, 0x0000, 0x0000, 0x1a84, 0x00b4, 0x0000, 0x010f, 0xffff, 0xffff, 0xffff,
+        ]);
+        let now = timestamp("2019-06-30T12:00:00Z");
+
+        assert_eq!(history.record_logged_days(&[day.clone()], 0x2402 + 24, now).unwrap(), 1);
+        // Already stored days are ignored
+        assert_eq!(history.record_logged_days(&[day.clone()], 0x2402 + 48, now).unwrap(), 0);
+        // As are days from before a controller reset
+        assert_eq!(history.record_logged_days(&[day], 0x0010, now).unwrap(), 0);
+        assert_eq!(history.logged_day_count().unwrap(), 1);
+    }
+
+    /// Holds the logged day below in its first record, the rest read back as erased EEPROM
+    struct LoggedConnection;
+
+    impl SunSaverConnection for LoggedConnection {
+        fn read_raw_registers(&mut self) -> Result<[u16; 44], SunSaverConnectionError> {
+            Err(SunSaverConnectionError::Unsupported("reading registers"))
+        }
+
+        fn read_raw_logged(&mut self) -> Result<[u16; 32 * 16], SunSaverConnectionError> {
+            let mut logged = [0xffff; 32 * 16];
+            logged[..13].copy_from_slice(&[
+                0x2402, 0x0100, 0x0000, 0x1011, 0x11fb, 0x0047, 0x001b, 0x0000, 0x0000, 0x1a84, 0x00b4, 0x0000, 0x010f,
+            ]);
+            Ok(logged)
+        }
+    }
+
+    #[test]
+    fn historyrecorder_on_snapshot() {
+        let history = Arc::new(History::open_in_memory(RETENTION).unwrap());
+        let connection: SharedConnection = Arc::new(Mutex::new(Box::new(LoggedConnection)));
+        let mut recorder = HistoryRecorder::new(history.clone(), connection, Duration::hours(1));
+        let mut registers = [0u16; 44];
+        // hourmeter a day after the logged day
+        registers[26] = 0x2402 + 24;
+        let mut sample = Snapshot {
+            status: SunSaverResponse::from_raw_bits(registers),
+            read_at: timestamp("2019-06-30T12:00:00Z"),
+        };
+
+        recorder.on_snapshot(&sample);
+        assert_eq!(history.logged_day_count().unwrap(), 1);
+        // Logged days are read again only once the interval has passed
+        sample.read_at = timestamp("2019-06-30T12:30:00Z");
+        recorder.on_snapshot(&sample);
+        assert_eq!(recorder.last_logged, Some(timestamp("2019-06-30T12:00:00Z")));
+    }
+
+    #[test]
+    fn history_prune() {
+        let history = History::open_in_memory(Retention {
+            samples: Some(Duration::days(7)),
+            logged_days: None,
+        })
+        .unwrap();
+        history.record_sample(&snapshot("2019-06-01T12:00:00Z", 0x1000)).unwrap();
+        history.record_sample(&snapshot("2019-06-29T12:00:00Z", 0x1200)).unwrap();
+        history.prune(timestamp("2019-06-30T12:00:00Z")).unwrap();
+
+        let query = HistoryQuery::parse(Some("2019-06-01T00:00:00Z"), Some("2019-07-01T00:00:00Z"), None, Some("1d"), Utc::now()).unwrap();
+        let buckets = history.query(&query).unwrap();
+        assert_eq!(buckets.len(), 1);
+        assert_eq!(buckets[0].start, timestamp("2019-06-29T00:00:00Z"));
+    }
+}
@@ -0,0 +1,497 @@
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use serde_json::{self, Value};
+use ureq;
+
+use crate::api::ApiStatusResponse;
+use crate::mqtt_publisher::sensors;
+use crate::poller::{PollListener, Snapshot};
+
+// Limits how long a request can take when the endpoint stops answering
+const IO_TIMEOUT: Duration = Duration::from_secs(5);
+// Wait after a failed write before trying again, doubled after each failure up to the maximum
+const MIN_RETRY_DELAY: Duration = Duration::from_secs(5);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(300);
+
+const HTTP_SCHEME: &str = "http://";
+const HTTP_DEFAULT_PORT: u16 = 80;
+const HTTPS_SCHEME: &str = "https://";
+const HTTPS_DEFAULT_PORT: u16 = 443;
+
+const MEASUREMENT: &str = "sunsaver";
+// Keeps each request a reasonable size when a long outage's worth of lines is written
+const MAX_LINES_PER_WRITE: usize = 5000;
+// Lines kept in memory while the endpoint is down, when there is no on-disk buffer
+const MAX_PENDING_LINES: usize = 10_000;
+
+const KEY_SPECIAL: &[char] = &[',', '=', ' '];
+const STRING_SPECIAL: &[char] = &['"', '\\'];
+
+#[derive(Debug)]
+pub enum InfluxError {
+    /// Reading or removing the buffer file failed
+    Io(io::Error),
+    /// The endpoint answered with this HTTP status and body
+    Status(u16, String),
+    /// The endpoint could not be reached or did not answer with an HTTP response
+    Request(String),
+}
+
+impl InfluxError {
+    /// Whether writing the same lines again later could succeed
+    fn is_retryable(&self) -> bool {
+        match self {
+            // Malformed, too large or outside the retention policy, resending the same lines would be rejected again
+            InfluxError::Status(400, _) | InfluxError::Status(413, _) | InfluxError::Status(422, _) => false,
+            _ => true,
+        }
+    }
+}
+
+impl fmt::Display for InfluxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InfluxError::Io(error) => write!(f, "InfluxDB buffer failed: {}", error),
+            InfluxError::Status(status, body) => write!(f, "InfluxDB responded with {}: {}", status, body),
+            InfluxError::Request(reason) => write!(f, "InfluxDB request failed: {}", reason),
+        }
+    }
+}
+
+impl Error for InfluxError {}
+
+impl From<io::Error> for InfluxError {
+    fn from(error: io::Error) -> Self {
+        InfluxError::Io(error)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum InfluxAuth {
+    None,
+    /// InfluxDB 1.x username and password, sent as basic auth
+    Basic {
+        username: String,
+        password: String,
+    },
+    /// InfluxDB 2.x API token
+    Token(String),
+}
+
+/// An InfluxDB write endpoint, `/write?db=<database>` for 1.x or `/api/v2/write?org=<org>&bucket=<bucket>` for 2.x.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InfluxEndpoint {
+    pub url: String,
+    pub auth: InfluxAuth,
+}
+
+impl InfluxEndpoint {
+    /// Parses an `http[s]://host[:port]/path?query` write URL
+    pub fn parse(url: &str, auth: InfluxAuth) -> Option<InfluxEndpoint> {
+        let (rest, default_port) = if url.starts_with(HTTP_SCHEME) {
+            (&url[HTTP_SCHEME.len()..], HTTP_DEFAULT_PORT)
+        } else if url.starts_with(HTTPS_SCHEME) {
+            (&url[HTTPS_SCHEME.len()..], HTTPS_DEFAULT_PORT)
+        } else {
+            return None;
+        };
+        let index = rest.find('/')?;
+        crate::parse_host_port(&rest[..index], default_port)?;
+        Some(InfluxEndpoint {
+            url: String::from(url),
+            auth,
+        })
+    }
+
+    fn post(&self, body: &str) -> Result<(), InfluxError> {
+        let mut request = ureq::post(&self.url);
+        request.timeout(IO_TIMEOUT).set("Content-Type", "text/plain; charset=utf-8");
+        match &self.auth {
+            InfluxAuth::None => (),
+            InfluxAuth::Basic { username, password } => {
+                request.auth(username, password);
+            }
+            InfluxAuth::Token(token) => {
+                request.auth_kind("Token", token);
+            }
+        }
+        let response = request.send_string(body);
+        if let Some(error) = response.synthetic_error() {
+            return Err(InfluxError::Request(error.to_string()));
+        }
+        if response.ok() {
+            return Ok(());
+        }
+        let status = response.status();
+        let body = response.into_string().unwrap_or_default();
+        Err(InfluxError::Status(status, String::from(body.trim())))
+    }
+}
+
+fn escape(value: &str, special: &[char]) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if special.contains(&c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+fn field_value(value: &Value) -> String {
+    match value {
+        Value::Number(number) if number.is_f64() => number.to_string(),
+        Value::Number(number) => format!("{}i", number),
+        Value::String(value) => format!("\"{}\"", escape(value, STRING_SPECIAL)),
+        value => value.to_string(),
+    }
+}
+
+/// Formats `snapshot` as a single line of line protocol, with a field per value of `ApiStatusResponse`
+pub fn line(snapshot: &Snapshot, tags: &[(String, String)]) -> String {
+    let state = serde_json::to_value(ApiStatusResponse::from(snapshot.status.clone())).unwrap();
+    let mut line = String::from(MEASUREMENT);
+    for (key, value) in tags {
+        line.push_str(&format!(",{}={}", escape(key, KEY_SPECIAL), escape(value, KEY_SPECIAL)));
+    }
+    let fields: Vec<String> = sensors(&state)
+        .iter()
+        .map(|sensor| format!("{}={}", escape(&sensor.id(), KEY_SPECIAL), field_value(&sensor.value)))
+        .collect();
+    format!("{} {} {}", line, fields.join(","), snapshot.read_at.timestamp_nanos())
+}
+
+fn read_buffer(path: &Path) -> Result<Vec<String>, InfluxError> {
+    match fs::read_to_string(path) {
+        Ok(buffered) => Ok(buffered.lines().filter(|line| !line.is_empty()).map(String::from).collect()),
+        Err(ref error) if error.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(error) => Err(error.into()),
+    }
+}
+
+fn append_buffer(path: &Path, lines: &[String]) -> Result<(), io::Error> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    for line in lines {
+        writeln!(file, "{}", line)?;
+    }
+    Ok(())
+}
+
+/// Writes every polled status to an InfluxDB endpoint in batches of line protocol.
+pub struct InfluxWriter {
+    endpoint: InfluxEndpoint,
+    tags: Vec<(String, String)>,
+    batch_size: usize,
+    /// Lines that could not be written are kept here until the endpoint is back
+    buffer: Option<PathBuf>,
+    pending: Vec<String>,
+    /// After a failed write, lines are kept rather than written until then
+    retry_at: Option<Instant>,
+    retry_delay: Duration,
+}
+
+impl InfluxWriter {
+    pub fn new(endpoint: InfluxEndpoint, mut tags: Vec<(String, String)>, batch_size: usize, buffer: Option<PathBuf>) -> InfluxWriter {
+        // InfluxDB expects tags sorted by key
+        tags.sort();
+        InfluxWriter {
+            endpoint,
+            tags,
+            batch_size,
+            buffer,
+            pending: Vec::new(),
+            retry_at: None,
+            retry_delay: MIN_RETRY_DELAY,
+        }
+    }
+
+    /// Writes `lines` in chunks, dropping only the chunks the endpoint rejects
+    fn write(&self, lines: &[String]) -> Result<(), InfluxError> {
+        for chunk in lines.chunks(MAX_LINES_PER_WRITE) {
+            self.write_chunk(chunk)?;
+        }
+        Ok(())
+    }
+
+    fn write_chunk(&self, chunk: &[String]) -> Result<(), InfluxError> {
+        match self.endpoint.post(&chunk.join("\n")) {
+            // Too large for the endpoint, so write it in halves
+            Err(InfluxError::Status(413, _)) if chunk.len() > 1 => {
+                let (first, second) = chunk.split_at(chunk.len() / 2);
+                self.write_chunk(first)?;
+                self.write_chunk(second)
+            }
+            Err(ref error) if !error.is_retryable() => {
+                warn!("InfluxDB: dropped {} lines the endpoint rejected: {}", chunk.len(), error);
+                Ok(())
+            }
+            result => result,
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), InfluxError> {
+        // Buffered lines go first, if only some chunks are written they are all written again later,
+        // which is harmless as InfluxDB overwrites points with the same tags and timestamp
+        if let Some(path) = &self.buffer {
+            let buffered = read_buffer(path)?;
+            if !buffered.is_empty() {
+                self.write(&buffered)?;
+                fs::remove_file(path)?;
+                info!("InfluxDB: wrote {} buffered lines", buffered.len());
+            }
+        }
+        self.write(&self.pending)?;
+        trace!("InfluxDB: wrote {} lines", self.pending.len());
+        self.pending.clear();
+        Ok(())
+    }
+
+    fn keep_pending(&mut self) {
+        if let Some(path) = &self.buffer {
+            match append_buffer(path, &self.pending) {
+                Ok(()) => self.pending.clear(),
+                Err(error) => warn!("InfluxDB: failed to buffer lines to {:?}: {}", path, error),
+            }
+        }
+        if self.pending.len() > MAX_PENDING_LINES {
+            let dropped = self.pending.len() - MAX_PENDING_LINES;
+            warn!("InfluxDB: dropped the {} oldest unwritten lines", dropped);
+            self.pending.drain(..dropped);
+        }
+    }
+}
+
+impl PollListener for InfluxWriter {
+    fn on_snapshot(&mut self, snapshot: &Snapshot) {
+        self.pending.push(line(snapshot, &self.tags));
+        if self.pending.len() < self.batch_size {
+            return;
+        }
+        if self.retry_at.map_or(false, |retry_at| Instant::now() < retry_at) {
+            self.keep_pending();
+            return;
+        }
+        match self.flush() {
+            Ok(()) => {
+                self.retry_at = None;
+                self.retry_delay = MIN_RETRY_DELAY;
+            }
+            Err(error) => {
+                warn!(
+                    "InfluxDB: failed to write {} lines, trying again in {:?}: {}",
+                    self.pending.len(),
+                    self.retry_delay,
+                    error
+                );
+                self.keep_pending();
+                self.retry_at = Some(Instant::now() + self.retry_delay);
+                self.retry_delay = (self.retry_delay * 2).min(MAX_RETRY_DELAY);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Read;
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    use chrono::{TimeZone, Utc};
+    use tempdir::TempDir;
+
+    use super::*;
+
+    use crate::sunsaver::SunSaverResponse;
+
+    /// Requests received by the stand-in as (head, body)
+    type Requests = Arc<Mutex<Vec<(String, String)>>>;
+
+    /// Answers each request with the next of `statuses` and records it
+    fn spawn_http_stand_in(statuses: Vec<u16>) -> (u16, Requests) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let thread_requests = requests.clone();
+        thread::spawn(move || {
+            for status in statuses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut head = Vec::new();
+                let mut byte = [0u8];
+                while !head.ends_with(b"\r\n\r\n") {
+                    stream.read_exact(&mut byte).unwrap();
+                    head.push(byte[0]);
+                }
+                let head = String::from_utf8(head).unwrap();
+                let length = head
+                    .lines()
+                    .find(|line| line.starts_with("Content-Length: "))
+                    .map_or(0, |line| line["Content-Length: ".len()..].parse::<usize>().unwrap());
+                let mut body = vec![0u8; length];
+                stream.read_exact(&mut body).unwrap();
+                thread_requests.lock().unwrap().push((head, String::from_utf8(body).unwrap()));
+                write!(
+                    stream,
+                    "HTTP/1.1 {} Status\r\nContent-Length: 5\r\nConnection: close\r\n\r\nerror",
+                    status
+                )
+                .unwrap();
+            }
+        });
+        (port, requests)
+    }
+
+    fn test_snapshot(second: u32) -> Snapshot {
+        let mut registers = [0u16; 44];
+        registers[0] = 0x1200;
+        Snapshot {
+            status: SunSaverResponse::from_raw_bits(registers),
+            read_at: Utc.ymd(2020, 1, 2).and_hms(3, 4, second),
+        }
+    }
+
+    #[test]
+    fn influxendpoint_parse() {
+        assert_eq!(
+            InfluxEndpoint::parse("http://localhost:8086/write?db=sunsaver", InfluxAuth::None),
+            Some(InfluxEndpoint {
+                url: String::from("http://localhost:8086/write?db=sunsaver"),
+                auth: InfluxAuth::None,
+            })
+        );
+        assert!(InfluxEndpoint::parse("http://[::1]/api/v2/write?org=home&bucket=solar", InfluxAuth::None).is_some());
+        assert!(InfluxEndpoint::parse("https://influx.example.com/api/v2/write?org=home&bucket=solar", InfluxAuth::None).is_some());
+        assert_eq!(InfluxEndpoint::parse("ftp://localhost:8086/write?db=sunsaver", InfluxAuth::None), None);
+        assert_eq!(InfluxEndpoint::parse("http://localhost:8086", InfluxAuth::None), None);
+        assert_eq!(InfluxEndpoint::parse("http://localhost:port/write?db=sunsaver", InfluxAuth::None), None);
+    }
+
+    #[test]
+    fn line_protocol() {
+        let tags = vec![
+            (String::from("controller"), String::from("1")),
+            (String::from("site"), String::from("north shed")),
+        ];
+        let line = line(&test_snapshot(5), &tags);
+        assert!(line.starts_with("sunsaver,controller=1,site=north\\ shed "));
+        assert!(line.ends_with(" 1577934245000000000"));
+        assert!(line.contains("storage_battery_voltage_filtered=14.0625"));
+        assert!(line.contains("counters_hourmeter=0i"));
+        assert!(line.contains("storage_charge_state=\"Start\""));
+        assert!(line.contains("faults_array_OVERCURENT=false"));
+
+        assert_eq!(escape("a,b=c d", KEY_SPECIAL), "a\\,b\\=c\\ d");
+        assert_eq!(field_value(&Value::from("say \"hi\"")), "\"say \\\"hi\\\"\"");
+    }
+
+    #[test]
+    fn influxwriter_batches() {
+        let (port, requests) = spawn_http_stand_in(vec![204]);
+        let endpoint = InfluxEndpoint::parse(
+            &format!("http://127.0.0.1:{}/api/v2/write?org=home&bucket=solar", port),
+            InfluxAuth::Token(String::from("secret")),
+        )
+        .unwrap();
+        let mut writer = InfluxWriter::new(endpoint, vec![(String::from("site"), String::from("home"))], 2, None);
+
+        writer.on_snapshot(&test_snapshot(0));
+        assert!(requests.lock().unwrap().is_empty());
+        writer.on_snapshot(&test_snapshot(1));
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 1);
+        let (head, body) = &requests[0];
+        assert!(head.starts_with("POST /api/v2/write?org=home&bucket=solar HTTP/1.1\r\n"));
+        assert!(head.contains("Authorization: Token secret\r\n"));
+        assert_eq!(body.lines().count(), 2);
+        assert!(writer.pending.is_empty());
+    }
+
+    #[test]
+    fn influxwriter_buffers() {
+        let (port, requests) = spawn_http_stand_in(vec![503, 204, 204]);
+        let endpoint = InfluxEndpoint::parse(
+            &format!("http://127.0.0.1:{}/write?db=sunsaver", port),
+            InfluxAuth::Basic {
+                username: String::from("user"),
+                password: String::from("pass"),
+            },
+        )
+        .unwrap();
+        let dir = TempDir::new("influxdb").unwrap();
+        let buffer = dir.path().join("buffer.txt");
+        let mut writer = InfluxWriter::new(endpoint, Vec::new(), 1, Some(buffer.clone()));
+
+        // The endpoint is down, so the line is buffered to disk
+        writer.on_snapshot(&test_snapshot(0));
+        assert_eq!(read_buffer(&buffer).unwrap(), vec![line(&test_snapshot(0), &[])]);
+        assert!(writer.pending.is_empty());
+
+        assert!(writer.retry_at.is_some());
+        assert_eq!(writer.retry_delay, MIN_RETRY_DELAY * 2);
+
+        // It is not tried again until the retry delay has passed
+        writer.on_snapshot(&test_snapshot(1));
+        assert_eq!(requests.lock().unwrap().len(), 1);
+        assert_eq!(read_buffer(&buffer).unwrap().len(), 2);
+
+        // Once it is back the buffered lines are written first
+        writer.retry_at = Some(Instant::now());
+        writer.on_snapshot(&test_snapshot(2));
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 3);
+        assert!(requests[0].0.contains("Authorization: Basic dXNlcjpwYXNz\r\n"));
+        assert_eq!(
+            requests[1].1,
+            format!("{}\n{}", line(&test_snapshot(0), &[]), line(&test_snapshot(1), &[]))
+        );
+        assert_eq!(requests[2].1, line(&test_snapshot(2), &[]));
+        assert!(!buffer.exists());
+        assert_eq!((writer.retry_at, writer.retry_delay), (None, MIN_RETRY_DELAY));
+    }
+
+    #[test]
+    fn influxwriter_rejects() {
+        let (port, requests) = spawn_http_stand_in(vec![413, 204, 400, 400, 204]);
+        let endpoint = InfluxEndpoint::parse(&format!("http://127.0.0.1:{}/write?db=sunsaver", port), InfluxAuth::None).unwrap();
+        let dir = TempDir::new("influxdb").unwrap();
+        let buffer = dir.path().join("buffer.txt");
+        let mut writer = InfluxWriter::new(endpoint, Vec::new(), 4, Some(buffer.clone()));
+        let lines: Vec<String> = (0..8).map(|second| line(&test_snapshot(second), &[])).collect();
+
+        // Too large, so it is split in two, and only the half that is then rejected is dropped
+        for second in 0..4 {
+            writer.on_snapshot(&test_snapshot(second));
+        }
+        assert!(writer.pending.is_empty());
+        assert!(!buffer.exists());
+
+        // A rejected buffer is dropped without dropping the new lines
+        append_buffer(&buffer, &lines[..1]).unwrap();
+        for second in 4..8 {
+            writer.on_snapshot(&test_snapshot(second));
+        }
+        assert!(writer.pending.is_empty());
+        assert!(!buffer.exists());
+
+        let requests = requests.lock().unwrap();
+        let bodies: Vec<&str> = requests.iter().map(|(_, body)| body.as_str()).collect();
+        assert_eq!(
+            bodies,
+            vec![
+                lines[..4].join("\n"),
+                lines[..2].join("\n"),
+                lines[2..4].join("\n"),
+                lines[0].clone(),
+                lines[4..].join("\n")
+            ]
+        );
+    }
+}
@@ -6,88 +6,72 @@ use env_logger;
 // json
 #[macro_use]
 extern crate serde_derive;
-use serde_json;
 
 // datatypes
 #[macro_use]
-extern crate enum_primitive;
-#[macro_use]
 extern crate bitflags;
 
 use std::fs;
+use std::io;
+use std::net::TcpListener;
 use std::os::unix::fs::FileTypeExt;
-use std::path::Path;
-use std::sync::{Arc, Mutex};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::Duration;
 
 use clap;
 
+use chrono::FixedOffset;
+
 use actix_web;
-use actix_web::dev::Handler;
-use actix_web::{HttpRequest, HttpResponse};
 
 mod sunsaver_connection;
-use crate::sunsaver_connection::{FileSunSaverConnection, ModbusSunSaverConnection, SunSaverConnection};
+use crate::sunsaver_connection::{ModbusSunSaverConnection, SunSaverConnection};
+mod recording;
+use crate::recording::{Recorder, ReplaySunSaverConnection};
+mod config;
+use crate::config::{Config, DeviceConfig};
+mod devices;
+use crate::devices::{BusDeviceConnection, Device};
+mod aggregate;
 mod sunsaver;
-use crate::sunsaver::{ArrayFault, ChargeState, LoggedResponseDay};
+use crate::sunsaver::{Alarm, ArrayFault, ChargeState, Coil, Command, DecodeError, LoadFault, LoadState, LoggedResponseDay};
 mod api;
-use crate::api::*;
-
-#[derive(Clone)]
-struct ApiHandler {
-    connection: Arc<Mutex<Box<dyn SunSaverConnection>>>,
-}
-
-impl ApiHandler {
-    fn new(connection: Box<dyn SunSaverConnection>) -> ApiHandler {
-        ApiHandler {
-            connection: Arc::new(Mutex::new(connection)),
-        }
-    }
-}
-
-unsafe impl Send for ApiHandler {}
-unsafe impl Sync for ApiHandler {}
-
-impl<S> Handler<S> for ApiHandler {
-    type Result = HttpResponse;
-
-    fn handle(&self, req: &HttpRequest<S>) -> Self::Result {
-        debug!("ApiHandler: {:?}", req.uri());
+mod poller;
+use crate::poller::{ListenerThread, PollListener, Poller};
+mod history;
+use crate::history::{History, HistoryRecorder, Retention};
+mod metrics;
+use crate::metrics::ModbusMetrics;
+mod mqtt;
+use crate::mqtt::MqttOptions;
+mod mqtt_commands;
+use crate::mqtt_commands::MqttCommands;
+mod mqtt_publisher;
+use crate::mqtt_publisher::MqttPublisher;
+mod influxdb;
+use crate::influxdb::{InfluxAuth, InfluxEndpoint, InfluxWriter};
+mod stream;
+use crate::stream::StatusStream;
+mod handlers;
+use crate::handlers::{
+    AggregateHandler, ApiHandler, CommandHandler, DevicesHandler, HistoryHandler, MetricsHandler, SettingsHandler, StatusStreamHandler,
+};
+mod simulator;
+use crate::simulator::{Fault, Profile, SimulatedController, SimulatorServer};
 
-        let mut response_builder = HttpResponse::Ok();
-        response_builder.header(http::header::ACCESS_CONTROL_ALLOW_METHODS, "GET");
-        response_builder.header(http::header::ACCESS_CONTROL_ALLOW_ORIGIN, "*");
-        response_builder.header(http::header::CONTENT_TYPE, "application/json");
+type SharedConnection = Arc<Mutex<Box<dyn SunSaverConnection>>>;
 
-        let path = req.path();
-        let last_path = path.rsplitn(2, '/').next().unwrap();
-        trace!("ApiHandler: last_path={:?}", last_path);
-        match last_path {
-            "status" => {
-                let a = {
-                    let connection = self.connection.clone();
-                    let mut unlocked_connection = connection.lock().unwrap();
-                    ApiStatusResponse::from(unlocked_connection.read_status())
-                };
-                let b = serde_json::to_string_pretty(&a).unwrap();
-                response_builder.status(http::StatusCode::OK).body(b)
-            }
-            "logged" => {
-                let a = {
-                    let connection = self.connection.clone();
-                    let mut unlocked_connection = connection.lock().unwrap();
-                    ApiLoggedResponse::from(unlocked_connection.read_logged())
-                };
-                let b = serde_json::to_string_pretty(&a).unwrap();
-                response_builder.status(http::StatusCode::OK).body(b)
-            }
-            _ => response_builder.status(http::StatusCode::NOT_FOUND).finish(),
-        }
-    }
+fn lock_connection(connection: &SharedConnection) -> MutexGuard<Box<dyn SunSaverConnection>> {
+    // A panic while holding the lock must not take every later request down with it
+    connection.lock().unwrap_or_else(PoisonError::into_inner)
 }
 
-fn is_rtu_modbus_device(path: &Path) -> bool {
-    let metadata = fs::metadata(path).unwrap();
+fn is_rtu_modbus_device(path: &Path) -> io::Result<bool> {
+    let metadata = fs::metadata(path)?;
     let file_type = metadata.file_type();
     debug!("is_socket for {:?} had metadata {:?}", path, metadata);
     debug!(
@@ -98,84 +82,1200 @@ fn is_rtu_modbus_device(path: &Path) -> bool {
         file_type.is_fifo(),
         file_type.is_socket()
     );
-    metadata.file_type().is_char_device()
+    Ok(metadata.file_type().is_char_device())
+}
+
+static TCP_DEVICE_SCHEME: &'static str = "tcp://";
+static TCP_DEFAULT_PORT: u16 = 502;
+
+fn parse_host_port(address: &str, default_port: u16) -> Option<(String, u16)> {
+    let address = address.trim_end_matches('/');
+    let (host, port) = match address.rfind(':') {
+        // A colon inside brackets belongs to an IPv6 address, not the port
+        Some(index) if !address[index..].contains(']') => (&address[..index], address[index + 1..].parse::<u16>().ok()?),
+        _ => (address, default_port),
+    };
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    if host.is_empty() {
+        return None;
+    }
+    Some((String::from(host), port))
+}
+
+fn parse_tcp_device(device: &str) -> Option<(String, u16)> {
+    if !device.starts_with(TCP_DEVICE_SCHEME) {
+        return None;
+    }
+    parse_host_port(&device[TCP_DEVICE_SCHEME.len()..], TCP_DEFAULT_PORT)
 }
 
+/// Identifies the bus a device is on, devices on the same bus share one connection
+fn bus_key(path: &str) -> String {
+    if let Some((host, port)) = parse_tcp_device(path) {
+        return format!("{}{}:{}", TCP_DEVICE_SCHEME, host, port);
+    }
+    // Resolves links such as /dev/serial/by-id/* to the serial device they name
+    fs::canonicalize(path).map_or_else(|_| String::from(path), |path| path.to_string_lossy().into_owned())
+}
+
+static CLI_ARG_CONFIG: &'static str = "CONFIG";
+static CLI_ARG_PRINT_CONFIG: &'static str = "PRINT_CONFIG";
 static CLI_ARG_DEVICE: &'static str = "DEVICE";
+static CLI_ARG_SLAVE_ID: &'static str = "SLAVE_ID";
+static CLI_ARG_BAUD_RATE: &'static str = "BAUD_RATE";
+static CLI_ARG_PARITY: &'static str = "PARITY";
+static CLI_ARG_DATA_BITS: &'static str = "DATA_BITS";
+static CLI_ARG_STOP_BITS: &'static str = "STOP_BITS";
+static CLI_ARG_RESPONSE_TIMEOUT: &'static str = "RESPONSE_TIMEOUT";
+static CLI_ARG_RETRY_ATTEMPTS: &'static str = "RETRY_ATTEMPTS";
+static CLI_ARG_RETRY_WAIT: &'static str = "RETRY_WAIT";
+static CLI_ARG_RECORD: &'static str = "RECORD";
+static CLI_ARG_REPLAY_LOOP: &'static str = "REPLAY_LOOP";
+static CLI_ARG_REPLAY_SEEK: &'static str = "REPLAY_SEEK";
+static CLI_ARG_BIND_ADDRESS: &'static str = "BIND_ADDRESS";
 static CLI_ARG_PORT: &'static str = "PORT";
 static CLI_ARG_WEB_ROOT: &'static str = "WEB_ROOT";
+static CLI_ARG_API_TOKEN: &'static str = "API_TOKEN";
+static CLI_ARG_UTC_OFFSET: &'static str = "UTC_OFFSET";
+static CLI_ARG_POLL_INTERVAL: &'static str = "POLL_INTERVAL";
+static CLI_ARG_HISTORY: &'static str = "HISTORY";
+static CLI_ARG_MQTT_BROKER: &'static str = "MQTT_BROKER";
+static CLI_ARG_MQTT_USERNAME: &'static str = "MQTT_USERNAME";
+static CLI_ARG_MQTT_PASSWORD: &'static str = "MQTT_PASSWORD";
+static CLI_ARG_MQTT_TOPIC: &'static str = "MQTT_TOPIC";
+static CLI_ARG_MQTT_DISCOVERY_PREFIX: &'static str = "MQTT_DISCOVERY_PREFIX";
+static CLI_ARG_MQTT_COMMANDS: &'static str = "MQTT_COMMANDS";
+static CLI_ARG_INFLUXDB_URL: &'static str = "INFLUXDB_URL";
+static CLI_ARG_INFLUXDB_USERNAME: &'static str = "INFLUXDB_USERNAME";
+static CLI_ARG_INFLUXDB_PASSWORD: &'static str = "INFLUXDB_PASSWORD";
+static CLI_ARG_INFLUXDB_TOKEN: &'static str = "INFLUXDB_TOKEN";
+static CLI_ARG_INFLUXDB_TAGS: &'static str = "INFLUXDB_TAGS";
+static CLI_ARG_INFLUXDB_BATCH_SIZE: &'static str = "INFLUXDB_BATCH_SIZE";
+static CLI_ARG_INFLUXDB_BUFFER: &'static str = "INFLUXDB_BUFFER";
+static CLI_ARG_HISTORY_RETENTION: &'static str = "HISTORY_RETENTION";
+static CLI_ARG_HISTORY_LOGGED_RETENTION: &'static str = "HISTORY_LOGGED_RETENTION";
+
+static CLI_SUBCOMMAND_SIMULATE: &'static str = "simulate";
+static CLI_ARG_SIMULATE_LISTEN: &'static str = "SIMULATE_LISTEN";
+static CLI_ARG_SIMULATE_PTY: &'static str = "SIMULATE_PTY";
+static CLI_ARG_SIMULATE_LINK: &'static str = "SIMULATE_LINK";
+static CLI_ARG_SIMULATE_PROFILE: &'static str = "SIMULATE_PROFILE";
+static CLI_ARG_SIMULATE_SPEED: &'static str = "SIMULATE_SPEED";
+static CLI_ARG_SIMULATE_FAULT: &'static str = "SIMULATE_FAULT";
+static CLI_ARG_SIMULATE_HISTORY_DAYS: &'static str = "SIMULATE_HISTORY_DAYS";
+
+static MQTT_DEFAULT_PORT: u16 = 1883;
+// The broker drops us, publishing the last will, once the connection has been silent for this many poll intervals
+const MQTT_KEEP_ALIVE_POLLS: u32 = 3;
+const MQTT_MIN_KEEP_ALIVE_SECONDS: u64 = 30;
+
+// Logged days only change once a day, so there is no need to read them on every poll
+const HISTORY_LOGGED_INTERVAL_HOURS: i64 = 1;
+
+static SIMULATE_DEFAULT_PROFILE: &'static str = "cycle";
+// Days the simulator runs before serving, so the logged days and counters are not empty
+const SIMULATE_DEFAULT_HISTORY_DAYS: u64 = 7;
+// The logged days only hold the last 32 days, a year is more than enough to fill them
+const SIMULATE_MAX_HISTORY_DAYS: u64 = 366;
 
 fn is_port_number(port_string: String) -> Result<(), String> {
     port_string.parse::<u16>().map(|_| ()).map_err(|_| String::from("Invalid port number"))
 }
 
-fn main() {
-    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("restful_sunsaver=info")).init();
+fn parse_utc_offset(utc_offset: &str) -> Option<FixedOffset> {
+    if utc_offset == "Z" {
+        return Some(FixedOffset::east(0));
+    }
+    let sign = match utc_offset.chars().next()? {
+        '+' => 1,
+        '-' => -1,
+        _ => return None,
+    };
+    let mut parts = utc_offset[1..].splitn(2, ':');
+    let hours = parts.next()?.parse::<i32>().ok()?;
+    let minutes = parts.next().map_or(Some(0), |minutes| minutes.parse::<i32>().ok())?;
+    if hours > 23 || minutes > 59 {
+        return None;
+    }
+    FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))
+}
+
+fn is_poll_interval(poll_interval: String) -> Result<(), String> {
+    match poll_interval.parse::<u64>() {
+        Ok(seconds) if seconds > 0 => Ok(()),
+        _ => Err(String::from("Invalid poll interval, expected a whole number of seconds above zero")),
+    }
+}
+
+fn retention(days: u32) -> Option<chrono::Duration> {
+    match days {
+        // Zero keeps the history forever
+        0 => None,
+        days => Some(chrono::Duration::days(i64::from(days))),
+    }
+}
+
+fn parse_retention_days(days: &str) -> Option<Option<chrono::Duration>> {
+    days.parse::<u32>().ok().map(retention)
+}
+
+fn is_retention_days(days: String) -> Result<(), String> {
+    parse_retention_days(&days)
+        .map(|_| ())
+        .ok_or_else(|| String::from("Invalid retention, expected a whole number of days"))
+}
+
+fn is_mqtt_broker(broker: String) -> Result<(), String> {
+    parse_host_port(&broker, MQTT_DEFAULT_PORT)
+        .map(|_| ())
+        .ok_or_else(|| String::from("Invalid MQTT broker, expected host[:port]"))
+}
+
+fn parse_commands(commands: &str) -> Result<Vec<Command>, String> {
+    let mut parsed = Vec::new();
+    for name in commands.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+        let command = name.parse::<Command>()?;
+        if !parsed.contains(&command) {
+            parsed.push(command);
+        }
+    }
+    Ok(parsed)
+}
+
+fn is_commands(commands: String) -> Result<(), String> {
+    parse_commands(&commands).map(|_| ())
+}
+
+fn is_influxdb_url(url: String) -> Result<(), String> {
+    InfluxEndpoint::parse(&url, InfluxAuth::None)
+        .map(|_| ())
+        .ok_or_else(|| String::from("Invalid InfluxDB URL, expected http[s]://host[:port]/path e.g. http://localhost:8086/write?db=sunsaver"))
+}
+
+fn parse_tags(tags: &str) -> Option<Vec<(String, String)>> {
+    let mut parsed = Vec::new();
+    for tag in tags.split(',').filter(|tag| !tag.is_empty()) {
+        let mut parts = tag.splitn(2, '=');
+        let key = parts.next()?.trim();
+        let value = parts.next()?.trim();
+        if key.is_empty() || value.is_empty() {
+            return None;
+        }
+        parsed.push((String::from(key), String::from(value)));
+    }
+    Some(parsed)
+}
+
+fn is_tags(tags: String) -> Result<(), String> {
+    parse_tags(&tags)
+        .map(|_| ())
+        .ok_or_else(|| String::from("Invalid tags, expected key=value pairs separated by commas"))
+}
+
+fn is_batch_size(batch_size: String) -> Result<(), String> {
+    match batch_size.parse::<usize>() {
+        Ok(size) if size > 0 => Ok(()),
+        _ => Err(String::from("Invalid batch size, expected a whole number above zero")),
+    }
+}
+
+fn is_utc_offset(utc_offset: String) -> Result<(), String> {
+    parse_utc_offset(&utc_offset)
+        .map(|_| ())
+        .ok_or_else(|| String::from("Invalid UTC offset, expected e.g. +01:00"))
+}
+
+fn is_device(device: String) -> Result<(), String> {
+    if device.starts_with(TCP_DEVICE_SCHEME) && parse_tcp_device(&device).is_none() {
+        return Err(String::from("Invalid Modbus TCP address, expected tcp://host[:port]"));
+    }
+    Ok(())
+}
+
+fn is_slave_id(slave_id: String) -> Result<(), String> {
+    match slave_id.parse::<u8>() {
+        Ok(1..=247) => Ok(()),
+        _ => Err(String::from("Invalid slave id, expected 1 to 247")),
+    }
+}
+
+fn is_baud_rate(baud_rate: String) -> Result<(), String> {
+    match baud_rate.parse::<i32>() {
+        Ok(baud_rate) if baud_rate > 0 => Ok(()),
+        _ => Err(String::from("Invalid baud rate")),
+    }
+}
+
+fn is_parity(parity: String) -> Result<(), String> {
+    match parity.as_str() {
+        "N" | "E" | "O" => Ok(()),
+        _ => Err(String::from("Invalid parity, expected N, E or O")),
+    }
+}
+
+fn is_data_bits(data_bits: String) -> Result<(), String> {
+    match data_bits.parse::<i32>() {
+        Ok(5..=8) => Ok(()),
+        _ => Err(String::from("Invalid data bits, expected 5 to 8")),
+    }
+}
+
+fn is_stop_bits(stop_bits: String) -> Result<(), String> {
+    match stop_bits.parse::<i32>() {
+        Ok(1) | Ok(2) => Ok(()),
+        _ => Err(String::from("Invalid stop bits, expected 1 or 2")),
+    }
+}
+
+fn is_milliseconds(milliseconds: String) -> Result<(), String> {
+    milliseconds
+        .parse::<u64>()
+        .map(|_| ())
+        .map_err(|_| String::from("Invalid duration, expected a whole number of milliseconds"))
+}
+
+fn is_response_timeout(milliseconds: String) -> Result<(), String> {
+    match milliseconds.parse::<u64>() {
+        Ok(0) => Err(String::from("Invalid response timeout, expected at least 1 millisecond")),
+        Ok(_) => Ok(()),
+        Err(_) => Err(String::from("Invalid duration, expected a whole number of milliseconds")),
+    }
+}
+
+fn is_replay_seek(reads: String) -> Result<(), String> {
+    reads
+        .parse::<u64>()
+        .map(|_| ())
+        .map_err(|_| String::from("Invalid seek, expected a whole number of status reads"))
+}
+
+fn is_retry_attempts(attempts: String) -> Result<(), String> {
+    match attempts.parse::<u64>() {
+        Ok(attempts) if attempts > 0 => Ok(()),
+        _ => Err(String::from("Invalid retry attempts, expected a whole number above zero")),
+    }
+}
+
+fn is_listen_address(address: String) -> Result<(), String> {
+    parse_host_port(&address, TCP_DEFAULT_PORT)
+        .map(|_| ())
+        .ok_or_else(|| String::from("Invalid listen address, expected host[:port]"))
+}
+
+fn load_profile(profile: &str) -> Result<Profile, String> {
+    let profile = match Profile::named(profile) {
+        Some(profile) => profile,
+        None => Profile::load(Path::new(profile))
+            .map_err(|error| format!("{}: {}, the built in profiles are {}", profile, error, Profile::NAMES.join(", ")))?,
+    };
+    profile.validate()?;
+    Ok(profile)
+}
+
+fn is_profile(profile: String) -> Result<(), String> {
+    load_profile(&profile).map(|_| ())
+}
+
+fn is_fault(fault: String) -> Result<(), String> {
+    fault.parse::<Fault>().map(|_| ())
+}
+
+fn is_speed(speed: String) -> Result<(), String> {
+    speed
+        .parse::<u64>()
+        .map(|_| ())
+        .map_err(|_| String::from("Invalid speed, expected a whole number of simulated seconds per second"))
+}
+
+fn is_history_days(days: String) -> Result<(), String> {
+    match days.parse::<u64>() {
+        Ok(days) if days <= SIMULATE_MAX_HISTORY_DAYS => Ok(()),
+        _ => Err(format!("Invalid history, expected 0 to {} days", SIMULATE_MAX_HISTORY_DAYS)),
+    }
+}
+
+fn is_device_id(id: &str) -> Result<(), String> {
+    if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        return Err(format!("Invalid device id {:?}, expected letters, digits, '-' or '_'", id));
+    }
+    Ok(())
+}
+
+fn validate_device(device: &DeviceConfig) -> Result<(), String> {
+    let path = device
+        .path
+        .as_ref()
+        .ok_or_else(|| String::from("No device given, pass --device or set device.path in the config file"))?;
+    is_device(path.clone())?;
+    if parse_tcp_device(path).is_none() && !Path::new(path).exists() {
+        return Err(format!("Device does not exist: {}", path));
+    }
+    is_slave_id(device.slave_id.to_string())?;
+    is_baud_rate(device.serial.baud_rate.to_string())?;
+    is_parity(device.serial.parity.to_string())?;
+    is_data_bits(device.serial.data_bits.to_string())?;
+    is_stop_bits(device.serial.stop_bits.to_string())?;
+    is_response_timeout(device.response_timeout_ms.to_string())?;
+    is_retry_attempts(device.retry.attempts.to_string())
+}
+
+fn validate_devices(config: &Config) -> Result<(), String> {
+    // The device flags and environment variables only set up the single device, so they cannot be mixed with a list
+    if !config.devices.is_empty() && config.device != DeviceConfig::default() {
+        return Err(String::from(
+            "Give either a single device, with --device and the other device flags or [device], or a list of [[devices]], not both",
+        ));
+    }
+    let devices = config.devices();
+    for (index, device) in devices.iter().enumerate() {
+        let id = device.id.as_ref().ok_or_else(|| format!("Device {} has no id", index + 1))?;
+        is_device_id(id)?;
+        if devices[..index].iter().any(|other| other.id == device.id) {
+            return Err(format!("Device id {:?} is used more than once", id));
+        }
+        validate_device(device).map_err(|error| {
+            if config.devices.is_empty() {
+                error
+            } else {
+                format!("Device {:?}: {}", id, error)
+            }
+        })?;
+
+        // Devices on one bus share its connection, so they must agree on how it is set up
+        let bus = bus_key(device.path.as_ref().unwrap());
+        for other in devices[..index].iter().filter(|other| bus_key(other.path.as_ref().unwrap()) == bus) {
+            let other_id = other.id.as_ref().unwrap();
+            if other.slave_id == device.slave_id {
+                return Err(format!("Devices {:?} and {:?} share a bus and slave id", other_id, id));
+            }
+            if other.serial != device.serial
+                || other.response_timeout_ms != device.response_timeout_ms
+                || other.retry != device.retry
+                || other.record != device.record
+                || other.replay != device.replay
+            {
+                return Err(format!(
+                    "Devices {:?} and {:?} share a bus but not its serial, timeout, retry, record or replay settings",
+                    other_id, id
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Checks the merged settings, as values from the config file have not been through the argument validators
+fn validate_config(config: &Config) -> Result<(), String> {
+    validate_devices(config)?;
+    is_utc_offset(config.server.utc_offset.clone())?;
+    is_poll_interval(config.poller.interval_seconds.to_string())?;
+    match &config.mqtt.broker {
+        Some(broker) => is_mqtt_broker(broker.clone())?,
+        None if !config.mqtt.commands.is_empty() => return Err(String::from("MQTT commands need an MQTT broker")),
+        None => (),
+    }
+    is_commands(config.mqtt.commands.join(","))?;
+    if let Some(url) = &config.influxdb.url {
+        is_influxdb_url(url.clone())?;
+    }
+    if config.influxdb.username.is_some() && config.influxdb.token.is_some() {
+        return Err(String::from("Give either an InfluxDB username or token, not both"));
+    }
+    is_batch_size(config.influxdb.batch_size.to_string())
+}
+
+fn override_value<T: FromStr>(matches: &clap::ArgMatches, name: &str, target: &mut T) {
+    // Values have already been checked by the argument's validator
+    if let Some(value) = matches.value_of(name).and_then(|value| value.parse().ok()) {
+        *target = value;
+    }
+}
+
+fn override_option(matches: &clap::ArgMatches, name: &str, target: &mut Option<String>) {
+    if let Some(value) = matches.value_of(name) {
+        *target = Some(String::from(value));
+    }
+}
+
+/// Overrides `config` with the flags and environment variables that were given
+fn apply_matches(config: &mut Config, matches: &clap::ArgMatches) {
+    override_option(matches, CLI_ARG_DEVICE, &mut config.device.path);
+    override_value(matches, CLI_ARG_SLAVE_ID, &mut config.device.slave_id);
+    override_value(matches, CLI_ARG_BAUD_RATE, &mut config.device.serial.baud_rate);
+    override_value(matches, CLI_ARG_PARITY, &mut config.device.serial.parity);
+    override_value(matches, CLI_ARG_DATA_BITS, &mut config.device.serial.data_bits);
+    override_value(matches, CLI_ARG_STOP_BITS, &mut config.device.serial.stop_bits);
+    override_value(matches, CLI_ARG_RESPONSE_TIMEOUT, &mut config.device.response_timeout_ms);
+    override_value(matches, CLI_ARG_RETRY_ATTEMPTS, &mut config.device.retry.attempts);
+    override_value(matches, CLI_ARG_RETRY_WAIT, &mut config.device.retry.wait_ms);
+    override_option(matches, CLI_ARG_RECORD, &mut config.device.record);
+    if matches.is_present(CLI_ARG_REPLAY_LOOP) {
+        config.device.replay.repeat = true;
+    }
+    override_value(matches, CLI_ARG_REPLAY_SEEK, &mut config.device.replay.seek);
+
+    override_value(matches, CLI_ARG_BIND_ADDRESS, &mut config.server.bind_address);
+    override_value(matches, CLI_ARG_PORT, &mut config.server.port);
+    override_value(matches, CLI_ARG_WEB_ROOT, &mut config.server.web_root);
+    override_option(matches, CLI_ARG_API_TOKEN, &mut config.server.api_token);
+    override_value(matches, CLI_ARG_UTC_OFFSET, &mut config.server.utc_offset);
+    override_value(matches, CLI_ARG_POLL_INTERVAL, &mut config.poller.interval_seconds);
 
-    let matches = clap::App::new(env!("CARGO_PKG_NAME"))
+    override_option(matches, CLI_ARG_HISTORY, &mut config.history.path);
+    override_value(matches, CLI_ARG_HISTORY_RETENTION, &mut config.history.retention_days);
+    override_value(matches, CLI_ARG_HISTORY_LOGGED_RETENTION, &mut config.history.logged_retention_days);
+
+    override_option(matches, CLI_ARG_MQTT_BROKER, &mut config.mqtt.broker);
+    override_option(matches, CLI_ARG_MQTT_USERNAME, &mut config.mqtt.username);
+    override_option(matches, CLI_ARG_MQTT_PASSWORD, &mut config.mqtt.password);
+    override_value(matches, CLI_ARG_MQTT_TOPIC, &mut config.mqtt.topic);
+    override_value(matches, CLI_ARG_MQTT_DISCOVERY_PREFIX, &mut config.mqtt.discovery_prefix);
+    if let Some(commands) = matches.value_of(CLI_ARG_MQTT_COMMANDS) {
+        config.mqtt.commands = parse_commands(commands)
+            .unwrap()
+            .iter()
+            .map(|command| String::from(command.name()))
+            .collect();
+    }
+
+    override_option(matches, CLI_ARG_INFLUXDB_URL, &mut config.influxdb.url);
+    override_option(matches, CLI_ARG_INFLUXDB_USERNAME, &mut config.influxdb.username);
+    override_option(matches, CLI_ARG_INFLUXDB_PASSWORD, &mut config.influxdb.password);
+    override_option(matches, CLI_ARG_INFLUXDB_TOKEN, &mut config.influxdb.token);
+    if let Some(tags) = matches.value_of(CLI_ARG_INFLUXDB_TAGS) {
+        config.influxdb.tags = parse_tags(tags).unwrap().into_iter().collect();
+    }
+    override_value(matches, CLI_ARG_INFLUXDB_BATCH_SIZE, &mut config.influxdb.batch_size);
+    override_option(matches, CLI_ARG_INFLUXDB_BUFFER, &mut config.influxdb.buffer);
+}
+
+fn exit_with_error(message: &str) -> ! {
+    eprintln!("error: {}", message);
+    process::exit(1)
+}
+
+fn cli() -> clap::App<'static, 'static> {
+    clap::App::new(env!("CARGO_PKG_NAME"))
         .version(env!("CARGO_PKG_VERSION"))
         .about("HTTP RESTful server for SunSaver MPPT ModBus data")
         .author("Guy Taylor <thebiggerguy.co.uk@gmail.com>")
+        .arg(
+            clap::Arg::with_name(CLI_ARG_CONFIG)
+                .help("TOML config file, flags and environment variables override its values")
+                .long("config")
+                .short("c")
+                .env("SUNSAVER_CONFIG")
+                .takes_value(true)
+                .empty_values(false)
+                .required(false),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_PRINT_CONFIG)
+                .help("Prints the effective config, with secrets hidden, and exits")
+                .long("print-config"),
+        )
         .arg(
             clap::Arg::with_name(CLI_ARG_DEVICE)
-                .help("Serial device e.g. /dev/ttyUSB0")
+                .help("Serial device e.g. /dev/ttyUSB0, Modbus TCP gateway e.g. tcp://192.168.1.10:502 or a --record file to replay")
                 .long("device")
                 .short("d")
+                .env("SUNSAVER_DEVICE")
                 .takes_value(true)
                 .empty_values(false)
-                .required(true),
+                .required(false)
+                .validator(is_device),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_SLAVE_ID)
+                .help("Modbus slave id of the controller [default: 1]")
+                .long("slave-id")
+                .env("SUNSAVER_SLAVE_ID")
+                .takes_value(true)
+                .empty_values(false)
+                .required(false)
+                .validator(is_slave_id),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_BAUD_RATE)
+                .help("Serial baud rate [default: 9600]")
+                .long("baud-rate")
+                .env("SUNSAVER_BAUD_RATE")
+                .takes_value(true)
+                .empty_values(false)
+                .required(false)
+                .validator(is_baud_rate),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_PARITY)
+                .help("Serial parity, N, E or O [default: N]")
+                .long("parity")
+                .env("SUNSAVER_PARITY")
+                .takes_value(true)
+                .empty_values(false)
+                .required(false)
+                .validator(is_parity),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_DATA_BITS)
+                .help("Serial data bits [default: 8]")
+                .long("data-bits")
+                .env("SUNSAVER_DATA_BITS")
+                .takes_value(true)
+                .empty_values(false)
+                .required(false)
+                .validator(is_data_bits),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_STOP_BITS)
+                .help("Serial stop bits [default: 2]")
+                .long("stop-bits")
+                .env("SUNSAVER_STOP_BITS")
+                .takes_value(true)
+                .empty_values(false)
+                .required(false)
+                .validator(is_stop_bits),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_RESPONSE_TIMEOUT)
+                .help("Milliseconds to wait for the controller to respond [default: 1000]")
+                .long("response-timeout")
+                .env("SUNSAVER_RESPONSE_TIMEOUT")
+                .takes_value(true)
+                .empty_values(false)
+                .required(false)
+                .validator(is_response_timeout),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_RETRY_ATTEMPTS)
+                .help("Attempts at each Modbus request before giving up [default: 3]")
+                .long("retry-attempts")
+                .env("SUNSAVER_RETRY_ATTEMPTS")
+                .takes_value(true)
+                .empty_values(false)
+                .required(false)
+                .validator(is_retry_attempts),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_RETRY_WAIT)
+                .help("Milliseconds between Modbus request attempts [default: 100]")
+                .long("retry-wait")
+                .env("SUNSAVER_RETRY_WAIT")
+                .takes_value(true)
+                .empty_values(false)
+                .required(false)
+                .validator(is_milliseconds),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_RECORD)
+                .help("Appends every Modbus request and its answer to a file, which can be replayed by passing it as --device")
+                .long("record")
+                .env("SUNSAVER_RECORD")
+                .takes_value(true)
+                .empty_values(false)
+                .required(false),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_REPLAY_LOOP)
+                .help("Replays the --device recording or fixture again from the start once it runs out")
+                .long("replay-loop"),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_REPLAY_SEEK)
+                .help("Status reads of the --device recording or fixture to skip before replaying [default: 0]")
+                .long("replay-seek")
+                .env("SUNSAVER_REPLAY_SEEK")
+                .takes_value(true)
+                .empty_values(false)
+                .required(false)
+                .validator(is_replay_seek),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_BIND_ADDRESS)
+                .help("HTTP server bind address [default: 0.0.0.0]")
+                .long("bind-address")
+                .env("SUNSAVER_BIND_ADDRESS")
+                .takes_value(true)
+                .empty_values(false)
+                .required(false),
         )
         .arg(
             clap::Arg::with_name(CLI_ARG_PORT)
-                .help("HTTP server port")
+                .help("HTTP server port [default: 8080]")
                 .long("port")
                 .short("p")
+                .env("SUNSAVER_PORT")
                 .takes_value(true)
                 .empty_values(false)
                 .required(false)
-                .default_value("8080")
                 .validator(is_port_number),
         )
         .arg(
             clap::Arg::with_name(CLI_ARG_WEB_ROOT)
-                .help("HTTP server root folder")
+                .help("HTTP server root folder [default: web]")
                 .long("webroot")
+                .env("SUNSAVER_WEB_ROOT")
+                .takes_value(true)
+                .empty_values(false)
+                .required(false),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_API_TOKEN)
+                .help("Bearer token required by POST /api/v1/commands/* and PUT /api/v1/settings, both are disabled without one")
+                .long("api-token")
+                .env("SUNSAVER_API_TOKEN")
+                .hide_env_values(true)
+                .takes_value(true)
+                .empty_values(false)
+                .required(false),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_UTC_OFFSET)
+                .help("UTC offset of the controller's site, used to date the logged days e.g. +01:00 [default: +00:00]")
+                .long("utc-offset")
+                .env("SUNSAVER_UTC_OFFSET")
+                .takes_value(true)
+                .empty_values(false)
+                .required(false)
+                .allow_hyphen_values(true)
+                .validator(is_utc_offset),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_POLL_INTERVAL)
+                .help("Seconds between reads of the device status [default: 5]")
+                .long("poll-interval")
+                .env("SUNSAVER_POLL_INTERVAL")
                 .takes_value(true)
                 .empty_values(false)
                 .required(false)
-                .default_value("web"),
+                .validator(is_poll_interval),
         )
-        .get_matches();
+        .arg(
+            clap::Arg::with_name(CLI_ARG_HISTORY)
+                .help("SQLite file to record the polled status in, enables GET /api/v1/history")
+                .long("history")
+                .env("SUNSAVER_HISTORY")
+                .takes_value(true)
+                .empty_values(false)
+                .required(false),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_HISTORY_RETENTION)
+                .help("Days of polled status to keep in the history, 0 keeps it forever [default: 30]")
+                .long("history-retention")
+                .env("SUNSAVER_HISTORY_RETENTION")
+                .takes_value(true)
+                .empty_values(false)
+                .required(false)
+                .validator(is_retention_days),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_HISTORY_LOGGED_RETENTION)
+                .help("Days of logged days to keep in the history, 0 keeps them forever [default: 0]")
+                .long("history-logged-retention")
+                .env("SUNSAVER_HISTORY_LOGGED_RETENTION")
+                .takes_value(true)
+                .empty_values(false)
+                .required(false)
+                .validator(is_retention_days),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_MQTT_BROKER)
+                .help("MQTT broker to publish the polled status to e.g. localhost:1883")
+                .long("mqtt-broker")
+                .env("SUNSAVER_MQTT_BROKER")
+                .takes_value(true)
+                .empty_values(false)
+                .required(false)
+                .validator(is_mqtt_broker),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_MQTT_USERNAME)
+                .help("MQTT broker username")
+                .long("mqtt-username")
+                .env("SUNSAVER_MQTT_USERNAME")
+                .takes_value(true)
+                .empty_values(false)
+                .required(false),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_MQTT_PASSWORD)
+                .help("MQTT broker password")
+                .long("mqtt-password")
+                .env("SUNSAVER_MQTT_PASSWORD")
+                .hide_env_values(true)
+                .takes_value(true)
+                .empty_values(false)
+                .required(false),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_MQTT_TOPIC)
+                .help("MQTT topic the status is published under [default: sunsaver]")
+                .long("mqtt-topic")
+                .env("SUNSAVER_MQTT_TOPIC")
+                .takes_value(true)
+                .empty_values(false)
+                .required(false),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_MQTT_DISCOVERY_PREFIX)
+                .help("Home Assistant MQTT discovery prefix [default: homeassistant]")
+                .long("mqtt-discovery-prefix")
+                .env("SUNSAVER_MQTT_DISCOVERY_PREFIX")
+                .takes_value(true)
+                .empty_values(false)
+                .required(false),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_MQTT_COMMANDS)
+                .help("Comma separated commands that may be run over MQTT e.g. load_disconnect,load_connect,equalize")
+                .long("mqtt-commands")
+                .env("SUNSAVER_MQTT_COMMANDS")
+                .takes_value(true)
+                .empty_values(false)
+                .required(false)
+                .validator(is_commands),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_INFLUXDB_URL)
+                .help("InfluxDB write URL to push the polled status to e.g. http://localhost:8086/write?db=sunsaver")
+                .long("influxdb-url")
+                .env("SUNSAVER_INFLUXDB_URL")
+                .takes_value(true)
+                .empty_values(false)
+                .required(false)
+                .validator(is_influxdb_url),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_INFLUXDB_USERNAME)
+                .help("InfluxDB 1.x username")
+                .long("influxdb-username")
+                .env("SUNSAVER_INFLUXDB_USERNAME")
+                .takes_value(true)
+                .empty_values(false)
+                .required(false),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_INFLUXDB_PASSWORD)
+                .help("InfluxDB 1.x password")
+                .long("influxdb-password")
+                .env("SUNSAVER_INFLUXDB_PASSWORD")
+                .hide_env_values(true)
+                .takes_value(true)
+                .empty_values(false)
+                .required(false),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_INFLUXDB_TOKEN)
+                .help("InfluxDB 2.x API token")
+                .long("influxdb-token")
+                .env("SUNSAVER_INFLUXDB_TOKEN")
+                .hide_env_values(true)
+                .takes_value(true)
+                .empty_values(false)
+                .required(false),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_INFLUXDB_TAGS)
+                .help("Tags added to every InfluxDB point e.g. site=home,controller=1")
+                .long("influxdb-tags")
+                .env("SUNSAVER_INFLUXDB_TAGS")
+                .takes_value(true)
+                .empty_values(false)
+                .required(false)
+                .validator(is_tags),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_INFLUXDB_BATCH_SIZE)
+                .help("Number of polls written to InfluxDB at a time [default: 12]")
+                .long("influxdb-batch-size")
+                .env("SUNSAVER_INFLUXDB_BATCH_SIZE")
+                .takes_value(true)
+                .empty_values(false)
+                .required(false)
+                .validator(is_batch_size),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_INFLUXDB_BUFFER)
+                .help("File to keep points in while InfluxDB cannot be reached")
+                .long("influxdb-buffer")
+                .env("SUNSAVER_INFLUXDB_BUFFER")
+                .takes_value(true)
+                .empty_values(false)
+                .required(false),
+        )
+        .subcommand(simulate_cli())
+}
 
-    let serial_interface = Path::new(matches.value_of(CLI_ARG_DEVICE).unwrap());
-    let port_number = matches.value_of(CLI_ARG_PORT).unwrap().parse::<u16>().unwrap();
-    // TODO: Make static
-    //let web_root: &'static Path = Path::new(matches.value_of(CLI_ARG_WEB_ROOT).unwrap());
+fn simulate_cli() -> clap::App<'static, 'static> {
+    clap::SubCommand::with_name(CLI_SUBCOMMAND_SIMULATE)
+        .about("Serves a simulated SunSaver MPPT over Modbus TCP or a pseudo terminal, for development and tests")
+        .arg(
+            clap::Arg::with_name(CLI_ARG_SIMULATE_LISTEN)
+                .help("Address to serve Modbus TCP on e.g. 127.0.0.1:5020")
+                .long("listen")
+                .short("l")
+                .env("SUNSAVER_SIMULATE_LISTEN")
+                .takes_value(true)
+                .empty_values(false)
+                .required_unless(CLI_ARG_SIMULATE_PTY)
+                .validator(is_listen_address),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_SIMULATE_PTY)
+                .help("Serves Modbus RTU on a pseudo terminal and prints its path")
+                .long("pty"),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_SIMULATE_LINK)
+                .help("Symlink to create to the pseudo terminal e.g. /tmp/sunsaver")
+                .long("link")
+                .env("SUNSAVER_SIMULATE_LINK")
+                .takes_value(true)
+                .empty_values(false)
+                .requires(CLI_ARG_SIMULATE_PTY),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_SLAVE_ID)
+                .help("Modbus slave id to answer to [default: 1]")
+                .long("slave-id")
+                .env("SUNSAVER_SIMULATE_SLAVE_ID")
+                .takes_value(true)
+                .empty_values(false)
+                .required(false)
+                .validator(is_slave_id),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_SIMULATE_PROFILE)
+                .help("Built in profile, day, night or cycle, or a TOML script of [[step]]s [default: cycle]")
+                .long("profile")
+                .env("SUNSAVER_SIMULATE_PROFILE")
+                .takes_value(true)
+                .empty_values(false)
+                .required(false)
+                .validator(is_profile),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_SIMULATE_SPEED)
+                .help("Simulated seconds passing each second, 0 stops the clock [default: 1]")
+                .long("speed")
+                .env("SUNSAVER_SIMULATE_SPEED")
+                .takes_value(true)
+                .empty_values(false)
+                .required(false)
+                .validator(is_speed),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_SIMULATE_FAULT)
+                .help(
+                    "Fault to inject as KIND[@START[+SECONDS]] in simulated seconds, \
+                     KIND is timeout, crc, exception, array:FLAG, load:FLAG or alarm:FLAG e.g. array:OVERCURENT@3600",
+                )
+                .long("fault")
+                .env("SUNSAVER_SIMULATE_FAULTS")
+                .takes_value(true)
+                .empty_values(false)
+                .multiple(true)
+                .number_of_values(1)
+                .use_delimiter(true)
+                .required(false)
+                .validator(is_fault),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_SIMULATE_HISTORY_DAYS)
+                .help("Days of the profile to run before serving, filling the logged days [default: 7]")
+                .long("history-days")
+                .env("SUNSAVER_SIMULATE_HISTORY_DAYS")
+                .takes_value(true)
+                .empty_values(false)
+                .required(false)
+                .validator(is_history_days),
+        )
+}
 
-    if !serial_interface.exists() {
-        panic!("Device does not exists: {:?}", serial_interface);
+fn simulate(matches: &clap::ArgMatches) {
+    let profile =
+        load_profile(matches.value_of(CLI_ARG_SIMULATE_PROFILE).unwrap_or(SIMULATE_DEFAULT_PROFILE)).unwrap_or_else(|error| exit_with_error(&error));
+    let faults: Vec<Fault> = matches
+        .values_of(CLI_ARG_SIMULATE_FAULT)
+        .map_or_else(Vec::new, |faults| faults.map(|fault| fault.parse().unwrap()).collect());
+    let slave_id = matches.value_of(CLI_ARG_SLAVE_ID).map_or(1, |slave_id| slave_id.parse().unwrap());
+    let speed = matches.value_of(CLI_ARG_SIMULATE_SPEED).map_or(1, |speed| speed.parse().unwrap());
+    let history_days = matches
+        .value_of(CLI_ARG_SIMULATE_HISTORY_DAYS)
+        .map_or(SIMULATE_DEFAULT_HISTORY_DAYS, |days| days.parse().unwrap());
+
+    let mut controller = SimulatedController::new(profile);
+    info!("Simulating {} days of history", history_days);
+    controller.warm_up(history_days);
+    for fault in faults {
+        controller.inject(fault);
     }
+    let server = SimulatorServer::new(controller, slave_id, speed);
 
-    let connection: Box<dyn SunSaverConnection> = if is_rtu_modbus_device(serial_interface) {
-        info!("Device is a socket. Using Modbus");
-        Box::new(ModbusSunSaverConnection::open(serial_interface))
+    if matches.is_present(CLI_ARG_SIMULATE_PTY) {
+        let path = server
+            .serve_pty()
+            .unwrap_or_else(|error| exit_with_error(&format!("Failed to open a pseudo terminal: {}", error)));
+        info!("Serving Modbus RTU on {:?}", path);
+        if let Some(link) = matches.value_of(CLI_ARG_SIMULATE_LINK) {
+            // Only a link left by an earlier run is replaced, never a file or device
+            if fs::symlink_metadata(link)
+                .map(|metadata| metadata.file_type().is_symlink())
+                .unwrap_or(false)
+            {
+                fs::remove_file(link).unwrap_or_else(|error| exit_with_error(&format!("{}: {}", link, error)));
+            }
+            std::os::unix::fs::symlink(&path, link).unwrap_or_else(|error| exit_with_error(&format!("{}: {}", link, error)));
+            info!("Linked {} to {:?}", link, path);
+        }
+        // Printed for scripts that start the simulator and connect to it
+        println!("{}", path.display());
+    }
+
+    match matches.value_of(CLI_ARG_SIMULATE_LISTEN) {
+        Some(address) => {
+            let (host, port) = parse_host_port(address, TCP_DEFAULT_PORT).unwrap();
+            let listener = TcpListener::bind((host.as_str(), port)).unwrap_or_else(|error| exit_with_error(&format!("{}: {}", address, error)));
+            info!("Serving Modbus TCP on {}", address);
+            server.serve_tcp(listener);
+        }
+        None => loop {
+            thread::park();
+        },
+    }
+}
+
+fn open_recorder(device: &DeviceConfig) -> Option<Recorder> {
+    let path = device.record.as_ref()?;
+    info!("Recording Modbus requests to {}", path);
+    let recorder = Recorder::create(Path::new(path), device.slave_id).unwrap_or_else(|error| exit_with_error(&format!("{}: {}", path, error)));
+    Some(recorder)
+}
+
+fn open_connection(device: &DeviceConfig) -> Box<dyn SunSaverConnection> {
+    let path = device.path.as_ref().unwrap();
+    let modbus_options = device.modbus_options();
+    if let Some((host, port)) = parse_tcp_device(path) {
+        info!("Device is a network address. Using Modbus TCP");
+        let mut connection =
+            ModbusSunSaverConnection::open_tcp(&host, port, &modbus_options).unwrap_or_else(|error| exit_with_error(&format!("{}: {}", path, error)));
+        if let Some(recorder) = open_recorder(device) {
+            connection.record(recorder);
+        }
+        Box::new(connection)
     } else {
-        info!("Device is not a socket. Using File");
-        Box::new(FileSunSaverConnection::open(serial_interface))
+        let serial_interface = Path::new(path);
+        // The device was checked when the config was validated, but it may have been unplugged since
+        let is_rtu = is_rtu_modbus_device(serial_interface).unwrap_or_else(|error| exit_with_error(&format!("{}: {}", path, error)));
+        if is_rtu {
+            info!("Device is a socket. Using Modbus");
+            let mut connection = ModbusSunSaverConnection::open_rtu(serial_interface, &device.serial, &modbus_options)
+                .unwrap_or_else(|error| exit_with_error(&format!("{}: {}", path, error)));
+            if let Some(recorder) = open_recorder(device) {
+                connection.record(recorder);
+            }
+            Box::new(connection)
+        } else {
+            info!("Device is not a socket. Replaying a recording or fixture");
+            if device.record.is_some() {
+                warn!("Not recording, {} is already a recording or fixture", path);
+            }
+            let connection = ReplaySunSaverConnection::open(serial_interface, device.replay)
+                .unwrap_or_else(|error| exit_with_error(&format!("{}: {}", path, error)));
+            Box::new(connection)
+        }
+    }
+}
+
+/// Opens a connection for each device, devices on the same bus take turns on one connection
+fn open_devices(devices: &[DeviceConfig]) -> Vec<SharedConnection> {
+    let buses: Vec<String> = devices.iter().map(|device| bus_key(device.path.as_ref().unwrap())).collect();
+    let mut shared_buses: Vec<(&String, SharedConnection)> = Vec::new();
+    let mut connections: Vec<SharedConnection> = Vec::new();
+    for (device, bus) in devices.iter().zip(buses.iter()) {
+        if buses.iter().filter(|other| *other == bus).count() == 1 {
+            connections.push(Arc::new(Mutex::new(open_connection(device))));
+            continue;
+        }
+        let shared = match shared_buses.iter().find(|(key, _)| *key == bus) {
+            Some((_, shared)) => shared.clone(),
+            None => {
+                info!("Sharing {} between several devices", bus);
+                let shared: SharedConnection = Arc::new(Mutex::new(open_connection(device)));
+                shared_buses.push((bus, shared.clone()));
+                shared
+            }
+        };
+        let connection: Box<dyn SunSaverConnection> = Box::new(BusDeviceConnection::new(shared, device.slave_id));
+        connections.push(Arc::new(Mutex::new(connection)));
+    }
+    connections
+}
+
+fn main() {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("restful_sunsaver=info")).init();
+
+    let matches = cli().get_matches();
+    if let Some(matches) = matches.subcommand_matches(CLI_SUBCOMMAND_SIMULATE) {
+        simulate(matches);
+        return;
+    }
+
+    let mut config = match matches.value_of(CLI_ARG_CONFIG) {
+        Some(path) => Config::load(Path::new(path)).unwrap_or_else(|error| exit_with_error(&format!("{}: {}", path, error))),
+        None => Config::default(),
     };
+    apply_matches(&mut config, &matches);
 
-    let api_handler = ApiHandler::new(connection);
+    if matches.is_present(CLI_ARG_PRINT_CONFIG) {
+        print!("{}", config.redacted().to_toml());
+        return;
+    }
+    if let Err(error) = validate_config(&config) {
+        exit_with_error(&error);
+    }
 
-    info!("Starting server ...");
-    let bind_address = format!("0.0.0.0:{}", port_number);
+    let api_token = config.server.api_token.clone();
+    let utc_offset = parse_utc_offset(&config.server.utc_offset).unwrap();
+    let poll_interval = Duration::from_secs(config.poller.interval_seconds);
+    let history_retention = Retention {
+        samples: retention(config.history.retention_days),
+        logged_days: retention(config.history.logged_retention_days),
+    };
+
+    let device_configs = config.devices();
+    let connections = open_devices(&device_configs);
+    let device_metrics: Vec<Arc<ModbusMetrics>> = connections
+        .iter()
+        .map(|connection| {
+            let metrics = Arc::new(ModbusMetrics::new());
+            lock_connection(connection).measure(metrics.clone());
+            metrics
+        })
+        .collect();
+    // The unprefixed routes, commands, settings and exporters all use the first device, only /metrics covers every one
+    let connection = connections[0].clone();
+    if device_configs.len() > 1 {
+        info!(
+            "The history, stream, commands, settings and MQTT and InfluxDB exporters only cover the first device {:?}",
+            device_configs[0].id.as_ref().unwrap()
+        );
+    }
+
+    if api_token.is_none() {
+        info!("No API token configured. Commands and settings writes are disabled");
+    }
+
+    let history = config.history.path.as_ref().map(|path| {
+        info!("Recording history to {:?}", path);
+        let history = History::open(Path::new(path), history_retention).unwrap_or_else(|error| exit_with_error(&format!("{}: {}", path, error)));
+        Arc::new(history)
+    });
+    let status_stream = StatusStream::new();
+    let mut poll_listeners: Vec<Box<dyn PollListener>> = vec![Box::new(status_stream.clone())];
+    if let Some(history) = &history {
+        poll_listeners.push(Box::new(HistoryRecorder::new(
+            history.clone(),
+            connection.clone(),
+            chrono::Duration::hours(HISTORY_LOGGED_INTERVAL_HOURS),
+        )));
+    }
+
+    if let Some(broker) = &config.mqtt.broker {
+        let (host, port) = parse_host_port(broker, MQTT_DEFAULT_PORT).unwrap();
+        let topic = config.mqtt.topic.clone();
+        let keep_alive = (poll_interval * MQTT_KEEP_ALIVE_POLLS).max(Duration::from_secs(MQTT_MIN_KEEP_ALIVE_SECONDS));
+        let options = MqttOptions {
+            host,
+            port,
+            client_id: format!("{}-{}", env!("CARGO_PKG_NAME"), topic.replace('/', "-")),
+            username: config.mqtt.username.clone(),
+            password: config.mqtt.password.clone(),
+            keep_alive,
+            last_will: None,
+        };
+        info!("Publishing to MQTT broker {}:{} under {:?}", options.host, options.port, topic);
+        let mut publisher = MqttPublisher::new(options.clone(), topic.clone(), config.mqtt.discovery_prefix.clone());
+        if !config.mqtt.commands.is_empty() {
+            let allowed = parse_commands(&config.mqtt.commands.join(",")).unwrap();
+            info!("Accepting MQTT commands {:?}", allowed);
+            let command_options = MqttOptions {
+                client_id: format!("{}-commands", options.client_id),
+                ..options
+            };
+            // Retries as often as the publisher, which reconnects on the next poll
+            MqttCommands::new(connection.clone(), allowed.clone()).spawn(command_options, topic, poll_interval);
+            publisher = publisher.with_commands(&allowed);
+        }
+        poll_listeners.push(Box::new(ListenerThread::spawn("mqtt", Box::new(publisher))));
+    }
+
+    if let Some(url) = &config.influxdb.url {
+        let auth = if let Some(token) = &config.influxdb.token {
+            InfluxAuth::Token(token.clone())
+        } else if let Some(username) = &config.influxdb.username {
+            InfluxAuth::Basic {
+                username: username.clone(),
+                password: config.influxdb.password.clone().unwrap_or_default(),
+            }
+        } else {
+            InfluxAuth::None
+        };
+        let endpoint = InfluxEndpoint::parse(url, auth).unwrap();
+        let tags = config.influxdb.tags.clone().into_iter().collect();
+        let batch_size = config.influxdb.batch_size;
+        let buffer = config.influxdb.buffer.as_ref().map(PathBuf::from);
+        info!("Writing to InfluxDB at {} in batches of {}", endpoint.url, batch_size);
+        let writer = InfluxWriter::new(endpoint, tags, batch_size, buffer);
+        poll_listeners.push(Box::new(ListenerThread::spawn("influxdb", Box::new(writer))));
+    }
+
+    info!("Polling the device every {:?}", poll_interval);
+    let poller = Poller::spawn(connection.clone(), poll_interval, poll_listeners);
+    let mut devices = Vec::new();
+    for (index, ((device, device_connection), metrics)) in device_configs.iter().zip(connections).zip(device_metrics).enumerate() {
+        let device_poller = if index == 0 {
+            poller.clone()
+        } else {
+            Poller::spawn(device_connection.clone(), poll_interval, Vec::new())
+        };
+        devices.push(Device {
+            id: device.id.clone().unwrap(),
+            path: device.path.clone().unwrap(),
+            slave_id: device.slave_id,
+            connection: device_connection,
+            poller: device_poller,
+            metrics,
+        });
+    }
+    let metrics_handler = MetricsHandler::new(devices.clone());
+    let aggregate_handler = AggregateHandler::new(devices.clone(), utc_offset);
+    let devices_handler = DevicesHandler::new(devices, utc_offset);
+    let stream_handler = StatusStreamHandler::new(status_stream, poller.clone());
+    let api_handler = ApiHandler::new(connection.clone(), poller, utc_offset);
+    let settings_handler = SettingsHandler::new(connection.clone(), api_token.clone());
+    let command_handler = CommandHandler::new(connection, api_token);
+    let history_handler = history.map(HistoryHandler::new);
+
+    info!("Starting server on {}:{} ...", config.server.bind_address, config.server.port);
+    let web_root = config.server.web_root.clone();
     actix_web::server::new(move || {
-        actix_web::App::new()
+        let mut app = actix_web::App::new()
             .handler("/api/v1/status", api_handler.clone())
+            .handler("/api/v1/stream", stream_handler.clone())
             .handler("/api/v1/logged", api_handler.clone())
-            .handler("/", actix_web::fs::StaticFiles::new("web").unwrap().index_file("index.html"))
+            .handler("/api/v1/devices", devices_handler.clone())
+            .handler("/api/v1/aggregate", aggregate_handler.clone())
+            .handler("/api/v1/settings", settings_handler.clone())
+            .handler("/api/v1/commands", command_handler.clone())
+            .handler("/metrics", metrics_handler.clone());
+        if let Some(history_handler) = &history_handler {
+            app = app.handler("/api/v1/history", history_handler.clone());
+        }
+        app.handler("/", actix_web::fs::StaticFiles::new(web_root.as_str()).unwrap().index_file("index.html"))
             .finish()
     })
-    .bind(bind_address)
+    .bind((config.server.bind_address.as_str(), config.server.port))
     .unwrap()
     .run();
 }
@@ -190,19 +1290,308 @@ mod test {
 
     use super::*;
 
+    use crate::recording::ReplayOptions;
+
     #[test]
     fn is_rtu_modbus_device_test() {
-        assert_eq!(is_rtu_modbus_device(Path::new("/dev/zero")), true); // TODO
-        assert_eq!(is_rtu_modbus_device(Path::new("/dev/urandom")), true); // TODO
-        assert_eq!(is_rtu_modbus_device(Path::new("/dev/tty")), true);
+        assert_eq!(is_rtu_modbus_device(Path::new("/dev/zero")).unwrap(), true); // TODO
+        assert_eq!(is_rtu_modbus_device(Path::new("/dev/urandom")).unwrap(), true); // TODO
+        assert_eq!(is_rtu_modbus_device(Path::new("/dev/tty")).unwrap(), true);
         let tty_usb = Path::new("/dev/ttyUSB0");
         if tty_usb.exists() {
-            assert_eq!(is_rtu_modbus_device(tty_usb), true);
+            assert_eq!(is_rtu_modbus_device(tty_usb).unwrap(), true);
         }
+        assert!(is_rtu_modbus_device(Path::new("/dev/does-not-exist")).is_err());
 
         let temp_dir = TempDir::new(concat!(module_path!(), "is_rtu_modbus_device_test")).unwrap();
         let test_file = temp_dir.path().join("test");
         OpenOptions::new().create(true).write(true).open(&test_file).unwrap();
-        assert_eq!(is_rtu_modbus_device(test_file.as_path()), false);
+        assert_eq!(is_rtu_modbus_device(test_file.as_path()).unwrap(), false);
+    }
+
+    #[test]
+    fn parse_utc_offset_test() {
+        assert_eq!(parse_utc_offset("Z"), Some(FixedOffset::east(0)));
+        assert_eq!(parse_utc_offset("+00:00"), Some(FixedOffset::east(0)));
+        assert_eq!(parse_utc_offset("+01:00"), Some(FixedOffset::east(3600)));
+        assert_eq!(parse_utc_offset("+05:30"), Some(FixedOffset::east(5 * 3600 + 30 * 60)));
+        assert_eq!(parse_utc_offset("-08"), Some(FixedOffset::west(8 * 3600)));
+        assert_eq!(parse_utc_offset("01:00"), None);
+        assert_eq!(parse_utc_offset("+24:00"), None);
+        assert_eq!(parse_utc_offset("+01:60"), None);
+        assert_eq!(parse_utc_offset("+one"), None);
+        assert_eq!(parse_utc_offset(""), None);
+    }
+
+    #[test]
+    fn parse_retention_days_test() {
+        assert_eq!(parse_retention_days("30"), Some(Some(chrono::Duration::days(30))));
+        assert_eq!(parse_retention_days("0"), Some(None));
+        assert_eq!(parse_retention_days("-1"), None);
+        assert_eq!(parse_retention_days("1.5"), None);
+    }
+
+    #[test]
+    fn is_poll_interval_test() {
+        assert!(is_poll_interval(String::from("5")).is_ok());
+        assert!(is_poll_interval(String::from("0")).is_err());
+        assert!(is_poll_interval(String::from("-1")).is_err());
+        assert!(is_poll_interval(String::from("1.5")).is_err());
+    }
+
+    #[test]
+    fn is_response_timeout_test() {
+        assert!(is_response_timeout(String::from("1000")).is_ok());
+        assert!(is_response_timeout(String::from("0")).is_err());
+        assert!(is_response_timeout(String::from("-1")).is_err());
+        assert!(is_milliseconds(String::from("0")).is_ok());
+    }
+
+    #[test]
+    fn parse_tcp_device_test() {
+        assert_eq!(parse_tcp_device("/dev/ttyUSB0"), None);
+        assert_eq!(parse_tcp_device("tcp://"), None);
+        assert_eq!(parse_tcp_device("tcp://:502"), None);
+        assert_eq!(parse_tcp_device("tcp://gateway:port"), None);
+        assert_eq!(parse_tcp_device("tcp://gateway"), Some((String::from("gateway"), 502)));
+        assert_eq!(parse_tcp_device("tcp://192.168.1.10:1502/"), Some((String::from("192.168.1.10"), 1502)));
+        assert_eq!(parse_tcp_device("tcp://[::1]"), Some((String::from("::1"), 502)));
+        assert_eq!(parse_tcp_device("tcp://[::1]:1502"), Some((String::from("::1"), 1502)));
+    }
+
+    #[test]
+    fn parse_host_port_test() {
+        assert_eq!(parse_host_port("localhost", 1883), Some((String::from("localhost"), 1883)));
+        assert_eq!(parse_host_port("broker.lan:8883", 1883), Some((String::from("broker.lan"), 8883)));
+        assert_eq!(parse_host_port("[::1]:1884", 1883), Some((String::from("::1"), 1884)));
+        assert_eq!(parse_host_port(":1883", 1883), None);
+    }
+
+    #[test]
+    fn parse_commands_test() {
+        assert_eq!(
+            parse_commands("load_disconnect, load_connect,load_disconnect"),
+            Ok(vec![Command::LoadDisconnect, Command::LoadConnect])
+        );
+        assert_eq!(parse_commands("equalize,"), Ok(vec![Command::Equalize]));
+        assert!(parse_commands("equalize,format").is_err());
+    }
+
+    #[test]
+    fn parse_tags_test() {
+        assert_eq!(
+            parse_tags("site=home, controller=1"),
+            Some(vec![
+                (String::from("site"), String::from("home")),
+                (String::from("controller"), String::from("1"))
+            ])
+        );
+        assert_eq!(parse_tags("site=my shed,"), Some(vec![(String::from("site"), String::from("my shed"))]));
+        assert_eq!(parse_tags("site"), None);
+        assert_eq!(parse_tags("site="), None);
+    }
+
+    #[test]
+    fn apply_matches_test() {
+        let mut config = Config::default();
+        config.device.path = Some(String::from("/dev/ttyUSB0"));
+        config.device.slave_id = 2;
+        config.server.port = 9000;
+        config.mqtt.topic = String::from("shed");
+
+        let matches = cli()
+            .get_matches_from_safe(vec![
+                "restful-sunsaver",
+                "--port",
+                "8000",
+                "--parity",
+                "E",
+                "--retry-attempts",
+                "5",
+                "--mqtt-commands",
+                "equalize, load_connect",
+                "--influxdb-tags",
+                "site=home",
+                "--record",
+                "/var/lib/sunsaver/recording.jsonl",
+                "--replay-loop",
+                "--replay-seek",
+                "12",
+            ])
+            .unwrap();
+        apply_matches(&mut config, &matches);
+
+        // Flags override the file, which overrides the defaults
+        assert_eq!(config.device.path, Some(String::from("/dev/ttyUSB0")));
+        assert_eq!(config.device.slave_id, 2);
+        assert_eq!(config.server.port, 8000);
+        assert_eq!(config.mqtt.topic, "shed");
+        assert_eq!(config.device.serial.parity, 'E');
+        assert_eq!(config.device.retry.attempts, 5);
+        assert_eq!(config.device.retry.wait_ms, 100);
+        assert_eq!(config.device.record, Some(String::from("/var/lib/sunsaver/recording.jsonl")));
+        assert_eq!(config.device.replay, ReplayOptions { repeat: true, seek: 12 });
+        assert_eq!(config.mqtt.commands, vec![String::from("equalize"), String::from("load_connect")]);
+        assert_eq!(config.influxdb.tags.get("site"), Some(&String::from("home")));
+
+        assert!(cli().get_matches_from_safe(vec!["restful-sunsaver", "--parity", "X"]).is_err());
+    }
+
+    #[test]
+    fn validate_config_test() {
+        let mut config = Config::default();
+        assert!(validate_config(&config).is_err());
+        config.device.path = Some(String::from("tcp://192.168.1.10"));
+        assert_eq!(validate_config(&config), Ok(()));
+
+        let mut invalid = config.clone();
+        invalid.device.slave_id = 0;
+        assert!(validate_config(&invalid).is_err());
+
+        let mut invalid = config.clone();
+        invalid.device.path = Some(String::from("/dev/does-not-exist"));
+        assert_eq!(validate_config(&invalid), Err(String::from("Device does not exist: /dev/does-not-exist")));
+        invalid.device.path = Some(String::from("/dev/null"));
+        assert_eq!(validate_config(&invalid), Ok(()));
+
+        let mut invalid = config.clone();
+        invalid.device.response_timeout_ms = 0;
+        assert!(validate_config(&invalid).is_err());
+
+        let mut invalid = config.clone();
+        invalid.mqtt.commands = vec![String::from("load_connect")];
+        assert_eq!(validate_config(&invalid), Err(String::from("MQTT commands need an MQTT broker")));
+        invalid.mqtt.broker = Some(String::from("localhost"));
+        assert_eq!(validate_config(&invalid), Ok(()));
+        invalid.mqtt.commands = vec![String::from("format")];
+        assert!(validate_config(&invalid).is_err());
+
+        let mut invalid = config.clone();
+        invalid.influxdb.url = Some(String::from("http://localhost:8086/write?db=sunsaver"));
+        invalid.influxdb.username = Some(String::from("sunsaver"));
+        invalid.influxdb.token = Some(String::from("s3cret"));
+        assert!(validate_config(&invalid).is_err());
+
+        let mut invalid = config.clone();
+        invalid.server.utc_offset = String::from("01:00");
+        assert!(validate_config(&invalid).is_err());
+    }
+
+    fn device_config(id: &str, path: &str, slave_id: u8) -> DeviceConfig {
+        DeviceConfig {
+            id: Some(String::from(id)),
+            path: Some(String::from(path)),
+            slave_id,
+            ..DeviceConfig::default()
+        }
+    }
+
+    #[test]
+    fn validate_devices_test() {
+        let mut config = Config::default();
+        config.devices = vec![
+            device_config("north", "tcp://192.168.1.10", 1),
+            device_config("south", "tcp://192.168.1.10:502", 2),
+            device_config("cabin", "tcp://192.168.2.10", 1),
+        ];
+        assert_eq!(validate_config(&config), Ok(()));
+
+        let mut invalid = config.clone();
+        invalid.device.path = Some(String::from("/dev/ttyUSB0"));
+        assert!(validate_config(&invalid).is_err());
+
+        let mut invalid = config.clone();
+        let matches = cli()
+            .get_matches_from_safe(vec!["restful-sunsaver", "--slave-id", "3", "--retry-wait", "500"])
+            .unwrap();
+        apply_matches(&mut invalid, &matches);
+        assert!(validate_config(&invalid).is_err());
+
+        let mut invalid = config.clone();
+        invalid.devices[2].id = Some(String::from("north"));
+        assert_eq!(validate_config(&invalid), Err(String::from("Device id \"north\" is used more than once")));
+
+        let mut invalid = config.clone();
+        invalid.devices[2].id = Some(String::from("north/south"));
+        assert!(validate_config(&invalid).is_err());
+
+        let mut invalid = config.clone();
+        invalid.devices[2].id = None;
+        assert_eq!(validate_config(&invalid), Err(String::from("Device 3 has no id")));
+
+        let mut invalid = config.clone();
+        invalid.devices[1].slave_id = 1;
+        assert_eq!(
+            validate_config(&invalid),
+            Err(String::from("Devices \"north\" and \"south\" share a bus and slave id"))
+        );
+
+        let mut invalid = config.clone();
+        invalid.devices[1].retry.attempts = 5;
+        assert!(validate_config(&invalid).is_err());
+
+        let mut invalid = config.clone();
+        invalid.devices[1].record = Some(String::from("south.jsonl"));
+        assert!(validate_config(&invalid).is_err());
+
+        let mut invalid = config.clone();
+        invalid.devices[1].path = None;
+        assert!(validate_config(&invalid).unwrap_err().starts_with("Device \"south\": "));
+    }
+
+    #[test]
+    fn bus_key_test() {
+        assert_eq!(bus_key("tcp://192.168.1.10"), "tcp://192.168.1.10:502");
+        assert_eq!(bus_key("tcp://192.168.1.10:502/"), "tcp://192.168.1.10:502");
+        assert_eq!(bus_key("/dev/null"), "/dev/null");
+        assert_eq!(bus_key("/dev/../dev/null"), "/dev/null");
+        assert_eq!(bus_key("/dev/does-not-exist"), "/dev/does-not-exist");
+    }
+
+    #[test]
+    fn simulate_cli_test() {
+        let matches = cli()
+            .get_matches_from_safe(vec!["restful-sunsaver", "simulate", "--pty", "--fault", "timeout@60+30,array:OVERCURENT"])
+            .unwrap();
+        let matches = matches.subcommand_matches(CLI_SUBCOMMAND_SIMULATE).unwrap();
+        assert!(matches.is_present(CLI_ARG_SIMULATE_PTY));
+        assert_eq!(matches.values_of(CLI_ARG_SIMULATE_FAULT).unwrap().count(), 2);
+
+        // Serves on at least one of TCP and a pseudo terminal
+        assert!(cli().get_matches_from_safe(vec!["restful-sunsaver", "simulate"]).is_err());
+        assert!(cli()
+            .get_matches_from_safe(vec![
+                "restful-sunsaver",
+                "simulate",
+                "--listen",
+                "127.0.0.1:5020",
+                "--link",
+                "/tmp/sunsaver"
+            ])
+            .is_err());
+        assert!(cli()
+            .get_matches_from_safe(vec!["restful-sunsaver", "simulate", "--pty", "--fault", "smoke"])
+            .is_err());
+        assert!(cli()
+            .get_matches_from_safe(vec!["restful-sunsaver", "simulate", "--pty", "--profile", "dusk"])
+            .is_err());
+    }
+
+    #[test]
+    fn load_profile_test() {
+        assert_eq!(load_profile("night"), Ok(Profile::named("night").unwrap()));
+
+        let temp_dir = TempDir::new(concat!(module_path!(), "load_profile_test")).unwrap();
+        let script = temp_dir.path().join("profile.toml");
+        fs::write(&script, "[[step]]\nseconds = 60\nirradiance = 0.5\n").unwrap();
+        assert_eq!(load_profile(script.to_str().unwrap()).unwrap().seconds(), 60);
+        fs::write(&script, "[[step]]\nseconds = 60\nirradiance = 1.5\n").unwrap();
+        assert_eq!(
+            load_profile(script.to_str().unwrap()),
+            Err(String::from("Step 1 irradiance must be from 0 to 1"))
+        );
+        let error = load_profile("does-not-exist.toml").unwrap_err();
+        assert!(error.starts_with("does-not-exist.toml: "));
+        assert!(error.ends_with(", the built in profiles are day, night, cycle"));
     }
 }
@@ -0,0 +1,471 @@
+use std::convert::TryFrom;
+use std::fmt::{Debug, Display, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+
+use crate::poller::Snapshot;
+use crate::sunsaver::{ChargeState, LoadState, ARRAY_FAULT_FLAGS};
+use crate::sunsaver_connection::SunSaverConnectionError;
+
+/// Content type of the Prometheus text exposition format
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+// Upper bounds in seconds of the Modbus read latency histogram buckets
+const LATENCY_BUCKETS: [f64; 9] = [0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0];
+
+/// Writes metrics in the Prometheus text exposition format.
+///
+/// Samples are grouped by metric, so several devices can be written one after another.
+pub struct MetricsWriter {
+    /// Added to every sample, e.g. `device="north"`
+    label: Option<String>,
+    families: Vec<Family>,
+}
+
+/// The header and samples of one metric
+struct Family {
+    name: String,
+    output: String,
+}
+
+impl MetricsWriter {
+    pub fn new() -> MetricsWriter {
+        MetricsWriter {
+            label: None,
+            families: Vec::new(),
+        }
+    }
+
+    /// Adds `label` to every later sample, in place of any set before
+    pub fn set_label(&mut self, label: &str, value: &str) {
+        self.label = Some(format!("{}=\"{}\"", label, value));
+    }
+
+    fn header(&mut self, name: &str, help: &str, kind: &str) {
+        if self.families.iter().any(|family| family.name == name) {
+            return;
+        }
+        let mut output = String::new();
+        writeln!(output, "# HELP {} {}", name, help).unwrap();
+        writeln!(output, "# TYPE {} {}", name, kind).unwrap();
+        self.families.push(Family {
+            name: String::from(name),
+            output,
+        });
+    }
+
+    /// Writes a sample of the metric `family`, named `name` with the `_bucket`, `_sum` or `_count` suffix of a histogram
+    fn sample<T: Display>(&mut self, family: &str, name: &str, label: Option<(&str, &str)>, value: T) {
+        let labels: Vec<String> = self
+            .label
+            .iter()
+            .cloned()
+            .chain(label.map(|(label, label_value)| format!("{}=\"{}\"", label, label_value)))
+            .collect();
+        let family = self.families.iter_mut().find(|other| other.name == family).unwrap();
+        if labels.is_empty() {
+            writeln!(family.output, "{} {}", name, value).unwrap();
+        } else {
+            writeln!(family.output, "{}{{{}}} {}", name, labels.join(","), value).unwrap();
+        }
+    }
+
+    pub fn gauge(&mut self, name: &str, help: &str, value: f32) {
+        self.header(name, help, "gauge");
+        self.sample(name, name, None, value);
+    }
+
+    pub fn counter(&mut self, name: &str, help: &str, value: u64) {
+        self.header(name, help, "counter");
+        self.sample(name, name, None, value);
+    }
+
+    /// A gauge with one sample per value of `label`
+    pub fn labelled_gauge(&mut self, name: &str, help: &str, label: &str, values: &[(String, f32)]) {
+        self.header(name, help, "gauge");
+        for (label_value, value) in values {
+            self.sample(name, name, Some((label, label_value)), value);
+        }
+    }
+
+    /// A counter with one sample per value of `label`
+    pub fn labelled_counter(&mut self, name: &str, help: &str, label: &str, values: &[(&str, u64)]) {
+        self.header(name, help, "counter");
+        for (label_value, value) in values {
+            self.sample(name, name, Some((label, label_value)), value);
+        }
+    }
+
+    /// `buckets` holds the upper bound and cumulative count of each bucket, the `+Inf` bucket is added from `count`
+    pub fn histogram(&mut self, name: &str, help: &str, buckets: &[(f64, u64)], count: u64, sum: f64) {
+        self.header(name, help, "histogram");
+        let bucket_name = format!("{}_bucket", name);
+        for (upper_bound, bucket_count) in buckets {
+            self.sample(name, &bucket_name, Some(("le", &upper_bound.to_string())), bucket_count);
+        }
+        self.sample(name, &bucket_name, Some(("le", "+Inf")), count);
+        self.sample(name, &format!("{}_sum", name), None, sum);
+        self.sample(name, &format!("{}_count", name), None, count);
+    }
+
+    pub fn finish(self) -> String {
+        self.families.into_iter().map(|family| family.output).collect()
+    }
+}
+
+/// Counters kept for one device's Modbus reads since the process started.
+pub struct ModbusMetrics {
+    reads: AtomicU64,
+    retries: AtomicU64,
+    /// Failures keyed by `SunSaverConnectionError::kind()`
+    failures: Vec<(&'static str, AtomicU64)>,
+    latency_buckets: Vec<AtomicU64>,
+    latency_count: AtomicU64,
+    latency_sum_micros: AtomicU64,
+}
+
+impl ModbusMetrics {
+    pub fn new() -> ModbusMetrics {
+        ModbusMetrics {
+            reads: AtomicU64::new(0),
+            retries: AtomicU64::new(0),
+            failures: SunSaverConnectionError::KINDS.iter().map(|kind| (*kind, AtomicU64::new(0))).collect(),
+            latency_buckets: LATENCY_BUCKETS.iter().map(|_| AtomicU64::new(0)).collect(),
+            latency_count: AtomicU64::new(0),
+            latency_sum_micros: AtomicU64::new(0),
+        }
+    }
+
+    /// Records one register read, `elapsed` covers every attempt including the waits between them
+    pub fn record_read(&self, attempts: u32, elapsed: Duration, error: Option<&SunSaverConnectionError>) {
+        self.reads.fetch_add(1, Ordering::Relaxed);
+        self.retries.fetch_add(u64::from(attempts.saturating_sub(1)), Ordering::Relaxed);
+        if let Some((_, failures)) = error.and_then(|error| self.failures.iter().find(|(kind, _)| *kind == error.kind())) {
+            failures.fetch_add(1, Ordering::Relaxed);
+        }
+
+        let seconds = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_micros()) / 1_000_000.0;
+        for (bucket, upper_bound) in self.latency_buckets.iter().zip(LATENCY_BUCKETS.iter()) {
+            if seconds <= *upper_bound {
+                bucket.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.latency_count.fetch_add(1, Ordering::Relaxed);
+        self.latency_sum_micros.fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    pub fn write(&self, writer: &mut MetricsWriter) {
+        writer.counter(
+            "sunsaver_modbus_reads_total",
+            "Modbus register reads, each may take several attempts",
+            self.reads.load(Ordering::Relaxed),
+        );
+        writer.counter(
+            "sunsaver_modbus_read_retries_total",
+            "Modbus register read attempts after the first",
+            self.retries.load(Ordering::Relaxed),
+        );
+        let failures: Vec<(&str, u64)> = self
+            .failures
+            .iter()
+            .map(|(kind, failures)| (*kind, failures.load(Ordering::Relaxed)))
+            .collect();
+        writer.labelled_counter(
+            "sunsaver_modbus_read_failures_total",
+            "Modbus register reads that failed after every attempt",
+            "error",
+            &failures,
+        );
+        let buckets: Vec<(f64, u64)> = LATENCY_BUCKETS
+            .iter()
+            .zip(self.latency_buckets.iter())
+            .map(|(upper_bound, bucket)| (*upper_bound, bucket.load(Ordering::Relaxed)))
+            .collect();
+        writer.histogram(
+            "sunsaver_modbus_read_duration_seconds",
+            "Time taken by Modbus register reads including retries",
+            &buckets,
+            self.latency_count.load(Ordering::Relaxed),
+            self.latency_sum_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0,
+        );
+    }
+}
+
+/// One sample per known state, set to 1 for the current one
+fn state_values<T: PartialEq + Debug>(states: Vec<T>, current: &T) -> Vec<(String, f32)> {
+    let mut values: Vec<(String, f32)> = states
+        .iter()
+        .map(|state| (format!("{:?}", state), if state == current { 1.0 } else { 0.0 }))
+        .collect();
+    if !states.contains(current) {
+        values.push((String::from("Unknown"), 1.0));
+    }
+    values
+}
+
+/// Writes a gauge for each value of the status in `snapshot` along with how old it is.
+pub fn write_snapshot(writer: &mut MetricsWriter, snapshot: &Snapshot, now: DateTime<Utc>) {
+    let status = &snapshot.status;
+    let battery_voltage = status.battery_voltage_filtered();
+    let battery_current = status.battery_charge_current_filtered();
+    let load_voltage = status.load_voltage_filtered();
+    let load_current = status.load_current_filtered();
+    let solar_voltage = status.solar_input_voltage_filtered();
+
+    writer.gauge(
+        "sunsaver_snapshot_age_seconds",
+        "Seconds since the status was read",
+        snapshot.age(now).num_milliseconds() as f32 / 1000.0,
+    );
+
+    writer.gauge("sunsaver_solar_input_voltage_volts", "Solar input voltage", solar_voltage);
+    writer.gauge(
+        "sunsaver_generation_power_watts",
+        "Solar power, calculated from the battery and load currents",
+        (load_current + battery_current) * solar_voltage,
+    );
+    writer.gauge("sunsaver_output_power_watts", "Output power", status.output_power());
+
+    writer.gauge("sunsaver_battery_voltage_volts", "Battery voltage", battery_voltage);
+    writer.gauge(
+        "sunsaver_battery_voltage_slow_volts",
+        "Battery voltage, slow filtered",
+        status.battery_voltage_slow_filtered(),
+    );
+    writer.gauge(
+        "sunsaver_battery_regulation_voltage_volts",
+        "Target battery voltage",
+        status.battery_regulation_voltage(),
+    );
+    writer.gauge("sunsaver_battery_charge_current_amps", "Battery charge current", battery_current);
+    writer.gauge(
+        "sunsaver_battery_charge_power_watts",
+        "Battery charge power",
+        battery_voltage * battery_current,
+    );
+    let charge_states: Vec<ChargeState> = (0u16..)
+        .map(ChargeState::try_from)
+        .take_while(Result::is_ok)
+        .map(Result::unwrap)
+        .collect();
+    writer.labelled_gauge(
+        "sunsaver_charge_state",
+        "Charge state, 1 for the current state",
+        "state",
+        &state_values(charge_states, &status.charge_state()),
+    );
+
+    writer.gauge("sunsaver_load_voltage_volts", "Load voltage", load_voltage);
+    writer.gauge("sunsaver_load_current_amps", "Load current", load_current);
+    writer.gauge("sunsaver_load_power_watts", "Load power", load_voltage * load_current);
+    writer.gauge(
+        "sunsaver_load_low_voltage_disconnect_volts",
+        "Battery voltage at which the load is disconnected",
+        status.load_low_voltage_disconnect(),
+    );
+    let load_states: Vec<LoadState> = (0u16..).map(LoadState::try_from).take_while(Result::is_ok).map(Result::unwrap).collect();
+    writer.labelled_gauge(
+        "sunsaver_load_state",
+        "Load state, 1 for the current state",
+        "state",
+        &state_values(load_states, &status.load_state()),
+    );
+
+    writer.labelled_gauge(
+        "sunsaver_temperature_celsius",
+        "Temperature of each sensor",
+        "sensor",
+        &[
+            (String::from("heatsink"), f32::from(status.heatsink_temperature())),
+            (String::from("battery"), f32::from(status.battery_temperature())),
+            (String::from("ambient"), f32::from(status.ambient_temperature())),
+            (String::from("remote"), f32::from(status.remote_temperature())),
+        ],
+    );
+
+    let array_fault = status.array_fault();
+    let array_faults: Vec<(String, f32)> = ARRAY_FAULT_FLAGS
+        .iter()
+        .map(|flag| (format!("{:?}", flag), if array_fault.contains(*flag) { 1.0 } else { 0.0 }))
+        .collect();
+    writer.labelled_gauge("sunsaver_array_fault", "Array faults, 1 while raised", "fault", &array_faults);
+
+    writer.gauge(
+        "sunsaver_battery_charge_resettable_amp_hours",
+        "Charge into the battery since the counter was reset",
+        status.battery_charge_resettable(),
+    );
+    writer.gauge(
+        "sunsaver_battery_charge_total_amp_hours",
+        "Charge into the battery over the controller's life",
+        status.battery_charge_total(),
+    );
+    writer.gauge(
+        "sunsaver_battery_charge_energy_total_kilowatt_hours",
+        "Energy into the battery over the controller's life",
+        status.battery_charge_energy_total(),
+    );
+    writer.gauge(
+        "sunsaver_load_charge_resettable_amp_hours",
+        "Charge drawn by the load since the counter was reset",
+        status.load_charge_resettable(),
+    );
+    writer.gauge(
+        "sunsaver_load_charge_total_amp_hours",
+        "Charge drawn by the load over the controller's life",
+        status.load_charge_total(),
+    );
+    writer.gauge(
+        "sunsaver_hourmeter_hours",
+        "Hours the controller has been powered",
+        status.hourmeter() as f32,
+    );
+
+    writer.gauge(
+        "sunsaver_sweep_maximum_power_voltage_volts",
+        "Array voltage at the maximum power point of the last sweep",
+        status.sweep_maximum_power_voltage(),
+    );
+    writer.gauge(
+        "sunsaver_sweep_maximum_power_watts",
+        "Array power at the maximum power point of the last sweep",
+        status.sweep_maximum_power(),
+    );
+    writer.gauge(
+        "sunsaver_sweep_open_circuit_voltage_volts",
+        "Array open circuit voltage of the last sweep",
+        status.sweep_open_circuit_voltage(),
+    );
+
+    writer.gauge(
+        "sunsaver_today_battery_voltage_min_volts",
+        "Minimum battery voltage today",
+        status.battery_voltage_min_daily(),
+    );
+    writer.gauge(
+        "sunsaver_today_battery_voltage_max_volts",
+        "Maximum battery voltage today",
+        status.battery_voltage_max_daily(),
+    );
+    writer.gauge(
+        "sunsaver_today_battery_charge_amp_hours",
+        "Charge into the battery today",
+        status.battery_charge_daily(),
+    );
+    writer.gauge(
+        "sunsaver_today_load_charge_amp_hours",
+        "Charge drawn by the load today",
+        status.load_charge_daily(),
+    );
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::sunsaver::SunSaverResponse;
+
+    #[test]
+    fn metricswriter_gauge() {
+        let mut writer = MetricsWriter::new();
+        writer.gauge("sunsaver_battery_voltage_volts", "Battery voltage", 14.0625);
+        writer.labelled_gauge(
+            "sunsaver_temperature_celsius",
+            "Temperature of each sensor",
+            "sensor",
+            &[(String::from("heatsink"), 25.0), (String::from("battery"), -3.0)],
+        );
+        assert_eq!(
+            writer.finish(),
+            "# HELP sunsaver_battery_voltage_volts Battery voltage\n\
+             # TYPE sunsaver_battery_voltage_volts gauge\n\
+             sunsaver_battery_voltage_volts 14.0625\n\
+             # HELP sunsaver_temperature_celsius Temperature of each sensor\n\
+             # TYPE sunsaver_temperature_celsius gauge\n\
+             sunsaver_temperature_celsius{sensor=\"heatsink\"} 25\n\
+             sunsaver_temperature_celsius{sensor=\"battery\"} -3\n"
+        );
+    }
+
+    #[test]
+    fn metricswriter_label() {
+        let mut writer = MetricsWriter::new();
+        writer.set_label("device", "north");
+        writer.gauge("sunsaver_up", "1 if the last poll of the device succeeded", 1.0);
+        writer.histogram("sunsaver_modbus_read_duration_seconds", "Time taken", &[(0.5, 1)], 1, 0.25);
+        writer.set_label("device", "south");
+        writer.gauge("sunsaver_up", "1 if the last poll of the device succeeded", 0.0);
+        writer.histogram("sunsaver_modbus_read_duration_seconds", "Time taken", &[(0.5, 0)], 0, 0.0);
+        // Each metric's samples stay together under one header
+        assert_eq!(
+            writer.finish(),
+            "# HELP sunsaver_up 1 if the last poll of the device succeeded\n\
+             # TYPE sunsaver_up gauge\n\
+             sunsaver_up{device=\"north\"} 1\n\
+             sunsaver_up{device=\"south\"} 0\n\
+             # HELP sunsaver_modbus_read_duration_seconds Time taken\n\
+             # TYPE sunsaver_modbus_read_duration_seconds histogram\n\
+             sunsaver_modbus_read_duration_seconds_bucket{device=\"north\",le=\"0.5\"} 1\n\
+             sunsaver_modbus_read_duration_seconds_bucket{device=\"north\",le=\"+Inf\"} 1\n\
+             sunsaver_modbus_read_duration_seconds_sum{device=\"north\"} 0.25\n\
+             sunsaver_modbus_read_duration_seconds_count{device=\"north\"} 1\n\
+             sunsaver_modbus_read_duration_seconds_bucket{device=\"south\",le=\"0.5\"} 0\n\
+             sunsaver_modbus_read_duration_seconds_bucket{device=\"south\",le=\"+Inf\"} 0\n\
+             sunsaver_modbus_read_duration_seconds_sum{device=\"south\"} 0\n\
+             sunsaver_modbus_read_duration_seconds_count{device=\"south\"} 0\n"
+        );
+    }
+
+    #[test]
+    fn modbusmetrics_record_read() {
+        let metrics = ModbusMetrics::new();
+        metrics.record_read(1, Duration::from_millis(20), None);
+        metrics.record_read(3, Duration::from_millis(300), Some(&SunSaverConnectionError::Timeout));
+
+        let mut writer = MetricsWriter::new();
+        metrics.write(&mut writer);
+        let output = writer.finish();
+
+        assert!(output.contains("\nsunsaver_modbus_reads_total 2\n"));
+        assert!(output.contains("\nsunsaver_modbus_read_retries_total 2\n"));
+        assert!(output.contains("\nsunsaver_modbus_read_failures_total{error=\"timeout\"} 1\n"));
+        assert!(output.contains("\nsunsaver_modbus_read_failures_total{error=\"crc\"} 0\n"));
+        assert!(output.contains("\nsunsaver_modbus_read_duration_seconds_bucket{le=\"0.01\"} 0\n"));
+        assert!(output.contains("\nsunsaver_modbus_read_duration_seconds_bucket{le=\"0.025\"} 1\n"));
+        assert!(output.contains("\nsunsaver_modbus_read_duration_seconds_bucket{le=\"0.5\"} 2\n"));
+        assert!(output.contains("\nsunsaver_modbus_read_duration_seconds_bucket{le=\"+Inf\"} 2\n"));
+        assert!(output.contains("\nsunsaver_modbus_read_duration_seconds_sum 0.32\n"));
+        assert!(output.contains("\nsunsaver_modbus_read_duration_seconds_count 2\n"));
+    }
+
+    #[test]
+    fn write_snapshot_states() {
+        let mut registers = [0u16; 44];
+        // charge_state Float
+        registers[9] = 7;
+        // array_fault OVERCURENT | ARRAY_HVD
+        registers[10] = 0b0001_0001;
+        // load_state undocumented
+        registers[18] = 42;
+        let snapshot = Snapshot {
+            status: SunSaverResponse::from_raw_bits(registers),
+            read_at: Utc::now(),
+        };
+
+        let mut writer = MetricsWriter::new();
+        write_snapshot(&mut writer, &snapshot, snapshot.read_at + chrono::Duration::milliseconds(1500));
+        let output = writer.finish();
+
+        assert!(output.contains("\nsunsaver_snapshot_age_seconds 1.5\n"));
+        assert!(output.contains("\nsunsaver_charge_state{state=\"Float\"} 1\n"));
+        assert!(output.contains("\nsunsaver_charge_state{state=\"Night\"} 0\n"));
+        assert!(!output.contains("sunsaver_charge_state{state=\"Unknown\"}"));
+        assert!(output.contains("\nsunsaver_load_state{state=\"Normal\"} 0\n"));
+        assert!(output.contains("\nsunsaver_load_state{state=\"Unknown\"} 1\n"));
+        assert!(output.contains("\nsunsaver_array_fault{fault=\"OVERCURENT\"} 1\n"));
+        assert!(output.contains("\nsunsaver_array_fault{fault=\"FETS_SHORTED\"} 0\n"));
+        assert!(output.contains("\nsunsaver_array_fault{fault=\"ARRAY_HVD\"} 1\n"));
+    }
+}
@@ -0,0 +1,567 @@
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::Duration;
+
+// Limits how long connecting to or writing to the broker can hold up the publishing thread
+const IO_TIMEOUT: Duration = Duration::from_secs(5);
+
+const CONNECT: u8 = 0x10;
+const CONNACK: u8 = 0x20;
+const PUBLISH: u8 = 0x30;
+const SUBSCRIBE: u8 = 0x82;
+const SUBACK: u8 = 0x90;
+const PINGREQ: u8 = 0xc0;
+
+#[derive(Debug)]
+pub enum MqttError {
+    Io(io::Error),
+    /// The broker refused the connection with this CONNACK return code
+    Refused(u8),
+    /// The broker sent something other than the expected packet
+    Protocol(String),
+}
+
+impl fmt::Display for MqttError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MqttError::Io(error) => write!(f, "MQTT connection failed: {}", error),
+            MqttError::Refused(code) => write!(f, "MQTT broker refused the connection with code {}", code),
+            MqttError::Protocol(reason) => write!(f, "MQTT protocol error: {}", reason),
+        }
+    }
+}
+
+impl Error for MqttError {}
+
+impl From<io::Error> for MqttError {
+    fn from(error: io::Error) -> Self {
+        MqttError::Io(error)
+    }
+}
+
+/// A message the broker publishes on our behalf if the connection is lost.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LastWill {
+    pub topic: String,
+    pub payload: String,
+    pub retain: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MqttOptions {
+    pub host: String,
+    pub port: u16,
+    pub client_id: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub keep_alive: Duration,
+    pub last_will: Option<LastWill>,
+}
+
+fn encode_remaining_length(mut length: usize, packet: &mut Vec<u8>) {
+    loop {
+        let mut byte = (length % 128) as u8;
+        length /= 128;
+        if length > 0 {
+            byte |= 0x80;
+        }
+        packet.push(byte);
+        if length == 0 {
+            break;
+        }
+    }
+}
+
+fn encode_string(value: &[u8], packet: &mut Vec<u8>) {
+    packet.extend_from_slice(&(value.len() as u16).to_be_bytes());
+    packet.extend_from_slice(value);
+}
+
+fn decode_string(body: &[u8]) -> Option<(String, &[u8])> {
+    if body.len() < 2 {
+        return None;
+    }
+    let length = usize::from(u16::from_be_bytes([body[0], body[1]]));
+    let value = body.get(2..2 + length)?;
+    Some((String::from_utf8(value.to_vec()).ok()?, &body[2 + length..]))
+}
+
+fn encode_packet(header: u8, body: &[u8]) -> Vec<u8> {
+    let mut packet = vec![header];
+    encode_remaining_length(body.len(), &mut packet);
+    packet.extend_from_slice(body);
+    packet
+}
+
+fn encode_connect(options: &MqttOptions) -> Vec<u8> {
+    let mut flags = 0x02; // clean session
+    if let Some(last_will) = &options.last_will {
+        flags |= 0x04;
+        if last_will.retain {
+            flags |= 0x20;
+        }
+    }
+    if options.username.is_some() {
+        flags |= 0x80;
+    }
+    if options.password.is_some() {
+        flags |= 0x40;
+    }
+
+    let mut body = Vec::new();
+    encode_string(b"MQTT", &mut body);
+    body.push(4); // protocol level 3.1.1
+    body.push(flags);
+    body.extend_from_slice(&(options.keep_alive.as_secs().min(u64::from(u16::max_value())) as u16).to_be_bytes());
+    encode_string(options.client_id.as_bytes(), &mut body);
+    if let Some(last_will) = &options.last_will {
+        encode_string(last_will.topic.as_bytes(), &mut body);
+        encode_string(last_will.payload.as_bytes(), &mut body);
+    }
+    if let Some(username) = &options.username {
+        encode_string(username.as_bytes(), &mut body);
+    }
+    if let Some(password) = &options.password {
+        encode_string(password.as_bytes(), &mut body);
+    }
+    encode_packet(CONNECT, &body)
+}
+
+fn encode_publish(topic: &str, payload: &[u8], retain: bool) -> Vec<u8> {
+    let mut body = Vec::new();
+    encode_string(topic.as_bytes(), &mut body);
+    body.extend_from_slice(payload);
+    encode_packet(PUBLISH | retain as u8, &body)
+}
+
+fn encode_subscribe(packet_id: u16, filter: &str) -> Vec<u8> {
+    let mut body = packet_id.to_be_bytes().to_vec();
+    encode_string(filter.as_bytes(), &mut body);
+    body.push(0); // QoS 0
+    encode_packet(SUBSCRIBE, &body)
+}
+
+/// Reads a packet, returning its first header byte and body
+fn read_packet(stream: &mut dyn Read) -> io::Result<(u8, Vec<u8>)> {
+    let mut byte = [0u8; 1];
+    stream.read_exact(&mut byte)?;
+    let header = byte[0];
+    let mut length = 0usize;
+    for shift in 0..4 {
+        stream.read_exact(&mut byte)?;
+        length |= usize::from(byte[0] & 0x7f) << (7 * shift);
+        if byte[0] & 0x80 == 0 {
+            let mut body = vec![0u8; length];
+            stream.read_exact(&mut body)?;
+            return Ok((header, body));
+        }
+    }
+    Err(io::Error::new(io::ErrorKind::InvalidData, "Remaining length is longer than four bytes"))
+}
+
+/// A message received for a subscription.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MqttMessage {
+    pub topic: String,
+    pub payload: Vec<u8>,
+    pub retain: bool,
+}
+
+fn decode_publish(header: u8, body: &[u8]) -> Option<MqttMessage> {
+    let (topic, rest) = decode_string(body)?;
+    // Messages above QoS 0 carry a packet identifier before the payload
+    let payload = if header & 0x06 != 0 { rest.get(2..).unwrap_or(&[]) } else { rest };
+    Some(MqttMessage {
+        topic,
+        payload: payload.to_vec(),
+        retain: header & 0x01 != 0,
+    })
+}
+
+fn write_packet(stream: &Mutex<TcpStream>, packet: &[u8]) -> io::Result<()> {
+    // Whole packets are written under the lock so handles on other threads cannot interleave them
+    let mut stream = stream.lock().unwrap_or_else(PoisonError::into_inner);
+    stream.write_all(packet)
+}
+
+/// Where the reader thread hands on what the broker sends
+#[derive(Default)]
+struct Subscription {
+    messages: Option<Sender<MqttMessage>>,
+    /// Body of the SUBACK being waited for
+    ack: Option<Sender<Vec<u8>>>,
+}
+
+/// A minimal MQTT 3.1.1 client publishing and subscribing at QoS 0, cloned handles share the connection.
+#[derive(Clone)]
+pub struct MqttClient {
+    stream: Arc<Mutex<TcpStream>>,
+    subscription: Arc<Mutex<Subscription>>,
+}
+
+impl MqttClient {
+    pub fn connect(options: &MqttOptions) -> Result<MqttClient, MqttError> {
+        let address = (options.host.as_str(), options.port)
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Broker address did not resolve"))?;
+        let mut stream = TcpStream::connect_timeout(&address, IO_TIMEOUT)?;
+        stream.set_read_timeout(Some(IO_TIMEOUT))?;
+        stream.set_write_timeout(Some(IO_TIMEOUT))?;
+
+        stream.write_all(&encode_connect(options))?;
+        match read_packet(&mut stream)? {
+            (CONNACK, ref body) if body.len() == 2 && body[1] == 0 => (),
+            (CONNACK, ref body) if body.len() == 2 => return Err(MqttError::Refused(body[1])),
+            (header, _) => return Err(MqttError::Protocol(format!("Expected CONNACK, got packet type {:#x}", header))),
+        }
+        let reader = stream.try_clone()?;
+        let client = MqttClient {
+            stream: Arc::new(Mutex::new(stream)),
+            subscription: Arc::new(Mutex::new(Subscription::default())),
+        };
+        client.spawn_reader(reader, options.keep_alive)?;
+        Ok(client)
+    }
+
+    /// Reads everything the broker sends from a dedicated thread until the connection closes.
+    /// A PINGREQ is sent whenever the broker has been quiet for half of `keep_alive`, so an idle connection is not dropped.
+    fn spawn_reader(&self, mut stream: TcpStream, keep_alive: Duration) -> io::Result<()> {
+        let ping_interval = keep_alive / 2;
+        stream.set_read_timeout(if ping_interval > Duration::from_secs(0) {
+            Some(ping_interval)
+        } else {
+            None
+        })?;
+        // Once every handle is dropped there is nothing to keep alive
+        let writer = Arc::downgrade(&self.stream);
+        let subscription = self.subscription.clone();
+        thread::Builder::new().name(String::from("mqtt-reader")).spawn(move || {
+            loop {
+                // Only the wait for the start of a packet times out, so a ping never splits one
+                match stream.peek(&mut [0u8; 1]) {
+                    Ok(0) => break,
+                    Ok(_) => (),
+                    Err(ref error) if error.kind() == io::ErrorKind::WouldBlock || error.kind() == io::ErrorKind::TimedOut => {
+                        match writer.upgrade().map(|writer| write_packet(&writer, &[PINGREQ, 0x00])) {
+                            Some(Ok(())) => continue,
+                            _ => break,
+                        }
+                    }
+                    Err(_) => break,
+                }
+                let (header, body) = match read_packet(&mut stream) {
+                    Ok(packet) => packet,
+                    Err(_) => break,
+                };
+                let mut subscription = subscription.lock().unwrap_or_else(PoisonError::into_inner);
+                match header & 0xf0 {
+                    PUBLISH => match (decode_publish(header, &body), &subscription.messages) {
+                        (Some(message), Some(messages)) => {
+                            // The reader may have been dropped
+                            let _ = messages.send(message);
+                        }
+                        (None, _) => warn!("MQTT: ignored a malformed PUBLISH"),
+                        (Some(_), None) => (),
+                    },
+                    SUBACK => {
+                        if let Some(ack) = subscription.ack.take() {
+                            let _ = ack.send(body);
+                        }
+                    }
+                    // PINGRESP
+                    _ => (),
+                }
+            }
+            debug!("MQTT: connection closed");
+            // Ends the subscription's reader
+            *subscription.lock().unwrap_or_else(PoisonError::into_inner) = Subscription::default();
+        })?;
+        Ok(())
+    }
+
+    fn write(&self, packet: &[u8]) -> Result<(), MqttError> {
+        write_packet(&self.stream, packet)?;
+        Ok(())
+    }
+
+    pub fn publish(&self, topic: &str, payload: &[u8], retain: bool) -> Result<(), MqttError> {
+        self.write(&encode_publish(topic, payload, retain))
+    }
+
+    /// Subscribes to `filter`, returning the reader the messages arrive on.
+    /// Only one reader may exist per connection.
+    pub fn subscribe(&self, filter: &str) -> Result<MqttReader, MqttError> {
+        let (messages, receiver) = mpsc::channel();
+        let (ack, acks) = mpsc::channel();
+        *self.subscription.lock().unwrap_or_else(PoisonError::into_inner) = Subscription {
+            messages: Some(messages),
+            ack: Some(ack),
+        };
+        self.write(&encode_subscribe(1, filter))?;
+        match acks.recv_timeout(IO_TIMEOUT) {
+            Ok(ref body) if body.len() == 3 && body[2] != 0x80 => Ok(MqttReader { messages: receiver }),
+            Ok(_) => Err(MqttError::Protocol(format!("Subscription to {:?} was refused", filter))),
+            Err(_) => Err(MqttError::Protocol(format!("No SUBACK for the subscription to {:?}", filter))),
+        }
+    }
+
+    /// Closes the connection, ending any reader
+    pub fn shutdown(&self) {
+        let stream = self.stream.lock().unwrap_or_else(PoisonError::into_inner);
+        // The connection may already be gone
+        let _ = stream.shutdown(Shutdown::Both);
+    }
+}
+
+/// Receives the messages for a client's subscription.
+pub struct MqttReader {
+    messages: Receiver<MqttMessage>,
+}
+
+impl MqttReader {
+    /// Blocks until the next message arrives, failing once the connection is closed
+    pub fn next_message(&mut self) -> Result<MqttMessage, MqttError> {
+        self.messages
+            .recv()
+            .map_err(|_| MqttError::Io(io::Error::new(io::ErrorKind::ConnectionAborted, "The connection was closed")))
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Instant;
+
+    use super::*;
+
+    /// Messages received by the stand-in broker as (topic, payload, retain)
+    pub type Published = Arc<Mutex<Vec<(String, String, bool)>>>;
+
+    /// A broker accepting one client at a time
+    pub struct StandIn {
+        pub port: u16,
+        /// Body of the latest client's CONNECT packet
+        pub connect: Arc<Mutex<Vec<u8>>>,
+        pub published: Published,
+        pub subscriptions: Arc<Mutex<Vec<String>>>,
+        /// PINGREQs received
+        pub pings: Arc<Mutex<u32>>,
+        client: Arc<Mutex<Option<TcpStream>>>,
+    }
+
+    impl StandIn {
+        /// Drops the connected client, as a broker restart would
+        pub fn disconnect(&self) {
+            if let Some(client) = self.client.lock().unwrap().take() {
+                client.shutdown(Shutdown::Both).unwrap();
+            }
+        }
+    }
+
+    /// Accepts clients, recording everything they send, and once one subscribes publishes `messages` to it
+    pub fn spawn_mqtt_stand_in(return_code: u8, messages: Vec<(String, String, bool)>) -> StandIn {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let stand_in = StandIn {
+            port: listener.local_addr().unwrap().port(),
+            connect: Arc::new(Mutex::new(Vec::new())),
+            published: Arc::new(Mutex::new(Vec::new())),
+            subscriptions: Arc::new(Mutex::new(Vec::new())),
+            pings: Arc::new(Mutex::new(0)),
+            client: Arc::new(Mutex::new(None)),
+        };
+        let (connect, published, subscriptions) = (stand_in.connect.clone(), stand_in.published.clone(), stand_in.subscriptions.clone());
+        let (pings, client) = (stand_in.pings.clone(), stand_in.client.clone());
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                *client.lock().unwrap() = Some(stream.try_clone().unwrap());
+                let (header, body) = read_packet(&mut stream).unwrap();
+                assert_eq!(header, CONNECT);
+                *connect.lock().unwrap() = body;
+                stream.write_all(&[CONNACK, 0x02, 0x00, return_code]).unwrap();
+                while let Ok((header, body)) = read_packet(&mut stream) {
+                    match header & 0xf0 {
+                        PUBLISH => {
+                            let (topic, payload) = decode_string(&body).unwrap();
+                            let message = (topic, String::from_utf8(payload.to_vec()).unwrap(), header & 0x01 != 0);
+                            published.lock().unwrap().push(message);
+                        }
+                        _ if header == SUBSCRIBE => {
+                            let (filter, _) = decode_string(&body[2..]).unwrap();
+                            subscriptions.lock().unwrap().push(filter);
+                            stream.write_all(&[SUBACK, 0x03, body[0], body[1], 0x00]).unwrap();
+                            for (topic, payload, retain) in messages.iter() {
+                                stream.write_all(&encode_publish(topic, payload.as_bytes(), *retain)).unwrap();
+                            }
+                        }
+                        PINGREQ => {
+                            *pings.lock().unwrap() += 1;
+                            stream.write_all(&[0xd0, 0x00]).unwrap();
+                        }
+                        _ => (),
+                    }
+                }
+            }
+        });
+        stand_in
+    }
+
+    pub fn test_options(port: u16) -> MqttOptions {
+        MqttOptions {
+            host: String::from("127.0.0.1"),
+            port,
+            client_id: String::from("restful-sunsaver"),
+            username: None,
+            password: None,
+            keep_alive: Duration::from_secs(30),
+            last_will: None,
+        }
+    }
+
+    /// Waits for the stand-in broker to have received `count` messages
+    pub fn wait_for_published(published: &Published, count: usize) -> Vec<(String, String, bool)> {
+        for _ in 0..100 {
+            if published.lock().unwrap().len() >= count {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        published.lock().unwrap().clone()
+    }
+
+    #[test]
+    fn encode_remaining_length_test() {
+        let lengths = [
+            (0, vec![0x00]),
+            (127, vec![0x7f]),
+            (128, vec![0x80, 0x01]),
+            (16_383, vec![0xff, 0x7f]),
+            (16_384, vec![0x80, 0x80, 0x01]),
+        ];
+        for (length, expected) in lengths.iter() {
+            let packet = encode_packet(PUBLISH, &vec![0u8; *length]);
+            assert_eq!(&packet[1..=expected.len()], &expected[..]);
+            assert_eq!(read_packet(&mut packet.as_slice()).unwrap(), (PUBLISH, vec![0u8; *length]));
+        }
+    }
+
+    #[test]
+    fn encode_connect_test() {
+        let options = MqttOptions {
+            username: Some(String::from("user")),
+            password: Some(String::from("pass")),
+            last_will: Some(LastWill {
+                topic: String::from("sunsaver/availability"),
+                payload: String::from("offline"),
+                retain: true,
+            }),
+            ..test_options(1883)
+        };
+        let packet = encode_connect(&options);
+        assert_eq!(packet[0], CONNECT);
+        assert_eq!(usize::from(packet[1]), packet.len() - 2);
+        // Protocol name and level
+        assert_eq!(&packet[2..9], b"\x00\x04MQTT\x04");
+        // Username, password, retained will and clean session
+        assert_eq!(packet[9], 0b1110_0110);
+        // Keep alive
+        assert_eq!(&packet[10..12], &[0, 30]);
+
+        let (client_id, rest) = decode_string(&packet[12..]).unwrap();
+        let (will_topic, rest) = decode_string(rest).unwrap();
+        let (will_payload, rest) = decode_string(rest).unwrap();
+        let (username, rest) = decode_string(rest).unwrap();
+        let (password, rest) = decode_string(rest).unwrap();
+        assert_eq!(
+            (
+                client_id.as_str(),
+                will_topic.as_str(),
+                will_payload.as_str(),
+                username.as_str(),
+                password.as_str()
+            ),
+            ("restful-sunsaver", "sunsaver/availability", "offline", "user", "pass")
+        );
+        assert!(rest.is_empty());
+    }
+
+    #[test]
+    fn mqttclient_publish() {
+        let stand_in = spawn_mqtt_stand_in(0, Vec::new());
+        let client = MqttClient::connect(&test_options(stand_in.port)).unwrap();
+        client.publish("sunsaver/state", b"{}", false).unwrap();
+        client.publish("sunsaver/availability", b"online", true).unwrap();
+
+        assert_eq!(
+            wait_for_published(&stand_in.published, 2),
+            vec![
+                (String::from("sunsaver/state"), String::from("{}"), false),
+                (String::from("sunsaver/availability"), String::from("online"), true),
+            ]
+        );
+    }
+
+    #[test]
+    fn mqttclient_ping() {
+        let stand_in = spawn_mqtt_stand_in(0, Vec::new());
+        let options = MqttOptions {
+            keep_alive: Duration::from_secs(1),
+            ..test_options(stand_in.port)
+        };
+        let _client = MqttClient::connect(&options).unwrap();
+
+        // An idle connection pings every half keep alive
+        let started = Instant::now();
+        while *stand_in.pings.lock().unwrap() < 2 && started.elapsed() < Duration::from_secs(5) {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(*stand_in.pings.lock().unwrap() >= 2);
+        assert!(stand_in.published.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn mqttclient_connect_refused() {
+        let stand_in = spawn_mqtt_stand_in(5, Vec::new());
+        match MqttClient::connect(&test_options(stand_in.port)) {
+            Err(MqttError::Refused(5)) => (),
+            Err(error) => panic!("Unexpected error {}", error),
+            Ok(_) => panic!("Connection was not refused"),
+        }
+    }
+
+    #[test]
+    fn mqttclient_subscribe() {
+        let stand_in = spawn_mqtt_stand_in(
+            0,
+            vec![
+                (String::from("sunsaver/command/equalize"), String::from("PRESS"), false),
+                (String::from("sunsaver/command/reset"), String::new(), true),
+            ],
+        );
+        let client = MqttClient::connect(&test_options(stand_in.port)).unwrap();
+        let mut reader = client.subscribe("sunsaver/command/+").unwrap();
+
+        assert_eq!(*stand_in.subscriptions.lock().unwrap(), vec![String::from("sunsaver/command/+")]);
+        assert_eq!(
+            reader.next_message().unwrap(),
+            MqttMessage {
+                topic: String::from("sunsaver/command/equalize"),
+                payload: b"PRESS".to_vec(),
+                retain: false,
+            }
+        );
+        assert_eq!(reader.next_message().unwrap().retain, true);
+
+        // Shutting the client down ends the reader
+        client.shutdown();
+        assert!(reader.next_message().is_err());
+    }
+}
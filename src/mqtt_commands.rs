@@ -0,0 +1,175 @@
+use std::thread;
+use std::time::Duration;
+
+use serde_json;
+
+use crate::api::{ApiCommandResponse, ApiErrorResponse};
+use crate::mqtt::{MqttClient, MqttError, MqttOptions};
+use crate::sunsaver::Command;
+use crate::{lock_connection, SharedConnection};
+
+/// Runs the controller commands published to `<topic>/command/<command>`, acknowledging each on
+/// `<topic>/command/<command>/result` with the same document as `POST /api/v1/commands/<command>`.
+#[derive(Clone)]
+pub struct MqttCommands {
+    connection: SharedConnection,
+    allowed: Vec<Command>,
+}
+
+impl MqttCommands {
+    pub fn new(connection: SharedConnection, allowed: Vec<Command>) -> MqttCommands {
+        MqttCommands { connection, allowed }
+    }
+
+    pub fn filter(topic: &str) -> String {
+        format!("{}/command/+", topic)
+    }
+
+    fn execute(&self, name: &str) -> String {
+        let command = match name.parse::<Command>() {
+            Ok(command) => command,
+            Err(message) => return serde_json::to_string(&ApiErrorResponse::new("unknown_command", message)).unwrap(),
+        };
+        if !self.allowed.contains(&command) {
+            warn!("MQTT: rejected {:?} as it is not allowed", command);
+            let message = format!("{} is not in the MQTT command allow-list", name);
+            return serde_json::to_string(&ApiErrorResponse::new("forbidden", message)).unwrap();
+        }
+        info!("MQTT: executing {:?}", command);
+        match lock_connection(&self.connection).execute_command(command) {
+            Ok(()) => serde_json::to_string(&ApiCommandResponse::from(command)).unwrap(),
+            Err(error) => {
+                error!("MQTT: {:?} failed: {}", command, error);
+                serde_json::to_string(&ApiErrorResponse::from(&error)).unwrap()
+            }
+        }
+    }
+
+    /// Runs the commands published under `topic` from a dedicated thread. The subscription has a connection of its own,
+    /// so commands keep working while polls fail, and reconnects `reconnect_delay` after it is lost.
+    pub fn spawn(&self, options: MqttOptions, topic: String, reconnect_delay: Duration) {
+        let commands = self.clone();
+        thread::Builder::new()
+            .name(String::from("mqtt-commands"))
+            .spawn(move || loop {
+                if let Err(error) = commands.serve(&options, &topic) {
+                    warn!("MQTT: command subscription ended: {}", error);
+                }
+                thread::sleep(reconnect_delay);
+            })
+            .unwrap();
+    }
+
+    /// Handles the commands arriving on one connection until it closes
+    fn serve(&self, options: &MqttOptions, topic: &str) -> Result<(), MqttError> {
+        let client = MqttClient::connect(options)?;
+        let result = client.subscribe(&MqttCommands::filter(topic)).and_then(|mut reader| {
+            info!("MQTT: subscribed to commands under {:?}", topic);
+            loop {
+                let message = reader.next_message()?;
+                // A retained command would otherwise run again on every reconnect
+                if message.retain {
+                    warn!("MQTT: ignored retained command on {:?}", message.topic);
+                    continue;
+                }
+                let name = message.topic.rsplit('/').next().unwrap_or("");
+                let result = self.execute(name);
+                client.publish(&format!("{}/result", message.topic), result.as_bytes(), false)?;
+            }
+        });
+        client.shutdown();
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    use crate::mqtt::test::{spawn_mqtt_stand_in, test_options, wait_for_published};
+    use crate::sunsaver::Coil;
+    use crate::sunsaver_connection::{SunSaverConnection, SunSaverConnectionError};
+
+    type CoilWrites = Arc<Mutex<Vec<(Coil, bool)>>>;
+
+    struct CoilConnection {
+        coil_writes: CoilWrites,
+    }
+
+    impl SunSaverConnection for CoilConnection {
+        fn read_raw_registers(&mut self) -> Result<[u16; 44], SunSaverConnectionError> {
+            Err(SunSaverConnectionError::Unsupported("reading registers"))
+        }
+
+        fn read_raw_logged(&mut self) -> Result<[u16; 32 * 16], SunSaverConnectionError> {
+            Err(SunSaverConnectionError::Unsupported("reading logged data"))
+        }
+
+        fn write_coil(&mut self, coil: Coil, value: bool) -> Result<(), SunSaverConnectionError> {
+            self.coil_writes.lock().unwrap().push((coil, value));
+            Ok(())
+        }
+    }
+
+    fn test_commands(allowed: Vec<Command>) -> (MqttCommands, CoilWrites) {
+        let coil_writes = Arc::new(Mutex::new(Vec::new()));
+        let connection: Box<dyn SunSaverConnection> = Box::new(CoilConnection {
+            coil_writes: coil_writes.clone(),
+        });
+        (MqttCommands::new(Arc::new(Mutex::new(connection)), allowed), coil_writes)
+    }
+
+    #[test]
+    fn mqttcommands_execute() {
+        let (commands, coil_writes) = test_commands(vec![Command::LoadDisconnect, Command::LoadConnect]);
+
+        assert_eq!(commands.execute("load_disconnect"), "{\"command\":\"load_disconnect\"}");
+        assert_eq!(
+            commands.execute("reset"),
+            "{\"error\":{\"kind\":\"forbidden\",\"message\":\"reset is not in the MQTT command allow-list\"}}"
+        );
+        assert_eq!(
+            commands.execute("format"),
+            "{\"error\":{\"kind\":\"unknown_command\",\"message\":\"Unknown command \\\"format\\\"\"}}"
+        );
+        assert_eq!(*coil_writes.lock().unwrap(), vec![(Coil::LoadDisconnect, true)]);
+    }
+
+    #[test]
+    fn mqttcommands_spawn() {
+        let stand_in = spawn_mqtt_stand_in(
+            0,
+            vec![
+                (String::from("sunsaver/command/load_disconnect"), String::from("PRESS"), true),
+                (String::from("sunsaver/command/load_connect"), String::from("PRESS"), false),
+            ],
+        );
+        let (commands, coil_writes) = test_commands(vec![Command::LoadConnect]);
+        commands.spawn(test_options(stand_in.port), String::from("sunsaver"), Duration::from_millis(10));
+
+        // The retained command is ignored
+        assert_eq!(
+            wait_for_published(&stand_in.published, 1),
+            vec![(
+                String::from("sunsaver/command/load_connect/result"),
+                String::from("{\"command\":\"load_connect\"}"),
+                false
+            )]
+        );
+        assert_eq!(*coil_writes.lock().unwrap(), vec![(Coil::LoadDisconnect, false)]);
+
+        // The subscription comes back by itself after the connection drops
+        stand_in.disconnect();
+        assert_eq!(wait_for_published(&stand_in.published, 2).len(), 2);
+        assert_eq!(
+            *stand_in.subscriptions.lock().unwrap(),
+            vec![String::from("sunsaver/command/+"), String::from("sunsaver/command/+")]
+        );
+        assert_eq!(
+            *coil_writes.lock().unwrap(),
+            vec![(Coil::LoadDisconnect, false), (Coil::LoadDisconnect, false)]
+        );
+    }
+}
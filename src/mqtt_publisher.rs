@@ -0,0 +1,516 @@
+use std::collections::HashSet;
+
+use serde_json::{self, Value};
+
+use crate::api::ApiStatusResponse;
+use crate::mqtt::{LastWill, MqttClient, MqttError, MqttOptions};
+use crate::poller::{PollListener, Snapshot};
+use crate::sunsaver::Command;
+use crate::sunsaver_connection::SunSaverConnectionError;
+
+const ONLINE: &str = "online";
+const OFFLINE: &str = "offline";
+
+/// A single value of `ApiStatusResponse`, addressed by its path through the JSON document.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sensor {
+    pub path: Vec<String>,
+    pub value: Value,
+}
+
+impl Sensor {
+    pub fn id(&self) -> String {
+        self.path.join("_")
+    }
+
+    fn payload(&self) -> String {
+        match &self.value {
+            Value::String(value) => value.clone(),
+            value => value.to_string(),
+        }
+    }
+
+    fn is_binary(&self) -> bool {
+        self.value.is_boolean()
+    }
+
+    /// Home Assistant unit and device class, looked up by the value's path
+    fn unit(&self) -> (Option<&'static str>, Option<&'static str>) {
+        let path = self.path.join("/");
+        if self.is_binary() {
+            let parent = self.path[..self.path.len() - 1].join("/");
+            let is_problem = PROBLEM_PATHS
+                .iter()
+                .any(|problems| parent == *problems || parent.starts_with(&format!("{}/", problems)));
+            (None, if is_problem { Some("problem") } else { None })
+        } else {
+            match UNITS.iter().find(|(unit_path, _, _)| *unit_path == path) {
+                Some((_, unit, device_class)) => (Some(*unit), *device_class),
+                None => (None, None),
+            }
+        }
+    }
+}
+
+// Binary values under these paths are raised faults or alarms, the rest such as the DIP switches are plain states
+const PROBLEM_PATHS: [&str; 5] = ["alarms", "faults", "today/alarms", "today/array_faults", "today/load_faults"];
+
+// Unit and device class of each numeric value of `/api/v1/status`, values not listed have neither
+const UNITS: [(&str, &str, Option<&str>); 30] = [
+    ("generation/solar_input_voltage_filtered", "V", Some("voltage")),
+    ("generation/calculated_generation_power", "W", Some("power")),
+    ("generation/output_power", "W", Some("power")),
+    ("storage/battery_voltage_filtered", "V", Some("voltage")),
+    ("storage/battery_voltage_slow_filtered", "V", Some("voltage")),
+    ("storage/battery_regulation_voltage", "V", Some("voltage")),
+    ("storage/battery_charge_current_filtered", "A", Some("current")),
+    ("storage/battery_charge_power_calculated", "W", Some("power")),
+    ("load/load_voltage_filtered", "V", Some("voltage")),
+    ("load/load_current_filtered", "A", Some("current")),
+    ("load/load_power_calculated", "W", Some("power")),
+    ("load/low_voltage_disconnect", "V", Some("voltage")),
+    ("temperature/heatsink_temperature", "°C", Some("temperature")),
+    ("temperature/battery_temperature", "°C", Some("temperature")),
+    ("temperature/ambient_temperature", "°C", Some("temperature")),
+    ("temperature/remote_temperature", "°C", Some("temperature")),
+    ("counters/battery_charge_resettable", "Ah", None),
+    ("counters/battery_charge_total", "Ah", None),
+    ("counters/battery_charge_energy_total", "kWh", Some("energy")),
+    ("counters/load_charge_resettable", "Ah", None),
+    ("counters/load_charge_total", "Ah", None),
+    ("counters/hourmeter", "h", None),
+    ("mppt_sweep/maximum_power_voltage", "V", Some("voltage")),
+    ("mppt_sweep/maximum_power", "W", Some("power")),
+    ("mppt_sweep/open_circuit_voltage", "V", Some("voltage")),
+    ("today/battery_voltage_min", "V", Some("voltage")),
+    ("today/battery_voltage_max", "V", Some("voltage")),
+    ("today/battery_charge", "Ah", None),
+    ("today/load_charge", "Ah", None),
+    ("snapshot/age", "s", None),
+];
+
+fn flatten(value: &Value, path: &mut Vec<String>, sensors: &mut Vec<Sensor>) {
+    match value {
+        // An undocumented enum value would otherwise appear as a sensor of its own
+        Value::Object(map) if map.len() == 1 && map.contains_key("Unknown") => sensors.push(Sensor {
+            path: path.clone(),
+            value: Value::String(format!("Unknown({})", map["Unknown"])),
+        }),
+        Value::Object(map) => {
+            for (key, value) in map {
+                path.push(key.clone());
+                flatten(value, path, sensors);
+                path.pop();
+            }
+        }
+        Value::Array(values) => {
+            for (i, value) in values.iter().enumerate() {
+                path.push(i.to_string());
+                flatten(value, path, sensors);
+                path.pop();
+            }
+        }
+        Value::Null => (),
+        value => sensors.push(Sensor {
+            path: path.clone(),
+            value: value.clone(),
+        }),
+    }
+}
+
+pub fn sensors(state: &Value) -> Vec<Sensor> {
+    let mut sensors = Vec::new();
+    flatten(state, &mut Vec::new(), &mut sensors);
+    sensors
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct DiscoveryConfig {
+    name: String,
+    unique_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    state_topic: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    command_topic: Option<String>,
+    availability_topic: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    unit_of_measurement: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    device_class: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    payload_on: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    payload_off: Option<&'static str>,
+    device: DiscoveryDevice,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct DiscoveryDevice {
+    identifiers: Vec<String>,
+    name: &'static str,
+    manufacturer: &'static str,
+    model: &'static str,
+}
+
+/// Publishes every polled status to an MQTT broker, along with Home Assistant discovery configs for each value.
+pub struct MqttPublisher {
+    options: MqttOptions,
+    topic: String,
+    discovery_prefix: String,
+    client: Option<MqttClient>,
+    /// Whether `online` is the availability last published on the current connection
+    online: bool,
+    discovered: HashSet<String>,
+    /// Commands discovered as buttons, `MqttCommands` runs them
+    commands: Vec<Command>,
+}
+
+impl MqttPublisher {
+    pub fn new(mut options: MqttOptions, topic: String, discovery_prefix: String) -> MqttPublisher {
+        // The broker marks us offline when the connection drops, `on_error` does when a poll fails
+        options.last_will = Some(LastWill {
+            topic: format!("{}/availability", topic),
+            payload: String::from(OFFLINE),
+            retain: true,
+        });
+        MqttPublisher {
+            options,
+            topic,
+            discovery_prefix,
+            client: None,
+            online: false,
+            discovered: HashSet::new(),
+            commands: Vec::new(),
+        }
+    }
+
+    /// Also discovers a button for each of the allowed commands
+    pub fn with_commands(mut self, commands: &[Command]) -> MqttPublisher {
+        self.commands = commands.to_vec();
+        self
+    }
+
+    fn availability_topic(&self) -> String {
+        format!("{}/availability", self.topic)
+    }
+
+    fn node_id(&self) -> String {
+        self.topic.replace('/', "_")
+    }
+
+    fn device(&self) -> DiscoveryDevice {
+        DiscoveryDevice {
+            identifiers: vec![self.node_id()],
+            name: "SunSaver MPPT",
+            manufacturer: "Morningstar",
+            model: "SunSaver MPPT",
+        }
+    }
+
+    fn discovery(&self, sensor: &Sensor) -> (String, String) {
+        let component = if sensor.is_binary() { "binary_sensor" } else { "sensor" };
+        let (unit_of_measurement, device_class) = sensor.unit();
+        let (payload_on, payload_off) = if sensor.is_binary() {
+            (Some("true"), Some("false"))
+        } else {
+            (None, None)
+        };
+        let config = DiscoveryConfig {
+            name: format!("SunSaver {}", sensor.path.join(" ").replace('_', " ")),
+            unique_id: format!("{}_{}", self.node_id(), sensor.id()),
+            state_topic: Some(format!("{}/{}", self.topic, sensor.path.join("/"))),
+            command_topic: None,
+            availability_topic: self.availability_topic(),
+            unit_of_measurement,
+            device_class,
+            payload_on,
+            payload_off,
+            device: self.device(),
+        };
+        let topic = format!("{}/{}/{}/{}/config", self.discovery_prefix, component, self.node_id(), sensor.id());
+        (topic, serde_json::to_string(&config).unwrap())
+    }
+
+    fn command_discovery(&self, command: Command) -> (String, String) {
+        let config = DiscoveryConfig {
+            name: format!("SunSaver {}", command.name().replace('_', " ")),
+            unique_id: format!("{}_command_{}", self.node_id(), command.name()),
+            state_topic: None,
+            command_topic: Some(format!("{}/command/{}", self.topic, command.name())),
+            availability_topic: self.availability_topic(),
+            unit_of_measurement: None,
+            device_class: None,
+            payload_on: None,
+            payload_off: None,
+            device: self.device(),
+        };
+        let topic = format!("{}/button/{}/command_{}/config", self.discovery_prefix, self.node_id(), command.name());
+        (topic, serde_json::to_string(&config).unwrap())
+    }
+
+    fn ensure_connected(&mut self) -> Result<(), MqttError> {
+        if self.client.is_none() {
+            let client = MqttClient::connect(&self.options)?;
+            client.publish(&self.availability_topic(), ONLINE.as_bytes(), true)?;
+            info!("MQTT: connected to {}:{}", self.options.host, self.options.port);
+            for command in self.commands.iter() {
+                let (topic, config) = self.command_discovery(*command);
+                client.publish(&topic, config.as_bytes(), true)?;
+            }
+            // The broker may have restarted without keeping retained configs
+            self.discovered.clear();
+            self.client = Some(client);
+            self.online = true;
+        }
+        if let (Some(client), false) = (&self.client, self.online) {
+            client.publish(&self.availability_topic(), ONLINE.as_bytes(), true)?;
+            self.online = true;
+        }
+        Ok(())
+    }
+
+    fn publish_offline(&mut self) -> Result<(), MqttError> {
+        if let Some(client) = &self.client {
+            if self.online {
+                client.publish(&self.availability_topic(), OFFLINE.as_bytes(), true)?;
+                self.online = false;
+            }
+        }
+        Ok(())
+    }
+
+    // Reconnect on the next poll
+    fn disconnect(&mut self) {
+        if let Some(client) = self.client.take() {
+            client.shutdown();
+        }
+    }
+
+    fn publish(&mut self, snapshot: &Snapshot) -> Result<(), MqttError> {
+        let state = serde_json::to_value(ApiStatusResponse::from(snapshot.status.clone())).unwrap();
+        let sensors = sensors(&state);
+        self.ensure_connected()?;
+
+        let discoveries: Vec<(String, String, String)> = sensors
+            .iter()
+            .filter(|sensor| !self.discovered.contains(&sensor.id()))
+            .map(|sensor| {
+                let (topic, config) = self.discovery(sensor);
+                (sensor.id(), topic, config)
+            })
+            .collect();
+        let topic = self.topic.clone();
+        let client = self.client.as_ref().unwrap();
+        for (id, discovery_topic, config) in discoveries {
+            client.publish(&discovery_topic, config.as_bytes(), true)?;
+            self.discovered.insert(id);
+        }
+
+        for sensor in sensors.iter() {
+            client.publish(&format!("{}/{}", topic, sensor.path.join("/")), sensor.payload().as_bytes(), true)?;
+        }
+        client.publish(&format!("{}/state", topic), state.to_string().as_bytes(), true)?;
+        Ok(())
+    }
+}
+
+impl PollListener for MqttPublisher {
+    fn on_snapshot(&mut self, snapshot: &Snapshot) {
+        if let Err(error) = self.publish(snapshot) {
+            warn!("MQTT: failed to publish status: {}", error);
+            self.disconnect();
+        }
+    }
+
+    fn on_error(&mut self, _error: &SunSaverConnectionError) {
+        if let Err(error) = self.publish_offline() {
+            warn!("MQTT: failed to publish availability: {}", error);
+            self.disconnect();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::Utc;
+
+    use super::*;
+
+    use crate::mqtt::test::{spawn_mqtt_stand_in, test_options, wait_for_published};
+    use crate::sunsaver::SunSaverResponse;
+
+    #[test]
+    fn sensors_flatten() {
+        let state: Value = serde_json::from_str(
+            r#"{"storage": {"battery_voltage_filtered": 14.0625, "charge_state": {"Unknown": 9}}, "controller": {"dip_switches": [true, false]}, "snapshot": null}"#,
+        )
+        .unwrap();
+        let sensors: Vec<(String, String)> = sensors(&state).iter().map(|sensor| (sensor.id(), sensor.payload())).collect();
+        assert_eq!(
+            sensors,
+            vec![
+                (String::from("controller_dip_switches_0"), String::from("true")),
+                (String::from("controller_dip_switches_1"), String::from("false")),
+                (String::from("storage_battery_voltage_filtered"), String::from("14.0625")),
+                (String::from("storage_charge_state"), String::from("Unknown(9)")),
+            ]
+        );
+    }
+
+    #[test]
+    fn sensor_unit() {
+        let sensor = |path: &[&str], value: Value| Sensor {
+            path: path.iter().map(|name| String::from(*name)).collect(),
+            value,
+        };
+        assert_eq!(
+            sensor(&["storage", "battery_charge_current_filtered"], Value::from(1.5)).unit(),
+            (Some("A"), Some("current"))
+        );
+        assert_eq!(
+            sensor(&["storage", "battery_charge_power_calculated"], Value::from(1.5)).unit(),
+            (Some("W"), Some("power"))
+        );
+        assert_eq!(
+            sensor(&["mppt_sweep", "maximum_power_voltage"], Value::from(1.5)).unit(),
+            (Some("V"), Some("voltage"))
+        );
+        assert_eq!(
+            sensor(&["counters", "battery_charge_energy_total"], Value::from(1.5)).unit(),
+            (Some("kWh"), Some("energy"))
+        );
+        assert_eq!(sensor(&["storage", "charge_state"], Value::from("Float")).unit(), (None, None));
+        assert_eq!(sensor(&["snapshot", "read_at"], Value::from("2019-06-30T12:00:00Z")).unit(), (None, None));
+        assert_eq!(
+            sensor(&["faults", "array", "OVERCURENT"], Value::from(false)).unit(),
+            (None, Some("problem"))
+        );
+        assert_eq!(
+            sensor(&["today", "load_faults", "HVD"], Value::from(false)).unit(),
+            (None, Some("problem"))
+        );
+        assert_eq!(sensor(&["controller", "dip_switches", "0"], Value::from(true)).unit(), (None, None));
+        assert_eq!(sensor(&["snapshot", "stale"], Value::from(false)).unit(), (None, None));
+    }
+
+    #[test]
+    fn sensor_unit_covers_status() {
+        let snapshot = Snapshot {
+            status: SunSaverResponse::from_raw_bits([0u16; 44]),
+            read_at: Utc::now(),
+        };
+        let state = serde_json::to_value(ApiStatusResponse::from_snapshot(snapshot.clone(), snapshot.read_at, false, None)).unwrap();
+        let paths: Vec<String> = sensors(&state).iter().map(|sensor| sensor.path.join("/")).collect();
+        // Every listed unit belongs to a value that is published, so renaming a value cannot silently drop its unit
+        for (path, _, _) in UNITS.iter() {
+            assert!(paths.contains(&String::from(*path)), "{} is not in the status", path);
+        }
+    }
+
+    #[test]
+    fn mqttpublisher_publish() {
+        let stand_in = spawn_mqtt_stand_in(0, Vec::new());
+        let mut publisher = MqttPublisher::new(test_options(stand_in.port), String::from("sunsaver"), String::from("homeassistant"));
+
+        let mut registers = [0u16; 44];
+        registers[0] = 0x1200;
+        let snapshot = Snapshot {
+            status: SunSaverResponse::from_raw_bits(registers),
+            read_at: Utc::now(),
+        };
+        publisher.on_snapshot(&snapshot);
+        let sensor_count = sensors(&serde_json::to_value(ApiStatusResponse::from(snapshot.status.clone())).unwrap()).len();
+        // Availability, a discovery config and value per sensor and the state document
+        let messages = wait_for_published(&stand_in.published, 2 + sensor_count * 2);
+        assert_eq!(messages.len(), 2 + sensor_count * 2);
+
+        let connect = stand_in.connect.lock().unwrap().clone();
+        assert!(String::from_utf8_lossy(&connect).contains("sunsaver/availability"));
+
+        assert_eq!(messages[0], (String::from("sunsaver/availability"), String::from("online"), true));
+        let discovery = messages
+            .iter()
+            .find(|(topic, _, _)| topic == "homeassistant/sensor/sunsaver/storage_battery_voltage_filtered/config")
+            .unwrap();
+        let config: Value = serde_json::from_str(&discovery.1).unwrap();
+        assert_eq!(config["state_topic"], "sunsaver/storage/battery_voltage_filtered");
+        assert_eq!(config["unit_of_measurement"], "V");
+        assert_eq!(config["availability_topic"], "sunsaver/availability");
+        assert!(messages
+            .iter()
+            .any(|(topic, _, _)| topic == "homeassistant/binary_sensor/sunsaver/faults_array_OVERCURENT/config"));
+        assert!(messages.contains(&(String::from("sunsaver/storage/battery_voltage_filtered"), String::from("14.0625"), true)));
+        let state = messages.last().unwrap();
+        assert_eq!(state.0, "sunsaver/state");
+        let state: Value = serde_json::from_str(&state.1).unwrap();
+        assert_eq!(state["storage"]["battery_voltage_filtered"], 14.0625);
+
+        // Discovery configs are only sent once per connection
+        publisher.on_snapshot(&snapshot);
+        let messages = wait_for_published(&stand_in.published, 2 + sensor_count * 3 + 1);
+        assert_eq!(messages.len(), 2 + sensor_count * 3 + 1);
+    }
+
+    #[test]
+    fn mqttpublisher_on_error() {
+        let stand_in = spawn_mqtt_stand_in(0, Vec::new());
+        let mut publisher = MqttPublisher::new(test_options(stand_in.port), String::from("sunsaver"), String::from("homeassistant"));
+        let snapshot = Snapshot {
+            status: SunSaverResponse::from_raw_bits([0u16; 44]),
+            read_at: Utc::now(),
+        };
+        let availability = || {
+            let published = stand_in.published.lock().unwrap();
+            published
+                .iter()
+                .filter(|(topic, _, _)| topic == "sunsaver/availability")
+                .map(|(_, payload, retain)| (payload.clone(), *retain))
+                .collect::<Vec<(String, bool)>>()
+        };
+
+        // Nothing is published before the first snapshot connects
+        publisher.on_error(&SunSaverConnectionError::Timeout);
+        assert!(stand_in.published.lock().unwrap().is_empty());
+
+        let sensor_count = sensors(&serde_json::to_value(ApiStatusResponse::from(snapshot.status.clone())).unwrap()).len();
+        publisher.on_snapshot(&snapshot);
+        publisher.on_error(&SunSaverConnectionError::Timeout);
+        publisher.on_error(&SunSaverConnectionError::Timeout);
+        // Published once however many polls fail, and back online with the next snapshot
+        publisher.on_snapshot(&snapshot);
+        // Availability, discovery configs and values, offline, then availability and values again
+        let messages = wait_for_published(&stand_in.published, 2 + sensor_count * 2 + 1 + 2 + sensor_count);
+        assert_eq!(messages.len(), 2 + sensor_count * 2 + 1 + 2 + sensor_count);
+        assert_eq!(
+            availability(),
+            vec![
+                (String::from("online"), true),
+                (String::from("offline"), true),
+                (String::from("online"), true)
+            ]
+        );
+    }
+
+    #[test]
+    fn mqttpublisher_commands() {
+        let stand_in = spawn_mqtt_stand_in(0, Vec::new());
+        let mut publisher = MqttPublisher::new(test_options(stand_in.port), String::from("sunsaver"), String::from("homeassistant"))
+            .with_commands(&[Command::LoadDisconnect]);
+
+        let snapshot = Snapshot {
+            status: SunSaverResponse::from_raw_bits([0u16; 44]),
+            read_at: Utc::now(),
+        };
+        publisher.on_snapshot(&snapshot);
+        let messages = wait_for_published(&stand_in.published, 2);
+
+        // The commands are subscribed to on a connection of their own
+        assert!(stand_in.subscriptions.lock().unwrap().is_empty());
+        assert_eq!(messages[0], (String::from("sunsaver/availability"), String::from("online"), true));
+        assert_eq!(messages[1].0, "homeassistant/button/sunsaver/command_load_disconnect/config");
+        let config: Value = serde_json::from_str(&messages[1].1).unwrap();
+        assert_eq!(config["command_topic"], "sunsaver/command/load_disconnect");
+        assert_eq!(config["name"], "SunSaver load disconnect");
+        assert!(config.get("state_topic").is_none());
+    }
+}
@@ -0,0 +1,344 @@
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, PoisonError, RwLock};
+use std::thread;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+
+use crate::sunsaver::SunSaverResponse;
+use crate::sunsaver_connection::{SunSaverConnection, SunSaverConnectionError};
+use crate::{lock_connection, SharedConnection};
+
+// A snapshot is stale once this many polls in a row have not replaced it
+const STALE_AFTER_POLLS: u32 = 3;
+
+/// The most recent status read from the device.
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    pub status: SunSaverResponse,
+    pub read_at: DateTime<Utc>,
+}
+
+impl Snapshot {
+    pub fn age(&self, now: DateTime<Utc>) -> chrono::Duration {
+        now.signed_duration_since(self.read_at)
+    }
+}
+
+/// Told about every poll, on the poller thread once the connection has been released.
+/// Listeners that read from the device hold their own handle to the connection and lock it themselves.
+pub trait PollListener: Send {
+    fn on_snapshot(&mut self, snapshot: &Snapshot);
+
+    /// Told when a poll failed, most listeners have nothing to send until the next snapshot
+    fn on_error(&mut self, _error: &SunSaverConnectionError) {}
+}
+
+/// Runs a listener on its own thread, fed over a channel, so a slow or unreachable endpoint never delays the next poll.
+pub struct ListenerThread {
+    name: String,
+    snapshots: Sender<Result<Snapshot, SunSaverConnectionError>>,
+}
+
+impl ListenerThread {
+    pub fn spawn(name: &str, mut listener: Box<dyn PollListener>) -> ListenerThread {
+        let (snapshots, receiver) = mpsc::channel::<Result<Snapshot, SunSaverConnectionError>>();
+        thread::Builder::new()
+            .name(String::from(name))
+            .spawn(move || {
+                for result in receiver {
+                    match result {
+                        Ok(snapshot) => listener.on_snapshot(&snapshot),
+                        Err(error) => listener.on_error(&error),
+                    }
+                }
+            })
+            .unwrap();
+        ListenerThread {
+            name: String::from(name),
+            snapshots,
+        }
+    }
+}
+
+impl ListenerThread {
+    fn send(&self, result: Result<Snapshot, SunSaverConnectionError>) {
+        if self.snapshots.send(result).is_err() {
+            error!("Poller: the {} thread has stopped", self.name);
+        }
+    }
+}
+
+impl PollListener for ListenerThread {
+    fn on_snapshot(&mut self, snapshot: &Snapshot) {
+        self.send(Ok(snapshot.clone()));
+    }
+
+    fn on_error(&mut self, error: &SunSaverConnectionError) {
+        self.send(Err(error.clone()));
+    }
+}
+
+#[derive(Debug, Default)]
+struct PollerState {
+    latest: Option<Snapshot>,
+    last_error: Option<SunSaverConnectionError>,
+}
+
+/// Reads the device status on a fixed interval so HTTP handlers never wait on the serial link.
+#[derive(Debug, Clone)]
+pub struct Poller {
+    state: Arc<RwLock<PollerState>>,
+    interval: Duration,
+}
+
+impl Poller {
+    pub fn new(interval: Duration) -> Poller {
+        Poller {
+            state: Arc::new(RwLock::new(PollerState::default())),
+            interval,
+        }
+    }
+
+    /// Polls `connection` from a dedicated thread until the process exits
+    pub fn spawn(connection: SharedConnection, interval: Duration, mut listeners: Vec<Box<dyn PollListener>>) -> Poller {
+        let poller = Poller::new(interval);
+        let thread_poller = poller.clone();
+        thread::Builder::new()
+            .name(String::from("poller"))
+            .spawn(move || loop {
+                // The connection is only held for the read, so slow listeners never hold up other requests to the device
+                let result = thread_poller.read(lock_connection(&connection).as_mut());
+                notify(&result, &mut listeners);
+                thread::sleep(thread_poller.interval);
+            })
+            .unwrap();
+        poller
+    }
+
+    /// Reads the status into the latest snapshot, returning it or the error that replaced it
+    pub fn read(&self, connection: &mut dyn SunSaverConnection) -> Result<Snapshot, SunSaverConnectionError> {
+        let result = connection.read_status();
+        let mut state = self.state.write().unwrap_or_else(PoisonError::into_inner);
+        match result {
+            Ok(status) => {
+                trace!("Poller: read status");
+                let snapshot = Snapshot { status, read_at: Utc::now() };
+                state.latest = Some(snapshot.clone());
+                state.last_error = None;
+                Ok(snapshot)
+            }
+            Err(error) => {
+                warn!("Poller: failed to read status: {}", error);
+                state.last_error = Some(error.clone());
+                Err(error)
+            }
+        }
+    }
+
+    pub fn latest(&self) -> Option<Snapshot> {
+        self.state.read().unwrap_or_else(PoisonError::into_inner).latest.clone()
+    }
+
+    /// The hourmeter of the latest snapshot, reading the status from `connection` only when there is none yet
+    pub fn current_hourmeter(&self, connection: &mut dyn SunSaverConnection) -> Result<u32, SunSaverConnectionError> {
+        match self.latest() {
+            Some(snapshot) => Ok(snapshot.status.hourmeter()),
+            None => Ok(connection.read_status()?.hourmeter()),
+        }
+    }
+
+    /// The error from the last poll, cleared by the next successful one
+    pub fn last_error(&self) -> Option<SunSaverConnectionError> {
+        self.state.read().unwrap_or_else(PoisonError::into_inner).last_error.clone()
+    }
+
+    pub fn is_stale(&self, snapshot: &Snapshot, now: DateTime<Utc>) -> bool {
+        // A snapshot from the future after a clock change is treated as fresh
+        snapshot.age(now).to_std().map_or(false, |age| age > self.interval * STALE_AFTER_POLLS)
+    }
+}
+
+// Listeners may be slow, so they run after the state lock is released and never block readers of the snapshot
+fn notify(result: &Result<Snapshot, SunSaverConnectionError>, listeners: &mut [Box<dyn PollListener>]) {
+    for listener in listeners.iter_mut() {
+        match result {
+            Ok(snapshot) => listener.on_snapshot(snapshot),
+            Err(error) => listener.on_error(error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{mpsc, Mutex};
+
+    use super::*;
+
+    /// Reads and notifies in one go, `spawn` releases the connection in between
+    fn poll(poller: &Poller, connection: &mut dyn SunSaverConnection, listeners: &mut [Box<dyn PollListener>]) {
+        let result = poller.read(connection);
+        notify(&result, listeners);
+    }
+
+    struct TestConnection {
+        responses: Vec<Result<[u16; 44], SunSaverConnectionError>>,
+    }
+
+    impl SunSaverConnection for TestConnection {
+        fn read_raw_registers(&mut self) -> Result<[u16; 44], SunSaverConnectionError> {
+            self.responses.remove(0)
+        }
+
+        fn read_raw_logged(&mut self) -> Result<[u16; 32 * 16], SunSaverConnectionError> {
+            Err(SunSaverConnectionError::Unsupported("reading logged data"))
+        }
+    }
+
+    #[test]
+    fn poller_poll() {
+        let poller = Poller::new(Duration::from_secs(5));
+        assert!(poller.latest().is_none());
+        assert!(poller.last_error().is_none());
+
+        let mut registers = [0u16; 44];
+        registers[0] = 0x1200;
+        let mut connection = TestConnection {
+            responses: vec![Ok(registers), Err(SunSaverConnectionError::Timeout)],
+        };
+
+        poll(&poller, &mut connection, &mut []);
+        let snapshot = poller.latest().unwrap();
+        assert_eq!(snapshot.status.battery_voltage_filtered(), 14.0625);
+        assert!(poller.last_error().is_none());
+
+        // A failed poll keeps the previous snapshot
+        poll(&poller, &mut connection, &mut []);
+        assert_eq!(poller.latest().unwrap().read_at, snapshot.read_at);
+        assert_eq!(poller.last_error(), Some(SunSaverConnectionError::Timeout));
+    }
+
+    struct CountingListener {
+        snapshots: Arc<RwLock<u32>>,
+        errors: Arc<RwLock<u32>>,
+    }
+
+    impl PollListener for CountingListener {
+        fn on_snapshot(&mut self, _snapshot: &Snapshot) {
+            *self.snapshots.write().unwrap() += 1;
+        }
+
+        fn on_error(&mut self, _error: &SunSaverConnectionError) {
+            *self.errors.write().unwrap() += 1;
+        }
+    }
+
+    #[test]
+    fn poller_poll_listeners() {
+        let poller = Poller::new(Duration::from_secs(5));
+        let snapshots = Arc::new(RwLock::new(0));
+        let errors = Arc::new(RwLock::new(0));
+        let mut listeners: Vec<Box<dyn PollListener>> = vec![Box::new(CountingListener {
+            snapshots: snapshots.clone(),
+            errors: errors.clone(),
+        })];
+        let mut connection = TestConnection {
+            responses: vec![Ok([0u16; 44]), Err(SunSaverConnectionError::Timeout)],
+        };
+
+        poll(&poller, &mut connection, &mut listeners);
+        assert_eq!(*snapshots.read().unwrap(), 1);
+        assert_eq!(*errors.read().unwrap(), 0);
+
+        // Failed polls are passed on as errors
+        poll(&poller, &mut connection, &mut listeners);
+        assert_eq!(*snapshots.read().unwrap(), 1);
+        assert_eq!(*errors.read().unwrap(), 1);
+    }
+
+    /// Reports whether the connection was still locked when it was told about a poll
+    struct LockCheckingListener {
+        connection: SharedConnection,
+        locked: mpsc::Sender<bool>,
+    }
+
+    impl PollListener for LockCheckingListener {
+        fn on_snapshot(&mut self, _snapshot: &Snapshot) {
+            let _ = self.locked.send(self.connection.try_lock().is_err());
+        }
+    }
+
+    #[test]
+    fn poller_spawn_releases_connection() {
+        let connection: SharedConnection = Arc::new(Mutex::new(Box::new(TestConnection {
+            responses: vec![Ok([0u16; 44])],
+        })));
+        let (sender, receiver) = mpsc::channel();
+        let listeners: Vec<Box<dyn PollListener>> = vec![Box::new(LockCheckingListener {
+            connection: connection.clone(),
+            locked: sender,
+        })];
+        let poller = Poller::spawn(connection, Duration::from_secs(60), listeners);
+
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(5)), Ok(false));
+        assert!(poller.latest().is_some());
+    }
+
+    /// Waits for a go ahead before counting each snapshot
+    struct BlockedListener {
+        go: mpsc::Receiver<()>,
+        snapshots: mpsc::Sender<DateTime<Utc>>,
+    }
+
+    impl PollListener for BlockedListener {
+        fn on_snapshot(&mut self, snapshot: &Snapshot) {
+            self.go.recv().unwrap();
+            self.snapshots.send(snapshot.read_at).unwrap();
+        }
+    }
+
+    #[test]
+    fn listenerthread_on_snapshot() {
+        let poller = Poller::new(Duration::from_secs(5));
+        let (go, go_receiver) = mpsc::channel();
+        let (sender, snapshots) = mpsc::channel();
+        let mut listeners: Vec<Box<dyn PollListener>> = vec![Box::new(ListenerThread::spawn(
+            "blocked",
+            Box::new(BlockedListener {
+                go: go_receiver,
+                snapshots: sender,
+            }),
+        ))];
+        let mut connection = TestConnection {
+            responses: vec![Ok([0u16; 44]), Ok([0u16; 44])],
+        };
+
+        // Neither poll waits for the blocked listener
+        poll(&poller, &mut connection, &mut listeners);
+        let first = poller.latest().unwrap().read_at;
+        poll(&poller, &mut connection, &mut listeners);
+        let second = poller.latest().unwrap().read_at;
+
+        go.send(()).unwrap();
+        go.send(()).unwrap();
+        assert_eq!(snapshots.recv_timeout(Duration::from_secs(5)), Ok(first));
+        assert_eq!(snapshots.recv_timeout(Duration::from_secs(5)), Ok(second));
+    }
+
+    #[test]
+    fn poller_is_stale() {
+        let poller = Poller::new(Duration::from_secs(5));
+        let snapshot = Snapshot {
+            status: SunSaverResponse::from_raw_bits([0u16; 44]),
+            read_at: Utc::now(),
+        };
+
+        assert_eq!(poller.is_stale(&snapshot, snapshot.read_at), false);
+        assert_eq!(poller.is_stale(&snapshot, snapshot.read_at + chrono::Duration::seconds(15)), false);
+        assert_eq!(poller.is_stale(&snapshot, snapshot.read_at + chrono::Duration::seconds(16)), true);
+        assert_eq!(
+            snapshot.age(snapshot.read_at + chrono::Duration::seconds(16)),
+            chrono::Duration::seconds(16)
+        );
+    }
+}
@@ -0,0 +1,616 @@
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, LineWriter, Write};
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+
+use serde_json::{self, Value};
+
+use crate::sunsaver::*;
+use crate::sunsaver_connection::{SunSaverConnection, SunSaverConnectionError};
+
+// Logged day records that have never been written read back as erased EEPROM
+const ERASED_REGISTER: u16 = 0xFFFF;
+
+/// The Modbus function a recorded request used
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordedFunction {
+    ReadRegisters,
+    WriteCoil,
+    WriteRegister,
+}
+
+/// Why a recorded request failed
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RecordedError {
+    /// One of `SunSaverConnectionError::KINDS`
+    pub kind: String,
+    pub message: String,
+}
+
+impl RecordedError {
+    fn new(error: &SunSaverConnectionError) -> RecordedError {
+        let message = match error {
+            SunSaverConnectionError::DeviceGone(reason) | SunSaverConnectionError::Protocol(reason) => reason.clone(),
+            other => other.to_string(),
+        };
+        RecordedError {
+            kind: String::from(error.kind()),
+            message,
+        }
+    }
+}
+
+/// A Modbus request and what the controller answered, one line of a recording.
+///
+/// Recordings are JSON lines, failed requests have an `error` and only the registers that were read:
+///
+/// ```json
+/// {"timestamp":"2019-06-01T12:00:00Z","slave_id":1,"function":"read_registers","address":8,"count":22,"values":[4613,...]}
+/// {"timestamp":"2019-06-01T12:00:05Z","slave_id":1,"function":"read_registers","address":8,"count":22,"values":[],"error":{"kind":"timeout","message":"Timed out waiting for the device"}}
+/// {"timestamp":"2019-06-01T12:00:09Z","slave_id":1,"function":"write_coil","address":20,"count":1,"values":[1]}
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RecordedRequest {
+    pub timestamp: DateTime<Utc>,
+    pub slave_id: u8,
+    pub function: RecordedFunction,
+    pub address: u16,
+    /// Registers requested, 1 for writes
+    pub count: u16,
+    /// Registers read or the value written, coils are written as 0 or 1
+    pub values: Vec<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<RecordedError>,
+}
+
+impl RecordedRequest {
+    /// The result the controller gave, as the connection reported it
+    pub fn result(&self) -> Result<&[u16], SunSaverConnectionError> {
+        let error = match &self.error {
+            None => return Ok(&self.values),
+            Some(error) => error,
+        };
+        Err(match error.kind.as_str() {
+            "timeout" => SunSaverConnectionError::Timeout,
+            "crc" => SunSaverConnectionError::Crc,
+            "short_read" => SunSaverConnectionError::ShortRead {
+                expected: usize::from(self.count),
+                actual: self.values.len(),
+            },
+            "device_gone" => SunSaverConnectionError::DeviceGone(error.message.clone()),
+            _ => SunSaverConnectionError::Protocol(error.message.clone()),
+        })
+    }
+
+    fn matches(&self, slave_id: Option<u8>, function: RecordedFunction, address: u16, count: u16) -> bool {
+        slave_id.map_or(true, |slave_id| slave_id == self.slave_id) && self.function == function && self.address == address && self.count == count
+    }
+}
+
+/// Appends every Modbus request made by a connection to a recording.
+#[derive(Debug)]
+pub struct Recorder {
+    file: LineWriter<File>,
+    slave_id: u8,
+}
+
+impl Recorder {
+    pub fn create(path: &Path, slave_id: u8) -> io::Result<Recorder> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Recorder {
+            file: LineWriter::new(file),
+            slave_id,
+        })
+    }
+
+    /// Later requests are recorded as made to `slave_id`
+    pub fn select_slave(&mut self, slave_id: u8) {
+        self.slave_id = slave_id;
+    }
+
+    /// Failing to record is logged rather than failing the request
+    pub fn record(&mut self, function: RecordedFunction, address: u16, count: u16, values: &[u16], error: Option<&SunSaverConnectionError>) {
+        let request = RecordedRequest {
+            timestamp: Utc::now(),
+            slave_id: self.slave_id,
+            function,
+            address,
+            count,
+            values: values.to_vec(),
+            error: error.map(RecordedError::new),
+        };
+        let line = serde_json::to_string(&request).unwrap();
+        if let Err(error) = writeln!(self.file, "{}", line) {
+            warn!("Failed to record {:?} at {:#x}: {}", function, address, error);
+        }
+    }
+}
+
+fn default_fixture_slave_id() -> u8 {
+    0x01
+}
+
+/// The registers a controller held at one moment, each region is optional and keeps its values from
+/// the snapshot before when left out.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FixtureSnapshot {
+    /// The 44 RAM registers from 0x0008
+    pub ram: Option<Vec<u16>>,
+    /// Up to 32 logged days of 16 registers from 0x8000, the days after them are empty
+    pub logged: Option<Vec<u16>>,
+    /// The 47 EEPROM registers from 0xE000
+    pub eeprom: Option<Vec<u16>>,
+}
+
+impl FixtureSnapshot {
+    /// The RAM, logged and EEPROM registers given, with the logged days padded out to all 32
+    fn regions(&self) -> Result<[Option<Vec<u16>>; 3], String> {
+        for (name, values, len) in [("RAM", &self.ram, RAM_LAYOUT.len()), ("EEPROM", &self.eeprom, EEPROM_LAYOUT.len())].iter() {
+            match values {
+                Some(values) if values.len() != *len => return Err(format!("{} has {} registers, expected {}", name, values.len(), len)),
+                _ => (),
+            }
+        }
+        let logged = match &self.logged {
+            Some(values) if values.len() > LOGGED_LAYOUT.len() || values.len() % 16 != 0 => {
+                return Err(format!("Logged has {} registers, expected up to 32 days of 16", values.len()));
+            }
+            Some(values) => {
+                let mut logged = values.clone();
+                logged.resize(LOGGED_LAYOUT.len(), ERASED_REGISTER);
+                Some(logged)
+            }
+            None => None,
+        };
+        Ok([self.ram.clone(), logged, self.eeprom.clone()])
+    }
+}
+
+/// A hand written sequence of snapshots, played back as if each had been recorded in turn.
+///
+/// ```json
+/// {
+///   "slave_id": 1,
+///   "snapshots": [
+///     {"ram": [4613, 4620, ...], "logged": [...], "eeprom": [4608, 4403, ...]},
+///     {"ram": [4620, 4625, ...]}
+///   ]
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Fixture {
+    #[serde(default = "default_fixture_slave_id")]
+    pub slave_id: u8,
+    pub snapshots: Vec<FixtureSnapshot>,
+}
+
+impl Fixture {
+    /// Whether `content` is a fixture rather than a recording, which has a JSON document on each line
+    fn is_fixture(content: &str) -> bool {
+        serde_json::from_str::<Value>(content)
+            .ok()
+            .map_or(false, |value| value.get("snapshots").is_some())
+    }
+
+    /// The reads that would have returned each snapshot, in order
+    pub fn requests(&self) -> Result<Vec<RecordedRequest>, String> {
+        let timestamp = Utc::now();
+        let layouts = [&RAM_LAYOUT, &LOGGED_LAYOUT, &EEPROM_LAYOUT];
+        let mut registers = [None, None, None];
+        let mut requests = Vec::new();
+        for (i, snapshot) in self.snapshots.iter().enumerate() {
+            let regions = snapshot.regions().map_err(|error| format!("Snapshot {}: {}", i + 1, error))?;
+            for (layout, (region, values)) in layouts.iter().zip(registers.iter_mut().zip(regions.iter())) {
+                if values.is_some() {
+                    *region = values.clone();
+                }
+                let region: &Vec<u16> = match region {
+                    Some(region) => region,
+                    None => continue,
+                };
+                for window in layout.windows() {
+                    let range = layout.index_range(&window).expect("Register window outside of layout");
+                    requests.push(RecordedRequest {
+                        timestamp,
+                        slave_id: self.slave_id,
+                        function: RecordedFunction::ReadRegisters,
+                        address: window.address,
+                        count: window.count,
+                        values: region[range].to_vec(),
+                        error: None,
+                    });
+                }
+            }
+        }
+        Ok(requests)
+    }
+}
+
+/// How a recording or fixture is played back
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReplayOptions {
+    /// Starts again from the beginning once a request has no more answers
+    #[serde(rename = "loop")]
+    pub repeat: bool,
+    /// Status reads to skip before playing
+    pub seek: u64,
+}
+
+/// The slave, function, address and count of a request, which each have their own place in a recording
+type RequestKey = (Option<u8>, RecordedFunction, u16, u16);
+
+/// Plays back a recording, answering each request with the next recorded answer to the same request.
+///
+/// Each request keeps its own place in the recording, so a recording replays the same way whatever
+/// order the server polls in and however often it reads each region. Once a request has no more
+/// recorded answers it fails with `DeviceGone`, unless the recording loops, and writes that were
+/// never recorded are unsupported.
+#[derive(Debug)]
+pub struct ReplaySunSaverConnection {
+    requests: Vec<RecordedRequest>,
+    /// Where requests that have not been answered since the last seek start from
+    start: usize,
+    /// Where each request that has been answered looks for its next answer
+    cursors: HashMap<RequestKey, usize>,
+    slave_id: Option<u8>,
+    repeat: bool,
+}
+
+impl ReplaySunSaverConnection {
+    pub fn new(requests: Vec<RecordedRequest>, options: ReplayOptions) -> ReplaySunSaverConnection {
+        let mut connection = ReplaySunSaverConnection {
+            requests,
+            start: 0,
+            cursors: HashMap::new(),
+            slave_id: None,
+            repeat: options.repeat,
+        };
+        connection.seek(options.seek);
+        connection
+    }
+
+    /// Opens a recording, or a fixture
+    pub fn open(path: &Path, options: ReplayOptions) -> io::Result<ReplaySunSaverConnection> {
+        ReplaySunSaverConnection::parse(&fs::read_to_string(path)?, options)
+    }
+
+    pub fn parse(content: &str, options: ReplayOptions) -> io::Result<ReplaySunSaverConnection> {
+        let requests = if Fixture::is_fixture(content) {
+            let fixture: Fixture = serde_json::from_str(content).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+            fixture.requests().map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?
+        } else {
+            let mut requests = Vec::new();
+            for (i, line) in content.lines().enumerate() {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let request =
+                    serde_json::from_str(line).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, format!("Line {}: {}", i + 1, error)))?;
+                requests.push(request);
+            }
+            requests
+        };
+        Ok(ReplaySunSaverConnection::new(requests, options))
+    }
+
+    /// Moves every request to the start of the status read after the first `reads`, wrapping around if the recording loops
+    pub fn seek(&mut self, reads: u64) {
+        let first_window = &RAM_LAYOUT.windows()[0];
+        let starts: Vec<usize> = (0..self.requests.len())
+            .filter(|index| self.requests[*index].matches(None, RecordedFunction::ReadRegisters, first_window.address, first_window.count))
+            .collect();
+        let reads = if self.repeat && !starts.is_empty() {
+            reads % starts.len() as u64
+        } else {
+            reads
+        };
+        self.start = match starts.get(reads as usize) {
+            Some(start) => *start,
+            None if reads == 0 => 0,
+            None => self.requests.len(),
+        };
+        self.cursors.clear();
+    }
+
+    fn replay(&mut self, function: RecordedFunction, address: u16, count: u16) -> Option<&RecordedRequest> {
+        let key = (self.slave_id, function, address, count);
+        let next = self.cursors.get(&key).cloned().unwrap_or(self.start);
+        let position = |requests: &[RecordedRequest]| requests.iter().position(|request| request.matches(key.0, function, address, count));
+        let index = match position(&self.requests[next..]) {
+            Some(offset) => next + offset,
+            // Looping starts this request again from the beginning of the recording
+            None if self.repeat => position(&self.requests)?,
+            None => return None,
+        };
+        self.cursors.insert(key, index + 1);
+        Some(&self.requests[index])
+    }
+
+    fn read_layout(&mut self, layout: &RegisterLayout, dest: &mut [u16]) -> Result<(), SunSaverConnectionError> {
+        for window in layout.windows() {
+            let request = self
+                .replay(RecordedFunction::ReadRegisters, window.address, window.count)
+                .ok_or_else(|| {
+                    SunSaverConnectionError::DeviceGone(format!(
+                        "The recording has no more reads of {} registers at {:#x}",
+                        window.count, window.address
+                    ))
+                })?;
+            let values = request.result()?;
+            if values.len() != usize::from(window.count) {
+                return Err(SunSaverConnectionError::ShortRead {
+                    expected: usize::from(window.count),
+                    actual: values.len(),
+                });
+            }
+            let range = layout.index_range(&window).expect("Register window outside of layout");
+            dest[range].copy_from_slice(values);
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, function: RecordedFunction, address: u16, value: u16, operation: &'static str) -> Result<(), SunSaverConnectionError> {
+        let request = self.replay(function, address, 1).ok_or(SunSaverConnectionError::Unsupported(operation))?;
+        if request.values != [value] {
+            return Err(SunSaverConnectionError::Protocol(format!(
+                "The recording wrote {:?} to {:#x}, not {:#x}",
+                request.values, address, value
+            )));
+        }
+        request.result().map(|_| ())
+    }
+}
+
+impl SunSaverConnection for ReplaySunSaverConnection {
+    fn read_raw_registers(&mut self) -> Result<[u16; 44], SunSaverConnectionError> {
+        let mut registers = [0u16; 44];
+        self.read_layout(&RAM_LAYOUT, &mut registers)?;
+        Ok(registers)
+    }
+
+    fn read_raw_logged(&mut self) -> Result<[u16; 32 * 16], SunSaverConnectionError> {
+        let mut logged_data = [0u16; 32 * 16];
+        self.read_layout(&LOGGED_LAYOUT, &mut logged_data)?;
+        Ok(logged_data)
+    }
+
+    fn write_coil(&mut self, coil: Coil, value: bool) -> Result<(), SunSaverConnectionError> {
+        self.write(RecordedFunction::WriteCoil, coil.address(), u16::from(value), "writing coils")
+    }
+
+    fn read_raw_eeprom(&mut self) -> Result<[u16; 47], SunSaverConnectionError> {
+        let mut eeprom_register = [0u16; 47];
+        self.read_layout(&EEPROM_LAYOUT, &mut eeprom_register)?;
+        Ok(eeprom_register)
+    }
+
+    fn write_register(&mut self, address: u16, value: u16) -> Result<(), SunSaverConnectionError> {
+        self.write(RecordedFunction::WriteRegister, address, value, "writing registers")
+    }
+
+    fn select_slave(&mut self, slave_id: u8) -> Result<(), SunSaverConnectionError> {
+        self.slave_id = Some(slave_id);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tempdir::TempDir;
+
+    use super::*;
+
+    fn recorded_reads(layout: &RegisterLayout, slave_id: u8, offset: u16) -> Vec<RecordedRequest> {
+        layout
+            .windows()
+            .iter()
+            .map(|window| RecordedRequest {
+                timestamp: Utc::now(),
+                slave_id,
+                function: RecordedFunction::ReadRegisters,
+                address: window.address,
+                count: window.count,
+                values: (window.address..window.address + window.count).map(|address| address + offset).collect(),
+                error: None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn replay_read_raw_registers() {
+        let mut requests = recorded_reads(&RAM_LAYOUT, 1, 0);
+        requests.extend(recorded_reads(&EEPROM_LAYOUT, 1, 0));
+        requests.extend(recorded_reads(&RAM_LAYOUT, 1, 1));
+        let mut connection = ReplaySunSaverConnection::new(requests, ReplayOptions::default());
+
+        let registers = connection.read_raw_registers().unwrap();
+        for (i, register) in registers.iter().enumerate() {
+            assert_eq!(*register, 0x0008 + i as u16);
+        }
+        // The EEPROM read in between does not hold up the next status read
+        let registers = connection.read_raw_registers().unwrap();
+        assert_eq!(registers[0] - 1, 0x0008);
+        assert_eq!(
+            connection.read_raw_registers(),
+            Err(SunSaverConnectionError::DeviceGone(String::from(
+                "The recording has no more reads of 22 registers at 0x8"
+            )))
+        );
+        // And is still answered after the status reads have run out
+        let registers = connection.read_raw_eeprom().unwrap();
+        assert_eq!(registers[0], 0xE000);
+        assert!(connection.read_raw_eeprom().is_err());
+    }
+
+    #[test]
+    fn replay_errors() {
+        // A read stops at the first window that fails, so its later windows were never recorded
+        let mut requests = recorded_reads(&RAM_LAYOUT, 1, 0);
+        requests.truncate(1);
+        requests[0].values.truncate(5);
+        requests[0].error = Some(RecordedError::new(&SunSaverConnectionError::ShortRead { expected: 22, actual: 5 }));
+        requests.extend(recorded_reads(&RAM_LAYOUT, 1, 0));
+        requests[2].values.clear();
+        requests[2].error = Some(RecordedError::new(&SunSaverConnectionError::DeviceGone(String::from("Broken pipe"))));
+        let mut connection = ReplaySunSaverConnection::new(requests, ReplayOptions::default());
+
+        assert_eq!(
+            connection.read_raw_registers(),
+            Err(SunSaverConnectionError::ShortRead { expected: 22, actual: 5 })
+        );
+        // Fails on the second window of the second read
+        assert_eq!(
+            connection.read_raw_registers(),
+            Err(SunSaverConnectionError::DeviceGone(String::from("Broken pipe")))
+        );
+    }
+
+    #[test]
+    fn replay_loop_seek() {
+        let mut requests = Vec::new();
+        for offset in 0..3 {
+            requests.extend(recorded_reads(&RAM_LAYOUT, 1, offset));
+            requests.extend(recorded_reads(&EEPROM_LAYOUT, 1, offset));
+        }
+        let first_register = |connection: &mut ReplaySunSaverConnection| connection.read_raw_registers().map(|registers| registers[0] - 0x0008);
+
+        let options = ReplayOptions { repeat: true, seek: 1 };
+        let mut connection = ReplaySunSaverConnection::new(requests.clone(), options);
+        assert_eq!(first_register(&mut connection), Ok(1));
+        assert_eq!(first_register(&mut connection), Ok(2));
+        assert_eq!(first_register(&mut connection), Ok(0));
+        // The EEPROM reads have their own place, starting from the seek
+        assert_eq!(connection.read_raw_eeprom().map(|registers| registers[0] - 0xE000), Ok(1));
+
+        connection.seek(5);
+        assert_eq!(first_register(&mut connection), Ok(2));
+
+        let options = ReplayOptions { repeat: false, seek: 3 };
+        let mut connection = ReplaySunSaverConnection::new(requests, options);
+        assert!(first_register(&mut connection).is_err());
+        connection.seek(0);
+        assert_eq!(first_register(&mut connection), Ok(0));
+    }
+
+    #[test]
+    fn fixture_requests() {
+        let fixture: Fixture = serde_json::from_str(include_str!("../fixtures/day.json")).unwrap();
+        let mut connection = ReplaySunSaverConnection::new(fixture.requests().unwrap(), ReplayOptions::default());
+
+        let status = connection.read_status().unwrap();
+        assert_eq!(status.charge_state(), ChargeState::Float);
+        assert_eq!(connection.read_logged().unwrap().days.len(), 3);
+        assert_eq!(connection.read_eeprom().unwrap().register(EepromSetting::AbsorptionVoltage), 0x1200);
+        // Later snapshots keep the logged days and EEPROM of the first
+        assert!(connection.read_status().is_ok());
+        assert_eq!(connection.read_logged().unwrap().days.len(), 3);
+        assert!(connection.read_raw_eeprom().is_ok());
+
+        let invalid: Fixture = serde_json::from_str(r#"{"snapshots": [{"ram": []}]}"#).unwrap();
+        assert_eq!(invalid.requests(), Err(String::from("Snapshot 1: RAM has 0 registers, expected 44")));
+        let invalid: Fixture = serde_json::from_str(r#"{"snapshots": [{}, {"logged": [1, 2]}]}"#).unwrap();
+        assert_eq!(
+            invalid.requests(),
+            Err(String::from("Snapshot 2: Logged has 2 registers, expected up to 32 days of 16"))
+        );
+    }
+
+    #[test]
+    fn replay_parse_fixture() {
+        let fixture = r#"{
+            "slave_id": 2,
+            "snapshots": [{"logged": [9024, 0, 0, 3996, 4667, 416, 210, 0, 0, 5763, 60, 0, 479, 65535, 65535, 65535]}]
+        }"#;
+        let mut connection = ReplaySunSaverConnection::parse(fixture, ReplayOptions::default()).unwrap();
+        connection.select_slave(2).unwrap();
+        let logged_data = connection.read_raw_logged().unwrap();
+        assert_eq!(logged_data[0], 9024);
+        assert_eq!(logged_data[16], 0xFFFF);
+        assert!(connection.read_raw_registers().is_err());
+
+        let error = ReplaySunSaverConnection::parse(r#"{"snapshots": [], "loop": true}"#, ReplayOptions::default()).unwrap_err();
+        assert!(error.to_string().contains("unknown field `loop`"));
+    }
+
+    #[test]
+    fn replay_select_slave() {
+        let mut requests = recorded_reads(&RAM_LAYOUT, 1, 0);
+        requests.extend(recorded_reads(&RAM_LAYOUT, 2, 0x100));
+        let mut connection = ReplaySunSaverConnection::new(requests, ReplayOptions::default());
+
+        connection.select_slave(2).unwrap();
+        assert_eq!(connection.read_raw_registers().unwrap()[0] - 0x100, 0x0008);
+        // Each slave has its own place in the recording
+        connection.select_slave(1).unwrap();
+        assert_eq!(connection.read_raw_registers().unwrap()[0], 0x0008);
+        assert!(connection.read_raw_registers().is_err());
+    }
+
+    #[test]
+    fn replay_write_unsupported() {
+        let mut connection = ReplaySunSaverConnection::new(Vec::new(), ReplayOptions::default());
+        let change = EepromSettingChange {
+            setting: EepromSetting::FloatVoltage,
+            from: 0x1133,
+            to: 0x1168,
+        };
+        assert_eq!(
+            connection.write_eeprom(&[change]),
+            Err(SunSaverConnectionError::Unsupported("writing registers"))
+        );
+        assert_eq!(
+            connection.execute_command(Command::Equalize),
+            Err(SunSaverConnectionError::Unsupported("writing coils"))
+        );
+    }
+
+    #[test]
+    fn recorder_replay() {
+        let temp_dir = TempDir::new(concat!(module_path!(), "recorder_replay")).unwrap();
+        let path = temp_dir.path().join("recording.jsonl");
+        let mut recorder = Recorder::create(&path, 1).unwrap();
+        for request in recorded_reads(&LOGGED_LAYOUT, 1, 0) {
+            recorder.record(request.function, request.address, request.count, &request.values, None);
+        }
+        recorder.record(RecordedFunction::WriteCoil, Coil::EqualizeTriggered.address(), 1, &[1], None);
+        recorder.select_slave(2);
+        recorder.record(
+            RecordedFunction::WriteCoil,
+            Coil::EqualizeTriggered.address(),
+            1,
+            &[1],
+            Some(&SunSaverConnectionError::Timeout),
+        );
+        drop(recorder);
+
+        let mut connection = ReplaySunSaverConnection::open(&path, ReplayOptions::default()).unwrap();
+        let logged_data = connection.read_raw_logged().unwrap();
+        for (i, register) in logged_data.iter().enumerate() {
+            assert_eq!(*register, 0x8000 + i as u16);
+        }
+        connection.execute_command(Command::Equalize).unwrap();
+        connection.select_slave(2).unwrap();
+        assert_eq!(connection.execute_command(Command::Equalize), Err(SunSaverConnectionError::Timeout));
+    }
+
+    #[test]
+    fn replay_open_invalid() {
+        let temp_dir = TempDir::new(concat!(module_path!(), "replay_open_invalid")).unwrap();
+        let path = temp_dir.path().join("recording.jsonl");
+        let mut file = File::create(&path).unwrap();
+        writeln!(file).unwrap();
+        writeln!(file, "{{\"function\":\"read_registers\"}}").unwrap();
+
+        let error = ReplaySunSaverConnection::open(&path, ReplayOptions::default()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(error.to_string().starts_with("Line 2: "));
+    }
+}
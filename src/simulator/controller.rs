@@ -0,0 +1,634 @@
+use crate::simulator::{Fault, FaultKind, Profile, ProfileStep};
+use crate::sunsaver::{Alarm, ArrayFault, ChargeState, Coil, EepromSetting, LoadFault, LoadState, EEPROM_LAYOUT, LOGGED_LAYOUT, RAM_LAYOUT};
+
+// Simulated seconds moved on at a time, short enough for the charge stages to change smoothly
+const TICK_SECONDS: u64 = 10;
+const DAY_SECONDS: u64 = 24 * 60 * 60;
+// Hours the controller has already been powered for when the simulator starts
+const INITIAL_HOURMETER: u64 = 9000;
+const LOGGED_DAY_REGISTERS: usize = 16;
+
+// A 100 Ah 12 V lead acid battery on a 200 W array, within the SunSaver MPPT's 15 A charge rating
+const BATTERY_CAPACITY_AH: f32 = 100.0;
+const ARRAY_POWER_W: f32 = 200.0;
+const MAX_CHARGE_CURRENT: f32 = 15.0;
+const CHARGE_EFFICIENCY: f32 = 0.95;
+const INITIAL_STATE_OF_CHARGE: f32 = 0.7;
+
+/// EEPROM as shipped, 14.0 V absorption, 13.5 V float and a 11.0 V load disconnect
+#[rustfmt::skip]
+const DEFAULT_EEPROM: [u16; 47] = [
+    0x1200, 0x1133, 0x0e10, 0x1c20, 0x0f80, 0x0f00, 0x1266, 0x001c,
+    0x0000, 0x1c20, 0xffec, 0x1400, 0x1333, 0x1800, 0x003c, 0xffe2,
+    0x0e13, 0x0ff9, 0x1400, 0x1333, 0x0000, 0x0096, 0x0000, 0x0000,
+    0x0000, 0x0000, 0x0001, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000,
+    0x0000, 0x26b6, 0x0000, 0x0f2a, 0x0000, 0x0f2a, 0x0000, 0x1712,
+    0x0000, 0x1712, 0x004e, 0x0e66, 0x1333, 0x2c00, 0x0007,
+];
+
+fn raw_voltage(volts: f32) -> u16 {
+    (volts.max(0.0) * 32768.0 / 100.0).round() as u16
+}
+
+fn raw_current(amps: f32) -> u16 {
+    (amps.max(0.0) * 32768.0 / 79.16).round() as u16
+}
+
+fn raw_power(watts: f32) -> u16 {
+    (watts.max(0.0) * 65536.0 / 989.5).round() as u16
+}
+
+fn raw_temperature(celsius: f32) -> u16 {
+    u16::from(celsius.round() as i8 as u8)
+}
+
+/// Tenths of an amp hour or kilowatt hour, as the counters are reported
+fn raw_tenths(value: f64) -> u32 {
+    (value * 10.0) as u32
+}
+
+fn high(value: u32) -> u16 {
+    (value >> 16) as u16
+}
+
+fn low(value: u32) -> u16 {
+    value as u16
+}
+
+/// Totals kept since midnight and written to the logged days when the day ends.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Daily {
+    battery_voltage_min: f32,
+    battery_voltage_max: f32,
+    array_voltage_max: f32,
+    charge_ah: f64,
+    load_ah: f64,
+    array_fault: ArrayFault,
+    load_fault: LoadFault,
+    alarm: Alarm,
+    absorption_seconds: u64,
+    equalize_seconds: u64,
+    float_seconds: u64,
+}
+
+impl Daily {
+    fn new(battery_voltage: f32) -> Daily {
+        Daily {
+            battery_voltage_min: battery_voltage,
+            battery_voltage_max: battery_voltage,
+            array_voltage_max: 0.0,
+            charge_ah: 0.0,
+            load_ah: 0.0,
+            array_fault: ArrayFault::empty(),
+            load_fault: LoadFault::empty(),
+            alarm: Alarm::empty(),
+            absorption_seconds: 0,
+            equalize_seconds: 0,
+            float_seconds: 0,
+        }
+    }
+}
+
+/// A SunSaver MPPT charging a battery from a solar array, driven by a profile rather than the weather.
+///
+/// Time only moves on through `advance`, which keeps the simulation deterministic.
+#[derive(Debug, Clone)]
+pub struct SimulatedController {
+    profile: Profile,
+    faults: Vec<Fault>,
+    /// Simulated seconds since the controller was powered by the simulator
+    elapsed: u64,
+    /// Simulated seconds spent warming up, faults are timed from the end of the warm up
+    warm_up: u64,
+    eeprom: [u16; 47],
+    logged: [u16; 32 * 16],
+    logged_days: usize,
+
+    state_of_charge: f32,
+    charge_state: ChargeState,
+    charge_state_seconds: u64,
+    load_state: LoadState,
+    equalize_triggered: bool,
+    load_disconnect: bool,
+    array_fault: ArrayFault,
+    load_fault: LoadFault,
+    alarm: Alarm,
+
+    battery_voltage: f32,
+    array_voltage: f32,
+    load_voltage: f32,
+    charge_current: f32,
+    load_current: f32,
+    regulation_voltage: f32,
+    heatsink_temperature: f32,
+    ambient_temperature: f32,
+    sweep_vmp: f32,
+    sweep_pmax: f32,
+    sweep_voc: f32,
+
+    charge_ah_resettable: f64,
+    charge_ah_total: f64,
+    charge_kwh_total: f64,
+    load_ah_resettable: f64,
+    load_ah_total: f64,
+    daily: Daily,
+}
+
+impl SimulatedController {
+    pub fn new(profile: Profile) -> SimulatedController {
+        let state_of_charge = INITIAL_STATE_OF_CHARGE;
+        let battery_voltage = SimulatedController::open_circuit_voltage(state_of_charge);
+        let mut controller = SimulatedController {
+            profile,
+            faults: Vec::new(),
+            elapsed: 0,
+            warm_up: 0,
+            eeprom: DEFAULT_EEPROM,
+            // Records that have never been written read back as erased EEPROM
+            logged: [0xFFFF; 32 * 16],
+            logged_days: 0,
+            state_of_charge,
+            charge_state: ChargeState::Start,
+            charge_state_seconds: 0,
+            load_state: LoadState::Start,
+            equalize_triggered: false,
+            load_disconnect: false,
+            array_fault: ArrayFault::empty(),
+            load_fault: LoadFault::empty(),
+            alarm: Alarm::empty(),
+            battery_voltage,
+            array_voltage: 0.0,
+            load_voltage: 0.0,
+            charge_current: 0.0,
+            load_current: 0.0,
+            regulation_voltage: 0.0,
+            heatsink_temperature: 0.0,
+            ambient_temperature: 0.0,
+            sweep_vmp: 0.0,
+            sweep_pmax: 0.0,
+            sweep_voc: 0.0,
+            charge_ah_resettable: 0.0,
+            charge_ah_total: 0.0,
+            charge_kwh_total: 0.0,
+            load_ah_resettable: 0.0,
+            load_ah_total: 0.0,
+            daily: Daily::new(battery_voltage),
+        };
+        controller.tick(0);
+        controller
+    }
+
+    /// Runs whole days of the profile without faults so the counters and logged days hold history
+    pub fn warm_up(&mut self, days: u64) {
+        let faults = std::mem::take(&mut self.faults);
+        self.advance(days * DAY_SECONDS);
+        self.warm_up = self.elapsed;
+        self.faults = faults;
+    }
+
+    /// Simulated seconds since the end of the warm up
+    pub fn elapsed(&self) -> u64 {
+        self.elapsed - self.warm_up
+    }
+
+    pub fn advance(&mut self, seconds: u64) {
+        let until = self.elapsed + seconds;
+        while self.elapsed < until {
+            // Ticks never cross midnight so each day is logged
+            let midnight = (self.elapsed / DAY_SECONDS + 1) * DAY_SECONDS;
+            let seconds = TICK_SECONDS.min(until - self.elapsed).min(midnight - self.elapsed);
+            self.elapsed += seconds;
+            self.tick(seconds);
+            if self.elapsed % DAY_SECONDS == 0 {
+                self.log_day();
+            }
+        }
+    }
+
+    /// Adds a fault, timed from the end of the warm up
+    pub fn inject(&mut self, fault: Fault) {
+        self.faults.push(fault);
+    }
+
+    /// Transport faults active now, register faults are applied by `advance`
+    pub fn transport_faults(&self) -> Vec<FaultKind> {
+        let elapsed = self.elapsed();
+        self.faults
+            .iter()
+            .filter(|fault| fault.kind.is_transport() && fault.is_active(elapsed))
+            .map(|fault| fault.kind)
+            .collect()
+    }
+
+    fn open_circuit_voltage(state_of_charge: f32) -> f32 {
+        11.0 + 1.8 * state_of_charge
+    }
+
+    /// Volts the battery rises by for each amp charged, climbing steeply as it fills
+    fn charge_resistance(state_of_charge: f32) -> f32 {
+        0.02 + 0.5 * state_of_charge.powi(6)
+    }
+
+    fn setting(&self, setting: EepromSetting) -> f32 {
+        setting.decode(self.eeprom[EEPROM_LAYOUT.index_of(setting.address()).unwrap()])
+    }
+
+    fn tick(&mut self, seconds: u64) {
+        let step: ProfileStep = *self.profile.step_at(self.elapsed);
+        let elapsed = self.elapsed();
+        for fault in self.faults.iter().filter(|fault| fault.is_active(elapsed)) {
+            match fault.kind {
+                FaultKind::Array(array_fault) => self.array_fault |= array_fault,
+                FaultKind::Load(load_fault) => self.load_fault |= load_fault,
+                FaultKind::Alarm(alarm) => self.alarm |= alarm,
+                FaultKind::Timeout | FaultKind::Crc | FaultKind::Exception => {}
+            }
+        }
+
+        // The array
+        self.ambient_temperature = step.ambient_temperature;
+        let available_power = ARRAY_POWER_W * step.irradiance;
+        if step.irradiance > 0.0 {
+            self.sweep_voc = 20.0 + 2.0 * step.irradiance;
+            self.sweep_vmp = 0.8 * self.sweep_voc;
+            self.sweep_pmax = available_power;
+        }
+
+        // The charge stage
+        let open_circuit_voltage = SimulatedController::open_circuit_voltage(self.state_of_charge);
+        let resistance = SimulatedController::charge_resistance(self.state_of_charge);
+        let absorption_voltage = self.setting(EepromSetting::AbsorptionVoltage);
+        let charge_state = if !self.array_fault.is_empty() {
+            ChargeState::Fault
+        } else if available_power <= 0.0 {
+            ChargeState::Night
+        } else {
+            match self.charge_state {
+                ChargeState::BulkCharge if self.battery_voltage >= absorption_voltage => ChargeState::Absorption,
+                ChargeState::Absorption if self.charge_state_seconds >= self.setting(EepromSetting::AbsorptionTime) as u64 => ChargeState::Float,
+                ChargeState::Float | ChargeState::Absorption if self.equalize_triggered => ChargeState::Equalize,
+                ChargeState::Equalize if self.charge_state_seconds >= self.setting(EepromSetting::EqualizeTime) as u64 => {
+                    self.equalize_triggered = false;
+                    ChargeState::Float
+                }
+                ChargeState::Float if self.battery_voltage < self.setting(EepromSetting::FloatCancelVoltage) => ChargeState::BulkCharge,
+                ChargeState::BulkCharge | ChargeState::Absorption | ChargeState::Float | ChargeState::Equalize => self.charge_state.clone(),
+                _ => ChargeState::BulkCharge,
+            }
+        };
+        if charge_state == self.charge_state {
+            self.charge_state_seconds += seconds;
+        } else {
+            self.charge_state = charge_state;
+            self.charge_state_seconds = 0;
+        }
+        self.regulation_voltage = match self.charge_state {
+            ChargeState::Float => self.setting(EepromSetting::FloatVoltage),
+            ChargeState::Equalize => self.setting(EepromSetting::EqualizeVoltage),
+            _ => absorption_voltage,
+        };
+
+        // The load
+        let low_voltage_disconnect = self.setting(EepromSetting::LoadLowVoltageDisconnect);
+        let low_voltage_reconnect = self.setting(EepromSetting::LoadLowVoltageReconnect);
+        self.load_state = if !self.load_fault.is_empty() {
+            LoadState::Fault
+        } else if self.load_disconnect {
+            LoadState::Disconnect
+        } else if self.battery_voltage < low_voltage_disconnect || (self.load_state == LoadState::Lvd && self.battery_voltage < low_voltage_reconnect)
+        {
+            LoadState::Lvd
+        } else {
+            LoadState::Normal
+        };
+        self.load_current = if self.load_state == LoadState::Normal { step.load_current } else { 0.0 };
+
+        // The battery
+        let available_current = (available_power * CHARGE_EFFICIENCY / self.battery_voltage).min(MAX_CHARGE_CURRENT);
+        self.charge_current = match self.charge_state {
+            ChargeState::BulkCharge => available_current,
+            // Only as much as holds the battery at the regulation voltage
+            ChargeState::Absorption | ChargeState::Float | ChargeState::Equalize => {
+                let accepted = ((self.regulation_voltage - open_circuit_voltage) / resistance).max(0.0);
+                available_current.min(self.load_current + accepted)
+            }
+            _ => 0.0,
+        };
+        let net_current = self.charge_current - self.load_current;
+        self.battery_voltage = if net_current > 0.0 {
+            open_circuit_voltage + net_current * resistance
+        } else {
+            open_circuit_voltage + net_current * 0.02
+        };
+        let hours = seconds as f32 / 3600.0;
+        self.state_of_charge = (self.state_of_charge + net_current * hours / BATTERY_CAPACITY_AH).clamp(0.0, 1.0);
+        self.array_voltage = if available_power > 0.0 { self.sweep_vmp } else { 0.3 };
+        self.load_voltage = if self.load_current > 0.0 { self.battery_voltage } else { 0.0 };
+        self.heatsink_temperature = self.ambient_temperature + self.output_power() / 20.0;
+
+        // The counters
+        let hours = f64::from(hours);
+        let charge_ah = f64::from(self.charge_current) * hours;
+        let load_ah = f64::from(self.load_current) * hours;
+        self.charge_ah_resettable += charge_ah;
+        self.charge_ah_total += charge_ah;
+        self.charge_kwh_total += f64::from(self.output_power()) * hours / 1000.0;
+        self.load_ah_resettable += load_ah;
+        self.load_ah_total += load_ah;
+
+        let daily = &mut self.daily;
+        daily.battery_voltage_min = daily.battery_voltage_min.min(self.battery_voltage);
+        daily.battery_voltage_max = daily.battery_voltage_max.max(self.battery_voltage);
+        daily.array_voltage_max = daily.array_voltage_max.max(self.array_voltage);
+        daily.charge_ah += charge_ah;
+        daily.load_ah += load_ah;
+        daily.array_fault |= self.array_fault;
+        daily.load_fault |= self.load_fault;
+        daily.alarm |= self.alarm;
+        match self.charge_state {
+            ChargeState::Absorption => daily.absorption_seconds += seconds,
+            ChargeState::Equalize => daily.equalize_seconds += seconds,
+            ChargeState::Float => daily.float_seconds += seconds,
+            _ => {}
+        }
+    }
+
+    fn output_power(&self) -> f32 {
+        self.battery_voltage * self.charge_current
+    }
+
+    fn hourmeter(&self) -> u32 {
+        (INITIAL_HOURMETER + self.elapsed / 3600) as u32
+    }
+
+    fn log_day(&mut self) {
+        let daily = self.daily;
+        let hourmeter = self.hourmeter();
+        let alarm = daily.alarm.bits();
+        let record: [u16; 13] = [
+            hourmeter as u16,
+            ((hourmeter >> 16) & 0x00FF) as u16 | ((alarm & 0x00FF) << 8) as u16,
+            (alarm >> 8) as u16,
+            raw_voltage(daily.battery_voltage_min),
+            raw_voltage(daily.battery_voltage_max),
+            raw_tenths(daily.charge_ah) as u16,
+            raw_tenths(daily.load_ah) as u16,
+            daily.array_fault.bits(),
+            daily.load_fault.bits(),
+            raw_voltage(daily.array_voltage_max),
+            (daily.absorption_seconds / 60) as u16,
+            (daily.equalize_seconds / 60) as u16,
+            (daily.float_seconds / 60) as u16,
+        ];
+        // The oldest day is overwritten once all 32 are in use
+        let offset = (self.logged_days % (LOGGED_LAYOUT.len() / LOGGED_DAY_REGISTERS)) * LOGGED_DAY_REGISTERS;
+        self.logged[offset..offset + record.len()].copy_from_slice(&record);
+        self.logged_days += 1;
+        self.daily = Daily::new(self.battery_voltage);
+    }
+
+    /// The RAM registers as the controller reports them now
+    #[rustfmt::skip]
+    pub fn ram(&self) -> [u16; 44] {
+        let charge_ah_resettable = raw_tenths(self.charge_ah_resettable);
+        let charge_ah_total = raw_tenths(self.charge_ah_total);
+        let load_ah_resettable = raw_tenths(self.load_ah_resettable);
+        let load_ah_total = raw_tenths(self.load_ah_total);
+        let hourmeter = self.hourmeter();
+        let alarm = self.alarm.bits();
+        let alarm_daily = self.daily.alarm.bits();
+        [
+            raw_voltage(self.battery_voltage),
+            raw_voltage(self.array_voltage),
+            raw_voltage(self.load_voltage),
+            raw_current(self.charge_current),
+            raw_current(self.load_current),
+            raw_temperature(self.heatsink_temperature),
+            raw_temperature(self.ambient_temperature),
+            raw_temperature(self.ambient_temperature),
+            // No remote temperature sensor is fitted, the ambient temperature is reported in its place
+            raw_temperature(self.ambient_temperature),
+            u16::from(self.charge_state.clone()),
+            self.array_fault.bits(),
+            raw_voltage(self.battery_voltage),
+            raw_voltage(self.regulation_voltage),
+            high(charge_ah_resettable), low(charge_ah_resettable),
+            high(charge_ah_total), low(charge_ah_total),
+            raw_tenths(self.charge_kwh_total) as u16,
+            u16::from(self.load_state.clone()),
+            self.load_fault.bits(),
+            raw_voltage(self.setting(EepromSetting::LoadLowVoltageDisconnect)),
+            high(load_ah_resettable), low(load_ah_resettable),
+            high(load_ah_total), low(load_ah_total),
+            high(hourmeter), low(hourmeter),
+            high(alarm), low(alarm),
+            // DIP switches all off and the SOC LEDs unlit
+            0x0000,
+            0x0000,
+            raw_power(self.output_power()),
+            raw_voltage(self.sweep_vmp),
+            raw_power(self.sweep_pmax),
+            raw_voltage(self.sweep_voc),
+            raw_voltage(self.daily.battery_voltage_min),
+            raw_voltage(self.daily.battery_voltage_max),
+            raw_tenths(self.daily.charge_ah) as u16,
+            raw_tenths(self.daily.load_ah) as u16,
+            self.daily.array_fault.bits(),
+            self.daily.load_fault.bits(),
+            high(alarm_daily), low(alarm_daily),
+            // The last register of the window is reserved
+            0x0000,
+        ]
+    }
+
+    /// Reads a register from the RAM, logged data or EEPROM, `None` for any other address
+    pub fn read_register(&self, address: u16) -> Option<u16> {
+        if let Some(index) = RAM_LAYOUT.index_of(address) {
+            return Some(self.ram()[index]);
+        }
+        if let Some(index) = LOGGED_LAYOUT.index_of(address) {
+            return Some(self.logged[index]);
+        }
+        EEPROM_LAYOUT.index_of(address).map(|index| self.eeprom[index])
+    }
+
+    /// Only the EEPROM is writable, returns false for any other address
+    pub fn write_register(&mut self, address: u16, value: u16) -> bool {
+        match EEPROM_LAYOUT.index_of(address) {
+            Some(index) => {
+                info!("EEPROM {:#x} set to {:#x}", address, value);
+                self.eeprom[index] = value;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Reads a coil, the action coils always read back as off
+    pub fn read_coil(&self, address: u16) -> bool {
+        if address == Coil::EqualizeTriggered.address() {
+            self.equalize_triggered
+        } else if address == Coil::LoadDisconnect.address() {
+            self.load_disconnect
+        } else {
+            false
+        }
+    }
+
+    /// Writes a coil, returns false for addresses without a coil
+    pub fn write_coil(&mut self, address: u16, value: bool) -> bool {
+        let coil = [
+            Coil::EqualizeTriggered,
+            Coil::LoadDisconnect,
+            Coil::ClearFaults,
+            Coil::ClearAlarms,
+            Coil::ResetControl,
+        ]
+        .iter()
+        .find(|coil| coil.address() == address)
+        .copied();
+        info!("Coil {:#x} set to {}", address, value);
+        match coil {
+            Some(Coil::EqualizeTriggered) => self.equalize_triggered = value,
+            Some(Coil::LoadDisconnect) => self.load_disconnect = value,
+            Some(Coil::ClearFaults) if value => {
+                self.array_fault = ArrayFault::empty();
+                self.load_fault = LoadFault::empty();
+            }
+            Some(Coil::ClearAlarms) if value => self.alarm = Alarm::empty(),
+            Some(Coil::ResetControl) if value => {
+                self.array_fault = ArrayFault::empty();
+                self.load_fault = LoadFault::empty();
+                self.alarm = Alarm::CONTROLLER_WAS_RESET;
+                self.charge_state = ChargeState::Start;
+                self.load_state = LoadState::Start;
+                self.charge_state_seconds = 0;
+            }
+            Some(_) => {}
+            None => return false,
+        }
+        // Faults still active are raised again straight away
+        self.tick(0);
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::sunsaver::{LoggedResponse, SunSaverResponse};
+
+    fn day() -> SimulatedController {
+        SimulatedController::new(Profile::named("day").unwrap())
+    }
+
+    #[test]
+    fn simulatedcontroller_charges() {
+        let mut controller = day();
+        let status = SunSaverResponse::from_raw_bits(controller.ram());
+        assert_eq!(status.charge_state(), ChargeState::BulkCharge);
+        assert_eq!(status.load_state(), LoadState::Normal);
+        assert!(status.battery_charge_current_filtered() > 10.0);
+        assert!((status.load_current_filtered() - 1.0).abs() < 0.01);
+        assert!(status.output_power() > 150.0);
+        assert_eq!(status.hourmeter(), 9000);
+        assert!(status.undocumented_values().is_empty());
+
+        // Full sun soon fills the battery, absorption lasts an hour before float
+        controller.advance(3600);
+        let status = SunSaverResponse::from_raw_bits(controller.ram());
+        assert_eq!(status.charge_state(), ChargeState::Absorption);
+        assert!((status.battery_voltage_filtered() - 14.0).abs() < 0.1);
+        controller.advance(3600);
+        let status = SunSaverResponse::from_raw_bits(controller.ram());
+        assert_eq!(status.charge_state(), ChargeState::Float);
+        assert!((status.battery_voltage_filtered() - 13.5).abs() < 0.1);
+        assert_eq!(status.hourmeter(), 9002);
+        assert!(status.battery_charge_total() > 20.0);
+        assert!((status.load_charge_total() - 2.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn simulatedcontroller_night() {
+        let mut controller = SimulatedController::new(Profile::named("night").unwrap());
+        let status = SunSaverResponse::from_raw_bits(controller.ram());
+        assert_eq!(status.charge_state(), ChargeState::Night);
+        assert_eq!(status.battery_charge_current_filtered(), 0.0);
+
+        // The battery runs down until the load is disconnected
+        controller.advance(4 * DAY_SECONDS);
+        let status = SunSaverResponse::from_raw_bits(controller.ram());
+        assert_eq!(status.load_state(), LoadState::Lvd);
+        assert_eq!(status.load_current_filtered(), 0.0);
+    }
+
+    #[test]
+    fn simulatedcontroller_logged() {
+        let mut controller = SimulatedController::new(Profile::named("cycle").unwrap());
+        assert!(LoggedResponse::from_raw_bits(controller.logged).days.is_empty());
+
+        controller.warm_up(40);
+        assert_eq!(controller.elapsed(), 0);
+        let logged = LoggedResponse::from_raw_bits(controller.logged);
+        // Only the latest 32 days are kept
+        assert_eq!(logged.days.len(), 32);
+        let latest = logged.days.last().unwrap();
+        assert_eq!(latest.hourmeter, 9000 + 40 * 24);
+        assert!(latest.battery_charge_daily() > 0.0);
+        assert!((latest.load_charge_daily() - 21.0).abs() < 0.1);
+        assert!(latest.battery_voltage_max() > latest.battery_voltage_min());
+        assert!(latest.array_voltage_max() > 16.0);
+        assert!(latest.undocumented_values().is_empty());
+    }
+
+    #[test]
+    fn simulatedcontroller_faults() {
+        let mut controller = day();
+        controller.warm_up(1);
+        controller.inject("array:OVERCURENT@60".parse().unwrap());
+        controller.inject("alarm:RTS_OPEN@60+60".parse().unwrap());
+        controller.inject("timeout@120".parse().unwrap());
+
+        controller.advance(60);
+        let status = SunSaverResponse::from_raw_bits(controller.ram());
+        assert_eq!(status.charge_state(), ChargeState::Fault);
+        assert_eq!(status.array_fault(), ArrayFault::OVERCURENT);
+        assert_eq!(status.alarm(), Alarm::RTS_OPEN);
+        assert!(controller.transport_faults().is_empty());
+
+        // The array fault is still active so it is raised again, the alarm has ended
+        controller.advance(60);
+        assert!(controller.write_coil(Coil::ClearFaults.address(), true));
+        assert!(controller.write_coil(Coil::ClearAlarms.address(), true));
+        let status = SunSaverResponse::from_raw_bits(controller.ram());
+        assert_eq!(status.array_fault(), ArrayFault::OVERCURENT);
+        assert_eq!(status.alarm(), Alarm::empty());
+        assert_eq!(controller.transport_faults(), vec![FaultKind::Timeout]);
+    }
+
+    #[test]
+    fn simulatedcontroller_registers() {
+        let mut controller = day();
+        assert_eq!(controller.read_register(0x0008), Some(controller.ram()[0]));
+        assert_eq!(controller.read_register(0x8000), Some(0xFFFF));
+        assert_eq!(controller.read_register(0xE001), Some(0x1133));
+        assert_eq!(controller.read_register(0x0100), None);
+
+        assert!(controller.write_register(0xE001, 0x1168));
+        assert_eq!(controller.read_register(0xE001), Some(0x1168));
+        assert!(!controller.write_register(0x0008, 0));
+        assert!(!controller.write_register(0x0100, 0));
+
+        assert!(controller.write_coil(Coil::LoadDisconnect.address(), true));
+        assert!(controller.read_coil(Coil::LoadDisconnect.address()));
+        let status = SunSaverResponse::from_raw_bits(controller.ram());
+        assert_eq!(status.load_state(), LoadState::Disconnect);
+        assert!(!controller.write_coil(0x0002, true));
+
+        assert!(controller.write_coil(Coil::ResetControl.address(), true));
+        assert!(!controller.read_coil(Coil::ResetControl.address()));
+        let status = SunSaverResponse::from_raw_bits(controller.ram());
+        assert_eq!(status.alarm(), Alarm::CONTROLLER_WAS_RESET);
+    }
+}
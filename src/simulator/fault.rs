@@ -0,0 +1,158 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::sunsaver::{Alarm, ArrayFault, LoadFault, ALARM_FLAGS, ARRAY_FAULT_FLAGS, LOAD_FAULT_FLAGS};
+
+/// What an injected fault does while it is active.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FaultKind {
+    /// Requests are not answered
+    Timeout,
+    /// Responses fail their CRC check, Modbus TCP has no CRC so the transaction id is corrupted instead
+    Crc,
+    /// Requests are answered with a "slave device failure" exception
+    Exception,
+    /// Raises an array fault, it stays latched until the clear faults coil is written
+    Array(ArrayFault),
+    /// Raises a load fault, it stays latched until the clear faults coil is written
+    Load(LoadFault),
+    /// Raises an alarm, it stays latched until the clear alarms coil is written
+    Alarm(Alarm),
+}
+
+impl FaultKind {
+    /// Whether the fault is seen on the wire rather than in the registers
+    pub fn is_transport(self) -> bool {
+        match self {
+            FaultKind::Timeout | FaultKind::Crc | FaultKind::Exception => true,
+            FaultKind::Array(_) | FaultKind::Load(_) | FaultKind::Alarm(_) => false,
+        }
+    }
+}
+
+fn find_flag<T: fmt::Debug + Copy>(flags: &[T], name: &str) -> Option<T> {
+    flags.iter().find(|flag| format!("{:?}", flag).eq_ignore_ascii_case(name)).copied()
+}
+
+impl FromStr for FaultKind {
+    type Err = String;
+
+    fn from_str(kind: &str) -> Result<FaultKind, String> {
+        let (group, flag) = match kind.find(':') {
+            Some(index) => (&kind[..index], &kind[index + 1..]),
+            None => (kind, ""),
+        };
+        let fault = match group {
+            "timeout" if flag.is_empty() => Some(FaultKind::Timeout),
+            "crc" if flag.is_empty() => Some(FaultKind::Crc),
+            "exception" if flag.is_empty() => Some(FaultKind::Exception),
+            "array" => find_flag(&ARRAY_FAULT_FLAGS, flag).map(FaultKind::Array),
+            "load" => find_flag(&LOAD_FAULT_FLAGS, flag).map(FaultKind::Load),
+            "alarm" => find_flag(&ALARM_FLAGS, flag).map(FaultKind::Alarm),
+            _ => None,
+        };
+        fault.ok_or_else(|| {
+            format!(
+                "Unknown fault {:?}, expected timeout, crc, exception, array:FLAG, load:FLAG or alarm:FLAG",
+                kind
+            )
+        })
+    }
+}
+
+/// A fault injected into the simulator, active from `start` simulated seconds for `seconds` or for good.
+///
+/// Written as `KIND[@START[+SECONDS]]` e.g. `timeout@60+30` or `array:OVERCURENT@3600`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Fault {
+    pub kind: FaultKind,
+    pub start: u64,
+    pub seconds: Option<u64>,
+}
+
+impl Fault {
+    pub fn new(kind: FaultKind) -> Fault {
+        Fault {
+            kind,
+            start: 0,
+            seconds: None,
+        }
+    }
+
+    pub fn is_active(&self, elapsed: u64) -> bool {
+        elapsed >= self.start && self.seconds.map_or(true, |seconds| elapsed - self.start < seconds)
+    }
+}
+
+impl FromStr for Fault {
+    type Err = String;
+
+    fn from_str(fault: &str) -> Result<Fault, String> {
+        let (kind, timing) = match fault.find('@') {
+            Some(index) => (&fault[..index], Some(&fault[index + 1..])),
+            None => (fault, None),
+        };
+        let mut fault = Fault::new(kind.parse()?);
+        if let Some(timing) = timing {
+            let (start, seconds) = match timing.find('+') {
+                Some(index) => (&timing[..index], Some(&timing[index + 1..])),
+                None => (timing, None),
+            };
+            fault.start = start.parse().map_err(|_| format!("Invalid fault start {:?}, expected seconds", start))?;
+            fault.seconds = match seconds {
+                Some(seconds) => Some(
+                    seconds
+                        .parse()
+                        .map_err(|_| format!("Invalid fault length {:?}, expected seconds", seconds))?,
+                ),
+                None => None,
+            };
+        }
+        Ok(fault)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn fault_from_str() {
+        assert_eq!("timeout".parse(), Ok(Fault::new(FaultKind::Timeout)));
+        assert_eq!(
+            "crc@60+30".parse(),
+            Ok(Fault {
+                kind: FaultKind::Crc,
+                start: 60,
+                seconds: Some(30),
+            })
+        );
+        assert_eq!(
+            "array:OVERCURENT@3600".parse(),
+            Ok(Fault {
+                kind: FaultKind::Array(ArrayFault::OVERCURENT),
+                start: 3600,
+                seconds: None,
+            })
+        );
+        assert_eq!("load:hvd".parse(), Ok(Fault::new(FaultKind::Load(LoadFault::HVD))));
+        assert_eq!("alarm:RTS_OPEN".parse(), Ok(Fault::new(FaultKind::Alarm(Alarm::RTS_OPEN))));
+
+        assert!("array:SMOKE".parse::<Fault>().is_err());
+        assert!("timeout:RTS_OPEN".parse::<Fault>().is_err());
+        assert!("exception@soon".parse::<Fault>().is_err());
+        assert!("exception@10+".parse::<Fault>().is_err());
+    }
+
+    #[test]
+    fn fault_is_active() {
+        let fault: Fault = "timeout@60+30".parse().unwrap();
+        assert!(!fault.is_active(59));
+        assert!(fault.is_active(60));
+        assert!(fault.is_active(89));
+        assert!(!fault.is_active(90));
+        assert!(Fault::new(FaultKind::Exception).is_active(u64::MAX));
+        assert!(FaultKind::Crc.is_transport());
+        assert!(!FaultKind::Alarm(Alarm::RTS_OPEN).is_transport());
+    }
+}
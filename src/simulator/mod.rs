@@ -0,0 +1,11 @@
+mod profile;
+pub use self::profile::{Profile, ProfileStep};
+
+mod fault;
+pub use self::fault::{Fault, FaultKind};
+
+mod controller;
+pub use self::controller::SimulatedController;
+
+mod server;
+pub use self::server::SimulatorServer;
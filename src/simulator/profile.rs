@@ -0,0 +1,178 @@
+use std::f32::consts::PI;
+use std::fs;
+use std::path::Path;
+
+use toml;
+
+use crate::config::ConfigError;
+
+// The SunSaver MPPT load output is rated for 15 A
+pub const MAX_LOAD_CURRENT: f32 = 15.0;
+
+fn default_ambient_temperature() -> f32 {
+    20.0
+}
+
+/// Conditions the simulated controller runs under until the step's time has passed.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProfileStep {
+    /// Simulated seconds the step lasts
+    pub seconds: u32,
+    /// Fraction of the array's rated power available, 0 at night
+    pub irradiance: f32,
+    /// Amps drawn by the load while it is connected
+    #[serde(default)]
+    pub load_current: f32,
+    /// Degrees C
+    #[serde(default = "default_ambient_temperature")]
+    pub ambient_temperature: f32,
+}
+
+/// Steps played in order, starting again from the first once the last has finished.
+///
+/// Scripts are TOML files with a `[[step]]` table for each step:
+///
+/// ```toml
+/// [[step]]
+/// seconds = 3600
+/// irradiance = 0.8
+/// load_current = 1.5
+/// ambient_temperature = 25
+/// ```
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    #[serde(rename = "step")]
+    pub steps: Vec<ProfileStep>,
+}
+
+impl Profile {
+    /// Profiles available without a script
+    pub const NAMES: [&'static str; 3] = ["day", "night", "cycle"];
+
+    pub fn named(name: &str) -> Option<Profile> {
+        let steps = match name {
+            "day" => vec![ProfileStep {
+                seconds: 3600,
+                irradiance: 1.0,
+                load_current: 1.0,
+                ambient_temperature: 25.0,
+            }],
+            "night" => vec![ProfileStep {
+                seconds: 3600,
+                irradiance: 0.0,
+                load_current: 1.0,
+                ambient_temperature: 15.0,
+            }],
+            "cycle" => (0..24).map(Profile::cycle_hour).collect(),
+            _ => return None,
+        };
+        Some(Profile { steps })
+    }
+
+    /// One hour of a clear day, the sun is up from 06:00 to 18:00 and the load is heaviest in the evening
+    fn cycle_hour(hour: u32) -> ProfileStep {
+        let sun = (PI * (hour as f32 + 0.5 - 6.0) / 12.0).sin();
+        let irradiance = if (6..18).contains(&hour) { sun.max(0.0) } else { 0.0 };
+        ProfileStep {
+            seconds: 3600,
+            irradiance,
+            load_current: if hour >= 18 { 2.0 } else { 0.5 },
+            ambient_temperature: 15.0 + 10.0 * irradiance,
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Profile, ConfigError> {
+        Ok(toml::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.steps.is_empty() {
+            return Err(String::from("A profile needs at least one step"));
+        }
+        for (i, step) in self.steps.iter().enumerate() {
+            if step.seconds == 0 {
+                return Err(format!("Step {} must last at least one second", i + 1));
+            }
+            if !(0.0..=1.0).contains(&step.irradiance) {
+                return Err(format!("Step {} irradiance must be from 0 to 1", i + 1));
+            }
+            if !(0.0..=MAX_LOAD_CURRENT).contains(&step.load_current) {
+                return Err(format!("Step {} load current must be from 0 to {} A", i + 1, MAX_LOAD_CURRENT));
+            }
+        }
+        Ok(())
+    }
+
+    /// Simulated seconds before the profile repeats
+    pub fn seconds(&self) -> u64 {
+        self.steps.iter().map(|step| u64::from(step.seconds)).sum()
+    }
+
+    /// The step running `elapsed` simulated seconds after the profile started
+    pub fn step_at(&self, elapsed: u64) -> &ProfileStep {
+        let mut offset = elapsed % self.seconds();
+        for step in &self.steps {
+            if offset < u64::from(step.seconds) {
+                return step;
+            }
+            offset -= u64::from(step.seconds);
+        }
+        unreachable!("offset is always within the profile")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn profile_named() {
+        for name in Profile::NAMES.iter() {
+            assert_eq!(Profile::named(name).unwrap().validate(), Ok(()));
+        }
+        assert!(Profile::named("dusk").is_none());
+
+        let cycle = Profile::named("cycle").unwrap();
+        assert_eq!(cycle.seconds(), 24 * 3600);
+        assert_eq!(cycle.step_at(0).irradiance, 0.0);
+        assert!(cycle.step_at(12 * 3600).irradiance > 0.9);
+        assert_eq!(cycle.step_at(20 * 3600).irradiance, 0.0);
+        assert_eq!(cycle.step_at(20 * 3600).load_current, 2.0);
+    }
+
+    #[test]
+    fn profile_script() {
+        let profile: Profile = toml::from_str(
+            r#"
+            [[step]]
+            seconds = 60
+            irradiance = 0.5
+
+            [[step]]
+            seconds = 30
+            irradiance = 0.0
+            load_current = 2.5
+            ambient_temperature = 5
+            "#,
+        )
+        .unwrap();
+        assert_eq!(profile.validate(), Ok(()));
+        assert_eq!(profile.seconds(), 90);
+        assert_eq!(profile.step_at(59).irradiance, 0.5);
+        assert_eq!(profile.step_at(59).ambient_temperature, 20.0);
+        assert_eq!(profile.step_at(60).load_current, 2.5);
+        // Repeats from the first step
+        assert_eq!(profile.step_at(90).irradiance, 0.5);
+
+        let invalid = Profile {
+            steps: vec![ProfileStep {
+                seconds: 0,
+                ..profile.steps[0]
+            }],
+        };
+        assert_eq!(invalid.validate(), Err(String::from("Step 1 must last at least one second")));
+        assert!(Profile { steps: vec![] }.validate().is_err());
+    }
+}
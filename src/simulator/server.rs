@@ -0,0 +1,471 @@
+use std::ffi::CStr;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::mem;
+use std::net::{TcpListener, TcpStream};
+use std::os::raw::c_char;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::path::PathBuf;
+use std::ptr;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::Instant;
+
+use libc;
+
+use crate::simulator::{FaultKind, SimulatedController};
+
+const READ_COILS: u8 = 0x01;
+const READ_HOLDING_REGISTERS: u8 = 0x03;
+const READ_INPUT_REGISTERS: u8 = 0x04;
+const WRITE_SINGLE_COIL: u8 = 0x05;
+const WRITE_SINGLE_REGISTER: u8 = 0x06;
+
+const ILLEGAL_FUNCTION: u8 = 0x01;
+const ILLEGAL_DATA_ADDRESS: u8 = 0x02;
+const ILLEGAL_DATA_VALUE: u8 = 0x03;
+const SLAVE_DEVICE_FAILURE: u8 = 0x04;
+
+// Largest counts a single Modbus read may request
+const MAX_READ_REGISTERS: u16 = 125;
+const MAX_READ_COILS: u16 = 2000;
+
+// Every supported request is a function code, an address and a count or value
+const REQUEST_PDU_LEN: usize = 5;
+const MBAP_HEADER_LEN: usize = 7;
+const RTU_FRAME_LEN: usize = 1 + REQUEST_PDU_LEN + 2;
+
+/// CRC-16/MODBUS, sent low byte first
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0xFFFFu16;
+    for byte in data {
+        crc ^= u16::from(*byte);
+        for _ in 0..8 {
+            crc = if crc & 0x0001 != 0 { (crc >> 1) ^ 0xA001 } else { crc >> 1 };
+        }
+    }
+    crc
+}
+
+fn exception(function: u8, code: u8) -> Vec<u8> {
+    vec![function | 0x80, code]
+}
+
+/// A response PDU, `corrupt` when a CRC fault is active
+struct Reply {
+    pdu: Vec<u8>,
+    corrupt: bool,
+}
+
+/// Answers Modbus requests for a simulated controller, its time moves on `speed` times faster than the wall clock.
+#[derive(Clone)]
+pub struct SimulatorServer {
+    controller: Arc<Mutex<SimulatedController>>,
+    slave_id: u8,
+    speed: u64,
+    started: Instant,
+}
+
+impl SimulatorServer {
+    /// A `speed` of 0 stops the clock, time then only moves on when the controller is advanced directly
+    pub fn new(controller: SimulatedController, slave_id: u8, speed: u64) -> SimulatorServer {
+        SimulatorServer {
+            controller: Arc::new(Mutex::new(controller)),
+            slave_id,
+            speed,
+            started: Instant::now(),
+        }
+    }
+
+    pub fn controller(&self) -> MutexGuard<SimulatedController> {
+        // The controller is only changed by whole requests, so it is consistent even if a handler panicked
+        self.controller.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// `None` when no response is sent, for requests to other slaves, broadcasts and timeouts
+    fn handle(&self, slave_id: u8, pdu: &[u8]) -> Option<Reply> {
+        let broadcast = slave_id == 0;
+        if slave_id != self.slave_id && !broadcast {
+            return None;
+        }
+        let mut controller = self.controller();
+        let elapsed = self.started.elapsed().as_secs() * self.speed;
+        if elapsed > controller.elapsed() {
+            let behind = elapsed - controller.elapsed();
+            controller.advance(behind);
+        }
+
+        let faults = controller.transport_faults();
+        if faults.contains(&FaultKind::Timeout) {
+            debug!("Ignoring request {:02x?} while timing out", pdu);
+            return None;
+        }
+        let pdu = if faults.contains(&FaultKind::Exception) {
+            exception(pdu.first().copied().unwrap_or(0), SLAVE_DEVICE_FAILURE)
+        } else {
+            SimulatorServer::process(&mut controller, pdu)
+        };
+        if broadcast {
+            return None;
+        }
+        Some(Reply {
+            pdu,
+            corrupt: faults.contains(&FaultKind::Crc),
+        })
+    }
+
+    fn process(controller: &mut SimulatedController, pdu: &[u8]) -> Vec<u8> {
+        let function = match pdu.first() {
+            Some(function) => *function,
+            None => return exception(0, ILLEGAL_FUNCTION),
+        };
+        match function {
+            READ_COILS | READ_HOLDING_REGISTERS | READ_INPUT_REGISTERS | WRITE_SINGLE_COIL | WRITE_SINGLE_REGISTER => {}
+            _ => return exception(function, ILLEGAL_FUNCTION),
+        }
+        if pdu.len() != REQUEST_PDU_LEN {
+            return exception(function, ILLEGAL_DATA_VALUE);
+        }
+        let address = (u16::from(pdu[1]) << 8) | u16::from(pdu[2]);
+        let value = (u16::from(pdu[3]) << 8) | u16::from(pdu[4]);
+
+        match function {
+            READ_COILS => {
+                let count = value;
+                if count == 0 || count > MAX_READ_COILS {
+                    return exception(function, ILLEGAL_DATA_VALUE);
+                }
+                let mut coils = vec![0u8; usize::from((count + 7) / 8)];
+                for i in 0..count {
+                    if controller.read_coil(address.wrapping_add(i)) {
+                        coils[usize::from(i / 8)] |= 1 << (i % 8);
+                    }
+                }
+                let mut response = vec![function, coils.len() as u8];
+                response.extend_from_slice(&coils);
+                response
+            }
+            READ_HOLDING_REGISTERS | READ_INPUT_REGISTERS => {
+                let count = value;
+                if count == 0 || count > MAX_READ_REGISTERS {
+                    return exception(function, ILLEGAL_DATA_VALUE);
+                }
+                let mut response = vec![function, (2 * count) as u8];
+                for i in 0..count {
+                    match controller.read_register(address.wrapping_add(i)) {
+                        Some(register) => response.extend_from_slice(&[(register >> 8) as u8, register as u8]),
+                        None => return exception(function, ILLEGAL_DATA_ADDRESS),
+                    }
+                }
+                response
+            }
+            WRITE_SINGLE_COIL => {
+                let value = match value {
+                    0xFF00 => true,
+                    0x0000 => false,
+                    _ => return exception(function, ILLEGAL_DATA_VALUE),
+                };
+                if !controller.write_coil(address, value) {
+                    return exception(function, ILLEGAL_DATA_ADDRESS);
+                }
+                // The response to a single write echoes the request
+                pdu.to_vec()
+            }
+            _ => {
+                if !controller.write_register(address, value) {
+                    return exception(function, ILLEGAL_DATA_ADDRESS);
+                }
+                pdu.to_vec()
+            }
+        }
+    }
+
+    /// Answers Modbus TCP requests from each client of `listener`, only returns if the listener fails
+    pub fn serve_tcp(&self, listener: TcpListener) {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(error) => {
+                    warn!("Failed to accept a Modbus TCP client: {}", error);
+                    return;
+                }
+            };
+            let server = self.clone();
+            thread::spawn(move || {
+                let peer = stream.peer_addr();
+                debug!("Modbus TCP client {:?} connected", peer);
+                if let Err(error) = server.serve_tcp_client(stream) {
+                    debug!("Modbus TCP client {:?} disconnected: {}", peer, error);
+                }
+            });
+        }
+    }
+
+    fn serve_tcp_client(&self, mut stream: TcpStream) -> io::Result<()> {
+        let mut header = [0u8; MBAP_HEADER_LEN];
+        loop {
+            stream.read_exact(&mut header)?;
+            let length = (usize::from(header[4]) << 8) | usize::from(header[5]);
+            if length < 2 {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "MBAP length too short"));
+            }
+            // The length counts the unit id in the header as well as the PDU
+            let mut pdu = vec![0u8; length - 1];
+            stream.read_exact(&mut pdu)?;
+
+            let reply = match self.handle(header[6], &pdu) {
+                Some(reply) => reply,
+                None => continue,
+            };
+            let mut transaction_id = [header[0], header[1]];
+            if reply.corrupt {
+                // TCP frames carry no CRC, a transaction id that does not match is rejected instead
+                transaction_id = [!header[0], !header[1]];
+            }
+            let length = reply.pdu.len() + 1;
+            let mut response = vec![
+                transaction_id[0],
+                transaction_id[1],
+                0x00,
+                0x00,
+                (length >> 8) as u8,
+                length as u8,
+                header[6],
+            ];
+            response.extend_from_slice(&reply.pdu);
+            stream.write_all(&response)?;
+        }
+    }
+
+    /// Opens a pseudo terminal answering Modbus RTU requests, returns the path to use as the serial device
+    pub fn serve_pty(&self) -> io::Result<PathBuf> {
+        let mut master = 0;
+        let mut slave = 0;
+        if unsafe { libc::openpty(&mut master, &mut slave, ptr::null_mut(), ptr::null_mut(), ptr::null_mut()) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // Owned straight away so both ends are closed if setting them up fails
+        let master = unsafe { File::from_raw_fd(master) };
+        // Held open so the master does not read a hang up between clients
+        let slave = unsafe { File::from_raw_fd(slave) };
+
+        let mut name = [0 as c_char; 128];
+        let path = unsafe {
+            // Raw mode so the bytes of each frame pass through unchanged
+            let mut termios: libc::termios = mem::zeroed();
+            if libc::tcgetattr(slave.as_raw_fd(), &mut termios) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            libc::cfmakeraw(&mut termios);
+            if libc::tcsetattr(slave.as_raw_fd(), libc::TCSANOW, &termios) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            if libc::ptsname_r(master.as_raw_fd(), name.as_mut_ptr(), name.len()) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            PathBuf::from(CStr::from_ptr(name.as_ptr()).to_string_lossy().into_owned())
+        };
+
+        let server = self.clone();
+        thread::spawn(move || {
+            let _slave = slave;
+            if let Err(error) = server.serve_rtu(master) {
+                warn!("Stopped serving Modbus RTU: {}", error);
+            }
+        });
+        Ok(path)
+    }
+
+    fn serve_rtu<T: Read + Write>(&self, mut port: T) -> io::Result<()> {
+        let mut buffer = Vec::new();
+        let mut read = [0u8; 256];
+        loop {
+            let len = port.read(&mut read)?;
+            if len == 0 {
+                return Ok(());
+            }
+            buffer.extend_from_slice(&read[..len]);
+            while buffer.len() >= RTU_FRAME_LEN {
+                let frame: Vec<u8> = buffer.drain(..RTU_FRAME_LEN).collect();
+                let (data, crc) = frame.split_at(RTU_FRAME_LEN - 2);
+                if crc16(data) != (u16::from(crc[1]) << 8) | u16::from(crc[0]) {
+                    // The controller drops frames that fail their CRC, which also drops anything queued behind them
+                    debug!("Dropping RTU frame {:02x?} with a bad CRC", frame);
+                    buffer.clear();
+                    break;
+                }
+                if let Some(response) = self.rtu_response(data[0], &data[1..]) {
+                    port.write_all(&response)?;
+                }
+            }
+        }
+    }
+
+    fn rtu_response(&self, slave_id: u8, pdu: &[u8]) -> Option<Vec<u8>> {
+        let reply = self.handle(slave_id, pdu)?;
+        let mut response = vec![slave_id];
+        response.extend_from_slice(&reply.pdu);
+        let mut crc = crc16(&response);
+        if reply.corrupt {
+            crc = !crc;
+        }
+        response.extend_from_slice(&[crc as u8, (crc >> 8) as u8]);
+        Some(response)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::Shutdown;
+
+    use super::*;
+
+    use crate::simulator::{Fault, Profile};
+
+    fn server() -> SimulatorServer {
+        SimulatorServer::new(SimulatedController::new(Profile::named("day").unwrap()), 1, 0)
+    }
+
+    struct TestPort {
+        requests: io::Cursor<Vec<u8>>,
+        responses: Vec<u8>,
+    }
+
+    impl Read for TestPort {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.requests.read(buf)
+        }
+    }
+
+    impl Write for TestPort {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.responses.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn rtu_request(slave_id: u8, function: u8, address: u16, value: u16) -> Vec<u8> {
+        let mut request = vec![slave_id, function, (address >> 8) as u8, address as u8, (value >> 8) as u8, value as u8];
+        let crc = crc16(&request);
+        request.extend_from_slice(&[crc as u8, (crc >> 8) as u8]);
+        request
+    }
+
+    #[test]
+    fn crc16_modbus() {
+        assert_eq!(crc16(b"123456789"), 0x4B37);
+        // Read the 44 RAM registers from slave 1
+        assert_eq!(crc16(&[0x01, 0x03, 0x00, 0x08, 0x00, 0x2C]), 0xD5C5);
+    }
+
+    #[test]
+    fn simulatorserver_process() {
+        let server = server();
+        let mut controller = server.controller();
+
+        let response = SimulatorServer::process(&mut controller, &[0x03, 0xE0, 0x00, 0x00, 0x02]);
+        assert_eq!(response, vec![0x03, 0x04, 0x12, 0x00, 0x11, 0x33]);
+        let response = SimulatorServer::process(&mut controller, &[0x04, 0xE0, 0x01, 0x00, 0x01]);
+        assert_eq!(response, vec![0x04, 0x02, 0x11, 0x33]);
+        // 0xE02F is past the end of the EEPROM
+        let response = SimulatorServer::process(&mut controller, &[0x03, 0xE0, 0x2E, 0x00, 0x02]);
+        assert_eq!(response, vec![0x83, ILLEGAL_DATA_ADDRESS]);
+        let response = SimulatorServer::process(&mut controller, &[0x03, 0x00, 0x08, 0x00, 0x00]);
+        assert_eq!(response, vec![0x83, ILLEGAL_DATA_VALUE]);
+
+        let response = SimulatorServer::process(&mut controller, &[0x06, 0xE0, 0x01, 0x11, 0x68]);
+        assert_eq!(response, vec![0x06, 0xE0, 0x01, 0x11, 0x68]);
+        assert_eq!(controller.read_register(0xE001), Some(0x1168));
+        let response = SimulatorServer::process(&mut controller, &[0x06, 0x00, 0x08, 0x00, 0x00]);
+        assert_eq!(response, vec![0x86, ILLEGAL_DATA_ADDRESS]);
+
+        let response = SimulatorServer::process(&mut controller, &[0x05, 0x00, 0x01, 0xFF, 0x00]);
+        assert_eq!(response, vec![0x05, 0x00, 0x01, 0xFF, 0x00]);
+        let response = SimulatorServer::process(&mut controller, &[0x05, 0x00, 0x01, 0x12, 0x34]);
+        assert_eq!(response, vec![0x85, ILLEGAL_DATA_VALUE]);
+        let response = SimulatorServer::process(&mut controller, &[0x01, 0x00, 0x00, 0x00, 0x02]);
+        assert_eq!(response, vec![0x01, 0x01, 0b0000_0010]);
+
+        let response = SimulatorServer::process(&mut controller, &[0x10, 0xE0, 0x01, 0x00, 0x01]);
+        assert_eq!(response, vec![0x90, ILLEGAL_FUNCTION]);
+        let response = SimulatorServer::process(&mut controller, &[0x03, 0x00]);
+        assert_eq!(response, vec![0x83, ILLEGAL_DATA_VALUE]);
+    }
+
+    #[test]
+    fn simulatorserver_rtu() {
+        let server = server();
+        let request = rtu_request(1, READ_HOLDING_REGISTERS, 0xE000, 1);
+        assert_eq!(
+            server.rtu_response(1, &request[1..6]),
+            Some(vec![0x01, 0x03, 0x02, 0x12, 0x00, 0xB4, 0xE4])
+        );
+        // Requests to other slaves go unanswered
+        assert_eq!(server.rtu_response(2, &request[1..6]), None);
+
+        let mut requests = rtu_request(1, WRITE_SINGLE_REGISTER, 0xE001, 0x1168);
+        let mut corrupted = rtu_request(1, WRITE_SINGLE_REGISTER, 0xE002, 0x0000);
+        corrupted[7] ^= 0xFF;
+        requests.extend(corrupted);
+        let mut port = TestPort {
+            requests: io::Cursor::new(requests),
+            responses: Vec::new(),
+        };
+        server.serve_rtu(&mut port).unwrap();
+        // Only the write with a good CRC is echoed
+        assert_eq!(port.responses, rtu_request(1, WRITE_SINGLE_REGISTER, 0xE001, 0x1168));
+        assert_eq!(server.controller().read_register(0xE001), Some(0x1168));
+        assert_eq!(server.controller().read_register(0xE002), Some(0x0E10));
+
+        server.controller().inject("crc".parse().unwrap());
+        let response = server.rtu_response(1, &request[1..6]).unwrap();
+        assert_eq!(&response[..5], &[0x01, 0x03, 0x02, 0x12, 0x00]);
+        assert_ne!(&response[5..], &[0xB4, 0xE4]);
+    }
+
+    #[test]
+    fn simulatorserver_tcp() {
+        let server = server();
+        server.controller().inject(Fault {
+            kind: FaultKind::Exception,
+            start: 60,
+            seconds: None,
+        });
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let tcp_server = server.clone();
+        thread::spawn(move || tcp_server.serve_tcp(listener));
+
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream
+            .write_all(&[0x00, 0x07, 0x00, 0x00, 0x00, 0x06, 0x01, 0x03, 0xE0, 0x00, 0x00, 0x01])
+            .unwrap();
+        let mut response = [0u8; 11];
+        stream.read_exact(&mut response).unwrap();
+        assert_eq!(response, [0x00, 0x07, 0x00, 0x00, 0x00, 0x05, 0x01, 0x03, 0x02, 0x12, 0x00]);
+
+        server.controller().advance(60);
+        stream
+            .write_all(&[0x00, 0x08, 0x00, 0x00, 0x00, 0x06, 0x01, 0x03, 0xE0, 0x00, 0x00, 0x01])
+            .unwrap();
+        let mut response = [0u8; 9];
+        stream.read_exact(&mut response).unwrap();
+        assert_eq!(response, [0x00, 0x08, 0x00, 0x00, 0x00, 0x03, 0x01, 0x83, SLAVE_DEVICE_FAILURE]);
+        stream.shutdown(Shutdown::Both).unwrap();
+    }
+
+    #[test]
+    fn simulatorserver_pty() {
+        let server = server();
+        let path = server.serve_pty().unwrap();
+        let mut port = std::fs::OpenOptions::new().read(true).write(true).open(&path).unwrap();
+
+        port.write_all(&rtu_request(1, READ_HOLDING_REGISTERS, 0xE000, 1)).unwrap();
+        let mut response = [0u8; 7];
+        port.read_exact(&mut response).unwrap();
+        assert_eq!(response, [0x01, 0x03, 0x02, 0x12, 0x00, 0xB4, 0xE4]);
+    }
+}
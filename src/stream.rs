@@ -0,0 +1,204 @@
+use std::sync::{Arc, Mutex, PoisonError};
+
+use chrono::Utc;
+use futures::sync::mpsc;
+use serde_json::{self, Map, Value};
+
+use crate::api::ApiStatusResponse;
+use crate::poller::{PollListener, Snapshot};
+use crate::sunsaver::SunSaverResponse;
+
+// Statuses queued for a subscriber that is not keeping up, later ones are skipped until it catches up
+const SUBSCRIBER_BUFFER: usize = 4;
+
+fn lookup<'a>(value: &'a Value, path: &[String]) -> Option<&'a Value> {
+    path.iter().try_fold(value, |value, key| value.get(key.as_str()))
+}
+
+fn insert(target: &mut Value, path: &[String], value: Value) {
+    let mut target = target;
+    for key in path {
+        if !target.is_object() {
+            *target = Value::Object(Map::new());
+        }
+        target = target.as_object_mut().unwrap().entry(key.clone()).or_insert(Value::Null);
+    }
+    *target = value;
+}
+
+/// The parts of `ApiStatusResponse` a subscriber asked for, as dotted paths e.g. `storage.battery_voltage_filtered`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldFilter {
+    paths: Vec<Vec<String>>,
+}
+
+impl FieldFilter {
+    /// Parses comma separated paths, no paths selects the whole response
+    pub fn parse(fields: Option<&str>) -> Result<FieldFilter, String> {
+        // Every field is present, if only as null, so paths can be checked before the first poll
+        let snapshot = Snapshot {
+            status: SunSaverResponse::from_raw_bits([0u16; 44]),
+            read_at: Utc::now(),
+        };
+        let sample = serde_json::to_value(ApiStatusResponse::from_snapshot(snapshot, Utc::now(), false, None)).unwrap();
+
+        let mut paths = Vec::new();
+        for field in fields.unwrap_or("").split(',').map(str::trim).filter(|field| !field.is_empty()) {
+            let path: Vec<String> = field.split('.').map(String::from).collect();
+            if lookup(&sample, &path).is_none() {
+                return Err(format!("Unknown field {:?}", field));
+            }
+            paths.push(path);
+        }
+        Ok(FieldFilter { paths })
+    }
+
+    pub fn apply(&self, value: &Value) -> Value {
+        if self.paths.is_empty() {
+            return value.clone();
+        }
+        let mut filtered = Value::Object(Map::new());
+        for path in self.paths.iter() {
+            if let Some(selected) = lookup(value, path) {
+                insert(&mut filtered, path, selected.clone());
+            }
+        }
+        filtered
+    }
+}
+
+struct Subscriber {
+    filter: FieldFilter,
+    sender: mpsc::Sender<String>,
+}
+
+impl Subscriber {
+    /// Queues `response` as JSON, returning false once the subscriber has gone away
+    fn send(&mut self, response: &Value) -> bool {
+        match self.sender.try_send(self.filter.apply(response).to_string()) {
+            Ok(()) => true,
+            Err(ref error) if error.is_full() => {
+                trace!("Stream: skipped a status for a slow subscriber");
+                true
+            }
+            Err(_) => false,
+        }
+    }
+}
+
+/// Pushes every polled status to the live stream subscribers, cloned handles share the subscribers.
+#[derive(Clone, Default)]
+pub struct StatusStream {
+    subscribers: Arc<Mutex<Vec<Subscriber>>>,
+}
+
+impl StatusStream {
+    pub fn new() -> StatusStream {
+        StatusStream::default()
+    }
+
+    /// Returns the JSON of each polled status, starting with `current` if the device has been read
+    pub fn subscribe(&self, filter: FieldFilter, current: Option<&ApiStatusResponse>) -> mpsc::Receiver<String> {
+        let (sender, receiver) = mpsc::channel(SUBSCRIBER_BUFFER);
+        let mut subscriber = Subscriber { filter, sender };
+        if let Some(current) = current {
+            subscriber.send(&serde_json::to_value(current).unwrap());
+        }
+        self.subscribers.lock().unwrap_or_else(PoisonError::into_inner).push(subscriber);
+        receiver
+    }
+
+    pub fn subscriber_count(&self) -> usize {
+        self.subscribers.lock().unwrap_or_else(PoisonError::into_inner).len()
+    }
+}
+
+impl PollListener for StatusStream {
+    fn on_snapshot(&mut self, snapshot: &Snapshot) {
+        let mut subscribers = self.subscribers.lock().unwrap_or_else(PoisonError::into_inner);
+        if subscribers.is_empty() {
+            return;
+        }
+        // The snapshot was read just now, so it is neither stale nor preceded by a failed poll
+        let response = ApiStatusResponse::from_snapshot(snapshot.clone(), snapshot.read_at, false, None);
+        let response = serde_json::to_value(response).unwrap();
+        let remaining = subscribers
+            .drain(..)
+            .filter_map(|mut subscriber| if subscriber.send(&response) { Some(subscriber) } else { None })
+            .collect();
+        *subscribers = remaining;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::Stream;
+
+    use super::*;
+
+    fn test_snapshot() -> Snapshot {
+        let mut registers = [0u16; 44];
+        registers[0] = 0x1200;
+        Snapshot {
+            status: SunSaverResponse::from_raw_bits(registers),
+            read_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn fieldfilter_parse() {
+        assert_eq!(FieldFilter::parse(None), Ok(FieldFilter { paths: Vec::new() }));
+        assert_eq!(FieldFilter::parse(Some("")), Ok(FieldFilter { paths: Vec::new() }));
+        assert_eq!(
+            FieldFilter::parse(Some("storage.battery_voltage_filtered, faults,snapshot.age")),
+            Ok(FieldFilter {
+                paths: vec![
+                    vec![String::from("storage"), String::from("battery_voltage_filtered")],
+                    vec![String::from("faults")],
+                    vec![String::from("snapshot"), String::from("age")],
+                ]
+            })
+        );
+        assert_eq!(
+            FieldFilter::parse(Some("storage.voltage")),
+            Err(String::from("Unknown field \"storage.voltage\""))
+        );
+        assert!(FieldFilter::parse(Some("storage..battery_voltage_filtered")).is_err());
+    }
+
+    #[test]
+    fn fieldfilter_apply() {
+        let value: Value =
+            serde_json::from_str(r#"{"storage": {"battery_voltage_filtered": 14.0625, "charge_state": "Float"}, "faults": {"array": []}}"#).unwrap();
+        assert_eq!(FieldFilter::parse(None).unwrap().apply(&value), value);
+        let filter = FieldFilter::parse(Some("storage.battery_voltage_filtered,faults")).unwrap();
+        assert_eq!(
+            filter.apply(&value).to_string(),
+            r#"{"faults":{"array":[]},"storage":{"battery_voltage_filtered":14.0625}}"#
+        );
+    }
+
+    #[test]
+    fn statusstream_on_snapshot() {
+        let mut stream = StatusStream::new();
+        let snapshot = test_snapshot();
+        let current = ApiStatusResponse::from_snapshot(snapshot.clone(), snapshot.read_at, false, None);
+        let all = stream.subscribe(FieldFilter::parse(None).unwrap(), Some(&current));
+        let voltage = stream.subscribe(FieldFilter::parse(Some("storage.battery_voltage_filtered")).unwrap(), None);
+        let gone = stream.subscribe(FieldFilter::parse(None).unwrap(), None);
+        drop(gone);
+
+        stream.on_snapshot(&snapshot);
+        assert_eq!(stream.subscriber_count(), 2);
+
+        let all: Vec<String> = all.take(2).wait().map(Result::unwrap).collect();
+        assert_eq!(all[0], all[1]);
+        let status: Value = serde_json::from_str(&all[0]).unwrap();
+        assert_eq!(status["storage"]["battery_voltage_filtered"], 14.0625);
+        assert_eq!(status["snapshot"]["stale"], false);
+        assert_eq!(
+            voltage.wait().next().unwrap().unwrap(),
+            r#"{"storage":{"battery_voltage_filtered":14.0625}}"#
+        );
+    }
+}
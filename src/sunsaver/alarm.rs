@@ -0,0 +1,158 @@
+use std::convert::TryFrom;
+
+use std::result::Result::{self, Ok};
+
+use serde::ser::{Serialize, SerializeMap, Serializer};
+
+use crate::DecodeError;
+
+bitflags! {
+    pub struct Alarm: u32 {
+        const RTS_OPEN                          = 0b0000_0000_0000_0000_0000_0001;
+        const RTS_SHORTED                       = 0b0000_0000_0000_0000_0000_0010;
+        const RTS_DISCONNECTED                  = 0b0000_0000_0000_0000_0000_0100;
+        const HEATSINK_TEMP_SENSOR_OPEN         = 0b0000_0000_0000_0000_0000_1000;
+        const HEATSINK_TEMP_SENSOR_SHORTED      = 0b0000_0000_0000_0000_0001_0000;
+        const HEATSINK_TEMP_LIMIT               = 0b0000_0000_0000_0000_0010_0000;
+        const CURRENT_LIMIT                     = 0b0000_0000_0000_0000_0100_0000;
+        const CURRENT_OFFSET                    = 0b0000_0000_0000_0000_1000_0000;
+        const BATTERY_SENSE_OUT_OF_RANGE        = 0b0000_0000_0000_0001_0000_0000;
+        const BATTERY_SENSE_DISCONNECTED        = 0b0000_0000_0000_0010_0000_0000;
+        const UNCALIBRATED                      = 0b0000_0000_0000_0100_0000_0000;
+        const RTS_MISWIRE                       = 0b0000_0000_0000_1000_0000_0000;
+        const HIGH_VOLTAGE_DISCONNECT           = 0b0000_0000_0001_0000_0000_0000;
+        const SYSTEM_MISWIRE                    = 0b0000_0000_0100_0000_0000_0000;
+        const MOSFET_OPEN                       = 0b0000_0000_1000_0000_0000_0000;
+        const P12_VOLTAGE_OFF                   = 0b0000_0001_0000_0000_0000_0000;
+        const HIGH_INPUT_VOLTAGE_CURRENT_LIMIT  = 0b0000_0010_0000_0000_0000_0000;
+        const ADC_INPUT_MAX                     = 0b0000_0100_0000_0000_0000_0000;
+        const CONTROLLER_WAS_RESET              = 0b0000_1000_0000_0000_0000_0000;
+    }
+}
+pub const ALARM_FLAGS: [Alarm; 19] = [
+    Alarm::RTS_OPEN,
+    Alarm::RTS_SHORTED,
+    Alarm::RTS_DISCONNECTED,
+    Alarm::HEATSINK_TEMP_SENSOR_OPEN,
+    Alarm::HEATSINK_TEMP_SENSOR_SHORTED,
+    Alarm::HEATSINK_TEMP_LIMIT,
+    Alarm::CURRENT_LIMIT,
+    Alarm::CURRENT_OFFSET,
+    Alarm::BATTERY_SENSE_OUT_OF_RANGE,
+    Alarm::BATTERY_SENSE_DISCONNECTED,
+    Alarm::UNCALIBRATED,
+    Alarm::RTS_MISWIRE,
+    Alarm::HIGH_VOLTAGE_DISCONNECT,
+    Alarm::SYSTEM_MISWIRE,
+    Alarm::MOSFET_OPEN,
+    Alarm::P12_VOLTAGE_OFF,
+    Alarm::HIGH_INPUT_VOLTAGE_CURRENT_LIMIT,
+    Alarm::ADC_INPUT_MAX,
+    Alarm::CONTROLLER_WAS_RESET,
+];
+
+impl Alarm {
+    /// Never fails, bits without a documented meaning are preserved
+    pub fn from_raw(val: u32) -> Alarm {
+        Alarm { bits: val }
+    }
+
+    pub fn unknown_bits(&self) -> u32 {
+        self.bits() & !Alarm::all().bits()
+    }
+}
+
+impl TryFrom<u32> for Alarm {
+    type Error = DecodeError;
+
+    fn try_from(val: u32) -> Result<Alarm, DecodeError> {
+        Alarm::from_bits(val).ok_or_else(|| DecodeError::new("alarm", val))
+    }
+}
+
+impl Serialize for Alarm {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let unknown_bits = self.unknown_bits();
+        let len = ALARM_FLAGS.len() + if unknown_bits == 0 { 0 } else { 1 };
+        let mut map = serializer.serialize_map(Some(len))?;
+        for flag in ALARM_FLAGS.iter() {
+            let is_set = self.contains(*flag);
+            map.serialize_entry(&format!("{:?}", flag), &is_set)?;
+        }
+        if unknown_bits != 0 {
+            map.serialize_entry("UNKNOWN", &unknown_bits)?;
+        }
+        map.end()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json;
+
+    #[test]
+    fn sunsaverresponse_alarm() {
+        assert_eq!(Alarm::try_from(0x00_0000).unwrap(), Alarm::empty());
+        assert_eq!(Alarm::try_from(0x00_0001).unwrap(), Alarm::RTS_OPEN);
+        assert_eq!(Alarm::try_from(0x00_0020).unwrap(), Alarm::HEATSINK_TEMP_LIMIT);
+        assert_eq!(Alarm::try_from(0x08_0040).unwrap(), Alarm::CURRENT_LIMIT | Alarm::CONTROLLER_WAS_RESET);
+        assert_eq!(Alarm::try_from(0x00_2000), Err(DecodeError::new("alarm", 0x2000)));
+    }
+
+    #[test]
+    fn sunsaverresponse_alarm_unknown() {
+        let native = Alarm::from_raw(0x80_2001);
+        assert!(native.contains(Alarm::RTS_OPEN));
+        assert_eq!(native.unknown_bits(), 0x80_2000);
+
+        let json = serde_json::to_string(&native).unwrap();
+        assert!(json.starts_with("{\"RTS_OPEN\":true,"), json);
+        assert!(json.ends_with("\"CONTROLLER_WAS_RESET\":false,\"UNKNOWN\":8396800}"), json);
+    }
+
+    #[test]
+    fn sunsaverresponse_alarm_serialize() {
+        let native = Alarm::empty();
+        let json = serde_json::to_string(&native).unwrap();
+        assert_eq!(
+            json,
+            "{\
+             \"RTS_OPEN\":false,\
+             \"RTS_SHORTED\":false,\
+             \"RTS_DISCONNECTED\":false,\
+             \"HEATSINK_TEMP_SENSOR_OPEN\":false,\
+             \"HEATSINK_TEMP_SENSOR_SHORTED\":false,\
+             \"HEATSINK_TEMP_LIMIT\":false,\
+             \"CURRENT_LIMIT\":false,\
+             \"CURRENT_OFFSET\":false,\
+             \"BATTERY_SENSE_OUT_OF_RANGE\":false,\
+             \"BATTERY_SENSE_DISCONNECTED\":false,\
+             \"UNCALIBRATED\":false,\
+             \"RTS_MISWIRE\":false,\
+             \"HIGH_VOLTAGE_DISCONNECT\":false,\
+             \"SYSTEM_MISWIRE\":false,\
+             \"MOSFET_OPEN\":false,\
+             \"P12_VOLTAGE_OFF\":false,\
+             \"HIGH_INPUT_VOLTAGE_CURRENT_LIMIT\":false,\
+             \"ADC_INPUT_MAX\":false,\
+             \"CONTROLLER_WAS_RESET\":false\
+             }"
+        );
+
+        let native = Alarm::RTS_OPEN | Alarm::RTS_SHORTED;
+        let json = serde_json::to_string(&native).unwrap();
+        assert!(
+            json.starts_with(
+                "{\
+                 \"RTS_OPEN\":true,\
+                 \"RTS_SHORTED\":true,\
+                 \"RTS_DISCONNECTED\":false,",
+            ),
+            json
+        );
+    }
+}
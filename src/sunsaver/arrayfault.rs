@@ -1,9 +1,11 @@
-use std::convert::From;
+use std::convert::TryFrom;
 
 use std::result::Result::{self, Ok};
 
 use serde::ser::{Serialize, SerializeMap, Serializer};
 
+use crate::DecodeError;
+
 bitflags! {
     pub struct ArrayFault: u16 {
         const OVERCURENT                = 0b0000_0000_0000_0001;
@@ -17,7 +19,7 @@ bitflags! {
         const INTERNAL_TEMP_SENSOR_FAIL = 0b0000_0001_0000_0000;
     }
 }
-const ARRAY_FAULT_FLAGS: [ArrayFault; 9] = [
+pub const ARRAY_FAULT_FLAGS: [ArrayFault; 9] = [
     ArrayFault::OVERCURENT,
     ArrayFault::FETS_SHORTED,
     ArrayFault::SOFTWARE_BUGS,
@@ -29,9 +31,22 @@ const ARRAY_FAULT_FLAGS: [ArrayFault; 9] = [
     ArrayFault::INTERNAL_TEMP_SENSOR_FAIL,
 ];
 
-impl From<u16> for ArrayFault {
-    fn from(val: u16) -> ArrayFault {
-        ArrayFault::from_bits(val).expect("Value does not match documented bit fields")
+impl ArrayFault {
+    /// Never fails, bits without a documented meaning are preserved
+    pub fn from_raw(val: u16) -> ArrayFault {
+        ArrayFault { bits: val }
+    }
+
+    pub fn unknown_bits(&self) -> u16 {
+        self.bits() & !ArrayFault::all().bits()
+    }
+}
+
+impl TryFrom<u16> for ArrayFault {
+    type Error = DecodeError;
+
+    fn try_from(val: u16) -> Result<ArrayFault, DecodeError> {
+        ArrayFault::from_bits(val).ok_or_else(|| DecodeError::new("array fault", u32::from(val)))
     }
 }
 
@@ -40,11 +55,16 @@ impl Serialize for ArrayFault {
     where
         S: Serializer,
     {
-        let mut map = serializer.serialize_map(Some(ARRAY_FAULT_FLAGS.len()))?;
+        let unknown_bits = self.unknown_bits();
+        let len = ARRAY_FAULT_FLAGS.len() + if unknown_bits == 0 { 0 } else { 1 };
+        let mut map = serializer.serialize_map(Some(len))?;
         for flag in ARRAY_FAULT_FLAGS.iter() {
             let is_set = self.contains(*flag);
             map.serialize_entry(&format!("{:?}", flag), &is_set)?;
         }
+        if unknown_bits != 0 {
+            map.serialize_entry("UNKNOWN", &unknown_bits)?;
+        }
         map.end()
     }
 }
@@ -56,10 +76,40 @@ mod test {
 
     #[test]
     fn sunsaverresponse_array_fault() {
-        assert_eq!(ArrayFault::from(0b0000_0000_0000_0000), ArrayFault::empty());
-        assert_eq!(ArrayFault::from(0b0000_0000_0000_0001), ArrayFault::OVERCURENT);
-        assert_eq!(ArrayFault::from(0b0000_0000_0000_0010), ArrayFault::FETS_SHORTED);
-        assert_eq!(ArrayFault::from(0b0000_0000_0000_0011), ArrayFault::OVERCURENT | ArrayFault::FETS_SHORTED);
+        assert_eq!(ArrayFault::try_from(0b0000_0000_0000_0000).unwrap(), ArrayFault::empty());
+        assert_eq!(ArrayFault::try_from(0b0000_0000_0000_0001).unwrap(), ArrayFault::OVERCURENT);
+        assert_eq!(ArrayFault::try_from(0b0000_0000_0000_0010).unwrap(), ArrayFault::FETS_SHORTED);
+        assert_eq!(
+            ArrayFault::try_from(0b0000_0000_0000_0011).unwrap(),
+            ArrayFault::OVERCURENT | ArrayFault::FETS_SHORTED
+        );
+        assert_eq!(ArrayFault::try_from(0b1000_0000_0000_0001), Err(DecodeError::new("array fault", 0x8001)));
+    }
+
+    #[test]
+    fn sunsaverresponse_array_fault_unknown() {
+        let native = ArrayFault::from_raw(0b1000_0000_0000_0001);
+        assert!(native.contains(ArrayFault::OVERCURENT));
+        assert!(!native.contains(ArrayFault::FETS_SHORTED));
+        assert_eq!(native.unknown_bits(), 0b1000_0000_0000_0000);
+        assert_eq!(native.bits(), 0b1000_0000_0000_0001);
+
+        let json = serde_json::to_string(&native).unwrap();
+        assert_eq!(
+            json,
+            "{\
+             \"OVERCURENT\":true,\
+             \"FETS_SHORTED\":false,\
+             \"SOFTWARE_BUGS\":false,\
+             \"BATTERY_HVD\":false,\
+             \"ARRAY_HVD\":false,\
+             \"EEPROM_EDIT\":false,\
+             \"RTS_SHORTED\":false,\
+             \"RTS_DISCONECTED\":false,\
+             \"INTERNAL_TEMP_SENSOR_FAIL\":false,\
+             \"UNKNOWN\":32768\
+             }"
+        );
     }
 
     #[test]
@@ -1,58 +1,124 @@
-use std::convert::From;
+use std::convert::{From, TryFrom};
 
-use enum_primitive::FromPrimitive;
+use crate::DecodeError;
 
-enum_from_primitive! {
 #[derive(Debug, Clone, Serialize, PartialEq)]
 pub enum ChargeState {
-    Start = 0,
-    NightCheck = 1,
-    Disconnect = 2,
-    Night = 3,
-    Fault = 4,
-    BulkCharge = 5,
-    Absorption = 6,
-    Float = 7,
-    Equalize = 8,
+    Start,
+    NightCheck,
+    Disconnect,
+    Night,
+    Fault,
+    BulkCharge,
+    Absorption,
+    Float,
+    Equalize,
+    Unknown(u16),
 }
+
+impl ChargeState {
+    /// Never fails, undocumented values are kept as `Unknown`
+    pub fn from_raw(val: u16) -> ChargeState {
+        ChargeState::try_from(val).unwrap_or(ChargeState::Unknown(val))
+    }
+
+    /// Ranks how far the battery is from being charged, faults and unknown states rank as the worst
+    pub fn severity(&self) -> u8 {
+        match self {
+            ChargeState::Float => 0,
+            ChargeState::Equalize => 1,
+            ChargeState::Absorption => 2,
+            ChargeState::BulkCharge => 3,
+            ChargeState::Start => 4,
+            ChargeState::NightCheck => 5,
+            ChargeState::Night => 6,
+            ChargeState::Disconnect => 7,
+            ChargeState::Unknown(_) => 8,
+            ChargeState::Fault => 9,
+        }
+    }
 }
 
-impl From<u16> for ChargeState {
-    fn from(val: u16) -> ChargeState {
-        ChargeState::from_u16(val).expect("Value does not match documented enum values")
+impl TryFrom<u16> for ChargeState {
+    type Error = DecodeError;
+
+    fn try_from(val: u16) -> Result<ChargeState, DecodeError> {
+        match val {
+            0 => Ok(ChargeState::Start),
+            1 => Ok(ChargeState::NightCheck),
+            2 => Ok(ChargeState::Disconnect),
+            3 => Ok(ChargeState::Night),
+            4 => Ok(ChargeState::Fault),
+            5 => Ok(ChargeState::BulkCharge),
+            6 => Ok(ChargeState::Absorption),
+            7 => Ok(ChargeState::Float),
+            8 => Ok(ChargeState::Equalize),
+            _ => Err(DecodeError::new("charge state", u32::from(val))),
+        }
     }
 }
 
 impl From<ChargeState> for u16 {
     fn from(val: ChargeState) -> u16 {
-        val as u16
+        match val {
+            ChargeState::Start => 0,
+            ChargeState::NightCheck => 1,
+            ChargeState::Disconnect => 2,
+            ChargeState::Night => 3,
+            ChargeState::Fault => 4,
+            ChargeState::BulkCharge => 5,
+            ChargeState::Absorption => 6,
+            ChargeState::Float => 7,
+            ChargeState::Equalize => 8,
+            ChargeState::Unknown(val) => val,
+        }
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use serde_json;
 
     #[test]
     fn sunsaverresponse_charge_state() {
-        assert_eq!(ChargeState::from(0u16), ChargeState::Start);
-        assert_eq!(ChargeState::from(1u16), ChargeState::NightCheck);
-        assert_eq!(ChargeState::from(2u16), ChargeState::Disconnect);
-        assert_eq!(ChargeState::from(3u16), ChargeState::Night);
-        assert_eq!(ChargeState::from(4u16), ChargeState::Fault);
-        assert_eq!(ChargeState::from(5u16), ChargeState::BulkCharge);
-        assert_eq!(ChargeState::from(6u16), ChargeState::Absorption);
-        assert_eq!(ChargeState::from(7u16), ChargeState::Float);
-        assert_eq!(ChargeState::from(8u16), ChargeState::Equalize);
-
-        assert_eq!(0u16, ChargeState::Start as u16);
-        assert_eq!(1u16, ChargeState::NightCheck as u16);
-        assert_eq!(3u16, ChargeState::Night as u16);
-        assert_eq!(2u16, ChargeState::Disconnect as u16);
-        assert_eq!(4u16, ChargeState::Fault as u16);
-        assert_eq!(5u16, ChargeState::BulkCharge as u16);
-        assert_eq!(6u16, ChargeState::Absorption as u16);
-        assert_eq!(7u16, ChargeState::Float as u16);
-        assert_eq!(8u16, ChargeState::Equalize as u16);
+        assert_eq!(ChargeState::try_from(0u16), Ok(ChargeState::Start));
+        assert_eq!(ChargeState::try_from(1u16), Ok(ChargeState::NightCheck));
+        assert_eq!(ChargeState::try_from(2u16), Ok(ChargeState::Disconnect));
+        assert_eq!(ChargeState::try_from(3u16), Ok(ChargeState::Night));
+        assert_eq!(ChargeState::try_from(4u16), Ok(ChargeState::Fault));
+        assert_eq!(ChargeState::try_from(5u16), Ok(ChargeState::BulkCharge));
+        assert_eq!(ChargeState::try_from(6u16), Ok(ChargeState::Absorption));
+        assert_eq!(ChargeState::try_from(7u16), Ok(ChargeState::Float));
+        assert_eq!(ChargeState::try_from(8u16), Ok(ChargeState::Equalize));
+        assert_eq!(ChargeState::try_from(9u16), Err(DecodeError::new("charge state", 9)));
+
+        assert_eq!(0u16, u16::from(ChargeState::Start));
+        assert_eq!(1u16, u16::from(ChargeState::NightCheck));
+        assert_eq!(3u16, u16::from(ChargeState::Night));
+        assert_eq!(2u16, u16::from(ChargeState::Disconnect));
+        assert_eq!(4u16, u16::from(ChargeState::Fault));
+        assert_eq!(5u16, u16::from(ChargeState::BulkCharge));
+        assert_eq!(6u16, u16::from(ChargeState::Absorption));
+        assert_eq!(7u16, u16::from(ChargeState::Float));
+        assert_eq!(8u16, u16::from(ChargeState::Equalize));
+    }
+
+    #[test]
+    fn sunsaverresponse_charge_state_unknown() {
+        assert_eq!(ChargeState::from_raw(5u16), ChargeState::BulkCharge);
+        assert_eq!(ChargeState::from_raw(0xbeefu16), ChargeState::Unknown(0xbeef));
+        assert_eq!(0xbeefu16, u16::from(ChargeState::Unknown(0xbeef)));
+
+        assert_eq!(serde_json::to_string(&ChargeState::Float).unwrap(), "\"Float\"");
+        assert_eq!(serde_json::to_string(&ChargeState::Unknown(9)).unwrap(), "{\"Unknown\":9}");
+    }
+
+    #[test]
+    fn sunsaverresponse_charge_state_severity() {
+        assert!(ChargeState::Float.severity() < ChargeState::BulkCharge.severity());
+        assert!(ChargeState::BulkCharge.severity() < ChargeState::Night.severity());
+        assert!(ChargeState::Disconnect.severity() < ChargeState::Unknown(9).severity());
+        assert!(ChargeState::Unknown(9).severity() < ChargeState::Fault.severity());
     }
 }
@@ -0,0 +1,40 @@
+/// Coils that trigger actions on the controller, addressed as in the request PDU.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Coil {
+    // [1][0x0000] Equalize triggered
+    EqualizeTriggered,
+    // [2][0x0001] Load disconnect
+    LoadDisconnect,
+    // [21][0x0014] Clear faults
+    ClearFaults,
+    // [22][0x0015] Clear alarms
+    ClearAlarms,
+    // [256][0x00FF] Reset control
+    ResetControl,
+}
+
+impl Coil {
+    pub fn address(self) -> u16 {
+        match self {
+            Coil::EqualizeTriggered => 0x0000,
+            Coil::LoadDisconnect => 0x0001,
+            Coil::ClearFaults => 0x0014,
+            Coil::ClearAlarms => 0x0015,
+            Coil::ResetControl => 0x00FF,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn coil_address() {
+        assert_eq!(Coil::EqualizeTriggered.address(), 0x0000);
+        assert_eq!(Coil::LoadDisconnect.address(), 0x0001);
+        assert_eq!(Coil::ClearFaults.address(), 0x0014);
+        assert_eq!(Coil::ClearAlarms.address(), 0x0015);
+        assert_eq!(Coil::ResetControl.address(), 0x00FF);
+    }
+}
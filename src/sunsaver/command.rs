@@ -0,0 +1,100 @@
+use std::str::FromStr;
+
+use crate::Coil;
+
+/// Actions that can be requested of the controller, each made up of one or more coil writes.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Command {
+    Equalize,
+    LoadDisconnect,
+    LoadConnect,
+    ClearFaults,
+    Reset,
+}
+
+impl Command {
+    /// Name of the command as used in `/api/v1/commands/<name>`
+    pub fn name(self) -> &'static str {
+        match self {
+            Command::Equalize => "equalize",
+            Command::LoadDisconnect => "load_disconnect",
+            Command::LoadConnect => "load_connect",
+            Command::ClearFaults => "clear_faults",
+            Command::Reset => "reset",
+        }
+    }
+
+    pub fn coil_writes(self) -> Vec<(Coil, bool)> {
+        match self {
+            Command::Equalize => vec![(Coil::EqualizeTriggered, true)],
+            Command::LoadDisconnect => vec![(Coil::LoadDisconnect, true)],
+            Command::LoadConnect => vec![(Coil::LoadDisconnect, false)],
+            Command::ClearFaults => vec![(Coil::ClearFaults, true), (Coil::ClearAlarms, true)],
+            Command::Reset => vec![(Coil::ResetControl, true)],
+        }
+    }
+}
+
+impl FromStr for Command {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Command, String> {
+        match name {
+            "equalize" => Ok(Command::Equalize),
+            "load_disconnect" => Ok(Command::LoadDisconnect),
+            "load_connect" => Ok(Command::LoadConnect),
+            "clear_faults" => Ok(Command::ClearFaults),
+            "reset" => Ok(Command::Reset),
+            _ => Err(format!("Unknown command {:?}", name)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json;
+
+    #[test]
+    fn command_from_str() {
+        assert_eq!("equalize".parse::<Command>(), Ok(Command::Equalize));
+        assert_eq!("load_disconnect".parse::<Command>(), Ok(Command::LoadDisconnect));
+        assert_eq!("load_connect".parse::<Command>(), Ok(Command::LoadConnect));
+        assert_eq!("clear_faults".parse::<Command>(), Ok(Command::ClearFaults));
+        assert_eq!("reset".parse::<Command>(), Ok(Command::Reset));
+        assert_eq!("format".parse::<Command>(), Err(String::from("Unknown command \"format\"")));
+    }
+
+    #[test]
+    fn command_name() {
+        for command in [
+            Command::Equalize,
+            Command::LoadDisconnect,
+            Command::LoadConnect,
+            Command::ClearFaults,
+            Command::Reset,
+        ]
+        .iter()
+        {
+            assert_eq!(command.name().parse::<Command>(), Ok(*command));
+        }
+    }
+
+    #[test]
+    fn command_coil_writes() {
+        assert_eq!(Command::Equalize.coil_writes(), vec![(Coil::EqualizeTriggered, true)]);
+        assert_eq!(Command::LoadDisconnect.coil_writes(), vec![(Coil::LoadDisconnect, true)]);
+        assert_eq!(Command::LoadConnect.coil_writes(), vec![(Coil::LoadDisconnect, false)]);
+        assert_eq!(
+            Command::ClearFaults.coil_writes(),
+            vec![(Coil::ClearFaults, true), (Coil::ClearAlarms, true)]
+        );
+        assert_eq!(Command::Reset.coil_writes(), vec![(Coil::ResetControl, true)]);
+    }
+
+    #[test]
+    fn command_serialize() {
+        assert_eq!(serde_json::to_string(&Command::LoadDisconnect).unwrap(), "\"load_disconnect\"");
+    }
+}
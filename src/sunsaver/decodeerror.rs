@@ -0,0 +1,23 @@
+use std::error::Error;
+use std::fmt;
+
+/// A register held a value with no documented meaning for the type it was decoded into.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecodeError {
+    pub type_name: &'static str,
+    pub value: u32,
+}
+
+impl DecodeError {
+    pub fn new(type_name: &'static str, value: u32) -> DecodeError {
+        DecodeError { type_name, value }
+    }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Undocumented {} value {:#x}", self.type_name, self.value)
+    }
+}
+
+impl Error for DecodeError {}
@@ -0,0 +1,269 @@
+use std::error::Error;
+use std::fmt;
+
+/// EEPROM settings that may be changed remotely, addressed as in the request PDU.
+/// Voltages are stored for a 12 V battery and scaled by the controller for 24 V systems.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EepromSetting {
+    // [57345][0xE000] EV_reg
+    AbsorptionVoltage,
+    // [57346][0xE001] EV_float
+    FloatVoltage,
+    // [57347][0xE002] Et_float
+    AbsorptionTime,
+    // [57348][0xE003] Et_floatlb
+    AbsorptionTimeLowBattery,
+    // [57349][0xE004] EV_floatlb_trip
+    LowBatteryTripVoltage,
+    // [57350][0xE005] EV_float_cancel
+    FloatCancelVoltage,
+    // [57351][0xE006] EV_eq
+    EqualizeVoltage,
+    // [57352][0xE007] Et_eqcalendar
+    EqualizeInterval,
+    // [57353][0xE008] Et_eq_above
+    EqualizeTimeAboveRegulation,
+    // [57354][0xE009] Et_eq_reg
+    EqualizeTime,
+    // [57356][0xE00B] EV_hvd
+    HighVoltageDisconnect,
+    // [57357][0xE00C] EV_hvr
+    HighVoltageReconnect,
+    // [57361][0xE010] EV_lvd
+    LoadLowVoltageDisconnect,
+    // [57362][0xE011] EV_lvr
+    LoadLowVoltageReconnect,
+    // [57363][0xE012] EV_lhvd
+    LoadHighVoltageDisconnect,
+    // [57364][0xE013] EV_lhvr
+    LoadHighVoltageReconnect,
+    // [57366][0xE015] Et_lvd_warn
+    LoadLowVoltageDisconnectWarningTime,
+}
+
+enum Scale {
+    Voltage,
+    Seconds,
+    Days,
+    TenthsOfSeconds,
+}
+
+impl EepromSetting {
+    pub const ALL: [EepromSetting; 17] = [
+        EepromSetting::AbsorptionVoltage,
+        EepromSetting::FloatVoltage,
+        EepromSetting::AbsorptionTime,
+        EepromSetting::AbsorptionTimeLowBattery,
+        EepromSetting::LowBatteryTripVoltage,
+        EepromSetting::FloatCancelVoltage,
+        EepromSetting::EqualizeVoltage,
+        EepromSetting::EqualizeInterval,
+        EepromSetting::EqualizeTimeAboveRegulation,
+        EepromSetting::EqualizeTime,
+        EepromSetting::HighVoltageDisconnect,
+        EepromSetting::HighVoltageReconnect,
+        EepromSetting::LoadLowVoltageDisconnect,
+        EepromSetting::LoadLowVoltageReconnect,
+        EepromSetting::LoadHighVoltageDisconnect,
+        EepromSetting::LoadHighVoltageReconnect,
+        EepromSetting::LoadLowVoltageDisconnectWarningTime,
+    ];
+
+    pub fn address(self) -> u16 {
+        match self {
+            EepromSetting::AbsorptionVoltage => 0xE000,
+            EepromSetting::FloatVoltage => 0xE001,
+            EepromSetting::AbsorptionTime => 0xE002,
+            EepromSetting::AbsorptionTimeLowBattery => 0xE003,
+            EepromSetting::LowBatteryTripVoltage => 0xE004,
+            EepromSetting::FloatCancelVoltage => 0xE005,
+            EepromSetting::EqualizeVoltage => 0xE006,
+            EepromSetting::EqualizeInterval => 0xE007,
+            EepromSetting::EqualizeTimeAboveRegulation => 0xE008,
+            EepromSetting::EqualizeTime => 0xE009,
+            EepromSetting::HighVoltageDisconnect => 0xE00B,
+            EepromSetting::HighVoltageReconnect => 0xE00C,
+            EepromSetting::LoadLowVoltageDisconnect => 0xE010,
+            EepromSetting::LoadLowVoltageReconnect => 0xE011,
+            EepromSetting::LoadHighVoltageDisconnect => 0xE012,
+            EepromSetting::LoadHighVoltageReconnect => 0xE013,
+            EepromSetting::LoadLowVoltageDisconnectWarningTime => 0xE015,
+        }
+    }
+
+    /// Name of the setting as it appears in `/api/v1/settings`
+    pub fn name(self) -> &'static str {
+        match self {
+            EepromSetting::AbsorptionVoltage => "charge.absorption_voltage",
+            EepromSetting::FloatVoltage => "charge.float_voltage",
+            EepromSetting::AbsorptionTime => "charge.absorption_time",
+            EepromSetting::AbsorptionTimeLowBattery => "charge.absorption_time_low_battery",
+            EepromSetting::LowBatteryTripVoltage => "charge.low_battery_trip_voltage",
+            EepromSetting::FloatCancelVoltage => "charge.float_cancel_voltage",
+            EepromSetting::EqualizeVoltage => "charge.equalize_voltage",
+            EepromSetting::EqualizeInterval => "charge.equalize_interval",
+            EepromSetting::EqualizeTimeAboveRegulation => "charge.equalize_time_above_regulation",
+            EepromSetting::EqualizeTime => "charge.equalize_time",
+            EepromSetting::HighVoltageDisconnect => "charge.high_voltage_disconnect",
+            EepromSetting::HighVoltageReconnect => "charge.high_voltage_reconnect",
+            EepromSetting::LoadLowVoltageDisconnect => "load.low_voltage_disconnect",
+            EepromSetting::LoadLowVoltageReconnect => "load.low_voltage_reconnect",
+            EepromSetting::LoadHighVoltageDisconnect => "load.high_voltage_disconnect",
+            EepromSetting::LoadHighVoltageReconnect => "load.high_voltage_reconnect",
+            EepromSetting::LoadLowVoltageDisconnectWarningTime => "load.low_voltage_disconnect_warning_time",
+        }
+    }
+
+    fn scale(self) -> Scale {
+        match self {
+            EepromSetting::AbsorptionTime
+            | EepromSetting::AbsorptionTimeLowBattery
+            | EepromSetting::EqualizeTimeAboveRegulation
+            | EepromSetting::EqualizeTime => Scale::Seconds,
+            EepromSetting::EqualizeInterval => Scale::Days,
+            EepromSetting::LoadLowVoltageDisconnectWarningTime => Scale::TenthsOfSeconds,
+            _ => Scale::Voltage,
+        }
+    }
+
+    pub fn unit(self) -> &'static str {
+        match self.scale() {
+            Scale::Voltage => "V",
+            Scale::Seconds | Scale::TenthsOfSeconds => "s",
+            Scale::Days => "days",
+        }
+    }
+
+    /// Inclusive range of accepted values in the setting's unit
+    pub fn range(self) -> (f32, f32) {
+        match self.scale() {
+            Scale::Voltage => (9.0, 17.0),
+            Scale::Seconds => (0.0, 65535.0),
+            Scale::Days => (0.0, 255.0),
+            Scale::TenthsOfSeconds => (0.0, 6553.5),
+        }
+    }
+
+    pub fn decode(self, raw: u16) -> f32 {
+        match self.scale() {
+            Scale::Voltage => conv_100_2_15_scale!(raw),
+            Scale::Seconds | Scale::Days => f32::from(raw),
+            Scale::TenthsOfSeconds => f32::from(raw) * 0.1,
+        }
+    }
+
+    pub fn encode(self, value: f32) -> Result<u16, SettingsValidationError> {
+        let (min, max) = self.range();
+        if !(value >= min && value <= max) {
+            return Err(SettingsValidationError::new(
+                self,
+                format!("{} {} is outside of {} to {} {}", value, self.unit(), min, max, self.unit()),
+            ));
+        }
+        let raw = match self.scale() {
+            Scale::Voltage => (value * 32768.0 / 100.0).round(),
+            Scale::TenthsOfSeconds => (value * 10.0).round(),
+            Scale::Seconds | Scale::Days => {
+                if value.fract() != 0.0 {
+                    return Err(SettingsValidationError::new(
+                        self,
+                        format!("{} {} is not a whole number", value, self.unit()),
+                    ));
+                }
+                value
+            }
+        };
+        Ok(raw as u16)
+    }
+}
+
+/// A requested setting was out of range or conflicted with another setting.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SettingsValidationError {
+    pub setting: EepromSetting,
+    pub message: String,
+}
+
+impl SettingsValidationError {
+    pub fn new(setting: EepromSetting, message: String) -> SettingsValidationError {
+        SettingsValidationError { setting, message }
+    }
+}
+
+impl fmt::Display for SettingsValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.setting.name(), self.message)
+    }
+}
+
+impl Error for SettingsValidationError {}
+
+/// A single register that differs between two sets of EEPROM settings.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EepromSettingChange {
+    pub setting: EepromSetting,
+    pub from: u16,
+    pub to: u16,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn eepromsetting_address_unique() {
+        for (i, setting) in EepromSetting::ALL.iter().enumerate() {
+            for other in EepromSetting::ALL[(i + 1)..].iter() {
+                assert_ne!(setting.address(), other.address());
+            }
+        }
+    }
+
+    #[test]
+    fn eepromsetting_name_unique() {
+        for (i, setting) in EepromSetting::ALL.iter().enumerate() {
+            for other in EepromSetting::ALL[(i + 1)..].iter() {
+                assert_ne!(setting.name(), other.name());
+            }
+        }
+    }
+
+    #[test]
+    fn eepromsetting_encode_voltage() {
+        assert_eq!(EepromSetting::FloatVoltage.encode(13.4375), Ok(0x1133));
+        assert_eq!(EepromSetting::FloatVoltage.decode(0x1133), 13.436_89);
+        assert_eq!(EepromSetting::AbsorptionVoltage.encode(14.0625), Ok(0x1200));
+    }
+
+    #[test]
+    fn eepromsetting_encode_time() {
+        assert_eq!(EepromSetting::AbsorptionTime.encode(3600.0), Ok(3600));
+        assert_eq!(EepromSetting::LoadLowVoltageDisconnectWarningTime.encode(15.0), Ok(150));
+        assert_eq!(
+            EepromSetting::AbsorptionTime.encode(1.5),
+            Err(SettingsValidationError::new(
+                EepromSetting::AbsorptionTime,
+                String::from("1.5 s is not a whole number")
+            ))
+        );
+    }
+
+    #[test]
+    fn eepromsetting_encode_out_of_range() {
+        assert_eq!(
+            EepromSetting::FloatVoltage.encode(18.0),
+            Err(SettingsValidationError::new(
+                EepromSetting::FloatVoltage,
+                String::from("18 V is outside of 9 to 17 V")
+            ))
+        );
+        assert!(EepromSetting::EqualizeInterval.encode(256.0).is_err());
+        assert!(EepromSetting::FloatVoltage.encode(std::f32::NAN).is_err());
+    }
+
+    #[test]
+    fn settingsvalidationerror_display() {
+        let error = SettingsValidationError::new(EepromSetting::LoadLowVoltageDisconnect, String::from("too low"));
+        assert_eq!(error.to_string(), "load.low_voltage_disconnect: too low");
+    }
+}
@@ -0,0 +1,520 @@
+use crate::sunsaver::{EepromSetting, EepromSettingChange, SettingsValidationError, EEPROM_LAYOUT};
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct EepromSettings {
+    // EV_reg
+    // [57345][0xE000] (V). Battery charge regulation (absorption) voltage @ 25C.
+    ev_reg: u16,
+    // EV_float
+    // [57346][0xE001] (V). Battery float voltage @ 25C.
+    ev_float: u16,
+    // Et_float
+    // [57347][0xE002] (s). Time spent in absorption before entering float.
+    et_float: u16,
+    // Et_floatlb
+    // [57348][0xE003] (s). Time spent in absorption before entering float after a low battery.
+    et_floatlb: u16,
+    // EV_floatlb_trip
+    // [57349][0xE004] (V). Battery voltage that triggers the low battery absorption time.
+    ev_floatlb_trip: u16,
+    // EV_float_cancel
+    // [57350][0xE005] (V). Battery voltage that cancels float.
+    ev_float_cancel: u16,
+    // EV_eq
+    // [57351][0xE006] (V). Battery equalize voltage @ 25C.
+    ev_eq: u16,
+    // Et_eqcalendar
+    // [57352][0xE007] (days). Days between equalize cycles.
+    et_eqcalendar: u16,
+    // Et_eq_above
+    // [57353][0xE008] (s). Equalize time limit above the regulation voltage.
+    et_eq_above: u16,
+    // Et_eq_reg
+    // [57354][0xE009] (s). Equalize time limit at the equalize voltage.
+    et_eq_reg: u16,
+    // EV_tempcomp
+    // [57355][0xE00A] (V/C). Battery charge temperature compensation.
+    ev_tempcomp: u16,
+    // EV_hvd
+    // [57356][0xE00B] (V). Battery high voltage disconnect.
+    ev_hvd: u16,
+    // EV_hvr
+    // [57357][0xE00C] (V). Battery high voltage reconnect.
+    ev_hvr: u16,
+    // Evb_ref_lim
+    // [57358][0xE00D] (V). Battery charge reference limit.
+    evb_ref_lim: u16,
+    // ETb_max
+    // [57359][0xE00E] (C). Temperature compensation upper limit.
+    etb_max: u16,
+    // ETb_min
+    // [57360][0xE00F] (C). Temperature compensation lower limit.
+    etb_min: u16,
+    // EV_lvd
+    // [57361][0xE010] (V). Load low voltage disconnect.
+    ev_lvd: u16,
+    // EV_lvr
+    // [57362][0xE011] (V). Load low voltage reconnect.
+    ev_lvr: u16,
+    // EV_lhvd
+    // [57363][0xE012] (V). Load high voltage disconnect.
+    ev_lhvd: u16,
+    // EV_lhvr
+    // [57364][0xE013] (V). Load high voltage reconnect.
+    ev_lhvr: u16,
+    // ER_icomp
+    // [57365][0xE014] (ohms). LVD load current compensation.
+    er_icomp: u16,
+    // Et_lvd_warn
+    // [57366][0xE015] (s). LVD warning timeout.
+    et_lvd_warn: u16,
+    // Ehourmeter
+    // [57377,57378][0xE020,0xE021] (h). Hourmeter.
+    ehourmeter: u32,
+    // EAhl_r
+    // [57379,57380][0xE022,0xE023] (Ah). Ah load, resettable.
+    eahl_r: u32,
+    // EAhl_t
+    // [57381,57382][0xE024,0xE025] (Ah). Ah load, total.
+    eahl_t: u32,
+    // EAhc_r
+    // [57383,57384][0xE026,0xE027] (Ah). Ah charge, resettable.
+    eahc_r: u32,
+    // EAhc_t
+    // [57385,57386][0xE028,0xE029] (Ah). Ah charge, total.
+    eahc_t: u32,
+    // EkWhc
+    // [57387][0xE02A] (kWh). kWh charge, total.
+    ekwhc: u16,
+    // EVb_min
+    // [57388][0xE02B] (V). Lifetime minimum battery voltage.
+    evb_min: u16,
+    // EVb_max
+    // [57389][0xE02C] (V). Lifetime maximum battery voltage.
+    evb_max: u16,
+    // EVa_max
+    // [57390][0xE02D] (V). Lifetime maximum array voltage.
+    eva_max: u16,
+    // Etmr_eqcalendar
+    // [57391][0xE02E] (days). Days since the last equalize.
+    etmr_eqcalendar: u16,
+}
+
+macro_rules! setting_field {
+    ($setting:expr, $($settings:tt)+) => {
+        match $setting {
+            EepromSetting::AbsorptionVoltage => $($settings)+.ev_reg,
+            EepromSetting::FloatVoltage => $($settings)+.ev_float,
+            EepromSetting::AbsorptionTime => $($settings)+.et_float,
+            EepromSetting::AbsorptionTimeLowBattery => $($settings)+.et_floatlb,
+            EepromSetting::LowBatteryTripVoltage => $($settings)+.ev_floatlb_trip,
+            EepromSetting::FloatCancelVoltage => $($settings)+.ev_float_cancel,
+            EepromSetting::EqualizeVoltage => $($settings)+.ev_eq,
+            EepromSetting::EqualizeInterval => $($settings)+.et_eqcalendar,
+            EepromSetting::EqualizeTimeAboveRegulation => $($settings)+.et_eq_above,
+            EepromSetting::EqualizeTime => $($settings)+.et_eq_reg,
+            EepromSetting::HighVoltageDisconnect => $($settings)+.ev_hvd,
+            EepromSetting::HighVoltageReconnect => $($settings)+.ev_hvr,
+            EepromSetting::LoadLowVoltageDisconnect => $($settings)+.ev_lvd,
+            EepromSetting::LoadLowVoltageReconnect => $($settings)+.ev_lvr,
+            EepromSetting::LoadHighVoltageDisconnect => $($settings)+.ev_lhvd,
+            EepromSetting::LoadHighVoltageReconnect => $($settings)+.ev_lhvr,
+            EepromSetting::LoadLowVoltageDisconnectWarningTime => $($settings)+.et_lvd_warn,
+        }
+    };
+}
+
+fn join_registers(high: u16, low: u16) -> u32 {
+    (u32::from(high) << 16) | u32::from(low)
+}
+
+impl EepromSettings {
+    pub fn from_raw_bits(raw_data: [u16; 47]) -> EepromSettings {
+        let register = |address: u16| raw_data[EEPROM_LAYOUT.index_of(address).expect("Address outside of EEPROM layout")];
+        EepromSettings {
+            ev_reg: register(0xE000),
+            ev_float: register(0xE001),
+            et_float: register(0xE002),
+            et_floatlb: register(0xE003),
+            ev_floatlb_trip: register(0xE004),
+            ev_float_cancel: register(0xE005),
+            ev_eq: register(0xE006),
+            et_eqcalendar: register(0xE007),
+            et_eq_above: register(0xE008),
+            et_eq_reg: register(0xE009),
+            ev_tempcomp: register(0xE00A),
+            ev_hvd: register(0xE00B),
+            ev_hvr: register(0xE00C),
+            evb_ref_lim: register(0xE00D),
+            etb_max: register(0xE00E),
+            etb_min: register(0xE00F),
+            ev_lvd: register(0xE010),
+            ev_lvr: register(0xE011),
+            ev_lhvd: register(0xE012),
+            ev_lhvr: register(0xE013),
+            er_icomp: register(0xE014),
+            et_lvd_warn: register(0xE015),
+            ehourmeter: join_registers(register(0xE020), register(0xE021)),
+            eahl_r: join_registers(register(0xE022), register(0xE023)),
+            eahl_t: join_registers(register(0xE024), register(0xE025)),
+            eahc_r: join_registers(register(0xE026), register(0xE027)),
+            eahc_t: join_registers(register(0xE028), register(0xE029)),
+            ekwhc: register(0xE02A),
+            evb_min: register(0xE02B),
+            evb_max: register(0xE02C),
+            eva_max: register(0xE02D),
+            etmr_eqcalendar: register(0xE02E),
+        }
+    }
+
+    pub fn absorption_voltage(&self) -> f32 {
+        conv_100_2_15_scale!(self.ev_reg)
+    }
+
+    pub fn float_voltage(&self) -> f32 {
+        conv_100_2_15_scale!(self.ev_float)
+    }
+
+    pub fn absorption_time(&self) -> u16 {
+        self.et_float
+    }
+
+    pub fn absorption_time_low_battery(&self) -> u16 {
+        self.et_floatlb
+    }
+
+    pub fn low_battery_trip_voltage(&self) -> f32 {
+        conv_100_2_15_scale!(self.ev_floatlb_trip)
+    }
+
+    pub fn float_cancel_voltage(&self) -> f32 {
+        conv_100_2_15_scale!(self.ev_float_cancel)
+    }
+
+    pub fn equalize_voltage(&self) -> f32 {
+        conv_100_2_15_scale!(self.ev_eq)
+    }
+
+    pub fn equalize_interval(&self) -> u16 {
+        self.et_eqcalendar
+    }
+
+    pub fn equalize_time_above_regulation(&self) -> u16 {
+        self.et_eq_above
+    }
+
+    pub fn equalize_time(&self) -> u16 {
+        self.et_eq_reg
+    }
+
+    pub fn temperature_compensation(&self) -> f32 {
+        conv_100_2_16_scale!(self.ev_tempcomp)
+    }
+
+    pub fn high_voltage_disconnect(&self) -> f32 {
+        conv_100_2_15_scale!(self.ev_hvd)
+    }
+
+    pub fn high_voltage_reconnect(&self) -> f32 {
+        conv_100_2_15_scale!(self.ev_hvr)
+    }
+
+    pub fn charge_reference_limit(&self) -> f32 {
+        conv_100_2_15_scale!(self.evb_ref_lim)
+    }
+
+    pub fn temperature_compensation_max(&self) -> i8 {
+        self.etb_max as i8
+    }
+
+    pub fn temperature_compensation_min(&self) -> i8 {
+        self.etb_min as i8
+    }
+
+    pub fn load_low_voltage_disconnect(&self) -> f32 {
+        conv_100_2_15_scale!(self.ev_lvd)
+    }
+
+    pub fn load_low_voltage_reconnect(&self) -> f32 {
+        conv_100_2_15_scale!(self.ev_lvr)
+    }
+
+    pub fn load_high_voltage_disconnect(&self) -> f32 {
+        conv_100_2_15_scale!(self.ev_lhvd)
+    }
+
+    pub fn load_high_voltage_reconnect(&self) -> f32 {
+        conv_100_2_15_scale!(self.ev_lhvr)
+    }
+
+    pub fn load_current_compensation(&self) -> f32 {
+        conv_1263_2_16_scale!(self.er_icomp)
+    }
+
+    pub fn load_low_voltage_disconnect_warning_time(&self) -> f32 {
+        f32::from(self.et_lvd_warn) * 0.1
+    }
+
+    pub fn hourmeter(&self) -> u32 {
+        self.ehourmeter
+    }
+
+    pub fn load_charge_resettable(&self) -> f32 {
+        self.eahl_r as f32 * 0.1
+    }
+
+    pub fn load_charge_total(&self) -> f32 {
+        self.eahl_t as f32 * 0.1
+    }
+
+    pub fn battery_charge_resettable(&self) -> f32 {
+        self.eahc_r as f32 * 0.1
+    }
+
+    pub fn battery_charge_total(&self) -> f32 {
+        self.eahc_t as f32 * 0.1
+    }
+
+    pub fn battery_charge_energy_total(&self) -> f32 {
+        f32::from(self.ekwhc) * 0.1
+    }
+
+    pub fn battery_voltage_min(&self) -> f32 {
+        conv_100_2_15_scale!(self.evb_min)
+    }
+
+    pub fn battery_voltage_max(&self) -> f32 {
+        conv_100_2_15_scale!(self.evb_max)
+    }
+
+    pub fn array_voltage_max(&self) -> f32 {
+        conv_100_2_15_scale!(self.eva_max)
+    }
+
+    pub fn days_since_equalize(&self) -> u16 {
+        self.etmr_eqcalendar
+    }
+
+    pub fn register(&self, setting: EepromSetting) -> u16 {
+        *setting_field!(setting, &self)
+    }
+
+    /// Applies the requested values on top of these settings, checking each value and the rules involving them
+    pub fn with_values(&self, values: &[(EepromSetting, f32)]) -> Result<EepromSettings, Vec<SettingsValidationError>> {
+        let mut settings = self.clone();
+        let mut errors = Vec::new();
+        for (setting, value) in values {
+            match setting.encode(*value) {
+                Ok(raw) => *setting_field!(*setting, &mut settings) = raw,
+                Err(error) => errors.push(error),
+            }
+        }
+        if errors.is_empty() {
+            let changed: Vec<EepromSetting> = values.iter().map(|(setting, _)| *setting).collect();
+            errors = settings.validate_touching(&changed);
+        }
+        if errors.is_empty() {
+            Ok(settings)
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Checks the constraints between settings that the controller does not enforce itself
+    pub fn validate(&self) -> Vec<SettingsValidationError> {
+        self.validate_touching(&EepromSetting::ALL)
+    }
+
+    /// Checks only the constraints where at least one side is in `settings`
+    fn validate_touching(&self, settings: &[EepromSetting]) -> Vec<SettingsValidationError> {
+        let rules = [
+            (EepromSetting::FloatVoltage, EepromSetting::AbsorptionVoltage),
+            (EepromSetting::AbsorptionVoltage, EepromSetting::EqualizeVoltage),
+            (EepromSetting::FloatCancelVoltage, EepromSetting::FloatVoltage),
+            (EepromSetting::LowBatteryTripVoltage, EepromSetting::FloatVoltage),
+            (EepromSetting::EqualizeVoltage, EepromSetting::HighVoltageDisconnect),
+            (EepromSetting::HighVoltageReconnect, EepromSetting::HighVoltageDisconnect),
+            (EepromSetting::LoadLowVoltageDisconnect, EepromSetting::LoadLowVoltageReconnect),
+            (EepromSetting::LoadLowVoltageReconnect, EepromSetting::LoadHighVoltageReconnect),
+            (EepromSetting::LoadHighVoltageReconnect, EepromSetting::LoadHighVoltageDisconnect),
+        ];
+        rules
+            .iter()
+            .filter(|(lower, higher)| settings.contains(lower) || settings.contains(higher))
+            .filter(|(lower, higher)| self.register(*lower) >= self.register(*higher))
+            .map(|(lower, higher)| {
+                SettingsValidationError::new(
+                    *lower,
+                    format!(
+                        "{} {} must be below {} of {} {}",
+                        lower.decode(self.register(*lower)),
+                        lower.unit(),
+                        higher.name(),
+                        higher.decode(self.register(*higher)),
+                        higher.unit()
+                    ),
+                )
+            })
+            .collect()
+    }
+
+    /// Writable settings whose registers differ in `other`
+    pub fn changes_to(&self, other: &EepromSettings) -> Vec<EepromSettingChange> {
+        EepromSetting::ALL
+            .iter()
+            .filter(|setting| self.register(**setting) != other.register(**setting))
+            .map(|setting| EepromSettingChange {
+                setting: *setting,
+                from: self.register(*setting),
+                to: other.register(*setting),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[rustfmt::skip]
+    const DEFAULT_TEST_RAW_BITS: [u16; 47] = [
+        0x1200, 0x1133, 0x0e10, 0x1c20, 0x0f80, 0x0f00, 0x1266, 0x001c,
+        0x0000, 0x1c20, 0xffec, 0x1400, 0x1333, 0x1800, 0x003c, 0xffe2,
+        0x0e13, 0x0ff9, 0x1400, 0x1333, 0x0000, 0x0096, 0x0000, 0x0000,
+        0x0000, 0x0000, 0x0001, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000,
+        0x0000, 0x26b6, 0x0000, 0x0f2a, 0x0000, 0x0f2a, 0x0000, 0x1712,
+        0x0000, 0x1712, 0x004e, 0x0e66, 0x1333, 0x2c00, 0x0007,
+    ];
+
+    #[test]
+    fn eepromsettings_from_raw_bits() {
+        let settings = EepromSettings::from_raw_bits(DEFAULT_TEST_RAW_BITS);
+
+        assert_eq!(settings.ev_reg, 0x1200);
+        assert_eq!(settings.ev_float, 0x1133);
+        assert_eq!(settings.et_float, 0x0e10);
+        assert_eq!(settings.ev_lvd, 0x0e13);
+        assert_eq!(settings.ev_lvr, 0x0ff9);
+        assert_eq!(settings.et_lvd_warn, 0x0096);
+        assert_eq!(settings.ehourmeter, 0x0000_26b6);
+        assert_eq!(settings.eahc_t, 0x0000_1712);
+        assert_eq!(settings.etmr_eqcalendar, 0x0007);
+    }
+
+    #[test]
+    fn eepromsettings_from_raw_bits_converted() {
+        let settings = EepromSettings::from_raw_bits(DEFAULT_TEST_RAW_BITS);
+
+        assert_eq!(settings.absorption_voltage(), 14.0625);
+        assert_eq!(settings.float_voltage(), 13.436_89);
+        assert_eq!(settings.absorption_time(), 3600);
+        assert_eq!(settings.absorption_time_low_battery(), 7200);
+        assert_eq!(settings.low_battery_trip_voltage(), 12.109_375);
+        assert_eq!(settings.float_cancel_voltage(), 11.718_75);
+        assert_eq!(settings.equalize_voltage(), 14.373_779);
+        assert_eq!(settings.equalize_interval(), 28);
+        assert_eq!(settings.equalize_time_above_regulation(), 0);
+        assert_eq!(settings.equalize_time(), 7200);
+        assert_eq!(settings.temperature_compensation(), -0.030_517_578);
+        assert_eq!(settings.high_voltage_disconnect(), 15.625);
+        assert_eq!(settings.high_voltage_reconnect(), 14.999_39);
+        assert_eq!(settings.charge_reference_limit(), 18.75);
+        assert_eq!(settings.temperature_compensation_max(), 60);
+        assert_eq!(settings.temperature_compensation_min(), -30);
+
+        assert_eq!(settings.load_low_voltage_disconnect(), 10.995_483);
+        assert_eq!(settings.load_low_voltage_reconnect(), 12.478_638);
+        assert_eq!(settings.load_high_voltage_disconnect(), 15.625);
+        assert_eq!(settings.load_high_voltage_reconnect(), 14.999_39);
+        assert_eq!(settings.load_current_compensation(), 0.0);
+        assert_eq!(settings.load_low_voltage_disconnect_warning_time(), 15.0);
+
+        assert_eq!(settings.hourmeter(), 9910);
+        assert_eq!(settings.load_charge_resettable(), 388.2);
+        assert_eq!(settings.load_charge_total(), 388.2);
+        assert_eq!(settings.battery_charge_resettable(), 590.600_04);
+        assert_eq!(settings.battery_charge_total(), 590.600_04);
+        assert_eq!(settings.battery_charge_energy_total(), 7.8);
+        assert_eq!(settings.battery_voltage_min(), 11.248_779);
+        assert_eq!(settings.battery_voltage_max(), 14.999_39);
+        assert_eq!(settings.array_voltage_max(), 34.375);
+        assert_eq!(settings.days_since_equalize(), 7);
+    }
+
+    #[test]
+    fn eepromsettings_validate_defaults() {
+        let settings = EepromSettings::from_raw_bits(DEFAULT_TEST_RAW_BITS);
+        assert_eq!(settings.validate(), vec![]);
+    }
+
+    #[test]
+    fn eepromsettings_with_values() {
+        let settings = EepromSettings::from_raw_bits(DEFAULT_TEST_RAW_BITS);
+        let updated = settings
+            .with_values(&[(EepromSetting::FloatVoltage, 13.6), (EepromSetting::AbsorptionTime, 7200.0)])
+            .unwrap();
+
+        assert_eq!(updated.float_voltage(), 13.598_633);
+        assert_eq!(updated.absorption_time(), 7200);
+        assert_eq!(
+            settings.changes_to(&updated),
+            vec![
+                EepromSettingChange {
+                    setting: EepromSetting::FloatVoltage,
+                    from: 0x1133,
+                    to: 0x1168,
+                },
+                EepromSettingChange {
+                    setting: EepromSetting::AbsorptionTime,
+                    from: 0x0e10,
+                    to: 0x1c20,
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn eepromsettings_with_values_unchanged() {
+        let settings = EepromSettings::from_raw_bits(DEFAULT_TEST_RAW_BITS);
+        let updated = settings.with_values(&[(EepromSetting::AbsorptionVoltage, 14.0625)]).unwrap();
+        assert_eq!(settings.changes_to(&updated), vec![]);
+    }
+
+    #[test]
+    fn eepromsettings_with_values_cross_field() {
+        let settings = EepromSettings::from_raw_bits(DEFAULT_TEST_RAW_BITS);
+        let errors = settings
+            .with_values(&[(EepromSetting::FloatVoltage, 14.5), (EepromSetting::LoadLowVoltageReconnect, 10.5)])
+            .unwrap_err();
+
+        let settings_in_error: Vec<EepromSetting> = errors.iter().map(|error| error.setting).collect();
+        assert_eq!(
+            settings_in_error,
+            vec![EepromSetting::FloatVoltage, EepromSetting::LoadLowVoltageDisconnect]
+        );
+        assert_eq!(errors[0].message, "14.498901 V must be below charge.absorption_voltage of 14.0625 V");
+    }
+
+    #[test]
+    fn eepromsettings_with_values_ignores_unrelated_rules() {
+        let mut raw_bits = DEFAULT_TEST_RAW_BITS;
+        raw_bits[12] = 0x1400;
+        let settings = EepromSettings::from_raw_bits(raw_bits);
+        assert_eq!(settings.validate().len(), 1);
+
+        let updated = settings.with_values(&[(EepromSetting::AbsorptionTime, 7200.0)]).unwrap();
+        assert_eq!(updated.absorption_time(), 7200);
+
+        let errors = settings.with_values(&[(EepromSetting::HighVoltageDisconnect, 15.625)]).unwrap_err();
+        assert_eq!(errors[0].setting, EepromSetting::HighVoltageReconnect);
+    }
+
+    #[test]
+    fn eepromsettings_with_values_out_of_range() {
+        let settings = EepromSettings::from_raw_bits(DEFAULT_TEST_RAW_BITS);
+        let errors = settings
+            .with_values(&[(EepromSetting::LoadLowVoltageDisconnect, 2.0), (EepromSetting::EqualizeInterval, 400.0)])
+            .unwrap_err();
+        assert_eq!(errors.len(), 2);
+    }
+}
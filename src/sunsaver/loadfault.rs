@@ -0,0 +1,116 @@
+use std::convert::TryFrom;
+
+use std::result::Result::{self, Ok};
+
+use serde::ser::{Serialize, SerializeMap, Serializer};
+
+use crate::DecodeError;
+
+bitflags! {
+    pub struct LoadFault: u16 {
+        const EXTERNAL_SHORT_CIRCUIT = 0b0000_0000_0000_0001;
+        const OVERCURRENT            = 0b0000_0000_0000_0010;
+        const FETS_SHORTED           = 0b0000_0000_0000_0100;
+        const SOFTWARE_BUGS          = 0b0000_0000_0000_1000;
+        const HVD                    = 0b0000_0000_0001_0000;
+        const HEATSINK_OVER_TEMP     = 0b0000_0000_0010_0000;
+        const EEPROM_EDIT            = 0b0000_0000_0100_0000;
+    }
+}
+pub const LOAD_FAULT_FLAGS: [LoadFault; 7] = [
+    LoadFault::EXTERNAL_SHORT_CIRCUIT,
+    LoadFault::OVERCURRENT,
+    LoadFault::FETS_SHORTED,
+    LoadFault::SOFTWARE_BUGS,
+    LoadFault::HVD,
+    LoadFault::HEATSINK_OVER_TEMP,
+    LoadFault::EEPROM_EDIT,
+];
+
+impl LoadFault {
+    /// Never fails, bits without a documented meaning are preserved
+    pub fn from_raw(val: u16) -> LoadFault {
+        LoadFault { bits: val }
+    }
+
+    pub fn unknown_bits(&self) -> u16 {
+        self.bits() & !LoadFault::all().bits()
+    }
+}
+
+impl TryFrom<u16> for LoadFault {
+    type Error = DecodeError;
+
+    fn try_from(val: u16) -> Result<LoadFault, DecodeError> {
+        LoadFault::from_bits(val).ok_or_else(|| DecodeError::new("load fault", u32::from(val)))
+    }
+}
+
+impl Serialize for LoadFault {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let unknown_bits = self.unknown_bits();
+        let len = LOAD_FAULT_FLAGS.len() + if unknown_bits == 0 { 0 } else { 1 };
+        let mut map = serializer.serialize_map(Some(len))?;
+        for flag in LOAD_FAULT_FLAGS.iter() {
+            let is_set = self.contains(*flag);
+            map.serialize_entry(&format!("{:?}", flag), &is_set)?;
+        }
+        if unknown_bits != 0 {
+            map.serialize_entry("UNKNOWN", &unknown_bits)?;
+        }
+        map.end()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json;
+
+    #[test]
+    fn sunsaverresponse_load_fault() {
+        assert_eq!(LoadFault::try_from(0b0000_0000_0000_0000).unwrap(), LoadFault::empty());
+        assert_eq!(LoadFault::try_from(0b0000_0000_0000_0001).unwrap(), LoadFault::EXTERNAL_SHORT_CIRCUIT);
+        assert_eq!(LoadFault::try_from(0b0000_0000_0010_0000).unwrap(), LoadFault::HEATSINK_OVER_TEMP);
+        assert_eq!(
+            LoadFault::try_from(0b0000_0000_0001_0010).unwrap(),
+            LoadFault::OVERCURRENT | LoadFault::HVD
+        );
+    }
+
+    #[test]
+    fn sunsaverresponse_load_fault_serialize() {
+        let native = LoadFault::empty();
+        let json = serde_json::to_string(&native).unwrap();
+        assert_eq!(
+            json,
+            "{\
+             \"EXTERNAL_SHORT_CIRCUIT\":false,\
+             \"OVERCURRENT\":false,\
+             \"FETS_SHORTED\":false,\
+             \"SOFTWARE_BUGS\":false,\
+             \"HVD\":false,\
+             \"HEATSINK_OVER_TEMP\":false,\
+             \"EEPROM_EDIT\":false\
+             }"
+        );
+
+        let native = LoadFault::EXTERNAL_SHORT_CIRCUIT | LoadFault::EEPROM_EDIT;
+        let json = serde_json::to_string(&native).unwrap();
+        assert_eq!(
+            json,
+            "{\
+             \"EXTERNAL_SHORT_CIRCUIT\":true,\
+             \"OVERCURRENT\":false,\
+             \"FETS_SHORTED\":false,\
+             \"SOFTWARE_BUGS\":false,\
+             \"HVD\":false,\
+             \"HEATSINK_OVER_TEMP\":false,\
+             \"EEPROM_EDIT\":true\
+             }"
+        );
+    }
+}
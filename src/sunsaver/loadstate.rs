@@ -0,0 +1,91 @@
+use std::convert::{From, TryFrom};
+
+use crate::DecodeError;
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub enum LoadState {
+    Start,
+    Normal,
+    LvdWarning,
+    Lvd,
+    Fault,
+    Disconnect,
+    Off,
+    Override,
+    Unknown(u16),
+}
+
+impl LoadState {
+    /// Never fails, undocumented values are kept as `Unknown`
+    pub fn from_raw(val: u16) -> LoadState {
+        LoadState::try_from(val).unwrap_or(LoadState::Unknown(val))
+    }
+}
+
+impl TryFrom<u16> for LoadState {
+    type Error = DecodeError;
+
+    fn try_from(val: u16) -> Result<LoadState, DecodeError> {
+        match val {
+            0 => Ok(LoadState::Start),
+            1 => Ok(LoadState::Normal),
+            2 => Ok(LoadState::LvdWarning),
+            3 => Ok(LoadState::Lvd),
+            4 => Ok(LoadState::Fault),
+            5 => Ok(LoadState::Disconnect),
+            6 => Ok(LoadState::Off),
+            7 => Ok(LoadState::Override),
+            _ => Err(DecodeError::new("load state", u32::from(val))),
+        }
+    }
+}
+
+impl From<LoadState> for u16 {
+    fn from(val: LoadState) -> u16 {
+        match val {
+            LoadState::Start => 0,
+            LoadState::Normal => 1,
+            LoadState::LvdWarning => 2,
+            LoadState::Lvd => 3,
+            LoadState::Fault => 4,
+            LoadState::Disconnect => 5,
+            LoadState::Off => 6,
+            LoadState::Override => 7,
+            LoadState::Unknown(val) => val,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn sunsaverresponse_load_state() {
+        assert_eq!(LoadState::try_from(0u16), Ok(LoadState::Start));
+        assert_eq!(LoadState::try_from(1u16), Ok(LoadState::Normal));
+        assert_eq!(LoadState::try_from(2u16), Ok(LoadState::LvdWarning));
+        assert_eq!(LoadState::try_from(3u16), Ok(LoadState::Lvd));
+        assert_eq!(LoadState::try_from(4u16), Ok(LoadState::Fault));
+        assert_eq!(LoadState::try_from(5u16), Ok(LoadState::Disconnect));
+        assert_eq!(LoadState::try_from(6u16), Ok(LoadState::Off));
+        assert_eq!(LoadState::try_from(7u16), Ok(LoadState::Override));
+        assert_eq!(LoadState::try_from(8u16), Err(DecodeError::new("load state", 8)));
+
+        assert_eq!(0u16, u16::from(LoadState::Start));
+        assert_eq!(1u16, u16::from(LoadState::Normal));
+        assert_eq!(2u16, u16::from(LoadState::LvdWarning));
+        assert_eq!(3u16, u16::from(LoadState::Lvd));
+        assert_eq!(4u16, u16::from(LoadState::Fault));
+        assert_eq!(5u16, u16::from(LoadState::Disconnect));
+        assert_eq!(6u16, u16::from(LoadState::Off));
+        assert_eq!(7u16, u16::from(LoadState::Override));
+    }
+
+    #[test]
+    fn sunsaverresponse_load_state_unknown() {
+        assert_eq!(LoadState::from_raw(1u16), LoadState::Normal);
+        assert_eq!(LoadState::from_raw(42u16), LoadState::Unknown(42));
+        assert_eq!(42u16, u16::from(LoadState::Unknown(42)));
+    }
+}
@@ -1,6 +1,6 @@
 use std::convert::AsMut;
 
-use crate::LoggedResponseDay;
+use crate::{DecodeError, LoggedResponseDay};
 
 #[derive(Debug, Clone, Serialize)]
 pub struct LoggedResponse {
@@ -12,9 +12,13 @@ impl LoggedResponse {
         let mut days = vec![];
         for i in 0..32 {
             let offset = i * 16;
-            let data = clone_into_array(&raw_data[offset..offset + 16]);
+            let data: [u16; 16] = clone_into_array(&raw_data[offset..offset + 16]);
+            // Records that have never been written read back as erased EEPROM
+            if data.iter().all(|&register| register == 0xffff) {
+                continue;
+            }
             let day = LoggedResponseDay::from_raw_bits(data);
-            if day.hourmeter == 0x00_0000 || day.hourmeter == 0xff_ffff {
+            if day.hourmeter == 0x00_0000 {
                 continue;
             }
             days.push(day);
@@ -22,6 +26,11 @@ impl LoggedResponse {
         days.sort();
         LoggedResponse { days }
     }
+
+    /// Lists values without a documented meaning, these are still decoded as unknown
+    pub fn undocumented_values(&self) -> Vec<DecodeError> {
+        self.days.iter().flat_map(LoggedResponseDay::undocumented_values).collect()
+    }
 }
 
 fn clone_into_array<A, T>(slice: &[T]) -> A
@@ -79,18 +88,48 @@ mod test {
         let response = LoggedResponse::from_raw_bits(DEFAULT_TEST_RAW_BITS);
 
         // sorted and filtered
-        assert_eq!(response.days.len(), 29);
-        assert_eq!(response.days[0].hourmeter, 0x010_224);
-        assert_eq!(response.days[1].hourmeter, 0x010_925);
+        assert_eq!(response.days.len(), 30);
+        assert_eq!(response.days[0].hourmeter, 0x00_2402);
+        assert_eq!(response.days[1].hourmeter, 0x00_2432);
+        assert_eq!(response.days[28].hourmeter, 0x00_26b8);
+        assert_eq!(response.days[29].hourmeter, 0x00_ffff);
 
         // test a day
         let day = &response.days[0];
-        assert_eq!(day.hourmeter, 0x010_224);
+        assert_eq!(day.hourmeter, 0x00_2402);
         assert_eq!(day.battery_voltage_min(), 12.551_88);
 
         // test antoher one
         let day = &response.days[5];
-        assert_eq!(day.hourmeter, 0x013_224);
-        assert_eq!(day.battery_voltage_min(), 12.591_553);
+        assert_eq!(day.hourmeter, 0x00_2491);
+        assert_eq!(day.battery_voltage_min(), 11.413_574);
+        assert_eq!(day.time_in_absorption(), 0);
+    }
+
+    #[test]
+    fn loggedresponse_first_register_erased() {
+        let mut raw_bits = [0xffff; 32 * 16];
+        raw_bits[..16].copy_from_slice(&DEFAULT_TEST_RAW_BITS[3 * 16..4 * 16]);
+        raw_bits[16..32].copy_from_slice(&DEFAULT_TEST_RAW_BITS[3 * 16..4 * 16]);
+        // Only the low 16 bits of the hourmeter are set, the record is still real
+        raw_bits[16] = 0xffff;
+        raw_bits[17] = 0x0101;
+        let response = LoggedResponse::from_raw_bits(raw_bits);
+
+        assert_eq!(response.days.len(), 2);
+        assert_eq!(response.days[0].hourmeter, 0x00_2402);
+        assert_eq!(response.days[1].hourmeter, 0x01_ffff);
+        assert_eq!(response.days[1].battery_voltage_min(), 12.551_88);
+    }
+
+    #[test]
+    fn loggedresponse_undocumented_values() {
+        let response = LoggedResponse::from_raw_bits(DEFAULT_TEST_RAW_BITS);
+        assert!(response.undocumented_values().is_empty());
+
+        let mut raw_bits = DEFAULT_TEST_RAW_BITS;
+        raw_bits[3 * 16 + 2] = 0x1000;
+        let response = LoggedResponse::from_raw_bits(raw_bits);
+        assert_eq!(response.undocumented_values(), vec![DecodeError::new("alarm", 0x10_0001)]);
     }
 }
@@ -1,20 +1,58 @@
 use std::cmp::{Ord, Ordering};
+use std::convert::TryFrom;
 
+use chrono::{DateTime, Duration, FixedOffset, NaiveDate};
+
+use crate::{Alarm, ArrayFault, DecodeError, LoadFault};
+
+/// One day of the controller's logged history, stored as 16 registers from 0x8000 onward.
 #[derive(Debug, Clone, Serialize, Eq)]
 pub struct LoggedResponseDay {
-    pub hourmeter: u32,   // u24
-    pub alarm_daily: u32, // u24
+    // hourmeter
+    // [+0,+1 low byte] (h). Hourmeter at the end of the day, u24.
+    pub hourmeter: u32,
+    // alarm_daily
+    // [+1 high byte,+2] Bitfield of alarms raised during the day, u24.
+    pub alarm_daily: u32,
+    // Vb_min_daily
+    // [+3] (V). Minimum battery voltage.
     vb_min_daily: u16,
+    // Vb_max_daily
+    // [+4] (V). Maximum battery voltage.
     vb_max_daily: u16,
+    // Ahc_daily
+    // [+5] (Ah). Charge into the battery.
     ahc_daily: u16,
+    // Ahl_daily
+    // [+6] (Ah). Charge drawn by the load.
     ahl_daily: u16,
+    // array_fault_daily
+    // [+7] Bitfield of array faults raised during the day.
+    array_fault_daily: u16,
+    // load_fault_daily
+    // [+8] Bitfield of load faults raised during the day.
+    load_fault_daily: u16,
+    // Va_max_daily
+    // [+9] (V). Maximum solar input voltage.
     va_max_daily: u16,
+    // time_ab_daily
+    // [+10] (min). Time spent in absorption.
+    time_ab_daily: u16,
+    // time_eq_daily
+    // [+11] (min). Time spent in equalize.
+    time_eq_daily: u16,
+    // time_fl_daily
+    // [+12] (min). Time spent in float.
+    time_fl_daily: u16,
+    // [+13,+14,+15] Unused, these read back as erased EEPROM (0xFFFF).
 }
 
 impl LoggedResponseDay {
     pub fn from_raw_bits(raw_data: [u16; 16]) -> LoggedResponseDay {
-        let hourmeter = u32::from_be(((u32::from(raw_data[0]) << 16) | u32::from(raw_data[1])) & 0xffff_ff00);
-        let alarm_daily = u32::from_be(((u32::from(raw_data[1]) << 16) | u32::from(raw_data[2])) & 0x00ff_ffff);
+        // The low byte of the second register holds the high byte of the hourmeter
+        let hourmeter = (u32::from(raw_data[1] & 0x00ff) << 16) | u32::from(raw_data[0]);
+        // The high byte of the second register holds the low byte of the alarms
+        let alarm_daily = (u32::from(raw_data[2]) << 8) | (u32::from(raw_data[1]) >> 8);
         LoggedResponseDay {
             hourmeter,
             alarm_daily,
@@ -22,10 +60,27 @@ impl LoggedResponseDay {
             vb_max_daily: raw_data[4],
             ahc_daily: raw_data[5],
             ahl_daily: raw_data[6],
+            array_fault_daily: raw_data[7],
+            load_fault_daily: raw_data[8],
             va_max_daily: raw_data[9],
+            time_ab_daily: raw_data[10],
+            time_eq_daily: raw_data[11],
+            time_fl_daily: raw_data[12],
         }
     }
 
+    pub fn alarm(&self) -> Alarm {
+        Alarm::from_raw(self.alarm_daily)
+    }
+
+    pub fn array_fault(&self) -> ArrayFault {
+        ArrayFault::from_raw(self.array_fault_daily)
+    }
+
+    pub fn load_fault(&self) -> LoadFault {
+        LoadFault::from_raw(self.load_fault_daily)
+    }
+
     pub fn battery_voltage_min(&self) -> f32 {
         conv_100_2_15_scale!(self.vb_min_daily)
     }
@@ -45,6 +100,38 @@ impl LoggedResponseDay {
     pub fn array_voltage_max(&self) -> f32 {
         conv_100_2_15_scale!(self.va_max_daily)
     }
+
+    pub fn time_in_absorption(&self) -> u16 {
+        self.time_ab_daily
+    }
+
+    pub fn time_in_equalize(&self) -> u16 {
+        self.time_eq_daily
+    }
+
+    pub fn time_in_float(&self) -> u16 {
+        self.time_fl_daily
+    }
+
+    /// Estimates the date this day was logged on from the controller's current hourmeter and the time it was read.
+    /// The hourmeter only runs while the controller is powered, so days before an outage are placed too recently.
+    pub fn estimated_date(&self, current_hourmeter: u32, now: &DateTime<FixedOffset>) -> Option<NaiveDate> {
+        let hours_ago = current_hourmeter.checked_sub(self.hourmeter)?;
+        let logged_at = *now - Duration::hours(i64::from(hours_ago));
+        Some(logged_at.naive_local().date())
+    }
+
+    /// Lists values without a documented meaning, these are still decoded as unknown
+    pub fn undocumented_values(&self) -> Vec<DecodeError> {
+        vec![
+            ArrayFault::try_from(self.array_fault_daily).err(),
+            LoadFault::try_from(self.load_fault_daily).err(),
+            Alarm::try_from(self.alarm_daily).err(),
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
 }
 
 impl Ord for LoggedResponseDay {
@@ -79,15 +166,86 @@ mod test {
     fn loggedresponse_from_raw_bits() {
         let day = LoggedResponseDay::from_raw_bits(DEFAULT_TEST_RAW_BITS);
 
-        assert_eq!(day.hourmeter, 0x01_0224);
-        assert_eq!(day.alarm_daily, 0x00_0000);
+        assert_eq!(day.hourmeter, 0x00_2402);
+        assert_eq!(day.alarm_daily, 0x00_0001);
         assert_eq!(day.vb_min_daily, 0x1011);
         assert_eq!(day.vb_max_daily, 0x11fb);
+        assert_eq!(day.va_max_daily, 0x1a84);
+        assert_eq!(day.time_ab_daily, 0x00b4);
+        assert_eq!(day.time_fl_daily, 0x010f);
 
         assert_eq!(day.battery_voltage_min(), 12.551_88);
         assert_eq!(day.battery_voltage_max(), 14.047_241);
         assert_eq!(day.battery_charge_daily(), 7.1);
         assert_eq!(day.load_charge_daily(), 2.7);
         assert_eq!(day.array_voltage_max(), 20.715_332);
+        assert_eq!(day.alarm(), Alarm::RTS_OPEN);
+        assert_eq!(day.array_fault(), ArrayFault::empty());
+        assert_eq!(day.load_fault(), LoadFault::empty());
+        assert_eq!(day.time_in_absorption(), 180);
+        assert_eq!(day.time_in_equalize(), 0);
+        assert_eq!(day.time_in_float(), 271);
+    }
+
+    #[test]
+    fn loggedresponse_estimated_date() {
+        let day = LoggedResponseDay::from_raw_bits(DEFAULT_TEST_RAW_BITS);
+        let now = DateTime::parse_from_rfc3339("2019-06-30T12:00:00+00:00").unwrap();
+
+        assert_eq!(day.estimated_date(0x2402, &now), Some(NaiveDate::from_ymd(2019, 6, 30)));
+        assert_eq!(day.estimated_date(0x2402 + 12, &now), Some(NaiveDate::from_ymd(2019, 6, 30)));
+        assert_eq!(day.estimated_date(0x2402 + 13, &now), Some(NaiveDate::from_ymd(2019, 6, 29)));
+        assert_eq!(day.estimated_date(0x2402 + 24 * 30, &now), Some(NaiveDate::from_ymd(2019, 5, 31)));
+        // The controller was reset since the day was logged
+        assert_eq!(day.estimated_date(0x0010, &now), None);
+    }
+
+    #[test]
+    fn loggedresponse_estimated_date_offset() {
+        let day = LoggedResponseDay::from_raw_bits(DEFAULT_TEST_RAW_BITS);
+        let now = DateTime::parse_from_rfc3339("2019-06-30T12:00:00+00:00").unwrap();
+
+        let east = now.with_timezone(&FixedOffset::east(2 * 3600));
+        assert_eq!(day.estimated_date(0x2402 + 13, &east), Some(NaiveDate::from_ymd(2019, 6, 30)));
+        let west = now.with_timezone(&FixedOffset::west(5 * 3600));
+        assert_eq!(day.estimated_date(0x2402 + 8, &west), Some(NaiveDate::from_ymd(2019, 6, 29)));
+    }
+
+    #[test]
+    fn loggedresponse_from_raw_bits_hourmeter() {
+        let mut raw_bits = DEFAULT_TEST_RAW_BITS;
+        raw_bits[0] = 0x2402;
+        raw_bits[1] = 0x2012;
+        let day = LoggedResponseDay::from_raw_bits(raw_bits);
+
+        // The alarm byte sharing the register must not leak into the hourmeter
+        assert_eq!(day.hourmeter, 0x12_2402);
+        assert_eq!(day.alarm_daily, 0x00_0020);
+    }
+
+    #[test]
+    fn loggedresponse_from_raw_bits_faults() {
+        let mut raw_bits = DEFAULT_TEST_RAW_BITS;
+        raw_bits[7] = 0x0001;
+        raw_bits[8] = 0x8002;
+        let day = LoggedResponseDay::from_raw_bits(raw_bits);
+
+        assert_eq!(day.array_fault(), ArrayFault::OVERCURENT);
+        assert!(day.load_fault().contains(LoadFault::OVERCURRENT));
+        assert_eq!(day.undocumented_values(), vec![DecodeError::new("load fault", 0x8002)]);
+    }
+
+    #[test]
+    fn loggedresponse_from_raw_bits_alarm() {
+        let mut raw_bits = DEFAULT_TEST_RAW_BITS;
+        raw_bits[1] = 0x2000;
+        raw_bits[2] = 0x0802;
+        let day = LoggedResponseDay::from_raw_bits(raw_bits);
+
+        assert_eq!(day.alarm_daily, 0x08_0220);
+        assert_eq!(
+            day.alarm(),
+            Alarm::HEATSINK_TEMP_LIMIT | Alarm::BATTERY_SENSE_DISCONNECTED | Alarm::CONTROLLER_WAS_RESET
+        );
     }
 }
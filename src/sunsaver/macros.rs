@@ -9,3 +9,21 @@ macro_rules! conv_7916_2_15_scale {
         (f32::from($expression) * 79.16) / 32768.0
     };
 }
+
+macro_rules! conv_9895_2_16_scale {
+    ($expression:expr) => {
+        (f32::from($expression) * 989.5) / 65536.0
+    };
+}
+
+macro_rules! conv_100_2_16_scale {
+    ($expression:expr) => {
+        (f32::from($expression as i16) * 100.0) / 65536.0
+    };
+}
+
+macro_rules! conv_1263_2_16_scale {
+    ($expression:expr) => {
+        (f32::from($expression) * 1.263) / 65536.0
+    };
+}
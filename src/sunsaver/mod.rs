@@ -1,11 +1,32 @@
 #[macro_use]
 mod macros;
 
+mod registerlayout;
+pub use self::registerlayout::{RegisterLayout, EEPROM_LAYOUT, LOGGED_LAYOUT, RAM_LAYOUT};
+
+mod decodeerror;
+pub use self::decodeerror::DecodeError;
+
 mod chargestate;
 pub use self::chargestate::ChargeState;
 
 mod arrayfault;
-pub use self::arrayfault::ArrayFault;
+pub use self::arrayfault::{ArrayFault, ARRAY_FAULT_FLAGS};
+
+mod alarm;
+pub use self::alarm::{Alarm, ALARM_FLAGS};
+
+mod loadstate;
+pub use self::loadstate::LoadState;
+
+mod loadfault;
+pub use self::loadfault::{LoadFault, LOAD_FAULT_FLAGS};
+
+mod coil;
+pub use self::coil::Coil;
+
+mod command;
+pub use self::command::Command;
 
 mod sunsaverresponse;
 pub use self::sunsaverresponse::SunSaverResponse;
@@ -15,3 +36,9 @@ pub use self::loggedresponseday::LoggedResponseDay;
 
 mod loggedresponse;
 pub use self::loggedresponse::LoggedResponse;
+
+mod eepromsetting;
+pub use self::eepromsetting::{EepromSetting, EepromSettingChange, SettingsValidationError};
+
+mod eepromsettings;
+pub use self::eepromsettings::EepromSettings;
@@ -0,0 +1,135 @@
+use std::ops::Range;
+
+/// A run of registers fetched with a single Modbus read.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RegisterWindow {
+    pub address: u16,
+    pub count: u16,
+}
+
+/// Maps a contiguous block of register addresses onto the indexes of a raw register array.
+/// The block is read in windows of at most `window_size` registers.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RegisterLayout {
+    start: u16,
+    len: u16,
+    window_size: u16,
+}
+
+// RAM registers 0x0008 to 0x0033
+pub const RAM_LAYOUT: RegisterLayout = RegisterLayout::new(0x0008, 44, 22);
+// 32 logged days of 16 registers each, from 0x8000
+pub const LOGGED_LAYOUT: RegisterLayout = RegisterLayout::new(0x8000, 32 * 16, 16);
+// EEPROM registers 0xE000 to 0xE02E
+pub const EEPROM_LAYOUT: RegisterLayout = RegisterLayout::new(0xE000, 47, 16);
+
+impl RegisterLayout {
+    pub const fn new(start: u16, len: u16, window_size: u16) -> RegisterLayout {
+        RegisterLayout { start, len, window_size }
+    }
+
+    pub fn len(&self) -> usize {
+        usize::from(self.len)
+    }
+
+    pub fn index_of(&self, address: u16) -> Option<usize> {
+        if address < self.start || address - self.start >= self.len {
+            return None;
+        }
+        Some(usize::from(address - self.start))
+    }
+
+    pub fn windows(&self) -> Vec<RegisterWindow> {
+        (0..self.len)
+            .step_by(usize::from(self.window_size))
+            .map(|offset| RegisterWindow {
+                address: self.start + offset,
+                count: self.window_size.min(self.len - offset),
+            })
+            .collect()
+    }
+
+    pub fn index_range(&self, window: &RegisterWindow) -> Option<Range<usize>> {
+        let start = self.index_of(window.address)?;
+        let end = start + usize::from(window.count);
+        if window.count == 0 || end > self.len() {
+            return None;
+        }
+        Some(start..end)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn registerlayout_ram() {
+        assert_eq!(RAM_LAYOUT.len(), 44);
+        assert_eq!(
+            RAM_LAYOUT.windows(),
+            vec![
+                RegisterWindow { address: 0x0008, count: 22 },
+                RegisterWindow { address: 0x001E, count: 22 }
+            ]
+        );
+
+        assert_eq!(RAM_LAYOUT.index_of(0x0007), None);
+        assert_eq!(RAM_LAYOUT.index_of(0x0008), Some(0));
+        assert_eq!(RAM_LAYOUT.index_of(0x001D), Some(21));
+        assert_eq!(RAM_LAYOUT.index_of(0x001E), Some(22));
+        assert_eq!(RAM_LAYOUT.index_of(0x0033), Some(43));
+        assert_eq!(RAM_LAYOUT.index_of(0x0034), None);
+
+        let windows = RAM_LAYOUT.windows();
+        assert_eq!(RAM_LAYOUT.index_range(&windows[0]), Some(0..22));
+        assert_eq!(RAM_LAYOUT.index_range(&windows[1]), Some(22..44));
+    }
+
+    #[test]
+    fn registerlayout_logged() {
+        assert_eq!(LOGGED_LAYOUT.len(), 512);
+
+        let windows = LOGGED_LAYOUT.windows();
+        assert_eq!(windows.len(), 32);
+        assert_eq!(windows[0], RegisterWindow { address: 0x8000, count: 16 });
+        assert_eq!(windows[31], RegisterWindow { address: 0x81F0, count: 16 });
+        assert_eq!(LOGGED_LAYOUT.index_range(&windows[31]), Some(496..512));
+    }
+
+    #[test]
+    fn registerlayout_eeprom() {
+        assert_eq!(EEPROM_LAYOUT.len(), 47);
+        assert_eq!(
+            EEPROM_LAYOUT.windows(),
+            vec![
+                RegisterWindow { address: 0xE000, count: 16 },
+                RegisterWindow { address: 0xE010, count: 16 },
+                RegisterWindow { address: 0xE020, count: 15 }
+            ]
+        );
+        assert_eq!(EEPROM_LAYOUT.index_of(0xE02E), Some(46));
+        assert_eq!(EEPROM_LAYOUT.index_of(0xE02F), None);
+    }
+
+    #[test]
+    fn registerlayout_windows_cover_layout() {
+        let layout = RegisterLayout::new(0x0100, 10, 4);
+        assert_eq!(
+            layout.windows(),
+            vec![
+                RegisterWindow { address: 0x0100, count: 4 },
+                RegisterWindow { address: 0x0104, count: 4 },
+                RegisterWindow { address: 0x0108, count: 2 }
+            ]
+        );
+    }
+
+    #[test]
+    fn registerlayout_index_range_bounds() {
+        assert_eq!(RAM_LAYOUT.index_range(&RegisterWindow { address: 0x0000, count: 8 }), None);
+        assert_eq!(RAM_LAYOUT.index_range(&RegisterWindow { address: 0x0008, count: 0 }), None);
+        assert_eq!(RAM_LAYOUT.index_range(&RegisterWindow { address: 0x001F, count: 22 }), None);
+        assert_eq!(RAM_LAYOUT.index_range(&RegisterWindow { address: 0x0034, count: 1 }), None);
+    }
+}
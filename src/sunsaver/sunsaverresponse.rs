@@ -1,4 +1,6 @@
-use crate::{ArrayFault, ChargeState};
+use std::convert::TryFrom;
+
+use crate::{Alarm, ArrayFault, ChargeState, DecodeError, LoadFault, LoadState};
 
 #[derive(Debug, Clone, Serialize)]
 pub struct SunSaverResponse {
@@ -47,6 +49,103 @@ pub struct SunSaverResponse {
     // [19][0x0012] (bit-field). Solar input self-diagnostic faults.
     // Reports faults identified by self diagnostics. Each bit corresponds to a specific fault.
     array_fault: u16,
+    // Vb_f
+    // [20][0x0013] (V). Battery voltage, slow filter.
+    // Battery voltage filtered with a 25 second time constant.
+    vb_f: u16,
+    // Vb_ref
+    // [21][0x0014] (V). Target regulation voltage.
+    // Current charging regulation voltage set-point, including temperature compensation.
+    vb_ref: u16,
+    // Ahc_r
+    // [22,23][0x0015,0x0016] (Ah). Ah charge, resettable.
+    // Total Ah charge since the last reset, split over a high and low register.
+    ahc_r: u32,
+    // Ahc_t
+    // [24,25][0x0017,0x0018] (Ah). Ah charge, total.
+    // Total Ah charge over the lifetime of the controller.
+    ahc_t: u32,
+    // kWhc
+    // [26][0x0019] (kWh). kWh charge.
+    // Total kWh charge over the lifetime of the controller.
+    kwhc: u16,
+    // Load_state
+    // [27][0x001A] ( ).
+    // Reports the load state.
+    load_state: u16,
+    // Load_fault
+    // [28][0x001B] (bit-field). Load self-diagnostic faults.
+    // Reports faults identified by self diagnostics. Each bit corresponds to a specific fault.
+    load_fault: u16,
+    // V_lvd
+    // [29][0x001C] (V). Load LVD setpoint.
+    // Current load low voltage disconnect set-point, including load current compensation.
+    v_lvd: u16,
+    // Ahl_r
+    // [30,31][0x001D,0x001E] (Ah). Ah load, resettable.
+    // Total Ah drawn by the load since the last reset.
+    ahl_r: u32,
+    // Ahl_t
+    // [32,33][0x001F,0x0020] (Ah). Ah load, total.
+    // Total Ah drawn by the load over the lifetime of the controller.
+    ahl_t: u32,
+    // hourmeter
+    // [34,35][0x0021,0x0022] (h). Hourmeter.
+    // Hours the controller has been powered.
+    hourmeter: u32,
+    // Alarm
+    // [36,37][0x0023,0x0024] (bit-field). Controller self-diagnostic alarms.
+    // Reports alarms identified by self diagnostics. Each bit corresponds to a specific alarm.
+    alarm: u32,
+    // dip_switch
+    // [38][0x0025] (bit-field). DIP switch positions.
+    // Bit 0 corresponds to switch 1.
+    dip_switch: u16,
+    // led_state
+    // [39][0x0026] ( ). SOC LED state.
+    // Reports the state of the battery state of charge LEDs.
+    led_state: u16,
+    // Power_out
+    // [40][0x0027] (W). Output power.
+    // Power delivered by the charger to the battery and load.
+    power_out: u16,
+    // Sweep_Vmp
+    // [41][0x0028] (V). Array Vmp.
+    // Array voltage at the maximum power point found by the last sweep.
+    sweep_vmp: u16,
+    // Sweep_Pmax
+    // [42][0x0029] (W). Array Pmax.
+    // Array maximum power found by the last sweep.
+    sweep_pmax: u16,
+    // Sweep_Voc
+    // [43][0x002A] (V). Array Voc.
+    // Array open circuit voltage measured by the last sweep.
+    sweep_voc: u16,
+    // Vb_min_daily
+    // [44][0x002B] (V). Minimum battery voltage today.
+    vb_min_daily: u16,
+    // Vb_max_daily
+    // [45][0x002C] (V). Maximum battery voltage today.
+    vb_max_daily: u16,
+    // Ahc_daily
+    // [46][0x002D] (Ah). Ah charge today.
+    ahc_daily: u16,
+    // Ahl_daily
+    // [47][0x002E] (Ah). Ah load today.
+    ahl_daily: u16,
+    // Array_fault_daily
+    // [48][0x002F] (bit-field). Array faults seen today.
+    array_fault_daily: u16,
+    // Load_fault_daily
+    // [49][0x0030] (bit-field). Load faults seen today.
+    load_fault_daily: u16,
+    // Alarm_daily
+    // [50,51][0x0031,0x0032] (bit-field). Alarms seen today.
+    alarm_daily: u32,
+}
+
+fn join_registers(high: u16, low: u16) -> u32 {
+    (u32::from(high) << 16) | u32::from(low)
 }
 
 impl SunSaverResponse {
@@ -64,9 +163,51 @@ impl SunSaverResponse {
             t_rts:    raw_data[8],
             charge_state: raw_data[9],
             array_fault: raw_data[10],
+            vb_f:     raw_data[11],
+            vb_ref:   raw_data[12],
+            ahc_r:    join_registers(raw_data[13], raw_data[14]),
+            ahc_t:    join_registers(raw_data[15], raw_data[16]),
+            kwhc:     raw_data[17],
+            load_state: raw_data[18],
+            load_fault: raw_data[19],
+            v_lvd:    raw_data[20],
+            ahl_r:    join_registers(raw_data[21], raw_data[22]),
+            ahl_t:    join_registers(raw_data[23], raw_data[24]),
+            hourmeter: join_registers(raw_data[25], raw_data[26]),
+            alarm:    join_registers(raw_data[27], raw_data[28]) & 0x00ff_ffff,
+            dip_switch: raw_data[29],
+            led_state: raw_data[30],
+            power_out: raw_data[31],
+            sweep_vmp: raw_data[32],
+            sweep_pmax: raw_data[33],
+            sweep_voc: raw_data[34],
+            vb_min_daily: raw_data[35],
+            vb_max_daily: raw_data[36],
+            ahc_daily: raw_data[37],
+            ahl_daily: raw_data[38],
+            array_fault_daily: raw_data[39],
+            load_fault_daily: raw_data[40],
+            alarm_daily: join_registers(raw_data[41], raw_data[42]) & 0x00ff_ffff,
         }
     }
 
+    /// Lists registers holding values without a documented meaning, these are still decoded as unknown
+    pub fn undocumented_values(&self) -> Vec<DecodeError> {
+        vec![
+            ChargeState::try_from(self.charge_state).err(),
+            ArrayFault::try_from(self.array_fault).err(),
+            ArrayFault::try_from(self.array_fault_daily).err(),
+            LoadState::try_from(self.load_state).err(),
+            LoadFault::try_from(self.load_fault).err(),
+            LoadFault::try_from(self.load_fault_daily).err(),
+            Alarm::try_from(self.alarm).err(),
+            Alarm::try_from(self.alarm_daily).err(),
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+
     pub fn battery_voltage_filtered(&self) -> f32 {
         conv_100_2_15_scale!(self.adc_vb_f)
     }
@@ -104,11 +245,115 @@ impl SunSaverResponse {
     }
 
     pub fn charge_state(&self) -> ChargeState {
-        self.charge_state.into()
+        ChargeState::from_raw(self.charge_state)
     }
 
     pub fn array_fault(&self) -> ArrayFault {
-        self.array_fault.into()
+        ArrayFault::from_raw(self.array_fault)
+    }
+
+    pub fn battery_voltage_slow_filtered(&self) -> f32 {
+        conv_100_2_15_scale!(self.vb_f)
+    }
+
+    pub fn battery_regulation_voltage(&self) -> f32 {
+        conv_100_2_15_scale!(self.vb_ref)
+    }
+
+    pub fn battery_charge_resettable(&self) -> f32 {
+        self.ahc_r as f32 * 0.1
+    }
+
+    pub fn battery_charge_total(&self) -> f32 {
+        self.ahc_t as f32 * 0.1
+    }
+
+    pub fn battery_charge_energy_total(&self) -> f32 {
+        f32::from(self.kwhc) * 0.1
+    }
+
+    pub fn load_state(&self) -> LoadState {
+        LoadState::from_raw(self.load_state)
+    }
+
+    pub fn load_fault(&self) -> LoadFault {
+        LoadFault::from_raw(self.load_fault)
+    }
+
+    pub fn load_low_voltage_disconnect(&self) -> f32 {
+        conv_100_2_15_scale!(self.v_lvd)
+    }
+
+    pub fn load_charge_resettable(&self) -> f32 {
+        self.ahl_r as f32 * 0.1
+    }
+
+    pub fn load_charge_total(&self) -> f32 {
+        self.ahl_t as f32 * 0.1
+    }
+
+    pub fn hourmeter(&self) -> u32 {
+        self.hourmeter
+    }
+
+    pub fn alarm(&self) -> Alarm {
+        Alarm::from_raw(self.alarm)
+    }
+
+    pub fn dip_switches(&self) -> [bool; 4] {
+        let mut dip_switches = [false; 4];
+        for (i, dip_switch) in dip_switches.iter_mut().enumerate() {
+            *dip_switch = self.dip_switch & (1 << i) != 0;
+        }
+        dip_switches
+    }
+
+    pub fn led_state(&self) -> u16 {
+        self.led_state
+    }
+
+    pub fn output_power(&self) -> f32 {
+        conv_9895_2_16_scale!(self.power_out)
+    }
+
+    pub fn sweep_maximum_power_voltage(&self) -> f32 {
+        conv_100_2_15_scale!(self.sweep_vmp)
+    }
+
+    pub fn sweep_maximum_power(&self) -> f32 {
+        conv_9895_2_16_scale!(self.sweep_pmax)
+    }
+
+    pub fn sweep_open_circuit_voltage(&self) -> f32 {
+        conv_100_2_15_scale!(self.sweep_voc)
+    }
+
+    pub fn battery_voltage_min_daily(&self) -> f32 {
+        conv_100_2_15_scale!(self.vb_min_daily)
+    }
+
+    pub fn battery_voltage_max_daily(&self) -> f32 {
+        conv_100_2_15_scale!(self.vb_max_daily)
+    }
+
+    pub fn battery_charge_daily(&self) -> f32 {
+        f32::from(self.ahc_daily) * 0.1
+    }
+
+    pub fn load_charge_daily(&self) -> f32 {
+        f32::from(self.ahl_daily) * 0.1
+    }
+
+    pub fn array_fault_daily(&self) -> ArrayFault {
+        ArrayFault::from_raw(self.array_fault_daily)
+    }
+
+    pub fn load_fault_daily(&self) -> LoadFault {
+        LoadFault::from_raw(self.load_fault_daily)
+    }
+
+    pub fn alarm_daily(&self) -> Alarm {
+        Alarm::from_raw(self.alarm_daily)
     }
 }
 
@@ -120,10 +365,10 @@ mod test {
     const DEFAULT_TEST_RAW_BITS: [u16; 44] = [
         0x1079, 0x11c9, 0x1074, 0x0035, 0x009a, 0x0017, 0x0017, 0x0017,
         0x0019, 0x0005, 0x0000, 0x1079, 0x1200, 0x0000, 0x1712, 0x0000,
-        0x1712, 0x004e, 0x0001, 0x0000, 0x0e13, 0x0000, 0x0000, 0x0f2a,
-        0x0000, 0x0f2a, 0x0000, 0x26b6, 0x0000, 0x0001, 0x000b, 0x0006,
-        0x006b, 0x10b1, 0x0123, 0x1640, 0x1009, 0x11f1, 0x004e, 0x002b,
-        0x0000, 0x0000, 0x0000, 0x0001
+        0x1712, 0x004e, 0x0001, 0x0000, 0x0e13, 0x0000, 0x0f2a, 0x0000,
+        0x0f2a, 0x0000, 0x26b6, 0x0000, 0x0001, 0x000b, 0x0006, 0x006b,
+        0x10b1, 0x0123, 0x1640, 0x1009, 0x11f1, 0x004e, 0x002b, 0x0000,
+        0x0000, 0x0000, 0x0001, 0x0000
     ];
 
     #[test]
@@ -144,6 +389,36 @@ mod test {
 
         assert_eq!(response.charge_state, 0x0005);
         assert_eq!(response.array_fault, 0x0000);
+
+        assert_eq!(response.vb_f, 0x1079);
+        assert_eq!(response.vb_ref, 0x1200);
+        assert_eq!(response.ahc_r, 0x0000_1712);
+        assert_eq!(response.ahc_t, 0x0000_1712);
+        assert_eq!(response.kwhc, 0x004e);
+
+        assert_eq!(response.load_state, 0x0001);
+        assert_eq!(response.load_fault, 0x0000);
+        assert_eq!(response.v_lvd, 0x0e13);
+        assert_eq!(response.ahl_r, 0x0000_0f2a);
+        assert_eq!(response.ahl_t, 0x0000_0f2a);
+
+        assert_eq!(response.hourmeter, 0x0000_26b6);
+        assert_eq!(response.alarm, 0x0000_0001);
+        assert_eq!(response.dip_switch, 0x000b);
+        assert_eq!(response.led_state, 0x0006);
+
+        assert_eq!(response.power_out, 0x006b);
+        assert_eq!(response.sweep_vmp, 0x10b1);
+        assert_eq!(response.sweep_pmax, 0x0123);
+        assert_eq!(response.sweep_voc, 0x1640);
+
+        assert_eq!(response.vb_min_daily, 0x1009);
+        assert_eq!(response.vb_max_daily, 0x11f1);
+        assert_eq!(response.ahc_daily, 0x004e);
+        assert_eq!(response.ahl_daily, 0x002b);
+        assert_eq!(response.array_fault_daily, 0x0000);
+        assert_eq!(response.load_fault_daily, 0x0000);
+        assert_eq!(response.alarm_daily, 0x0000_0001);
     }
 
     #[test]
@@ -164,5 +439,66 @@ mod test {
 
         assert_eq!(response.charge_state(), ChargeState::BulkCharge);
         assert!(response.array_fault().is_empty());
+
+        assert_eq!(response.battery_voltage_slow_filtered(), 12.869_263);
+        assert_eq!(response.battery_regulation_voltage(), 14.0625);
+        assert_eq!(response.battery_charge_resettable(), 590.600_04);
+        assert_eq!(response.battery_charge_total(), 590.600_04);
+        assert_eq!(response.battery_charge_energy_total(), 7.8);
+
+        assert_eq!(response.load_state(), LoadState::Normal);
+        assert!(response.load_fault().is_empty());
+        assert_eq!(response.load_low_voltage_disconnect(), 10.995_483);
+        assert_eq!(response.load_charge_resettable(), 388.2);
+        assert_eq!(response.load_charge_total(), 388.2);
+
+        assert_eq!(response.hourmeter(), 9910);
+        assert_eq!(response.alarm(), Alarm::RTS_OPEN);
+        assert_eq!(response.dip_switches(), [true, true, false, true]);
+        assert_eq!(response.led_state(), 6);
+
+        assert_eq!(response.output_power(), 1.615_547_2);
+        assert_eq!(response.sweep_maximum_power_voltage(), 13.040_161);
+        assert_eq!(response.sweep_maximum_power(), 4.393_684_4);
+        assert_eq!(response.sweep_open_circuit_voltage(), 17.382_813);
+
+        assert_eq!(response.battery_voltage_min_daily(), 12.527_466);
+        assert_eq!(response.battery_voltage_max_daily(), 14.016_724);
+        assert_eq!(response.battery_charge_daily(), 7.8);
+        assert_eq!(response.load_charge_daily(), 4.3);
+        assert!(response.array_fault_daily().is_empty());
+        assert!(response.load_fault_daily().is_empty());
+        assert_eq!(response.alarm_daily(), Alarm::RTS_OPEN);
+    }
+
+    #[test]
+    fn sunsaverresponse_undocumented_values() {
+        let response = SunSaverResponse::from_raw_bits(DEFAULT_TEST_RAW_BITS);
+        assert!(response.undocumented_values().is_empty());
+
+        let mut raw_bits = DEFAULT_TEST_RAW_BITS;
+        raw_bits[9] = 0x0009;
+        raw_bits[10] = 0x8000;
+        raw_bits[18] = 0x0008;
+        raw_bits[40] = 0x0080;
+        raw_bits[27] = 0x0010;
+        let response = SunSaverResponse::from_raw_bits(raw_bits);
+        assert_eq!(
+            response.undocumented_values(),
+            vec![
+                DecodeError::new("charge state", 0x0009),
+                DecodeError::new("array fault", 0x8000),
+                DecodeError::new("load state", 0x0008),
+                DecodeError::new("load fault", 0x0080),
+                DecodeError::new("alarm", 0x10_0001),
+            ]
+        );
+
+        assert_eq!(response.charge_state(), ChargeState::Unknown(0x0009));
+        assert_eq!(response.array_fault().unknown_bits(), 0x8000);
+        assert_eq!(response.load_state(), LoadState::Unknown(0x0008));
+        assert_eq!(response.load_fault_daily().unknown_bits(), 0x0080);
+        assert_eq!(response.alarm().unknown_bits(), 0x10_0000);
+        assert!(response.alarm().contains(Alarm::RTS_OPEN));
     }
 }
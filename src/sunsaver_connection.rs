@@ -1,40 +1,313 @@
-use std::fs::{File, OpenOptions};
-use std::io::Read;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::ops::{Deref, DerefMut};
 use std::path::Path;
 use std::result::Result::{self, Err, Ok};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use libmodbus_rs::{Modbus, ModbusClient, ModbusRTU, SerialMode, Timeout};
+use libc;
 
-use retry::{Retry, RetryError};
+use libmodbus_rs::{Modbus, ModbusClient, ModbusRTU, ModbusTCPPI, SerialMode, Timeout};
+
+use retry::Retry;
 
 use hex_slice::AsHex;
 
+use crate::metrics::ModbusMetrics;
+use crate::recording::{RecordedFunction, Recorder};
 use crate::sunsaver::*;
 
-pub trait SunSaverConnection {
-    fn read_raw_registers(&mut self) -> [u16; 44];
+// libmodbus reports protocol errors as errno values above MODBUS_ENOBASE (see modbus.h)
+const MODBUS_ENOBASE: i32 = 112_345_678;
+const MODBUS_EMBBADCRC: i32 = MODBUS_ENOBASE + 12;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SunSaverConnectionError {
+    /// The device did not answer within the response timeout
+    Timeout,
+    /// The response failed its CRC check
+    Crc,
+    /// Fewer registers were returned than requested
+    ShortRead { expected: usize, actual: usize },
+    /// The device or the link to it is no longer available
+    DeviceGone(String),
+    /// Any other Modbus level failure e.g. an exception response
+    Protocol(String),
+    /// The connection does not support the requested operation
+    Unsupported(&'static str),
+    /// A register did not hold the written value when read back
+    Verify { address: u16, expected: u16, actual: u16 },
+}
+
+impl SunSaverConnectionError {
+    /// Every value returned by `kind()`
+    pub const KINDS: [&'static str; 7] = ["timeout", "crc", "short_read", "device_gone", "protocol", "unsupported", "verify"];
+
+    /// Short machine readable name of the error
+    pub fn kind(&self) -> &'static str {
+        match self {
+            SunSaverConnectionError::Timeout => "timeout",
+            SunSaverConnectionError::Crc => "crc",
+            SunSaverConnectionError::ShortRead { .. } => "short_read",
+            SunSaverConnectionError::DeviceGone(_) => "device_gone",
+            SunSaverConnectionError::Protocol(_) => "protocol",
+            SunSaverConnectionError::Unsupported(_) => "unsupported",
+            SunSaverConnectionError::Verify { .. } => "verify",
+        }
+    }
+
+    /// The error libmodbus left in errno after a failed call
+    fn last_os_error() -> SunSaverConnectionError {
+        SunSaverConnectionError::from_errno(io::Error::last_os_error().raw_os_error())
+    }
+
+    fn from_errno(errno: Option<i32>) -> SunSaverConnectionError {
+        match errno {
+            Some(libc::ETIMEDOUT) => SunSaverConnectionError::Timeout,
+            Some(MODBUS_EMBBADCRC) => SunSaverConnectionError::Crc,
+            Some(errno @ libc::EBADF)
+            | Some(errno @ libc::EIO)
+            | Some(errno @ libc::ENODEV)
+            | Some(errno @ libc::ENXIO)
+            | Some(errno @ libc::EPIPE)
+            | Some(errno @ libc::ECONNREFUSED)
+            | Some(errno @ libc::ECONNRESET) => SunSaverConnectionError::DeviceGone(io::Error::from_raw_os_error(errno).to_string()),
+            Some(errno) => SunSaverConnectionError::Protocol(format!("Modbus error {}", errno)),
+            None => SunSaverConnectionError::Protocol(String::from("Unknown Modbus error")),
+        }
+    }
+}
+
+impl fmt::Display for SunSaverConnectionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SunSaverConnectionError::Timeout => write!(f, "Timed out waiting for the device"),
+            SunSaverConnectionError::Crc => write!(f, "Response failed CRC check"),
+            SunSaverConnectionError::ShortRead { expected, actual } => {
+                write!(f, "Short read, expected {} registers got {}", expected, actual)
+            }
+            SunSaverConnectionError::DeviceGone(reason) => write!(f, "Device unavailable: {}", reason),
+            SunSaverConnectionError::Protocol(reason) => write!(f, "Protocol error: {}", reason),
+            SunSaverConnectionError::Unsupported(operation) => write!(f, "Connection does not support {}", operation),
+            SunSaverConnectionError::Verify { address, expected, actual } => {
+                write!(f, "Register {:#x} read back {:#x} after writing {:#x}", address, actual, expected)
+            }
+        }
+    }
+}
+
+impl Error for SunSaverConnectionError {}
+
+pub trait SunSaverConnection: Send {
+    fn read_raw_registers(&mut self) -> Result<[u16; 44], SunSaverConnectionError>;
+
+    fn read_raw_logged(&mut self) -> Result<[u16; 32 * 16], SunSaverConnectionError>;
+
+    fn read_status(&mut self) -> Result<SunSaverResponse, SunSaverConnectionError> {
+        let response = SunSaverResponse::from_raw_bits(self.read_raw_registers()?);
+        for undocumented_value in response.undocumented_values() {
+            warn!("Status: {}", undocumented_value);
+        }
+        Ok(response)
+    }
+
+    fn write_coil(&mut self, _coil: Coil, _value: bool) -> Result<(), SunSaverConnectionError> {
+        Err(SunSaverConnectionError::Unsupported("writing coils"))
+    }
+
+    fn execute_command(&mut self, command: Command) -> Result<(), SunSaverConnectionError> {
+        info!("Executing command {:?}", command);
+        for (coil, value) in command.coil_writes() {
+            self.write_coil(coil, value)?;
+        }
+        Ok(())
+    }
+
+    fn read_logged(&mut self) -> Result<LoggedResponse, SunSaverConnectionError> {
+        let response = LoggedResponse::from_raw_bits(self.read_raw_logged()?);
+        for undocumented_value in response.undocumented_values() {
+            warn!("Logged: {}", undocumented_value);
+        }
+        Ok(response)
+    }
+
+    fn read_raw_eeprom(&mut self) -> Result<[u16; 47], SunSaverConnectionError> {
+        Err(SunSaverConnectionError::Unsupported("reading EEPROM"))
+    }
+
+    fn read_eeprom(&mut self) -> Result<EepromSettings, SunSaverConnectionError> {
+        Ok(EepromSettings::from_raw_bits(self.read_raw_eeprom()?))
+    }
+
+    fn write_register(&mut self, _address: u16, _value: u16) -> Result<(), SunSaverConnectionError> {
+        Err(SunSaverConnectionError::Unsupported("writing registers"))
+    }
+
+    /// Addresses later requests to another controller on the same bus
+    fn select_slave(&mut self, _slave_id: u8) -> Result<(), SunSaverConnectionError> {
+        Err(SunSaverConnectionError::Unsupported("selecting a slave id"))
+    }
+
+    /// Counts later register reads in `metrics`, connections that do not talk Modbus ignore it
+    fn measure(&mut self, _metrics: Arc<ModbusMetrics>) {}
+
+    /// Writes each change then reads the EEPROM back to check the controller stored every value.
+    ///
+    /// If a write, the read back or the check fails the changes already written are rolled back,
+    /// so settings are never left half changed.
+    fn write_eeprom(&mut self, changes: &[EepromSettingChange]) -> Result<EepromSettings, SunSaverConnectionError> {
+        for (written, change) in changes.iter().enumerate() {
+            info!("Writing {:?} {:#x} -> {:#x}", change.setting, change.from, change.to);
+            if let Err(error) = self.write_register(change.setting.address(), change.to) {
+                roll_back_eeprom(self, &changes[..written]);
+                return Err(error);
+            }
+        }
+        let settings = match self.read_eeprom() {
+            Ok(settings) => settings,
+            Err(error) => {
+                roll_back_eeprom(self, changes);
+                return Err(error);
+            }
+        };
+        for change in changes {
+            let actual = settings.register(change.setting);
+            if actual != change.to {
+                roll_back_eeprom(self, changes);
+                return Err(SunSaverConnectionError::Verify {
+                    address: change.setting.address(),
+                    expected: change.to,
+                    actual,
+                });
+            }
+        }
+        Ok(settings)
+    }
+}
+
+/// Writes back the values `changes` replaced, latest first, logging any setting that is left changed
+fn roll_back_eeprom<C: SunSaverConnection + ?Sized>(connection: &mut C, changes: &[EepromSettingChange]) {
+    for change in changes.iter().rev() {
+        warn!("Rolling back {:?} {:#x} -> {:#x}", change.setting, change.to, change.from);
+        if let Err(error) = connection.write_register(change.setting.address(), change.from) {
+            error!("Failed to roll back {:?}, it is still {:#x}: {}", change.setting, change.to, error);
+        }
+    }
+}
+
+/// How failed Modbus requests are retried
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetryPolicy {
+    /// Requests made before giving up, including the first
+    pub attempts: u64,
+    /// Milliseconds to wait between requests
+    pub wait_ms: u64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy { attempts: 3, wait_ms: 100 }
+    }
+}
+
+/// Settings shared by Modbus RTU and TCP connections.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ModbusOptions {
+    pub slave_id: u8,
+    pub response_timeout: Duration,
+    pub retry: RetryPolicy,
+}
+
+impl Default for ModbusOptions {
+    fn default() -> ModbusOptions {
+        // The SunSaver MPPT default server address is 0x01
+        ModbusOptions {
+            slave_id: 0x01,
+            response_timeout: Duration::from_secs(1),
+            retry: RetryPolicy::default(),
+        }
+    }
+}
+
+impl ModbusOptions {
+    fn configure(&self, connection: &mut Modbus) -> Result<(), SunSaverConnectionError> {
+        connection
+            .set_slave(self.slave_id)
+            .map_err(|_| SunSaverConnectionError::last_os_error())?;
+        let timeout = Timeout {
+            sec: self.response_timeout.as_secs() as u32,
+            usec: self.response_timeout.subsec_micros(),
+        };
+        // libmodbus rejects a zero timeout with EINVAL
+        connection
+            .set_response_timeout(timeout)
+            .map_err(|_| SunSaverConnectionError::last_os_error())?;
+        connection.set_debug(false).map_err(|_| SunSaverConnectionError::last_os_error())?;
+
+        info!("Response timeout {:?}", self.response_timeout);
+        Ok(())
+    }
+}
+
+/// Serial line settings, the SunSaver MPPT uses 9600 baud 8N2 by default.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SerialOptions {
+    pub baud_rate: i32,
+    /// 'N', 'E' or 'O'
+    pub parity: char,
+    pub data_bits: i32,
+    pub stop_bits: i32,
+}
+
+impl Default for SerialOptions {
+    fn default() -> SerialOptions {
+        SerialOptions {
+            baud_rate: 9600,
+            parity: 'N',
+            data_bits: 8,
+            stop_bits: 2,
+        }
+    }
+}
+
+/// A libmodbus context that can be moved to the thread holding the shared connection
+struct ModbusContext(Modbus);
+
+// The libmodbus context is only ever used by one thread at a time, behind the shared connection's mutex
+unsafe impl Send for ModbusContext {}
 
-    fn read_raw_logged(&mut self) -> [u16; 32 * 16];
+impl Deref for ModbusContext {
+    type Target = Modbus;
 
-    fn read_status(&mut self) -> SunSaverResponse {
-        SunSaverResponse::from_raw_bits(self.read_raw_registers())
+    fn deref(&self) -> &Modbus {
+        &self.0
     }
+}
 
-    fn read_logged(&mut self) -> LoggedResponse {
-        LoggedResponse::from_raw_bits(self.read_raw_logged())
+impl DerefMut for ModbusContext {
+    fn deref_mut(&mut self) -> &mut Modbus {
+        &mut self.0
     }
 }
 
+/// A controller reached over Modbus RTU on a serial device, or Modbus TCP through a gateway
 pub struct ModbusSunSaverConnection {
-    connection: Modbus,
+    connection: ModbusContext,
+    retry: RetryPolicy,
+    recorder: Option<Recorder>,
+    metrics: Option<Arc<ModbusMetrics>>,
 }
 
 impl ModbusSunSaverConnection {
-    pub fn open(device: &Path) -> ModbusSunSaverConnection {
+    pub fn open_rtu(device: &Path, serial: &SerialOptions, options: &ModbusOptions) -> Result<ModbusSunSaverConnection, SunSaverConnectionError> {
         /* A Meterbus to Serial Converter (MSC) is required to adapt the Meter interface to an isolated RS-232 interface**.
         The SunSaver MPPT supports RTU mode only.
         16bit MODBUS® addresses (per the modbus.org spec)
-        The serial communication parameters are:
+        The default serial communication parameters are:
           * BPS: 9600 baud
           * Parity: None
           * Data bits: 8
@@ -42,100 +315,630 @@ impl ModbusSunSaverConnection {
           * Flow control: None
          All addresses listed are for the request PDU.
          The SunSaver MPPT default server address: 0x01. */
-        debug!("Configuring device {:?}", device);
-        let mut connection = Modbus::new_rtu(device.to_str().unwrap(), 9600, 'N', 8, 2).unwrap();
-        assert!(connection.set_slave(0x01).is_ok());
-        assert!(connection.rtu_set_serial_mode(SerialMode::RtuRS232).is_ok());
-        assert!(connection.set_response_timeout(Timeout { sec: 1, usec: 0 }).is_ok());
-        connection.set_debug(false).unwrap();
+        debug!("Configuring device {:?} with {:?}", device, serial);
+        let mut connection = Modbus::new_rtu(
+            device.to_str().unwrap(),
+            serial.baud_rate,
+            serial.parity,
+            serial.data_bits,
+            serial.stop_bits,
+        )
+        .map_err(|_| SunSaverConnectionError::last_os_error())?;
+        connection
+            .rtu_set_serial_mode(SerialMode::RtuRS232)
+            .map_err(|_| SunSaverConnectionError::last_os_error())?;
+        ModbusSunSaverConnection::connect(connection, options)
+    }
 
-        let timeout = connection.get_response_timeout();
-        info!("Timout {:?}", timeout);
+    pub fn open_tcp(host: &str, port: u16, options: &ModbusOptions) -> Result<ModbusSunSaverConnection, SunSaverConnectionError> {
+        /* Modbus TCP is provided by an RTU-to-TCP gateway in front of the SunSaver MPPT.
+        The gateway forwards requests to the RTU device addressed by the unit id, so the
+        slave id is used as for a directly attached device. */
+        debug!("Configuring Modbus TCP connection to {}:{}", host, port);
+        let connection = Modbus::new_tcp_pi(host, &port.to_string()).map_err(|_| SunSaverConnectionError::last_os_error())?;
+        ModbusSunSaverConnection::connect(connection, options)
+    }
 
-        assert!(connection.connect().is_ok());
+    fn connect(mut connection: Modbus, options: &ModbusOptions) -> Result<ModbusSunSaverConnection, SunSaverConnectionError> {
+        options.configure(&mut connection)?;
+
+        connection.connect().map_err(|_| SunSaverConnectionError::last_os_error())?;
         debug!("Connected");
 
-        ModbusSunSaverConnection { connection }
+        Ok(ModbusSunSaverConnection {
+            connection: ModbusContext(connection),
+            retry: options.retry,
+            recorder: None,
+            metrics: None,
+        })
     }
 
-    fn read_registers_retry(&self, address: u16, num_bit: u16, dest: &mut [u16]) -> Result<usize, RetryError> {
-        match Retry::new(&mut || self.connection.read_registers(address, num_bit, dest), &mut |response| {
-            response.is_ok()
-        })
-        .r#try(3)
-        .wait(100)
-        .execute()
-        {
-            Ok(response) => Ok(response.unwrap() as usize),
-            Err(error) => Err(error),
-        }
+    /// Records every later request with `recorder`
+    pub fn record(&mut self, recorder: Recorder) {
+        self.recorder = Some(recorder);
     }
 }
 
 impl SunSaverConnection for ModbusSunSaverConnection {
-    fn read_raw_registers(&mut self) -> [u16; 44] {
-        let mut response_register = [0u16; 44 as usize];
-        let mut num_read_bytes = 0;
-        num_read_bytes += self.read_registers_retry(0x08, 22, &mut response_register[0..22]).unwrap();
-        num_read_bytes += self.read_registers_retry(0x1E, 22, &mut response_register[23..44]).unwrap();
-        //if num_read_bytes != 44 {
-        //    panic!("Failed to read all registers! Required 44 got {}", num_read_bytes);
-        //}
-        debug!("Read {} bytes", num_read_bytes);
-        debug!("read reg 0x08 + 44: {:#x}", response_register.as_hex());
+    fn read_raw_registers(&mut self) -> Result<[u16; 44], SunSaverConnectionError> {
+        read_raw_registers(&self.connection, self.retry, &mut self.recorder, &self.metrics)
+    }
 
-        response_register
+    fn read_raw_logged(&mut self) -> Result<[u16; 32 * 16], SunSaverConnectionError> {
+        read_raw_logged(&self.connection, self.retry, &mut self.recorder, &self.metrics)
     }
 
-    fn read_raw_logged(&mut self) -> [u16; 32 * 16] {
-        let mut logged_data = [0u16; (32 * 16) as usize];
+    fn write_coil(&mut self, coil: Coil, value: bool) -> Result<(), SunSaverConnectionError> {
+        write_bit_retry(&self.connection, self.retry, &mut self.recorder, coil.address(), value)
+    }
+
+    fn read_raw_eeprom(&mut self) -> Result<[u16; 47], SunSaverConnectionError> {
+        read_raw_eeprom(&self.connection, self.retry, &mut self.recorder, &self.metrics)
+    }
 
-        for i in 0..32 {
-            let offset: usize = i * 16;
-            self.read_registers_retry((0x8000 + offset) as u16, 16, &mut logged_data[offset..offset + 16])
-                .unwrap();
+    fn write_register(&mut self, address: u16, value: u16) -> Result<(), SunSaverConnectionError> {
+        write_register_retry(&self.connection, self.retry, &mut self.recorder, address, value)
+    }
+
+    fn select_slave(&mut self, slave_id: u8) -> Result<(), SunSaverConnectionError> {
+        select_slave(&mut self.connection, &mut self.recorder, slave_id)
+    }
+
+    fn measure(&mut self, metrics: Arc<ModbusMetrics>) {
+        self.metrics = Some(metrics);
+    }
+}
+
+fn select_slave(connection: &mut Modbus, recorder: &mut Option<Recorder>, slave_id: u8) -> Result<(), SunSaverConnectionError> {
+    connection.set_slave(slave_id).map_err(|_| SunSaverConnectionError::last_os_error())?;
+    if let Some(recorder) = recorder {
+        recorder.select_slave(slave_id);
+    }
+    Ok(())
+}
+
+fn read_registers_retry(
+    connection: &Modbus,
+    retry: RetryPolicy,
+    recorder: &mut Option<Recorder>,
+    metrics: &Option<Arc<ModbusMetrics>>,
+    address: u16,
+    num_bit: u16,
+    dest: &mut [u16],
+) -> Result<usize, SunSaverConnectionError> {
+    // libmodbus writes num_bit registers into dest without checking its length
+    assert_eq!(dest.len(), usize::from(num_bit), "Destination does not match the number of registers");
+    let mut last_errno = None;
+    let mut attempts = 0;
+    let started = Instant::now();
+    let result = Retry::new(
+        &mut || {
+            attempts += 1;
+            let response = connection.read_registers(address, num_bit, dest);
+            if response.is_err() {
+                last_errno = io::Error::last_os_error().raw_os_error();
+            }
+            response
+        },
+        &mut |response| response.is_ok(),
+    )
+    .r#try(retry.attempts)
+    .wait(retry.wait_ms)
+    .execute();
+
+    let result = match result {
+        Ok(Ok(num_read)) if num_read == num_bit => Ok(num_read as usize),
+        Ok(Ok(num_read)) => Err(SunSaverConnectionError::ShortRead {
+            expected: num_bit as usize,
+            actual: num_read as usize,
+        }),
+        _ => {
+            let error = SunSaverConnectionError::from_errno(last_errno);
+            warn!("Failed to read {} registers at {:#x}: {}", num_bit, address, error);
+            Err(error)
+        }
+    };
+    if let Some(metrics) = metrics {
+        metrics.record_read(attempts, started.elapsed(), result.as_ref().err());
+    }
+    if let Some(recorder) = recorder {
+        let values: &[u16] = match &result {
+            Ok(_) => &dest[..],
+            Err(SunSaverConnectionError::ShortRead { actual, .. }) => &dest[..*actual],
+            Err(_) => &[],
+        };
+        recorder.record(RecordedFunction::ReadRegisters, address, num_bit, values, result.as_ref().err());
+    }
+    result
+}
+
+fn write_bit_retry(
+    connection: &Modbus,
+    retry: RetryPolicy,
+    recorder: &mut Option<Recorder>,
+    address: u16,
+    value: bool,
+) -> Result<(), SunSaverConnectionError> {
+    let mut last_errno = None;
+    let result = Retry::new(
+        &mut || {
+            let response = connection.write_bit(address, value);
+            if response.is_err() {
+                last_errno = io::Error::last_os_error().raw_os_error();
+            }
+            response
+        },
+        &mut |response| response.is_ok(),
+    )
+    .r#try(retry.attempts)
+    .wait(retry.wait_ms)
+    .execute();
+
+    let result = match result {
+        Ok(Ok(_)) => Ok(()),
+        _ => {
+            let error = SunSaverConnectionError::from_errno(last_errno);
+            warn!("Failed to write coil {:#x}: {}", address, error);
+            Err(error)
         }
+    };
+    if let Some(recorder) = recorder {
+        recorder.record(RecordedFunction::WriteCoil, address, 1, &[u16::from(value)], result.as_ref().err());
+    }
+    result
+}
 
-        debug!("logged_data_start");
-        for i in (0 as usize)..32 {
-            let offset: usize = i * 16;
-            debug!("{:#x}", logged_data[offset..(offset + 16)].as_hex());
+fn write_register_retry(
+    connection: &Modbus,
+    retry: RetryPolicy,
+    recorder: &mut Option<Recorder>,
+    address: u16,
+    value: u16,
+) -> Result<(), SunSaverConnectionError> {
+    let mut last_errno = None;
+    let result = Retry::new(
+        &mut || {
+            let response = connection.write_register(address, value);
+            if response.is_err() {
+                last_errno = io::Error::last_os_error().raw_os_error();
+            }
+            response
+        },
+        &mut |response| response.is_ok(),
+    )
+    .r#try(retry.attempts)
+    .wait(retry.wait_ms)
+    .execute();
+
+    let result = match result {
+        Ok(Ok(_)) => Ok(()),
+        _ => {
+            let error = SunSaverConnectionError::from_errno(last_errno);
+            warn!("Failed to write register {:#x}: {}", address, error);
+            Err(error)
         }
-        debug!("logged_data_end");
+    };
+    if let Some(recorder) = recorder {
+        recorder.record(RecordedFunction::WriteRegister, address, 1, &[value], result.as_ref().err());
+    }
+    result
+}
 
-        logged_data
+fn read_layout_retry(
+    connection: &Modbus,
+    retry: RetryPolicy,
+    recorder: &mut Option<Recorder>,
+    metrics: &Option<Arc<ModbusMetrics>>,
+    layout: &RegisterLayout,
+    dest: &mut [u16],
+) -> Result<(), SunSaverConnectionError> {
+    assert_eq!(dest.len(), layout.len(), "Destination does not match the register layout");
+    let mut num_read = 0;
+    for window in layout.windows() {
+        let range = layout.index_range(&window).expect("Register window outside of layout");
+        num_read += read_registers_retry(connection, retry, recorder, metrics, window.address, window.count, &mut dest[range])?;
     }
+    if num_read != layout.len() {
+        return Err(SunSaverConnectionError::ShortRead {
+            expected: layout.len(),
+            actual: num_read,
+        });
+    }
+    Ok(())
 }
 
-#[derive(Debug)]
-pub struct FileSunSaverConnection {
-    file: File,
+fn read_raw_registers(
+    connection: &Modbus,
+    retry: RetryPolicy,
+    recorder: &mut Option<Recorder>,
+    metrics: &Option<Arc<ModbusMetrics>>,
+) -> Result<[u16; 44], SunSaverConnectionError> {
+    let mut response_register = [0u16; 44 as usize];
+    read_layout_retry(connection, retry, recorder, metrics, &RAM_LAYOUT, &mut response_register)?;
+    debug!("read reg 0x08 + 44: {:#x}", response_register.as_hex());
+
+    Ok(response_register)
 }
 
-impl FileSunSaverConnection {
-    pub fn open(filename: &Path) -> FileSunSaverConnection {
-        let file = OpenOptions::new().read(true).write(false).open(filename).unwrap();
+fn read_raw_logged(
+    connection: &Modbus,
+    retry: RetryPolicy,
+    recorder: &mut Option<Recorder>,
+    metrics: &Option<Arc<ModbusMetrics>>,
+) -> Result<[u16; 32 * 16], SunSaverConnectionError> {
+    let mut logged_data = [0u16; (32 * 16) as usize];
+    read_layout_retry(connection, retry, recorder, metrics, &LOGGED_LAYOUT, &mut logged_data)?;
 
-        FileSunSaverConnection { file }
+    debug!("logged_data_start");
+    for i in (0 as usize)..32 {
+        let offset: usize = i * 16;
+        debug!("{:#x}", logged_data[offset..(offset + 16)].as_hex());
     }
+    debug!("logged_data_end");
+
+    Ok(logged_data)
+}
+
+fn read_raw_eeprom(
+    connection: &Modbus,
+    retry: RetryPolicy,
+    recorder: &mut Option<Recorder>,
+    metrics: &Option<Arc<ModbusMetrics>>,
+) -> Result<[u16; 47], SunSaverConnectionError> {
+    let mut eeprom_register = [0u16; 47 as usize];
+    read_layout_retry(connection, retry, recorder, metrics, &EEPROM_LAYOUT, &mut eeprom_register)?;
+    debug!("read reg 0xE000 + 47: {:#x}", eeprom_register.as_hex());
+
+    Ok(eeprom_register)
 }
 
-impl SunSaverConnection for FileSunSaverConnection {
-    fn read_raw_registers(&mut self) -> [u16; 44] {
-        let mut response_register_u8 = [0u8; 88 as usize];
-        assert!(self.file.read_exact(&mut response_register_u8).is_ok());
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    use tempdir::TempDir;
+
+    use super::*;
+
+    use crate::recording::{ReplayOptions, ReplaySunSaverConnection};
+    use crate::simulator::{Profile, SimulatedController, SimulatorServer};
+
+    type CoilWrites = Arc<Mutex<Vec<(u16, bool)>>>;
+    type RegisterWrites = Arc<Mutex<HashMap<u16, u16>>>;
+
+    /// Minimal Modbus TCP server answering "read holding registers" (0x03), "write single coil" (0x05) and
+    /// "write single register" (0x06) requests.
+    /// Each register holds its own address until written, which makes window mapping errors obvious.
+    fn spawn_modbus_tcp_stand_in() -> (u16, CoilWrites, RegisterWrites) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let coil_writes = CoilWrites::default();
+        let register_writes = RegisterWrites::default();
+        let server_coil_writes = coil_writes.clone();
+        let server_register_writes = register_writes.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                serve_modbus_tcp(stream.unwrap(), &server_coil_writes, &server_register_writes);
+            }
+        });
+        (port, coil_writes, register_writes)
+    }
+
+    fn serve_modbus_tcp(mut stream: TcpStream, coil_writes: &CoilWrites, register_writes: &RegisterWrites) {
+        let mut request = [0u8; 12];
+        while stream.read_exact(&mut request).is_ok() {
+            let address = (u16::from(request[8]) << 8) | u16::from(request[9]);
+            let value = (u16::from(request[10]) << 8) | u16::from(request[11]);
+            match request[7] {
+                0x03 => {
+                    let count = value;
+                    let mut response = vec![request[0], request[1], 0x00, 0x00];
+                    let length = 3 + 2 * count;
+                    response.extend_from_slice(&[(length >> 8) as u8, length as u8, request[6], 0x03, (2 * count) as u8]);
+                    let register_writes = register_writes.lock().unwrap();
+                    for register in address..(address + count) {
+                        let register = *register_writes.get(&register).unwrap_or(&register);
+                        response.extend_from_slice(&[(register >> 8) as u8, register as u8]);
+                    }
+                    stream.write_all(&response).unwrap();
+                }
+                0x05 => {
+                    coil_writes.lock().unwrap().push((address, value == 0xFF00));
+                    // The response to a single coil write echoes the request
+                    stream.write_all(&request).unwrap();
+                }
+                0x06 => {
+                    register_writes.lock().unwrap().insert(address, value);
+                    // The response to a single register write echoes the request
+                    stream.write_all(&request).unwrap();
+                }
+                function => panic!("Unsupported function {:#x}", function),
+            }
+        }
+    }
+
+    #[test]
+    fn sunsaverconnectionerror_kind() {
+        let errors = [
+            SunSaverConnectionError::Timeout,
+            SunSaverConnectionError::Crc,
+            SunSaverConnectionError::ShortRead { expected: 44, actual: 5 },
+            SunSaverConnectionError::DeviceGone(String::from("gone")),
+            SunSaverConnectionError::Protocol(String::from("exception")),
+            SunSaverConnectionError::Unsupported("writing registers"),
+            SunSaverConnectionError::Verify {
+                address: 0xE001,
+                expected: 1,
+                actual: 2,
+            },
+        ];
+        let kinds: Vec<&str> = errors.iter().map(SunSaverConnectionError::kind).collect();
+        assert_eq!(kinds, SunSaverConnectionError::KINDS.to_vec());
+    }
+
+    #[test]
+    fn modbus_tcp_read_raw_registers() {
+        let (port, _, _) = spawn_modbus_tcp_stand_in();
+        let mut connection = ModbusSunSaverConnection::open_tcp("127.0.0.1", port, &ModbusOptions::default()).unwrap();
+
+        let response_register = connection.read_raw_registers().unwrap();
+        for (i, register) in response_register.iter().enumerate() {
+            assert_eq!(*register, 0x0008 + i as u16);
+        }
+    }
 
-        let response_register_vec_u16: Vec<u16> = response_register_u8
-            .chunks(2)
-            .map(|items| u16::from_le((u16::from(items[0]) << 8) + u16::from(items[1])))
-            .collect();
+    #[test]
+    fn modbus_tcp_read_raw_logged() {
+        let (port, _, _) = spawn_modbus_tcp_stand_in();
+        let mut connection = ModbusSunSaverConnection::open_tcp("127.0.0.1", port, &ModbusOptions::default()).unwrap();
 
-        let mut response_register_u16 = [0u16; 44 as usize];
-        response_register_u16.clone_from_slice(&response_register_vec_u16);
-        response_register_u16
+        let logged_data = connection.read_raw_logged().unwrap();
+        for (i, register) in logged_data.iter().enumerate() {
+            assert_eq!(*register, 0x8000 + i as u16);
+        }
     }
 
-    fn read_raw_logged(&mut self) -> [u16; 32 * 16] {
-        unimplemented!();
+    #[test]
+    fn modbus_tcp_read_raw_eeprom() {
+        let (port, _, _) = spawn_modbus_tcp_stand_in();
+        let mut connection = ModbusSunSaverConnection::open_tcp("127.0.0.1", port, &ModbusOptions::default()).unwrap();
+
+        let eeprom_register = connection.read_raw_eeprom().unwrap();
+        for (i, register) in eeprom_register.iter().enumerate() {
+            assert_eq!(*register, 0xE000 + i as u16);
+        }
+    }
+
+    #[test]
+    fn modbus_tcp_write_eeprom() {
+        let (port, _, register_writes) = spawn_modbus_tcp_stand_in();
+        let mut connection = ModbusSunSaverConnection::open_tcp("127.0.0.1", port, &ModbusOptions::default()).unwrap();
+
+        let change = EepromSettingChange {
+            setting: EepromSetting::FloatVoltage,
+            from: 0xE001,
+            to: 0x1168,
+        };
+        let settings = connection.write_eeprom(&[change]).unwrap();
+        assert_eq!(settings.register(EepromSetting::FloatVoltage), 0x1168);
+        assert_eq!(settings.register(EepromSetting::AbsorptionVoltage), 0xE000);
+        assert_eq!(register_writes.lock().unwrap().len(), 1);
+    }
+
+    /// Records register writes, failing those to `fail_address`, and reads back a fixed `eeprom`
+    struct RegisterConnection {
+        writes: Vec<(u16, u16)>,
+        fail_address: u16,
+        eeprom: [u16; 47],
+    }
+
+    impl SunSaverConnection for RegisterConnection {
+        fn read_raw_registers(&mut self) -> Result<[u16; 44], SunSaverConnectionError> {
+            Err(SunSaverConnectionError::Unsupported("reading registers"))
+        }
+
+        fn read_raw_logged(&mut self) -> Result<[u16; 32 * 16], SunSaverConnectionError> {
+            Err(SunSaverConnectionError::Unsupported("reading logged data"))
+        }
+
+        fn read_raw_eeprom(&mut self) -> Result<[u16; 47], SunSaverConnectionError> {
+            Ok(self.eeprom)
+        }
+
+        fn write_register(&mut self, address: u16, value: u16) -> Result<(), SunSaverConnectionError> {
+            if address == self.fail_address {
+                return Err(SunSaverConnectionError::Timeout);
+            }
+            self.writes.push((address, value));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn write_eeprom_roll_back() {
+        let changes = [
+            EepromSettingChange {
+                setting: EepromSetting::AbsorptionVoltage,
+                from: 0x1200,
+                to: 0x1180,
+            },
+            EepromSettingChange {
+                setting: EepromSetting::FloatVoltage,
+                from: 0x1133,
+                to: 0x1168,
+            },
+            EepromSettingChange {
+                setting: EepromSetting::AbsorptionTime,
+                from: 0x0E10,
+                to: 0x1C20,
+            },
+        ];
+        let mut connection = RegisterConnection {
+            writes: Vec::new(),
+            fail_address: EepromSetting::AbsorptionTime.address(),
+            eeprom: [0; 47],
+        };
+
+        assert_eq!(connection.write_eeprom(&changes).err(), Some(SunSaverConnectionError::Timeout));
+        // The two settings written before the failure are put back, latest first
+        assert_eq!(
+            connection.writes,
+            vec![(0xE000, 0x1180), (0xE001, 0x1168), (0xE001, 0x1133), (0xE000, 0x1200)]
+        );
+    }
+
+    #[test]
+    fn write_eeprom_verify_roll_back() {
+        let changes = [
+            EepromSettingChange {
+                setting: EepromSetting::AbsorptionVoltage,
+                from: 0x1200,
+                to: 0x1180,
+            },
+            EepromSettingChange {
+                setting: EepromSetting::FloatVoltage,
+                from: 0x1133,
+                to: 0x1168,
+            },
+        ];
+        let mut eeprom = [0; 47];
+        eeprom[0] = 0x1180;
+        eeprom[1] = 0x1133;
+        let mut connection = RegisterConnection {
+            writes: Vec::new(),
+            fail_address: 0,
+            eeprom,
+        };
+
+        assert_eq!(
+            connection.write_eeprom(&changes).err(),
+            Some(SunSaverConnectionError::Verify {
+                address: 0xE001,
+                expected: 0x1168,
+                actual: 0x1133,
+            })
+        );
+        // Both written settings are put back when the controller did not store one of them
+        assert_eq!(
+            connection.writes,
+            vec![(0xE000, 0x1180), (0xE001, 0x1168), (0xE001, 0x1133), (0xE000, 0x1200)]
+        );
+    }
+
+    #[test]
+    fn modbus_tcp_execute_command() {
+        let (port, coil_writes, _) = spawn_modbus_tcp_stand_in();
+        let mut connection = ModbusSunSaverConnection::open_tcp("127.0.0.1", port, &ModbusOptions::default()).unwrap();
+
+        connection.execute_command(Command::ClearFaults).unwrap();
+        connection.execute_command(Command::LoadConnect).unwrap();
+        assert_eq!(*coil_writes.lock().unwrap(), vec![(0x0014, true), (0x0015, true), (0x0001, false)]);
+    }
+
+    #[test]
+    fn modbus_tcp_record() {
+        let temp_dir = TempDir::new(concat!(module_path!(), "modbus_tcp_record")).unwrap();
+        let path = temp_dir.path().join("recording.jsonl");
+        let (port, _, _) = spawn_modbus_tcp_stand_in();
+        let mut connection = ModbusSunSaverConnection::open_tcp("127.0.0.1", port, &ModbusOptions::default()).unwrap();
+        connection.record(Recorder::create(&path, 0x01).unwrap());
+
+        let response_register = connection.read_raw_registers().unwrap();
+        let logged_data = connection.read_raw_logged().unwrap();
+        connection.execute_command(Command::Equalize).unwrap();
+        let eeprom_register = connection.read_raw_eeprom().unwrap();
+        drop(connection);
+
+        // Each request is replayed whatever order they are made in
+        let mut replay = ReplaySunSaverConnection::open(&path, ReplayOptions::default()).unwrap();
+        assert_eq!(replay.read_raw_logged().unwrap()[..], logged_data[..]);
+        assert_eq!(replay.read_raw_eeprom().unwrap()[..], eeprom_register[..]);
+        assert_eq!(replay.read_raw_registers(), Ok(response_register));
+        replay.execute_command(Command::Equalize).unwrap();
+        assert!(replay.execute_command(Command::Equalize).is_err());
+    }
+
+    /// Fails fast so injected timeouts and CRC faults are reported on the first request
+    fn impatient_options() -> ModbusOptions {
+        ModbusOptions {
+            response_timeout: Duration::from_millis(200),
+            retry: RetryPolicy { attempts: 1, wait_ms: 0 },
+            ..ModbusOptions::default()
+        }
+    }
+
+    fn simulator(history_days: u64) -> SimulatorServer {
+        let mut controller = SimulatedController::new(Profile::named("day").unwrap());
+        controller.warm_up(history_days);
+        // The clock is stopped so time only passes when the test advances it
+        SimulatorServer::new(controller, 0x01, 0)
+    }
+
+    #[test]
+    fn modbus_tcp_open_errors() {
+        let options = ModbusOptions {
+            response_timeout: Duration::from_millis(0),
+            ..ModbusOptions::default()
+        };
+        let error = ModbusSunSaverConnection::open_tcp("127.0.0.1", 502, &options).err().unwrap();
+        assert_eq!(error, SunSaverConnectionError::Protocol(String::from("Modbus error 22")));
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+        let error = ModbusSunSaverConnection::open_tcp("127.0.0.1", port, &impatient_options()).err().unwrap();
+        assert_eq!(error.kind(), "device_gone");
+    }
+
+    #[test]
+    fn modbus_tcp_simulator() {
+        let server = simulator(2);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let tcp_server = server.clone();
+        thread::spawn(move || tcp_server.serve_tcp(listener));
+        let mut connection = ModbusSunSaverConnection::open_tcp("127.0.0.1", port, &impatient_options()).unwrap();
+
+        let status = connection.read_status().unwrap();
+        assert_eq!(status.charge_state(), ChargeState::Float);
+        assert_eq!(status.load_state(), LoadState::Normal);
+        assert_eq!(connection.read_logged().unwrap().days.len(), 2);
+
+        connection.execute_command(Command::LoadDisconnect).unwrap();
+        assert_eq!(connection.read_status().unwrap().load_state(), LoadState::Disconnect);
+
+        let change = EepromSettingChange {
+            setting: EepromSetting::FloatVoltage,
+            from: 0x1133,
+            to: 0x1168,
+        };
+        let settings = connection.write_eeprom(&[change]).unwrap();
+        assert_eq!(settings.register(EepromSetting::FloatVoltage), 0x1168);
+
+        server.controller().inject("array:OVERCURENT".parse().unwrap());
+        server.controller().advance(10);
+        assert_eq!(connection.read_status().unwrap().array_fault(), ArrayFault::OVERCURENT);
+
+        server.controller().inject("timeout".parse().unwrap());
+        assert_eq!(connection.read_raw_registers(), Err(SunSaverConnectionError::Timeout));
+    }
+
+    #[test]
+    fn modbus_rtu_simulator() {
+        let server = simulator(0);
+        let path = server.serve_pty().unwrap();
+        let mut connection = ModbusSunSaverConnection::open_rtu(&path, &SerialOptions::default(), &impatient_options()).unwrap();
+
+        let status = connection.read_status().unwrap();
+        assert_eq!(status.charge_state(), ChargeState::BulkCharge);
+        assert!(connection.read_logged().unwrap().days.is_empty());
+        assert_eq!(connection.read_eeprom().unwrap().register(EepromSetting::AbsorptionVoltage), 0x1200);
+
+        server.controller().inject("crc".parse().unwrap());
+        assert_eq!(connection.read_raw_registers(), Err(SunSaverConnectionError::Crc));
     }
 }